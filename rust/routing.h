@@ -5,17 +5,314 @@
 extern "C" {
 #endif
 
+/*
+ * Thread safety: all functions may be called from any thread. Queries on the same mode run
+ * in parallel; loading, unloading and what-if edits (way delays, added edges) wait for
+ * running queries of that mode and block new ones until they finish.
+ *
+ * Modes: every mode argument must be one of the names listed by routing_supported_modes.
+ * Functions given any other name (e.g. a typo such as "walking", or "Auto") fail with
+ * ROUTING_UNKNOWN_MODE (-4.0 for functions returning a double) instead of using another
+ * mode's graph; routing_is_loaded returns 0 and the void functions do nothing.
+ * routing_last_error names the mode and the supported ones.
+ *
+ * Coordinates: latitudes must lie in [-90, 90] and longitudes in [-180, 180] (WGS84 degrees).
+ * Functions given a coordinate outside these ranges or NaN, including swapped lat/lon such as
+ * a latitude of 139.7, or a point parsed from a WKT/WKB geometry, fail with
+ * ROUTING_INVALID_COORDINATE (-5.0 for functions returning a double) instead of snapping to
+ * whatever node is nearest; functions taking arrays of points fail if any point is out of
+ * range, except routing_batch, which marks the pair. routing_last_error names the coordinate.
+ */
+
+#define ROUTING_UNKNOWN_MODE (-4)
+#define ROUTING_INVALID_COORDINATE (-5)
+
 /**
  * Load routing data from an OSM PBF file for a specific mode.
  *
  * @param pbf_path Path to the OSM PBF file
- * @param mode Transport mode: "auto", "bicycle", or "pedestrian"
- * @return 0 on success, -1 on error
+ * @param mode Transport mode: "auto", "bicycle", "pedestrian", "agricultural" or "hgv"
+ *             ("agricultural" routes tractors on tracks, by tracktype, honoring agricultural/forestry access;
+ *             "hgv" routes trucks at lower speeds, honoring hgv access, maxspeed:hgv and restriction:hgv)
+ *
+ * Ways whose access tags deny the mode (no/private) are left out. The most specific key decides:
+ * auto reads motorcar, motor_vehicle, vehicle, access; bicycle reads bicycle, vehicle, access;
+ * pedestrian reads foot, access; hgv reads hgv, motor_vehicle, vehicle, access. In hgv mode
+ * maxspeed:hgv sets the speed, while a plain maxspeed only caps the lower truck default
+ * (conditional limits are ignored). Values like destination are kept; bicycle=dismount ways are
+ * kept at pushing pace (5 km/h). In bicycle mode oneway:bicycle overrides oneway, and car
+ * one-ways with a contraflow cycleway (cycleway=opposite*) can be ridden both ways.
+ * highway=road is routed like unclassified and highway=services like service. Busways and
+ * raceways, and tracks and paths for auto, are only used where the mode's access key
+ * explicitly allows it (e.g. motor_vehicle=yes; psv=yes does not admit cars).
+ * Ferries (route=ferry) connect the network: pedestrians and cyclists board unless foot/bicycle/
+ * access deny it, motor vehicles only where motor_vehicle, motorcar (or vehicle/access) is yes.
+ * A crossing takes its duration tag (HH:MM:SS, HH:MM or minutes), else runs at 10 km/h.
+ * Bicycle and pedestrian speeds are scaled by the way's surface: cyclists ride at 0.9x on
+ * paving stones, compacted or fine gravel, 0.8x on gravel and unpaved ground, 0.7x on sett and
+ * cobblestone, 0.6x on grass and 0.5x on sand or mud; walkers slow to 0.9x on gravel and grass
+ * and 0.7x on sand or mud. Other surfaces keep full speed.
+ * Walkers also pay 0.5 s per step on highway=steps tagged with step_count, on top of the steps
+ * speed, and 5 s at highway=crossing nodes without signals (crossing=traffic_signals or
+ * crossing:signals=yes) when crossing the road there; walking along the road is not delayed.
+ *
+ * The graph is cached next to the PBF ({pbf}.{mode}.routing). A cache written by another
+ * extension version or cache format is rebuilt automatically. Node positions and adjacency
+ * are memory-mapped from the cache file rather than read into memory; the contraction
+ * hierarchy, spatial index and edge attributes are still deserialized on load.
+ *
+ * Turn restrictions (type=restriction relations with a via node, no_* and only_*) are read
+ * for every mode but pedestrian and stored in the cache. Route, travel time, isochrone and
+ * matrix queries honor them; batch and symmetric matrix queries on the contraction hierarchy,
+ * and shortest-distance routes, do not.
+ * @return 0 on success, -1 on error (e.g. an unreadable PBF; see routing_last_error)
  */
 int routing_load(const char *pbf_path, const char *mode);
 
+/**
+ * Load routing data like routing_load, optionally rebuilding from the PBF even if a cache
+ * exists. The rebuilt graph overwrites the cache file.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param force_rebuild Non-zero to ignore the cache and rebuild; 0 behaves like routing_load
+ * @return 0 on success, -1 on error (see routing_last_error)
+ */
+int routing_load_ex(const char *pbf_path, const char *mode, int force_rebuild);
+
+/**
+ * Rebuild routing data from the PBF, ignoring and overwriting the cache, e.g. after
+ * replacing the PBF with a newer OSM extract. Same as routing_load_ex with force_rebuild = 1.
+ * When the rebuilt graph has the same nodes as the cached one, the contraction order of the
+ * cached hierarchies is reused, which makes the preparation step considerably faster.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @return 0 on success, -1 on error (see routing_last_error)
+ */
+int routing_reload(const char *pbf_path, const char *mode);
+
+/**
+ * Progress callback for routing_load_progress.
+ *
+ * @param phase Current phase: "loading" (reading the cache), "reading" (the PBF), "building"
+ *              (edges), "contracting" (preparing the contraction hierarchies), "writing" (the
+ *              cache) or "done"
+ * @param percent Overall progress from 0 to 100; phases start at fixed points (reading 0,
+ *                building 30, contracting 50, writing 90), so it only rises between phases
+ * @param user_data The user_data pointer passed to routing_load_progress
+ */
+typedef void (*routing_progress_fn)(const char *phase, int percent, void *user_data);
+
+/**
+ * Load routing data like routing_load, reporting progress through a callback so long cold
+ * builds can be observed. The callback is only ever called on the thread calling
+ * routing_load_progress, before it returns.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param callback Progress callback, or NULL
+ * @param user_data Passed to every callback call
+ * @return 0 on success, -1 on error (see routing_last_error)
+ */
+int routing_load_progress(const char *pbf_path, const char *mode, routing_progress_fn callback, void *user_data);
+
+/**
+ * Load routing data for the part of a PBF inside a bounding box, e.g. one city out of a
+ * state extract. Road segments entirely outside the box are dropped; segments crossing
+ * its edge are kept whole. Each box is cached separately
+ * ({pbf}.{mode}.bbox_{min_lon}_{min_lat}_{max_lon}_{max_lat}.routing).
+ *
+ * @param pbf_path Path to the PBF file
+ * @param mode Transport mode
+ * @param min_lon West edge
+ * @param min_lat South edge
+ * @param max_lon East edge
+ * @param max_lat North edge
+ * @return 0 on success, -1 on error (including min > max; see routing_last_error)
+ */
+int routing_load_bbox(const char *pbf_path, const char *mode, double min_lon, double min_lat, double max_lon,
+                      double max_lat);
+
+/**
+ * Load one graph built from several PBF files, e.g. neighbouring regional extracts.
+ * Nodes are merged by OSM id, so ways crossing from one file into another connect;
+ * ways and relations present in more than one file are kept once. The merged graph is
+ * cached next to the first file in sorted order ({pbf}.{hash}.{mode}.routing, where hash
+ * covers the sorted file list), so the cache is reused whatever order the files are given in.
+ *
+ * @param pbf_paths Array of PBF file paths
+ * @param count Number of paths
+ * @param mode Transport mode
+ * @return 0 on success, -1 on error (see routing_last_error)
+ */
+int routing_load_multi(const char *const *pbf_paths, int count, const char *mode);
+
+/**
+ * Load routing data like routing_load, but keep it under a caller-chosen handle instead of
+ * the mode's slot, e.g. one graph per city. Any number of handles stay loaded side by side
+ * and are queried by handle (routing_travel_time_named); they are independent of the mode
+ * slots, so routing_free and routing_free_all leave them loaded. Loading an existing handle
+ * replaces its graph. The graph is cached next to the PBF as with routing_load.
+ *
+ * @param handle Name to load the graph under
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode; queries on the handle route in this mode
+ * @return 0 on success, -1 on error (see routing_last_error), -4 for an unknown mode
+ */
+int routing_load_named(const char *handle, const char *pbf_path, const char *mode);
+
+/**
+ * Load routing data with a custom speed profile.
+ * The profile is a JSON object of highway type -> km/h, e.g. {"residential": 20, "busway": 30},
+ * merged on top of the built-in speeds. Highway types unknown to the defaults are accepted;
+ * a speed of 0 excludes the type. An optional "surface" object maps surface=* values to speed
+ * multipliers, e.g. {"surface": {"gravel": 0.6, "sand": 0}}, merged on top of the defaults
+ * (see routing_load); a multiplier of 0 excludes ways with that surface. An optional
+ * "penalties" list of {"tag", "value", "multiplier"} rules multiplies the travel time of ways
+ * tagged tag=value (any value of tag if "value" is left out), e.g. {"penalties": [{"tag":
+ * "highway", "value": "primary", "multiplier": 3}]} to keep cyclists off primary roads.
+ * Multipliers of matching rules combine by multiplication, and on top of speeds and surface
+ * multipliers; a multiplier of 0 excludes matching ways. An optional "default" speed in km/h
+ * applies to highway types that no mode has a speed for (e.g. a value newly introduced in OSM),
+ * instead of leaving those ways out, e.g. {"default": 30} for auto, 12 for bicycle or 4 for
+ * pedestrian. Non-road values (proposed, construction, abandoned, platform, ...) stay out.
+ * Optional "step_delay" (seconds per step of highway=steps with a step_count) and
+ * "crossing_delay" (seconds at unsignalled crossings) replace the mode's defaults, 0.5 and 5 for
+ * pedestrian and 0 otherwise, e.g. {"step_delay": 1.5, "crossing_delay": 15}; 0 disables them.
+ * Graphs are cached per profile (the profile hash is part of the cache file name).
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param profile_json_path Path to the JSON profile file
+ * @return 0 on success, -1 on error (see routing_last_error for profile errors)
+ */
+int routing_load_profile(const char *pbf_path, const char *mode, const char *profile_json_path);
+
+/**
+ * Load routing data with grade-dependent bicycle and pedestrian speeds, so uphill and downhill
+ * directions of a two-way street get different travel times. Other modes load a flat graph.
+ * A segment's grade comes from the way's incline tag ("10%", "-5%", "4°"; positive is uphill
+ * in way direction), else from the terrain model. Bridges and tunnels without an incline tag
+ * are flat. Terrain grades are clamped to 30% to absorb elevation noise.
+ * Pedestrians follow Tobler's hiking function (fastest on a slight descent). Cyclists keep
+ * constant power against rolling resistance, gravity and drag, riding 1.5x harder on climbs
+ * and at most 1.5x their flat speed downhill.
+ * Graphs are cached per DEM contents ({pbf}.{mode}.elev_{hash}.routing), so replacing a DEM
+ * under the same path builds a new graph.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param dem_path Path to an ESRI ASCII grid (.asc) of terrain heights in meters on a WGS84
+ *                 lon/lat grid, or NULL to use incline tags only
+ * @return 0 on success, -1 on error (see routing_last_error for DEM errors)
+ */
+int routing_load_elevation(const char *pbf_path, const char *mode, const char *dem_path);
+
+/**
+ * Load routing data for a vehicle of a given size, e.g. a truck in "hgv" mode. Ways whose
+ * maxheight, maxwidth or maxweight tag is below the vehicle's dimensions are left out; limits
+ * in meters, feet and inches (12'6"), tonnes, kg or short tons (st) are understood, other
+ * values ("default", "none") don't restrict. Build settings apply as for routing_load. Each
+ * vehicle is cached separately ({pbf}.{mode}.veh_{height}_{width}_{weight}.routing).
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param height_m Vehicle height in meters, 0 to ignore maxheight
+ * @param width_m Vehicle width in meters, 0 to ignore maxwidth
+ * @param weight_t Vehicle weight in tonnes, 0 to ignore maxweight
+ * @return 0 on success, -1 on error (including negative dimensions)
+ */
+int routing_load_vehicle(const char *pbf_path, const char *mode, double height_m, double width_m, double weight_t);
+
+/* Road classes routing_load_opts can leave out of a graph */
+#define ROUTING_EXCLUDE_FERRIES 1
+#define ROUTING_EXCLUDE_TOLLS 2
+#define ROUTING_EXCLUDE_UNPAVED 4 /* highway=track and unsealed surface=* values */
+
+/**
+ * Load routing data built without some road classes, e.g. ferries for vehicles that cannot
+ * use them. Excluded ways are dropped entirely, unlike routing_route_avoid_tolls which only
+ * penalizes them. Build settings apply as for routing_load. Each combination is cached
+ * separately ({pbf}.{mode}.x{exclude}.routing).
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param exclude Bitwise OR of ROUTING_EXCLUDE_* flags (0 is the same as routing_load)
+ * @return 0 on success, -1 on error (including unknown flags)
+ */
+int routing_load_opts(const char *pbf_path, const char *mode, int exclude);
+
+/**
+ * Load routing data like routing_load, writing a newly built cache compressed with zstd.
+ * Compressed caches are typically several times smaller, e.g. for shipping in container
+ * images, but are decompressed into memory on load instead of being memory-mapped. Every
+ * load function detects compressed caches by their header, so an existing cache (compressed
+ * or not) is used as is; delete it to rewrite it with another level.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param mode Transport mode
+ * @param level zstd compression level from 1 (fastest) to 22 (smallest), or 0 for an
+ *              uncompressed cache
+ * @return 0 on success, -1 on error (including an invalid level; see routing_last_error)
+ */
+int routing_load_compressed(const char *pbf_path, const char *mode, int level);
+
+/* Mode bits returned by routing_preload */
+#define ROUTING_MODE_AUTO 1
+#define ROUTING_MODE_BICYCLE 2
+#define ROUTING_MODE_PEDESTRIAN 4
+#define ROUTING_MODE_AGRICULTURAL 8
+#define ROUTING_MODE_HGV 16
+
+/**
+ * Load several modes from one PBF file in one call, e.g. at service startup so that no
+ * query waits on a first load. Modes with a valid cache load from it; the others are built
+ * in parallel from a single read of the file, using the routing_set_threads pool. Build
+ * settings apply as for routing_load.
+ *
+ * @param pbf_path Path to the OSM PBF file
+ * @param modes_csv Comma-separated transport modes, e.g. "auto,bicycle,pedestrian"
+ * @return Bitmask of the modes loaded (ROUTING_MODE_*), -1 on error, or ROUTING_UNKNOWN_MODE
+ *         if a mode is unknown. Modes that failed to load are described by
+ *         routing_last_error
+ */
+int routing_preload(const char *pbf_path, const char *modes_csv);
+
+/**
+ * Load routing data from serialized cache bytes (the contents of a .routing file).
+ * Lets prebuilt graphs be embedded or streamed without touching the filesystem.
+ * Cache files start with a header (magic, format version, extension version, mode, profile);
+ * caches written by another extension version or for another mode are rejected. Compressed
+ * caches (see routing_load_compressed) are accepted too.
+ *
+ * @param bytes Serialized routing data
+ * @param len Length of bytes
+ * @param mode Transport mode the graph was built for
+ * @return 0 on success, -1 on error (invalid, stale or corrupt data; see routing_last_error)
+ */
+int routing_load_cache_bytes(const unsigned char *bytes, int len, const char *mode);
+
+/**
+ * Load routing data from an OSM PBF file held in memory, e.g. received over the network,
+ * without writing it to disk first. Build settings apply as for routing_load. The graph is
+ * cached under the SHA-256 of the contents in a directory private to the user
+ * ($XDG_CACHE_HOME/duckdb-routing, ~/.cache/duckdb-routing or %LOCALAPPDATA%\duckdb-routing),
+ * so loading identical bytes again reuses it. Cached graphs are never removed automatically;
+ * delete the directory to reclaim the space. Without such a directory the graph is built on
+ * every load.
+ *
+ * @param data PBF file contents
+ * @param len Length of data in bytes
+ * @param mode Transport mode
+ * @return 0 on success, -1 on error (see routing_last_error)
+ */
+int routing_load_buffer(const unsigned char *data, long long len, const char *mode);
+
 /**
  * Calculate travel time between two points.
+ * Both points are snapped onto the nearest road segment; the partial segment times
+ * before the first and after the last network node are included.
  *
  * @param lat1 Start latitude
  * @param lon1 Start longitude
@@ -26,6 +323,70 @@ int routing_load(const char *pbf_path, const char *mode);
  */
 double routing_travel_time(double lat1, double lon1, double lat2, double lon2, const char *mode);
 
+/**
+ * Calculate travel time between two points like routing_travel_time, and report how far each
+ * point is from the road segment it snapped to, so results for off-network points can be flagged.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_origin_snap_m Output: origin snap distance in meters (may be NULL)
+ * @param out_dest_snap_m Output: destination snap distance in meters (may be NULL)
+ * @return Travel time in seconds, -1.0 if no route found, -2.0 if not loaded. The snap distances
+ *         are written whenever both points snap, even without a route, and are -1 otherwise.
+ */
+double routing_travel_time_ex(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                              double *out_origin_snap_m, double *out_dest_snap_m);
+
+/**
+ * Travel time like routing_travel_time, falling back to an estimate when no route exists: the
+ * great-circle distance at a typical overall speed for the mode (auto 40 km/h, hgv 35,
+ * agricultural 15, bicycle 14, pedestrian 4.5), scaled by routing_set_speed_factor. Keeps
+ * results dense for analyses that cannot handle missing values; the flag tells estimates apart.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_estimated Output: 1 if the result is an estimate, 0 if routed (may be NULL)
+ * @return Travel time in seconds, -1.0 on error, -2.0 if not loaded
+ */
+double routing_travel_time_or_estimate(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                                       int *out_estimated);
+
+/**
+ * Calculate travel time like routing_travel_time, on the graph loaded under a handle
+ * (routing_load_named) and in the mode it was loaded for.
+ *
+ * @param handle Handle the graph was loaded under
+ * @param lat1 Origin latitude
+ * @param lon1 Origin longitude
+ * @param lat2 Destination latitude
+ * @param lon2 Destination longitude
+ * @return Travel time in seconds, -1.0 on error or no route, -2.0 if nothing is loaded under
+ *         the handle
+ */
+double routing_travel_time_named(const char *handle, double lat1, double lon1, double lat2, double lon2);
+
+/**
+ * Calculate travel time and road distance between two points in one call. Uses the same
+ * snapping and route as routing_route, without returning the geometry.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_seconds Output: travel time in seconds
+ * @param out_meters Output: road distance in meters (routing_set_distance_unit does not apply)
+ * @return 0 on success, -1 on error or if no route found, -2 if not loaded
+ */
+int routing_cost(double lat1, double lon1, double lat2, double lon2, const char *mode, double *out_seconds,
+                 double *out_meters);
+
 /**
  * Batch calculate travel times between pairs of points.
  *
@@ -33,7 +394,9 @@ double routing_travel_time(double lat1, double lon1, double lat2, double lon2, c
  * @param lons1 Array of start longitudes
  * @param lats2 Array of end latitudes
  * @param lons2 Array of end longitudes
- * @param results Output array for travel times in seconds (must be pre-allocated)
+ * @param results Output array for travel times in seconds (must be pre-allocated). Failed pairs
+ *                hold the reason: -1.0 if the start did not snap, -2.0 if the end did not snap,
+ *                -3.0 if no path connects them, -5.0 if a coordinate is out of range
  * @param count Number of pairs to calculate
  * @param mode Transport mode
  * @return Number of successful calculations, -1 on error, -2 if not loaded
@@ -41,8 +404,145 @@ double routing_travel_time(double lat1, double lon1, double lat2, double lon2, c
 int routing_batch(const double *lats1, const double *lons1, const double *lats2, const double *lons2, double *results,
                   int count, const char *mode);
 
+/**
+ * Batch travel times like routing_batch, giving pairs without a route the great-circle
+ * estimate of routing_travel_time_or_estimate instead of a negative reason, so every result
+ * is a travel time, except for pairs with an out-of-range coordinate, which keep -5.0 and
+ * are not flagged as estimated.
+ *
+ * @param lats1 Array of start latitudes
+ * @param lons1 Array of start longitudes
+ * @param lats2 Array of end latitudes
+ * @param lons2 Array of end longitudes
+ * @param results Output array for travel times in seconds (must be pre-allocated)
+ * @param count Number of pairs to calculate
+ * @param mode Transport mode
+ * @param out_estimated Output array of count flags: 1 for an estimated pair, 0 for a routed one
+ *                      (may be NULL)
+ * @return Number of routed (not estimated) pairs, -1 on error, -2 if not loaded
+ */
+int routing_batch_or_estimate(const double *lats1, const double *lons1, const double *lats2, const double *lons2,
+                              double *results, int count, const char *mode, int *out_estimated);
+
+/**
+ * Full travel-time matrix from every source to every destination.
+ * Points are snapped once; each source row is a single one-to-many search (rows run in parallel).
+ *
+ * @param src_lats Array of source latitudes
+ * @param src_lons Array of source longitudes
+ * @param n_src Number of sources
+ * @param dst_lats Array of destination latitudes
+ * @param dst_lons Array of destination longitudes
+ * @param n_dst Number of destinations
+ * @param results Output array of n_src * n_dst travel times in seconds, row-major (row = source);
+ *                -1.0 for unroutable cells
+ * @param mode Transport mode
+ * @return Number of routed cells, -1 on error, -2 if not loaded
+ */
+int routing_matrix(const double *src_lats, const double *src_lons, int n_src, const double *dst_lats,
+                   const double *dst_lons, int n_dst, double *results, const char *mode);
+
+/**
+ * Travel-time matrix like routing_matrix that also reports how far each point is from the
+ * node it snapped to, so cells where either endpoint snapped too far can be filtered out
+ * afterwards: cell (i, j) is suspect if out_src_snap_m[i] or out_dst_snap_m[j] is large.
+ * The distances come from the snapping the matrix does anyway.
+ *
+ * @param src_lats Array of source latitudes
+ * @param src_lons Array of source longitudes
+ * @param n_src Number of sources
+ * @param dst_lats Array of destination latitudes
+ * @param dst_lons Array of destination longitudes
+ * @param n_dst Number of destinations
+ * @param results Output array of n_src * n_dst travel times in seconds, row-major (row = source);
+ *                -1.0 for unroutable cells
+ * @param mode Transport mode
+ * @param out_src_snap_m Output array of n_src snap distances in meters (-1.0 if the source did not
+ *                       snap), or NULL
+ * @param out_dst_snap_m Output array of n_dst snap distances in meters, or NULL
+ * @return Number of routed cells, -1 on error, -2 if not loaded
+ */
+int routing_matrix_ex(const double *src_lats, const double *src_lons, int n_src, const double *dst_lats,
+                      const double *dst_lons, int n_dst, double *results, const char *mode, double *out_src_snap_m,
+                      double *out_dst_snap_m);
+
+/**
+ * Travel-time matrix among one set of points, computing only the upper triangle.
+ * Only valid for approximately symmetric graphs (t(i, j) ~ t(j, i)), e.g. pedestrian networks;
+ * graphs with many oneways (auto) should use a full matrix instead.
+ *
+ * @param lats Array of latitudes
+ * @param lons Array of longitudes
+ * @param n Number of points
+ * @param mode Transport mode
+ * @param out_triangle Output: n * (n + 1) / 2 doubles, packed row-major upper triangle; cell (i, j) with
+ *                     i <= j is at i * n - i * (i - 1) / 2 + (j - i). Diagonal is 0.0, unroutable pairs -1.0
+ * @return Number of routed pairs (i < j), -1 on error, -2 if not loaded
+ */
+int routing_matrix_symmetric(const double *lats, const double *lons, int n, const char *mode, double *out_triangle);
+
+/**
+ * Snap coordinates once to opaque node ids, for repeated queries through the *_by_id functions
+ * without re-snapping. Ids are only valid for the graph of this mode that is currently loaded:
+ * reloading (or freeing) the mode invalidates them.
+ *
+ * @param lats Array of latitudes
+ * @param lons Array of longitudes
+ * @param count Number of points
+ * @param mode Transport mode
+ * @param out_node_ids Output array of count node ids; -1 for points that cannot be snapped
+ * @return Number of points snapped, -1 on error, -2 if not loaded
+ */
+int routing_snap_ids(const double *lats, const double *lons, int count, const char *mode, long long *out_node_ids);
+
+/**
+ * Travel time between two node ids from routing_snap_ids, skipping snapping.
+ *
+ * @param id1 Start node id
+ * @param id2 End node id
+ * @param mode Transport mode
+ * @return Travel time in seconds, -1 if an id is invalid for the loaded graph or no route exists,
+ *         -2 if not loaded
+ */
+double routing_travel_time_by_id(long long id1, long long id2, const char *mode);
+
+/**
+ * Full travel-time matrix between node ids from routing_snap_ids, skipping snapping.
+ *
+ * @param src_ids Array of source node ids
+ * @param n_src Number of sources
+ * @param dst_ids Array of destination node ids
+ * @param n_dst Number of destinations
+ * @param results Output array of n_src * n_dst travel times in seconds, row-major (row = source);
+ *                -1.0 for unroutable cells and invalid ids
+ * @param mode Transport mode
+ * @return Number of routed cells, -1 on error, -2 if not loaded
+ */
+int routing_matrix_by_id(const long long *src_ids, int n_src, const long long *dst_ids, int n_dst, double *results,
+                         const char *mode);
+
+/**
+ * For each origin, count the POIs reachable within a time budget (e.g. for accessibility scores).
+ * POIs are snapped once; each origin is a single bounded search (origins run in parallel), which
+ * is far cheaper than a full matrix when only counts are needed.
+ *
+ * @param origin_lats Array of origin latitudes
+ * @param origin_lons Array of origin longitudes
+ * @param n Number of origins
+ * @param poi_lats Array of POI latitudes
+ * @param poi_lons Array of POI longitudes
+ * @param m Number of POIs
+ * @param max_seconds Travel time budget in seconds
+ * @param mode Transport mode
+ * @param out_counts Output array of n POI counts; -1 for origins that cannot be snapped
+ * @return Number of origins counted, -1 on error, -2 if not loaded
+ */
+int routing_reachable_count(const double *origin_lats, const double *origin_lons, int n, const double *poi_lats,
+                            const double *poi_lons, int m, double max_seconds, const char *mode, int *out_counts);
+
 /**
  * Snap a coordinate to the nearest road network node.
+ * Longitude wraparound is handled, so points near the antimeridian snap across +-180.
  *
  * @param lat Input latitude
  * @param lon Input longitude
@@ -55,117 +555,1160 @@ int routing_batch(const double *lats1, const double *lons1, const double *lats2,
 int routing_snap(double lat, double lon, const char *mode, double *out_lat, double *out_lon, double *out_distance_m);
 
 /**
- * Get count of nodes in the routing graph.
+ * Snap a coordinate to the nearest road network node, failing when that node is farther than
+ * max_distance_m, so off-network points can be rejected instead of snapped to a distant road.
  *
+ * @param lat Input latitude
+ * @param lon Input longitude
  * @param mode Transport mode
- * @return Number of nodes, -1 on error, -2 if not loaded
+ * @param max_distance_m Maximum snap distance in meters
+ * @param out_lat Output: snapped latitude
+ * @param out_lon Output: snapped longitude
+ * @param out_distance_m Output: distance to snapped point in meters
+ * @return 0 on success, -1 on error, -2 if not loaded, -3 if the nearest node is farther than
+ *         max_distance_m (the outputs still describe that node)
  */
-int routing_node_count(const char *mode);
+int routing_snap_within(double lat, double lon, const char *mode, double max_distance_m, double *out_lat,
+                        double *out_lon, double *out_distance_m);
 
 /**
- * Check if routing data is loaded for a mode.
+ * Snap a coordinate to the k nearest road network nodes, sorted by haversine distance.
+ * Useful where a point sits between parallel roads, e.g. the carriageways of a divided highway.
  *
+ * @param lat Input latitude
+ * @param lon Input longitude
+ * @param k Number of candidates wanted
  * @param mode Transport mode
- * @return 1 if loaded, 0 if not
+ * @param out_lats Output array for candidate latitudes (must be pre-allocated)
+ * @param out_lons Output array for candidate longitudes (must be pre-allocated)
+ * @param out_dists Output array for distances to the candidates in meters (must be pre-allocated)
+ * @param max_k Maximum number of candidates the arrays can hold
+ * @return Number of candidates written (fewer than k if the graph is smaller), -1 on error,
+ *         -2 if not loaded
  */
-int routing_is_loaded(const char *mode);
+int routing_snap_k(double lat, double lon, int k, const char *mode, double *out_lats, double *out_lons,
+                   double *out_dists, int max_k);
 
 /**
- * Free routing data for a mode.
+ * Snap a coordinate to the nearest node that has at least one incident edge of an allowed
+ * highway type (e.g. keep freight origins off pedestrian-only paths).
  *
+ * @param lat Input latitude
+ * @param lon Input longitude
  * @param mode Transport mode
+ * @param allowed_highway_csv Comma-separated highway=* values, e.g. "primary,secondary,tertiary"
+ * @param out_lat Output: snapped latitude
+ * @param out_lon Output: snapped longitude
+ * @param out_distance_m Output: distance to snapped point in meters
+ * @return 0 on success, -1 on error (including a highway type the graph doesn't track, named by
+ *         routing_last_error) or if no node matches, -2 if not loaded
  */
-void routing_free(const char *mode);
+int routing_snap_filtered(double lat, double lon, const char *mode, const char *allowed_highway_csv,
+                          double *out_lat, double *out_lon, double *out_distance_m);
 
 /**
- * Isochrone result struct.
+ * Get count of nodes in the routing graph.
+ *
+ * @param mode Transport mode
+ * @return Number of nodes, -1 on error, -2 if not loaded
  */
-typedef struct {
-	double lat;
-	double lon;
-	double seconds;
-} IsochroneResult;
+int routing_node_count(const char *mode);
 
 /**
- * Route point struct.
+ * Get count of directed edges in the routing graph (a two-way road segment counts twice).
+ *
+ * @param mode Transport mode
+ * @return Number of edges, -1 on error, -2 if not loaded
  */
-typedef struct {
-	double lat;
-	double lon;
-} RoutePoint;
+long long routing_edge_count(const char *mode);
 
 /**
- * Route result struct.
+ * Bounding box of the loaded graph's nodes. Lets callers reject coordinates outside the
+ * extract, which would otherwise snap to its edge and produce a bogus long route.
+ *
+ * @param mode Transport mode
+ * @param out_min_lon Output: minimum longitude
+ * @param out_min_lat Output: minimum latitude
+ * @param out_max_lon Output: maximum longitude
+ * @param out_max_lat Output: maximum latitude
+ * @return 0 on success, -1 on error or for an empty graph, -2 if not loaded
  */
-typedef struct {
-	double distance_m; /* Total road distance in meters */
-	double duration_s; /* Travel time in seconds */
-	int num_points;    /* Number of points in geometry */
-} RouteResult;
+int routing_graph_bounds(const char *mode, double *out_min_lon, double *out_min_lat, double *out_max_lon,
+                         double *out_max_lat);
 
 /**
- * Calculate isochrone - all reachable points within max_seconds.
+ * Number of weakly connected components in the input road network, before components
+ * smaller than the build minimum (see routing_set_build_min_component_size) were dropped.
+ * A high count means a fragmented extract.
  *
- * @param lat Origin latitude
- * @param lon Origin longitude
- * @param max_seconds Maximum travel time in seconds
  * @param mode Transport mode
- * @param out_results Output array for results (must be pre-allocated)
- * @param max_results Maximum number of results to return
- * @return Number of results written, -1 on error, -2 if not loaded
+ * @return Number of components, -1 on error, -2 if not loaded
  */
-int routing_isochrone(double lat, double lon, double max_seconds, const char *mode, IsochroneResult *out_results,
-                      int max_results);
+int routing_component_count(const char *mode);
 
 /**
- * Calculate route with full geometry.
+ * Check whether two points snap to roads in the same connected component of the graph
+ * (weakly connected, labelled when the graph is built). Points in different components never
+ * have a route, so this is a cheap check before routing and explains a -1 route result;
+ * points in one component may still have no route because of one-way roads.
  *
- * @param lat1 Start latitude
- * @param lon1 Start longitude
- * @param lat2 End latitude
- * @param lon2 End longitude
+ * @param lat1 First point latitude
+ * @param lon1 First point longitude
+ * @param lat2 Second point latitude
+ * @param lon2 Second point longitude
  * @param mode Transport mode
- * @param out_result Output: route summary (distance, duration, point count)
- * @param out_points Output: array for path coordinates (must be pre-allocated)
- * @param max_points Maximum number of points buffer can hold
- * @return Number of points written, -1 on error, -2 if not loaded
+ * @return 1 if in the same component, 0 if not, -1 on error (including a failed snap),
+ *         -2 if not loaded
  */
-int routing_route(double lat1, double lon1, double lat2, double lon2, const char *mode, RouteResult *out_result,
-                  RoutePoint *out_points, int max_points);
+int routing_same_component(double lat1, double lon1, double lat2, double lon2, const char *mode);
 
 /**
- * Calculate route using WKT geometries as input.
- * Uses centroid of each geometry as the routing point.
+ * Check if routing data is loaded for a mode.
  *
- * @param from_wkt WKT geometry string for start (e.g., "POINT(12.45 43.94)" or "POLYGON(...)")
- * @param to_wkt WKT geometry string for end
  * @param mode Transport mode
- * @param out_result Output: route summary (distance, duration, point count)
- * @param out_points Output: array for path coordinates (must be pre-allocated)
- * @param max_points Maximum number of points buffer can hold
- * @return Number of points written, -1 on error, -2 if not loaded
+ * @return 1 if loaded, 0 if not
  */
-int routing_route_geom(const char *from_wkt, const char *to_wkt, const char *mode, RouteResult *out_result,
-                       RoutePoint *out_points, int max_points);
+int routing_is_loaded(const char *mode);
 
 /**
- * Calculate route using WKB (Well-Known Binary) geometries as input.
- * Uses centroid of each geometry as the routing point.
- * Use with ST_AsWKB(geometry) from DuckDB spatial extension.
+ * List the supported transport modes, comma-separated: "auto,bicycle,pedestrian,agricultural,hgv".
  *
- * @param from_wkb WKB bytes for start geometry
- * @param from_wkb_len Length of from_wkb in bytes
- * @param to_wkb WKB bytes for end geometry
- * @param to_wkb_len Length of to_wkb in bytes
- * @param mode Transport mode
- * @param out_result Output: route summary (distance, duration, point count)
+ * @param out_buf Output buffer for the NUL-terminated list
+ * @param buf_len Size of out_buf in bytes
+ * @return List length in bytes (if >= buf_len the buffer was too small and nothing was written)
+ */
+int routing_supported_modes(char *out_buf, int buf_len);
+
+/**
+ * Get the path of the PBF file a mode's graph was built from. Graphs loaded with
+ * routing_load_multi report their files separated by ';'.
+ * Graphs loaded with routing_load_cache_bytes have no source path (empty string).
+ *
+ * @param mode Transport mode
+ * @param out_buf Output buffer for the NUL-terminated path
+ * @param buf_len Size of out_buf in bytes
+ * @return Path length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_source_path(const char *mode, char *out_buf, int buf_len);
+
+/**
+ * Get the ROUTING_EXCLUDE_* flags a mode's graph was built with (see routing_load_opts).
+ *
+ * @param mode Transport mode
+ * @return Bitwise OR of the flags (0 for a complete graph), -1 on error, -2 if not loaded
+ */
+int routing_graph_options(const char *mode);
+
+/**
+ * Free routing data for a mode.
+ *
+ * @param mode Transport mode
+ */
+void routing_free(const char *mode);
+
+/**
+ * Free routing data for every mode.
+ */
+void routing_free_all(void);
+
+/**
+ * Free the graph loaded under a handle (routing_load_named). Queries still running on it
+ * finish first.
+ *
+ * @param handle Handle the graph was loaded under
+ * @return 0 if a graph was freed, -2 if nothing is loaded under the handle, -1 on error
+ */
+int routing_free_named(const char *handle);
+
+/**
+ * Get the out-degree of the road network node a coordinate snaps to.
+ * A degree of 1 (or 0) marks a dead-end stub, which is often a poor snap target.
+ *
+ * @param lat Input latitude
+ * @param lon Input longitude
+ * @param mode Transport mode
+ * @param out_degree Output: number of outgoing edges of the snapped node
+ * @return 0 on success, -1 on error, -2 if not loaded
+ */
+int routing_snap_degree(double lat, double lon, const char *mode, int *out_degree);
+
+/**
+ * Get the contraction hierarchy level of every node (diagnostics).
+ * out_levels[node] is the node's position in the contraction order; higher levels were contracted later.
+ *
+ * @param mode Transport mode
+ * @param out_levels Output array indexed by node (entries beyond max_nodes are not written)
+ * @param max_nodes Size of out_levels
+ * @return Total node count, -1 on error, -2 if not loaded
+ */
+int routing_ch_levels(const char *mode, int *out_levels, int max_nodes);
+
+/**
+ * Dump the directed edge list in columnar form (for graph-ML feature pipelines).
+ * Edges are written in edge-id order, the ids used by routing_route_cover.
+ *
+ * @param mode Transport mode
+ * @param out_edge_from Output source node per edge
+ * @param out_edge_to Output target node per edge
+ * @param out_edge_weight Output travel time per edge in milliseconds
+ * @param max_edges Size of the output arrays; further edges are not written
+ * @return Total edge count, -1 on error, -2 if not loaded
+ */
+long long routing_dump_adjacency(const char *mode, int *out_edge_from, int *out_edge_to,
+                                 unsigned int *out_edge_weight, long long max_edges);
+
+/**
+ * Dump node coordinates, indexed by node id (as used by routing_dump_adjacency).
+ *
+ * @param mode Transport mode
+ * @param out_lats Output latitudes
+ * @param out_lons Output longitudes
+ * @param max_nodes Size of the output arrays; further nodes are not written
+ * @return Total node count, -1 on error, -2 if not loaded
+ */
+long long routing_dump_nodes(const char *mode, double *out_lats, double *out_lons, long long max_nodes);
+
+/**
+ * Get contraction hierarchy statistics (diagnostics).
+ * fast_paths does not expose shortcut flags, so ch_edges - base_edges is only an estimate of the
+ * shortcut count (parallel base edges are merged during preparation).
+ *
+ * @param mode Transport mode
+ * @param out_nodes Output: node count
+ * @param out_ch_edges Output: CH edge count (forward + backward, including shortcuts)
+ * @param out_base_edges Output: base graph edge count
+ * @return 0 on success, -1 on error, -2 if not loaded
+ */
+int routing_ch_stats(const char *mode, long long *out_nodes, long long *out_ch_edges, long long *out_base_edges);
+
+/**
+ * Isochrone result struct.
+ */
+typedef struct {
+	double lat;
+	double lon;
+	double seconds;
+} IsochroneResult;
+
+/**
+ * Route point struct.
+ */
+typedef struct {
+	double lat;
+	double lon;
+} RoutePoint;
+
+/**
+ * Isochrone edge struct: a road segment and the travel time to its end.
+ */
+typedef struct {
+	double from_lat;
+	double from_lon;
+	double to_lat;
+	double to_lon;
+	double seconds; /* Travel time from the origin to (to_lat, to_lon) */
+} IsochroneEdge;
+
+/**
+ * Route result struct.
+ */
+typedef struct {
+	double distance_m; /* Total road distance in meters */
+	double duration_s; /* Travel time in seconds */
+	int num_points;    /* Number of points in geometry */
+} RouteResult;
+
+/**
+ * Set the minimum size (in nodes) of the connected components kept when graphs are built.
+ * Smaller components (islands without a ferry the mode may use, tagging errors) are dropped so points
+ * near them snap to the main network instead of failing to route; the largest component is
+ * always kept. Affects subsequent routing_load calls; each size other than the default builds
+ * and caches its own graph, so a graph cached with another size is not reused.
+ *
+ * @param min_nodes Minimum component size; 0 keeps every component, negative restores the
+ *                  default of keeping only the largest component
+ */
+void routing_set_build_min_component_size(int min_nodes);
+
+/**
+ * Set the length below which dead-end spurs are dropped when graphs are built: the nodes from
+ * a dead end along the road up to the first junction, such as driveways and parking aisles
+ * that are connected but make points snap onto a narrow access instead of the road it leaves.
+ * Runs after the component filter; a spur is measured once, so a junction left with a dead
+ * end by the pruning is not pruned again. Affects subsequent routing_load calls; each length
+ * builds and caches its own graph, so a graph cached with another length is not reused.
+ *
+ * @param meters Minimum spur length; 0 or less keeps every spur (default)
+ */
+void routing_set_build_min_stub_length(double meters);
+
+/**
+ * Set the contraction hierarchy preparation parameters for subsequent builds from the PBF.
+ * Larger witness search limits make preparation slower but add fewer shortcuts, so queries
+ * get faster; smaller limits trade query speed for a faster build. Graphs already cached on
+ * disk keep the hierarchy they were built with. A rebuild (routing_reload) reuses the node
+ * order of the cached hierarchies when the node set is unchanged; the order is recovered from
+ * the cache itself, so the cache format is unaffected.
+ *
+ * @param hierarchy_depth_factor Node-ordering weight of the hierarchy depth (default 0.1)
+ * @param max_settled_initial Witness search limit for the initial priorities (default 500)
+ * @param max_settled_neighbor Witness search limit for neighbor updates (default 100)
+ * @param max_settled_contraction Witness search limit during contraction (default 500)
+ * @param max_settled_with_order Witness search limit when contracting in a reused order (default 100)
+ * @return 0 on success, -1 if a limit is negative or the depth factor is negative or not finite
+ */
+int routing_set_ch_params(double hierarchy_depth_factor, int max_settled_initial, int max_settled_neighbor,
+                          int max_settled_contraction, int max_settled_with_order);
+
+/**
+ * Set the minimum connected-component size (in nodes) for isochrone origins.
+ * Origins that snap into a smaller, isolated component make routing_isochrone and
+ * routing_isochrone_polygon return -3 instead of a misleadingly tiny result.
+ *
+ * @param min_nodes Minimum component size; 0 disables the check (default)
+ */
+void routing_set_min_component_size(int min_nodes);
+
+/**
+ * Scale a mode's travel times at query time, e.g. 1.4 to model rush-hour traffic that makes
+ * every trip 40% slower, without rebuilding the graph. The factor applies uniformly: routes
+ * are unchanged, reported durations are multiplied by it and isochrone limits divided by it.
+ * Per-road-class changes need a rebuilt graph (routing_load_profile), as contraction
+ * hierarchy weights are fixed at build time. The setting survives reloading the mode.
+ *
+ * Honored by routing_travel_time (and _or_estimate), routing_cost, routing_batch (and _or_estimate),
+ * routing_matrix (and _ex), routing_matrix_symmetric,
+ * routing_reachable_count, routing_travel_time_by_id, routing_matrix_by_id,
+ * the isochrone functions (routing_isochrone, _capped, _bounded, _reverse, _polygon, _boundary,
+ * _edges, _centroid),
+ * the route functions (routing_route and its _geom, _wkb, _via, _geojson, _polyline, _wkt_m,
+ * _samples, _cover, _with_delays, _dynamic, _avoid_tolls, _avoid_area, _shortest and _alternatives
+ * variants),
+ * routing_match, routing_directions, routing_route_segments and routing_meeting_point.
+ *
+ * @param mode Transport mode
+ * @param factor Travel time multiplier; 1.0 (default) for none
+ * @return 0 on success, -1 if factor is not positive and finite
+ */
+int routing_set_speed_factor(const char *mode, double factor);
+
+/**
+ * Choose how barriers are applied when graphs are built.
+ * Blocking barriers (bollards, stiles, locked gates, barriers with access=no/private for the
+ * mode, ...) cannot be passed; passable gates and toll booths add a delay (30 s gate, 15 s lift
+ * gate or toll booth for cars, 10 s gate for bicycles). Access tags on the barrier node
+ * override the default for its type. Pedestrians only stop at barriers denying them access.
+ * A barrier applies to every way through its node. Affects subsequent routing_load calls;
+ * graphs built without edge splitting are cached in files of their own.
+ *
+ * @param enabled Non-zero (default): split the ways through a blocking barrier at its node,
+ *                so each side stays routable up to the barrier but not through it.
+ *                Zero: drop every edge at the barrier node.
+ */
+void routing_set_barrier_edge_split(int enabled);
+
+/**
+ * Choose which nodes auto and agricultural graphs index for snapping.
+ * By default only nodes on main roads (service, residential and up; tracks too for agricultural)
+ * are indexed, so points near driveways snap to a road with through connectivity.
+ * Pedestrian and bicycle graphs always index every node, so points near footways and cycleways
+ * snap to them. Affects subsequent routing_load calls; graphs indexing every node are cached in
+ * files of their own.
+ *
+ * @param enabled Non-zero: index every usable node. Zero (default): main road nodes only.
+ */
+void routing_set_snap_all_roads(int enabled);
+
+/**
+ * Choose how routing_route and the other functions snapping onto road segments pick them.
+ * By default each point snaps to the nearest segment, which may be a poorly connected stub
+ * (e.g. a dead-end service road) that forces an absurd detour. With multi-snap enabled, up to
+ * 4 segments within 25 m of the nearest one are tried for origin and destination, and the pair
+ * with the fastest route wins; ties go to the nearer segments. This costs up to 16 route
+ * searches per query. routing_travel_time is unaffected.
+ *
+ * @param enabled Non-zero: try several candidates. Zero (default): nearest segment only.
+ */
+void routing_set_multi_snap(int enabled);
+
+/**
+ * Collapse chains of interior way nodes (nodes joining exactly two others along one way)
+ * into single edges when building graphs. This typically removes most nodes, which makes
+ * isochrones and other Dijkstra searches faster. Route geometry and distances still follow
+ * the full road shape, and the edge-snapping functions (routing_travel_time, routing_route,
+ * _geojson, _named and _polyline) snap anywhere along it. Functions that snap to nodes (the
+ * other route variants, isochrones, matrices, routing_snap) only see the remaining junctions
+ * and chain ends, and isochrones report those nodes only. A chain running parallel to another
+ * edge between the same two nodes keeps one interior node. Affects subsequent routing_load calls;
+ * contracted graphs are cached in files of their own.
+ *
+ * @param enabled Non-zero: collapse chains. Zero (default): keep every node.
+ */
+void routing_set_contract_chains(int enabled);
+
+/**
+ * Run parallel work on a dedicated pool of threads instead of one thread per core, to avoid
+ * oversubscribing the CPU when DuckDB or the host application is already busy. Covers
+ * routing_batch (and _or_estimate), the matrix functions (routing_matrix, _ex, _symmetric, _by_id), routing_snap_ids,
+ * routing_reachable_count, routing_meeting_point and graph builds in the load functions.
+ *
+ * @param threads Number of threads; 0 or negative restores the default (one per core)
+ * @return 0 on success, -1 if the pool could not be created (see routing_last_error)
+ */
+int routing_set_threads(int threads);
+
+/**
+ * Set the unit of the distance_m outputs of route functions (RouteResult.distance_m and the
+ * routing_directions and routing_route_segments JSON). The fields keep their _m names but then hold kilometers or miles.
+ * Snap distances (routing_snap) are always meters. Default: meters.
+ *
+ * @param unit "meters", "kilometers" or "miles" (also "m", "km", "mi")
+ * @return 0 on success, -1 for an unknown unit
+ */
+int routing_set_distance_unit(const char *unit);
+
+/**
+ * Calculate isochrone - all reachable points within max_seconds.
+ * Results are in order of travel time, points with equal times in a fixed order, so the same
+ * query on the same graph always returns the same sequence.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param out_results Output array for results (must be pre-allocated)
+ * @param max_results Maximum number of results to return
+ * @return Number of results written, -1 on error, -2 if not loaded,
+ *         -3 if the origin snapped into a component smaller than the minimum component size
+ */
+int routing_isochrone(double lat, double lon, double max_seconds, const char *mode, IsochroneResult *out_results,
+                      int max_results);
+
+/**
+ * Reverse isochrone: all points that can reach (lat, lon) within max_seconds, following
+ * one-ways and turn restrictions in the direction of travel. Use it for catchment areas
+ * (who can reach this hospital within 20 minutes).
+ *
+ * @param lat Target latitude
+ * @param lon Target longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param out_results Output array for results (must be pre-allocated); seconds is the
+ *        travel time from the point to the target
+ * @param max_results Maximum number of results to return
+ * @return Number of results written, -1 on error, -2 if not loaded,
+ *         -3 if the target snapped into a component smaller than the minimum component size
+ */
+int routing_isochrone_reverse(double lat, double lon, double max_seconds, const char *mode,
+                              IsochroneResult *out_results, int max_results);
+
+/**
+ * Calculate isochrone with a safety cap on the search: the traversal stops after settling
+ * max_nodes_explored nodes, bounding memory on dense graphs. Distinct from max_results,
+ * which only limits how many results are written.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param max_nodes_explored Maximum nodes to settle; 0 for no cap
+ * @param out_results Output array for results (must be pre-allocated)
+ * @param max_results Maximum number of results to return
+ * @param out_incomplete Output (may be NULL): 1 if the cap cut the search short, else 0
+ * @return Number of results written, -1 on error, -2 if not loaded,
+ *         -3 if the origin snapped into a component smaller than the minimum component size
+ */
+int routing_isochrone_capped(double lat, double lon, double max_seconds, const char *mode, int max_nodes_explored,
+                             IsochroneResult *out_results, int max_results, int *out_incomplete);
+
+/**
+ * Calculate isochrone with latency bounds for interactive use: the traversal stops after
+ * settling max_nodes_explored nodes or after deadline_ms of wall-clock time, whichever comes
+ * first, and returns the nodes settled so far (always the nearest ones, in settle order).
+ * The max_seconds budget still ends the search early as usual.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param max_nodes_explored Maximum nodes to settle; 0 for no cap
+ * @param deadline_ms Wall-clock limit in milliseconds, including waiting for a reload; 0 for none
+ * @param out_results Output array for results (must be pre-allocated)
+ * @param max_results Maximum number of results to return
+ * @param out_count Output: number of results written
+ * @return 0 if the search completed, 1 if a bound cut it short (partial results), -1 on error,
+ *         -2 if not loaded, -3 if the origin snapped into a component smaller than the minimum
+ *         component size
+ */
+int routing_isochrone_bounded(double lat, double lon, double max_seconds, const char *mode, int max_nodes_explored,
+                              int deadline_ms, IsochroneResult *out_results, int max_results, int *out_count);
+
+/**
+ * Calculate the isochrone as a WKT polygon: a concave hull around the reachable nodes and the
+ * points where the time budget runs out along the roads leaving them (see
+ * routing_isochrone_boundary), or a convex hull when there are too few points for a concave shape.
+ * With buckets (e.g. 5/10/15 min), returns a MULTIPOLYGON of the bands between them, in bucket
+ * order: each bucket's hull minus the hulls of the smaller buckets, so the polygons do not
+ * overlap and an outer band has the inner area as a hole. A band may be several polygons, or
+ * none when its bucket reaches no further than a smaller one.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds; ignored when buckets are given, which
+ *                    replace it
+ * @param mode Transport mode
+ * @param buckets Array of travel times in seconds, each finite and greater than 0, or NULL
+ * @param n_buckets Number of buckets; 0 for a single POLYGON at max_seconds
+ * @param out_buf Output buffer for the NUL-terminated WKT string
+ * @param buf_len Size of out_buf in bytes
+ * @return WKT length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error (including a bucket that is not finite or not greater than 0), -2 if not
+ *         loaded, -3 if the origin snapped into a component smaller than the minimum component size
+ */
+int routing_isochrone_polygon(double lat, double lon, double max_seconds, const char *mode, const double *buckets,
+                              int n_buckets, char *out_buf, int buf_len);
+
+/**
+ * Calculate the points where the isochrone's time budget runs out part-way along a road: for
+ * every edge leaving a reachable node whose far end is out of reach, the point at the share of
+ * the edge's travel time the remaining budget covers (assuming a uniform speed along the edge).
+ * Together with the reachable nodes (routing_isochrone) these trace the true reach instead of
+ * stopping at the last node on each road. Turn restrictions are not applied to the partial edges.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param out_points Output array of boundary points (may be NULL if max_points is 0)
+ * @param max_points Size of out_points; points beyond it are not written
+ * @return Total number of boundary points, -1 on error, -2 if not loaded
+ */
+int routing_isochrone_boundary(double lat, double lon, double max_seconds, const char *mode, RoutePoint *out_points,
+                               int max_points);
+
+/**
+ * Calculate the road network reachable within max_seconds as line segments, for highlighting
+ * the reachable roads instead of drawing a hull: every edge that is fully traversed within the
+ * budget, i.e. leaves a reachable node and ends within the budget. Edges with a shape (see
+ * routing_set_contract_chains) are split into one segment per shape piece, with the
+ * arrival time shared out along the piece lengths. A two-way road reached from both ends is
+ * written once, in the direction from its earlier-reached end. Turn restrictions are not
+ * applied to the last edge into a node; the partial edges where the budget runs out are left
+ * out (see routing_isochrone_boundary).
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param out_edges Output array of segments (may be NULL if max_edges is 0)
+ * @param max_edges Size of out_edges; segments beyond it are not written
+ * @return Total number of segments, -1 on error, -2 if not loaded
+ */
+int routing_isochrone_edges(double lat, double lon, double max_seconds, const char *mode, IsochroneEdge *out_edges,
+                            int max_edges);
+
+/**
+ * Calculate the center of mass of the area reachable within max_seconds.
+ * This is the uniform mean of the reached node coordinates.
+ *
+ * @param lat Origin latitude
+ * @param lon Origin longitude
+ * @param max_seconds Maximum travel time in seconds
+ * @param mode Transport mode
+ * @param out_lat Output: centroid latitude
+ * @param out_lon Output: centroid longitude
+ * @return Number of reached nodes averaged, -1 on error, -2 if not loaded
+ */
+int routing_isochrone_centroid(double lat, double lon, double max_seconds, const char *mode, double *out_lat,
+                               double *out_lon);
+
+/**
+ * Calculate route with full geometry.
+ * Both points are snapped onto the nearest road segment (or the best of several, see
+ * routing_set_multi_snap). The geometry runs from the projected origin through the route nodes
+ * to the projected destination; distance and duration include the partial segments.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_result Output: route summary (distance, duration, point count)
  * @param out_points Output: array for path coordinates (must be pre-allocated)
  * @param max_points Maximum number of points buffer can hold
  * @return Number of points written, -1 on error, -2 if not loaded
  */
+int routing_route(double lat1, double lon1, double lat2, double lon2, const char *mode, RouteResult *out_result,
+                  RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route using WKT geometries as input.
+ * Uses centroid of each geometry as the routing point.
+ * EWKT with an SRID prefix is accepted ("SRID=4326;POINT(...)"); SRIDs other than 4326 are rejected,
+ * as are coordinates outside the lon/lat range (e.g. projected meters without an SRID), with the
+ * reason in routing_last_error.
+ *
+ * @param from_wkt WKT geometry string for start (e.g., "POINT(12.45 43.94)" or "POLYGON(...)")
+ * @param to_wkt WKT geometry string for end
+ * @param mode Transport mode
+ * @param out_result Output: route summary (distance, duration, point count)
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded, -5 if a geometry
+ *         has a coordinate out of range
+ */
+int routing_route_geom(const char *from_wkt, const char *to_wkt, const char *mode, RouteResult *out_result,
+                       RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route using WKB (Well-Known Binary) geometries as input.
+ * Uses centroid of each geometry as the routing point.
+ * Use with ST_AsWKB(geometry) from DuckDB spatial extension. EWKB (PostGIS extended WKB) is
+ * accepted; an SRID other than 4326 (or 0, unknown) is rejected, as are coordinates outside the
+ * lon/lat range (e.g. projected meters without an SRID), with the reason in routing_last_error.
+ *
+ * @param from_wkb WKB bytes for start geometry
+ * @param from_wkb_len Length of from_wkb in bytes
+ * @param to_wkb WKB bytes for end geometry
+ * @param to_wkb_len Length of to_wkb in bytes
+ * @param mode Transport mode
+ * @param out_result Output: route summary (distance, duration, point count)
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded, -5 if a geometry
+ *         has a coordinate out of range
+ */
 int routing_route_wkb(const unsigned char *from_wkb, int from_wkb_len, const unsigned char *to_wkb, int to_wkb_len,
                       const char *mode, RouteResult *out_result, RoutePoint *out_points, int max_points);
 
+/**
+ * routing_route_geom choosing how each geometry becomes a routing point, and reporting it.
+ * The centroid of a non-convex polygon (e.g. a C-shaped area) may fall outside it and snap to a
+ * road the area doesn't touch; with interior set, a point guaranteed to lie on the geometry is
+ * used instead (inside a polygon, on a line, the point itself for a POINT).
+ *
+ * @param from_wkt WKT geometry string for start
+ * @param to_wkt WKT geometry string for end
+ * @param mode Transport mode
+ * @param interior Non-zero: route from/to interior points. Zero: centroids (as routing_route_geom)
+ * @param out_from Output (may be NULL): the start routing point, written once both geometries parse
+ * @param out_to Output (may be NULL): the end routing point
+ * @param out_result Output: route summary (distance, duration, point count)
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded, -5 if a geometry
+ *         has a coordinate out of range
+ */
+int routing_route_geom_ex(const char *from_wkt, const char *to_wkt, const char *mode, int interior,
+                          RoutePoint *out_from, RoutePoint *out_to, RouteResult *out_result, RoutePoint *out_points,
+                          int max_points);
+
+/**
+ * routing_route_wkb with the interior point option and routing point outputs of
+ * routing_route_geom_ex.
+ *
+ * @param from_wkb WKB bytes for start geometry
+ * @param from_wkb_len Length of from_wkb in bytes
+ * @param to_wkb WKB bytes for end geometry
+ * @param to_wkb_len Length of to_wkb in bytes
+ * @param mode Transport mode
+ * @param interior Non-zero: route from/to interior points. Zero: centroids (as routing_route_wkb)
+ * @param out_from Output (may be NULL): the start routing point, written once both geometries parse
+ * @param out_to Output (may be NULL): the end routing point
+ * @param out_result Output: route summary (distance, duration, point count)
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded, -5 if a geometry
+ *         has a coordinate out of range
+ */
+int routing_route_wkb_ex(const unsigned char *from_wkb, int from_wkb_len, const unsigned char *to_wkb,
+                         int to_wkb_len, const char *mode, int interior, RoutePoint *out_from, RoutePoint *out_to,
+                         RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate a route through a sequence of waypoints (origin, intermediate stops, destination).
+ * An optional dwell time per intermediate stop (e.g. delivery service time) is added to the duration;
+ * geometry and distance are those of the concatenated legs.
+ *
+ * @param lats Array of waypoint latitudes
+ * @param lons Array of waypoint longitudes
+ * @param dwell_seconds Optional array (count entries, may be NULL) of dwell seconds per waypoint;
+ *                      entries for the origin and destination are ignored
+ * @param count Number of waypoints (at least 2)
+ * @param mode Transport mode
+ * @param out_result Output: route summary; duration_s includes the dwell times
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error (including an unroutable leg), -2 if not loaded
+ */
+int routing_route_via(const double *lats, const double *lons, const double *dwell_seconds, int count,
+                      const char *mode, RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate a round trip that starts and ends at a point and takes about target_seconds,
+ * e.g. a recreational ride. The route sets off in a direction picked by the seed and curves
+ * back into a loop: the outbound leg stops once it plus the fastest way back reaches the target,
+ * and the fastest way back closes the loop, so the duration overshoots the target by about two
+ * road segments at most. It can fall short where the outbound leg runs into a dead end; check
+ * out_result and drop trips that missed the target. Use different seeds for different loops;
+ * the same seed always gives the same route on the same graph.
+ *
+ * @param lat Start and end latitude
+ * @param lon Start and end longitude
+ * @param target_seconds Target duration in seconds (positive)
+ * @param seed Seed of the direction choices
+ * @param mode Transport mode
+ * @param out_result Output: achieved distance and duration, and point count
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error (including no loop from the start), -2 if not loaded
+ */
+int routing_round_trip(double lat, double lon, double target_seconds, long long seed, const char *mode,
+                       RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route and write its geometry as a WKT LINESTRING.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param precision Decimal places per coordinate (clamped to 0..15)
+ * @param out_buf Output buffer for the NUL-terminated WKT string
+ * @param buf_len Size of out_buf in bytes
+ * @return WKT length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_route_wkt(double lat1, double lon1, double lat2, double lon2, const char *mode, int precision,
+                      char *out_buf, int buf_len);
+
+/**
+ * Calculate route and write the street name changes along it as a JSON array:
+ * [{"index":0,"name":"Via Roma"},{"index":7,"name":null},...]
+ * index refers to the points routing_route returns for the same query; each entry names the
+ * stretch from that point up to the next entry. Names come from the way's name tag, falling
+ * back to ref; unnamed ways give null.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_buf Output buffer for the NUL-terminated JSON string
+ * @param buf_len Size of out_buf in bytes
+ * @return JSON length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_route_named(double lat1, double lon1, double lat2, double lon2, const char *mode, char *out_buf,
+                        int buf_len);
+
+/**
+ * Calculate route and write it as a GeoJSON Feature:
+ * {"type":"Feature","geometry":{"type":"LineString","coordinates":[[lon,lat],...]},
+ *  "properties":{"distance_m":...,"duration_s":...}}
+ * Coordinates are the same as the points of routing_route (snapped origin, route nodes,
+ * snapped destination); distance_m follows routing_set_distance_unit.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_buf Output buffer for the NUL-terminated JSON string
+ * @param buf_len Size of out_buf in bytes
+ * @return JSON length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_route_geojson(double lat1, double lon1, double lat2, double lon2, const char *mode, char *out_buf,
+                          int buf_len);
+
+/**
+ * Calculate route and write its geometry as an encoded polyline (Google polyline algorithm,
+ * lat/lon order). Precision 5 matches Google, precision 6 matches Valhalla / OSRM polyline6.
+ * Coordinates are the same as the points of routing_route.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param precision Decimal places per coordinate: 5 or 6
+ * @param out_result Output: distance (see routing_set_distance_unit), duration and point count
+ * @param out_buf Output buffer for the NUL-terminated polyline string
+ * @param buf_len Size of out_buf in bytes
+ * @return Polyline length in bytes (if >= buf_len the buffer was too small and nothing was
+ *         written; out_result is still filled), -1 on error, -2 if not loaded
+ */
+int routing_route_polyline(double lat1, double lon1, double lat2, double lon2, const char *mode, int precision,
+                           RouteResult *out_result, char *out_buf, int buf_len);
+
+/**
+ * Calculate route and write its geometry as a WKT LINESTRING M for linear referencing.
+ * Each vertex carries an M value: the cumulative distance in meters from the origin (default)
+ * or the cumulative travel time in seconds. M values have 3 decimal places.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param precision Decimal places per coordinate (clamped to 0..15)
+ * @param measure_time 0: M is cumulative distance in meters; non-zero: cumulative time in seconds
+ * @param out_buf Output buffer for the NUL-terminated WKT string
+ * @param buf_len Size of out_buf in bytes
+ * @return WKT length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_route_wkt_m(double lat1, double lon1, double lat2, double lon2, const char *mode, int precision,
+                        int measure_time, char *out_buf, int buf_len);
+
+/**
+ * Sample positions along a route at fixed time intervals (e.g. animation keyframes).
+ * Positions are interpolated along each road segment using cumulative edge times.
+ * The arrival point is always included as the final sample, also when max_samples cuts the
+ * sampling short.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param interval_s Time between samples in seconds (must be > 0)
+ * @param out_lats Output array for sample latitudes (must be pre-allocated)
+ * @param out_lons Output array for sample longitudes (must be pre-allocated)
+ * @param out_times Output array for elapsed seconds at each sample (must be pre-allocated)
+ * @param max_samples Maximum number of samples the buffers can hold
+ * @return Number of samples written, -1 on error, -2 if not loaded
+ */
+int routing_route_samples(double lat1, double lon1, double lat2, double lon2, const char *mode, double interval_s,
+                          double *out_lats, double *out_lons, double *out_times, int max_samples);
+
+/**
+ * Calculate a route that prefers edges not yet covered (patrol/coverage routing).
+ * Edge ids are stable for a loaded graph: they index the graph's directed edges in node order.
+ * Listed edges get their weight multiplied by penalty_factor; only the given directed edges are
+ * penalized, so pass both directions to discourage re-using a road either way.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param used_edges Array of already covered edge ids (may be NULL if used_count is 0)
+ * @param used_count Number of entries in used_edges
+ * @param penalty_factor Weight multiplier for covered edges (>= 1.0)
+ * @param out_result Output: route summary; duration_s is the unpenalized travel time
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param out_edges Output: max_points - 1 entries; out_edges[i] is the edge id from point i to point i + 1
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_route_cover(double lat1, double lon1, double lat2, double lon2, const char *mode, const int *used_edges,
+                        int used_count, double penalty_factor, RouteResult *out_result, RoutePoint *out_points,
+                        int *out_edges, int max_points);
+
+/**
+ * Load query-time extra delays keyed by OSM way id (e.g. construction zones or incidents).
+ * Replaces any previously loaded delays for the mode; count 0 clears them.
+ * Each way's delay is spread over its edges in proportion to their travel time, so a full
+ * traversal of the way incurs the whole delay. Only routing_route_with_delays honors them.
+ *
+ * @param mode Transport mode
+ * @param way_ids Array of OSM way ids
+ * @param delays_s Array of extra seconds per way
+ * @param count Number of entries
+ * @return Number of delayed edges, -1 on error, -2 if not loaded
+ */
+int routing_load_way_delays(const char *mode, const long long *way_ids, const double *delays_s, int count);
+
+/**
+ * Clear the way delays of a mode, restoring baseline weights.
+ *
+ * @param mode Transport mode
+ */
+void routing_clear_way_delays(const char *mode);
+
+/**
+ * Calculate route honoring the way delays loaded with routing_load_way_delays.
+ * Runs a Dijkstra over the base graph, so it is slower than routing_route.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_result Output: route summary; duration_s includes the delays along the path
+ * @param out_points Output: array for path coordinates (must be pre-allocated)
+ * @param max_points Maximum number of points buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_route_with_delays(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                              RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route and return the signed turn angle at each interior node of the path.
+ * Angles are in degrees in (-180, 180]: positive turns right, negative turns left, 0 is straight on.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_angles Output array for turn angles (must be pre-allocated)
+ * @param max_angles Maximum number of angles the buffer can hold
+ * @return Number of angles written, -1 on error, -2 if not loaded
+ */
+int routing_route_turn_angles(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                              double *out_angles, int max_angles);
+
+/**
+ * Calculate route and write turn-by-turn directions as JSON:
+ * {"distance_m": .., "duration_s": .., "maneuvers": [{"type": .., "street": .., "distance_m": ..,
+ *  "duration_s": .., "exit": .., "lat": .., "lon": ..}, ...]}
+ * Maneuver types: depart, continue, slight_left, slight_right, left, right, sharp_left, sharp_right,
+ * uturn, roundabout, arrive. A maneuver starts where the street name changes or the route turns by
+ * 45 degrees or more; its distance and duration cover the stretch up to the next maneuver. "street"
+ * is null for unnamed ways.
+ * A roundabout maneuver (ways tagged junction=roundabout or circular) starts at the entry and covers
+ * the ring; "exit" is the number of the exit taken and "street" the street it leads onto. "exit" is
+ * null for other maneuvers. Graphs cached before roundabouts were recorded are rebuilt on load.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_json Output buffer for the NUL-terminated JSON string
+ * @param buf_len Size of out_json in bytes
+ * @return JSON length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_directions(double lat1, double lon1, double lat2, double lon2, const char *mode, char *out_json,
+                       int buf_len);
+
+/**
+ * Calculate route and write a per-edge breakdown as a JSON array, for debugging travel times:
+ * [{"from_lat": .., "from_lon": .., "to_lat": .., "to_lon": .., "distance_m": .., "duration_s": ..,
+ *   "speed_kmh": .., "highway": .., "way_id": ..}, ...]
+ * speed_kmh is implied by the edge's length and travel time (barrier delays included; null for
+ * a zero duration). "highway" is null for ferries and highway types the graph does not record.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_json Output buffer for the NUL-terminated JSON string
+ * @param buf_len Size of out_json in bytes
+ * @return JSON length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         -1 on error, -2 if not loaded
+ */
+int routing_route_segments(double lat1, double lon1, double lat2, double lon2, const char *mode, char *out_json,
+                           int buf_len);
+
+/**
+ * Calculate route and write the OSM way ids it traverses, in order, e.g. to open the ways
+ * behind a surprising travel time in an OSM editor. Consecutive edges of one way give a single
+ * entry; a way left and rejoined later appears again. The points snap onto the nearest road
+ * segment as in routing_route, so the ways of the segments the route starts and ends on are
+ * included.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_ids Output array of way ids (may be NULL if max_ids is 0)
+ * @param max_ids Size of out_ids; ids beyond it are not written
+ * @return Total number of ways on the route, -1 on error or if no route found, -2 if not loaded
+ */
+int routing_route_way_ids(double lat1, double lon1, double lat2, double lon2, const char *mode, long long *out_ids,
+                          int max_ids);
+
+/**
+ * Find the meeting point for a group: the network node minimising the summed travel time
+ * from all origins, or the worst single travel time when minimize_max is non-zero.
+ *
+ * @param lats Array of origin latitudes
+ * @param lons Array of origin longitudes
+ * @param n_origins Number of origins
+ * @param mode Transport mode
+ * @param minimize_max Non-zero to minimise the maximum travel time instead of the sum
+ * @param out_lat Output meeting point latitude
+ * @param out_lon Output meeting point longitude
+ * @param out_cost Output summed (or maximum) travel time in seconds
+ * @return 0 on success, -1 on error or if no node is reachable from every origin, -2 if not loaded
+ */
+int routing_meeting_point(const double *lats, const double *lons, int n_origins, const char *mode,
+                          int minimize_max, double *out_lat, double *out_lon, double *out_cost);
+
+/**
+ * Add a two-way what-if edge (e.g. a proposed road) between the nodes nearest to both points.
+ * Added edges are used by the Dijkstra-based queries (routing_route_dynamic, routing_route_cover,
+ * routing_route_with_delays), not by the contraction hierarchy routes.
+ *
+ * @param from_lat Start latitude
+ * @param from_lon Start longitude
+ * @param to_lat End latitude
+ * @param to_lon End longitude
+ * @param speed_kmh Travel speed on the new edge (straight-line distance)
+ * @param mode Transport mode
+ * @return 0 on success, -1 on error, -2 if not loaded
+ */
+int routing_add_edge(double from_lat, double from_lon, double to_lat, double to_lon, double speed_kmh,
+                     const char *mode);
+
+/**
+ * Remove all what-if edges for a mode, restoring the baseline graph.
+ *
+ * @param mode Transport mode
+ */
+void routing_clear_added_edges(const char *mode);
+
+/**
+ * Calculate route with the plain Dijkstra query, including edges added with routing_add_edge.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_result Output route result
+ * @param out_points Output array of route points
+ * @param max_points Size of out_points array
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_route_dynamic(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                          RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Benchmark the query path: route n_queries random origin/destination pairs drawn from the
+ * graph's bounding box (fixed seed, reproducible) and report the throughput.
+ *
+ * @param mode Transport mode
+ * @param n_queries Number of queries to run
+ * @param out_queries_per_sec Output throughput in queries per second
+ * @return Number of queries that found a route, -1 on error, -2 if not loaded
+ */
+int routing_benchmark(const char *mode, int n_queries, double *out_queries_per_sec);
+
+/**
+ * Get the message describing the most recent failure on the calling thread, e.g. why a
+ * route function returned -1 ("no path: endpoints in different components (12, 47); ..."),
+ * why a load failed ("Could not open PBF file ...: No such file or directory (os error 2)")
+ * or which mode a -2 result was not loaded for.
+ * Messages are per thread and are not cleared by later successful calls.
+ *
+ * @param out_buf Output buffer for the NUL-terminated message
+ * @param buf_len Size of out_buf in bytes
+ * @return Message length in bytes (if >= buf_len the buffer was too small and nothing was written),
+ *         0 if nothing has failed yet
+ */
+int routing_last_error(char *out_buf, int buf_len);
+
+/**
+ * Match a noisy GPS trace onto the road network as one connected path (hidden Markov model
+ * map matching). Candidates for each fix are the nodes within 100 m (the nearest node if none
+ * is). A Viterbi pass picks the most likely sequence, scoring each snap distance (GPS error
+ * sigma 20 m) and how much each road distance between consecutive candidates differs from the
+ * straight-line distance between the fixes. Where no candidate of a fix can be reached from
+ * the previous fix, the path restarts and jumps to it.
+ *
+ * @param lats Array of fix latitudes
+ * @param lons Array of fix longitudes
+ * @param timestamps Array of fix times in seconds, increasing, or NULL. When given, a transition
+ *                   is ruled out if the graph's travel time along it exceeds twice the elapsed
+ *                   time plus 30 s
+ * @param count Number of fixes
+ * @param mode Transport mode
+ * @param out_result Output: matched distance and travel time along the matched path
+ * @param out_points Output array for matched path points (must be pre-allocated)
+ * @param max_points Maximum number of points the buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_match(const double *lats, const double *lons, const double *timestamps, int count, const char *mode,
+                  RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route around an area, e.g. a temporary closure, without rebuilding the graph.
+ * Edges with an endpoint inside the area or crossing it are not used, and both points snap
+ * to the nearest node outside it. Runs Dijkstra on the full graph, so it is slower than
+ * routing_route.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param avoid_wkt Area to avoid as WKT or EWKT (SRID 4326) POLYGON or MULTIPOLYGON
+ * @param mode Transport mode
+ * @param out_result Output: route distance and duration
+ * @param out_points Output array for path points (must be pre-allocated)
+ * @param max_points Maximum number of points the buffer can hold
+ * @return Number of points written, -1 on error (an invalid area, or no route around it;
+ *         see routing_last_error), -2 if not loaded
+ */
+int routing_route_avoid_area(double lat1, double lon1, double lat2, double lon2, const char *avoid_wkt,
+                             const char *mode, RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate route avoiding toll roads (ways tagged toll=yes). Tolled edges are only used where
+ * no toll-free route exists. Runs Dijkstra on the full graph, so it is slower than routing_route.
+ * The reported duration is the plain travel time along the returned path.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_result Output: route distance and duration
+ * @param out_points Output array for path points (must be pre-allocated)
+ * @param max_points Maximum number of points the buffer can hold
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_route_avoid_tolls(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                              RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate the shortest-distance route instead of the fastest one.
+ * Uses a second contraction hierarchy weighted by edge length, stored in the same cache file.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param out_result Output route result; duration_s is the travel time along the returned path
+ * @param out_points Output array of route points
+ * @param max_points Size of out_points array
+ * @return Number of points written, -1 on error, -2 if not loaded
+ */
+int routing_route_shortest(double lat1, double lon1, double lat2, double lon2, const char *mode,
+                           RouteResult *out_result, RoutePoint *out_points, int max_points);
+
+/**
+ * Calculate up to k alternative routes, the optimal route first (iterative penalty method).
+ * Alternatives share at most max_shared_fraction of their travel time with previously returned
+ * routes and are at most 1.5x slower than the optimum. Fewer than k routes are returned when
+ * the network offers nothing distinct.
+ *
+ * @param lat1 Start latitude
+ * @param lon1 Start longitude
+ * @param lat2 End latitude
+ * @param lon2 End longitude
+ * @param mode Transport mode
+ * @param k Maximum number of routes
+ * @param max_shared_fraction Maximum shared fraction (0..1) of an alternative's travel time
+ * @param out_results Output array of k route results
+ * @param out_points Output array of k * max_points points; route i starts at out_points[i * max_points]
+ * @param max_points Points per route
+ * @return Number of routes written, -1 on error, -2 if not loaded
+ */
+int routing_route_alternatives(double lat1, double lon1, double lat2, double lon2, const char *mode, int k,
+                               double max_shared_fraction, RouteResult *out_results, RoutePoint *out_points,
+                               int max_points);
+
 #ifdef __cplusplus
 }
 #endif
@@ -1,35 +1,42 @@
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use anyhow::{Context, Result};
 use fast_paths::{FastGraph, InputGraph, PathCalculator};
 use geo::algorithm::centroid::Centroid;
-use geo::prelude::*;
-use geo::{Geometry, Point};
-use osmpbfreader::{OsmObj, OsmPbfReader};
+use geo::algorithm::interior_point::InteriorPoint;
+use geo::{Bearing, Distance, Geometry, Haversine, InterpolatePoint, Point};
+use osmpbfreader::{OsmId, OsmObj, OsmPbfReader, Tags};
 use rayon::prelude::*;
+use rstar::primitives::{GeomWithData, Line};
 use rstar::{PointDistance, RTree, RTreeObject, AABB};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
-use std::ffi::CStr;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::ffi::{c_void, CStr};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufWriter, Cursor, Read, Seek, Write};
 use std::os::raw::c_char;
-use std::path::Path;
-use std::sync::Mutex;
-use wkt::TryFromWkt;
-use geozero::wkb::Wkb;
+use std::ops::{Deref, Index};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, OnceLock, RwLock};
+use memmap2::Mmap;
+use wkt::{ToWkt, TryFromWkt};
+use geozero::wkb::{Ewkb, Wkb};
 use geozero::ToGeo;
 
 // Dijkstra priority queue state
 #[derive(Clone, Eq, PartialEq)]
 struct DijkstraState {
     cost: u32,  // milliseconds
-    node: usize,
+    node: usize, // Node, or TurnStates state in turn-aware searches
 }
 
 impl Ord for DijkstraState {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Min-heap: reverse ordering
-        other.cost.cmp(&self.cost)
+        // Min-heap: reverse ordering; equal costs pop by lowest node so results are deterministic
+        (other.cost, other.node).cmp(&(self.cost, self.node))
     }
 }
 
@@ -39,6 +46,13 @@ impl PartialOrd for DijkstraState {
     }
 }
 
+// Shortest path found by the dynamic (adj_list) Dijkstra
+struct DynamicPath {
+    nodes: Vec<usize>,
+    edges: Vec<usize>, // Stable edge ids, edges[i] connects nodes[i] -> nodes[i + 1]
+    cost_ms: u32,      // Total cost under the query weights
+}
+
 // Speed in km/h for different transport modes and road types
 fn get_speed_kmh(highway_type: &str, mode: &str) -> Option<f64> {
     match mode {
@@ -55,8 +69,9 @@ fn get_speed_kmh(highway_type: &str, mode: &str) -> Option<f64> {
             "tertiary_link" => Some(30.0),
             "residential" => Some(30.0),
             "living_street" => Some(20.0),
-            "service" => Some(20.0),
-            "unclassified" => Some(40.0),
+            "service" | "services" => Some(20.0),
+            // Unknown road type, treated as unclassified
+            "unclassified" | "road" => Some(40.0),
             _ => None,
         },
         "bicycle" => match highway_type {
@@ -66,11 +81,11 @@ fn get_speed_kmh(highway_type: &str, mode: &str) -> Option<f64> {
             "bridleway" => Some(10.0),
             "residential" => Some(18.0),
             "living_street" => Some(15.0),
-            "service" => Some(15.0),
+            "service" | "services" => Some(15.0),
             "tertiary" | "tertiary_link" => Some(20.0),
             "secondary" | "secondary_link" => Some(18.0),
             "primary" | "primary_link" => Some(15.0),
-            "unclassified" => Some(18.0),
+            "unclassified" | "road" => Some(18.0),
             "trunk" | "trunk_link" => Some(12.0),
             "motorway" | "motorway_link" => Some(5.0),
             "footway" => Some(10.0),
@@ -84,974 +99,12595 @@ fn get_speed_kmh(highway_type: &str, mode: &str) -> Option<f64> {
             "pedestrian" => Some(5.0),
             "steps" => Some(3.0),
             "track" | "bridleway" => Some(4.0),
-            "residential" | "living_street" | "service" | "cycleway" => Some(5.0),
+            "residential" | "living_street" | "service" | "services" | "cycleway" => Some(5.0),
             "tertiary" | "tertiary_link" => Some(5.0),
             "secondary" | "secondary_link" => Some(5.0),
             "primary" | "primary_link" => Some(5.0),
-            "unclassified" => Some(5.0),
+            "unclassified" | "road" => Some(5.0),
             "trunk" | "trunk_link" => Some(5.0),
             "motorway" | "motorway_link" => Some(3.0),
             _ => None,
         },
+        // Tractors and forestry vehicles: slow, no motorways, tracks allowed
+        "agricultural" => match highway_type {
+            "track" => Some(15.0),
+            "trunk" | "trunk_link" => Some(40.0),
+            "primary" | "primary_link" => Some(40.0),
+            "secondary" | "secondary_link" => Some(40.0),
+            "tertiary" | "tertiary_link" => Some(40.0),
+            "unclassified" | "road" => Some(30.0),
+            "residential" => Some(25.0),
+            "living_street" => Some(15.0),
+            "service" | "services" => Some(20.0),
+            _ => None,
+        },
+        // Heavy goods vehicles: lower limits than cars, slow on minor roads
+        "hgv" => match highway_type {
+            "motorway" => Some(80.0),
+            "motorway_link" => Some(60.0),
+            "trunk" => Some(80.0),
+            "trunk_link" => Some(50.0),
+            "primary" => Some(70.0),
+            "primary_link" => Some(45.0),
+            "secondary" => Some(60.0),
+            "secondary_link" => Some(40.0),
+            "tertiary" => Some(50.0),
+            "tertiary_link" => Some(30.0),
+            "residential" => Some(25.0),
+            "living_street" => Some(15.0),
+            "service" | "services" => Some(15.0),
+            "unclassified" | "road" => Some(40.0),
+            _ => None,
+        },
         _ => None,
     }
 }
 
-fn is_main_road(highway_type: &str) -> bool {
-    matches!(
-        highway_type,
-        "motorway"
-            | "motorway_link"
-            | "trunk"
-            | "trunk_link"
-            | "primary"
-            | "primary_link"
-            | "secondary"
-            | "secondary_link"
-            | "tertiary"
-            | "tertiary_link"
-            | "residential"
-            | "living_street"
-            | "service"
-            | "unclassified"
-    )
+/// Speed for highway types a mode may only use where the way's access tags explicitly
+/// allow it (e.g. motor_vehicle=yes on a track, motorcar=yes or bicycle=yes on a busway)
+fn permitted_speed_kmh(highway_type: &str, mode: &str) -> Option<f64> {
+    match (mode, highway_type) {
+        ("auto", "busway") => Some(40.0),
+        ("auto", "raceway") => Some(40.0),
+        ("auto", "track") => Some(15.0),
+        ("auto", "path") => Some(10.0),
+        ("hgv", "busway") => Some(40.0),
+        ("hgv", "track") => Some(10.0),
+        ("bicycle", "busway") => Some(18.0),
+        ("bicycle", "raceway") => Some(20.0),
+        ("pedestrian", "busway" | "raceway") => Some(5.0),
+        _ => None,
+    }
 }
 
-// R-tree point with node index
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-struct IndexedPoint {
-    lon: f64,
-    lat: f64,
-    idx: usize,
+/// Whether the mode's most specific access tag grants access outright
+fn is_access_granted(tags: &Tags, mode: &str) -> bool {
+    matches!(access_value(tags, mode), Some("yes" | "permissive" | "designated" | "destination"))
 }
 
-impl RTreeObject for IndexedPoint {
-    type Envelope = AABB<[f64; 2]>;
+/// Custom speed table loaded from a JSON profile: highway type -> km/h overrides
+/// merged on top of get_speed_kmh. Types unknown to the defaults are accepted;
+/// a non-positive speed excludes the type. An optional "surface" object holds
+/// surface -> speed multiplier overrides merged on top of default_surface_factor.
+/// An optional "penalties" list of {tag, value, multiplier} rules scales the travel time
+/// of matching ways (see way_penalty). An optional "default" speed applies to highway
+/// types no mode knows (see unknown_highway_kmh). Optional "step_delay" and "crossing_delay"
+/// seconds override the mode's node delays (see step_delay_ms and crossing_delay_ms).
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Profile {
+    speeds: HashMap<String, f64>,
+    surface_factors: HashMap<String, f64>,
+    penalties: Vec<Penalty>,
+    default_speed: Option<f64>,  // km/h for unknown highway types; None leaves them out
+    step_delay: Option<f64>,     // s per step of highway=steps; None keeps the mode's default
+    crossing_delay: Option<f64>, // s at unsignalled crossings; None keeps the mode's default
+}
 
-    fn envelope(&self) -> Self::Envelope {
-        AABB::from_point([self.lon, self.lat])
-    }
+/// Travel time multiplier for ways tagged `tag`=`value` (any value if value is missing)
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+struct Penalty {
+    tag: String,
+    #[serde(default)]
+    value: Option<String>,
+    multiplier: f64,
 }
 
-impl PointDistance for IndexedPoint {
-    fn distance_2(&self, point: &[f64; 2]) -> f64 {
-        let dx = self.lon - point[0];
-        let dy = self.lat - point[1];
-        dx * dx + dy * dy
+impl Profile {
+    fn from_json(text: &str) -> Result<Profile> {
+        let mut entries: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(text).context("Profile must be a JSON object of highway type -> km/h")?;
+        let surface_factors: HashMap<String, f64> = match entries.remove("surface") {
+            Some(table) => serde_json::from_value(table)
+                .context("Profile \"surface\" must be a JSON object of surface -> speed multiplier")?,
+            None => HashMap::new(),
+        };
+        let mut penalties: Vec<Penalty> = match entries.remove("penalties") {
+            Some(rules) => serde_json::from_value(rules)
+                .context("Profile \"penalties\" must be a JSON list of {tag, value, multiplier} objects")?,
+            None => Vec::new(),
+        };
+        let default_speed: Option<f64> = match entries.remove("default") {
+            Some(kmh) => serde_json::from_value(kmh).context("Profile \"default\" must be a speed in km/h")?,
+            None => None,
+        };
+        let mut delay = |key: &str| -> Result<Option<f64>> {
+            let Some(seconds) = entries.remove(key) else { return Ok(None) };
+            let seconds: f64 =
+                serde_json::from_value(seconds).with_context(|| format!("Profile \"{}\" must be seconds", key))?;
+            if !seconds.is_finite() {
+                anyhow::bail!("Invalid {}", key);
+            }
+            Ok(Some(seconds))
+        };
+        let step_delay = delay("step_delay")?;
+        let crossing_delay = delay("crossing_delay")?;
+        let speeds: HashMap<String, f64> = serde_json::from_value(serde_json::Value::Object(entries))
+            .context("Profile must be a JSON object of highway type -> km/h")?;
+        if let Some((highway, _)) = speeds.iter().find(|(_, kmh)| !kmh.is_finite()) {
+            anyhow::bail!("Invalid speed for highway type {}", highway);
+        }
+        if let Some((surface, _)) = surface_factors.iter().find(|(_, factor)| !factor.is_finite()) {
+            anyhow::bail!("Invalid multiplier for surface {}", surface);
+        }
+        if let Some(penalty) = penalties.iter().find(|p| !p.multiplier.is_finite()) {
+            anyhow::bail!("Invalid multiplier for penalty on tag {}", penalty.tag);
+        }
+        if default_speed.is_some_and(|kmh| !kmh.is_finite()) {
+            anyhow::bail!("Invalid default speed");
+        }
+        // Penalties multiply, so their order is irrelevant; sorting keeps the hash stable
+        penalties.sort_by(|a, b| (&a.tag, &a.value).cmp(&(&b.tag, &b.value)));
+        Ok(Profile {
+            speeds,
+            surface_factors,
+            penalties,
+            // A non-positive default is the same as none
+            default_speed: default_speed.filter(|&kmh| kmh > 0.0),
+            step_delay,
+            crossing_delay,
+        })
     }
-}
 
-// Adjacency list entry: (to_node, weight_ms)
-type AdjList = Vec<Vec<(usize, u32)>>;
+    /// Whether the profile overrides nothing, i.e. builds the default graph
+    fn is_empty(&self) -> bool {
+        self.speeds.is_empty()
+            && self.surface_factors.is_empty()
+            && self.penalties.is_empty()
+            && self.default_speed.is_none()
+            && self.step_delay.is_none()
+            && self.crossing_delay.is_none()
+    }
 
-#[derive(Serialize, Deserialize)]
-struct RoutingData {
-    node_positions: Vec<(f64, f64)>,
-    fast_graph: FastGraph,
-    spatial_index: RTree<IndexedPoint>,
-    adj_list: AdjList,  // For Dijkstra-based isochrone
+    /// Stable FNV-1a hash of the overrides, for cache file names
+    /// Surface entries follow a 0xff marker, penalties a 0xfe marker, the default speed a 0xfd
+    /// marker and the step and crossing delays 0xfc and 0xfb markers (never part of UTF-8 keys),
+    /// so profiles without them keep the hash they had before they were supported
+    fn hash(&self) -> u64 {
+        let sorted = |table: &HashMap<String, f64>| {
+            let mut entries: Vec<(String, f64)> = table.iter().map(|(k, &v)| (k.clone(), v)).collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            entries
+        };
+        let entry_bytes = |(key, value): (String, f64)| {
+            key.into_bytes().into_iter().chain([0]).chain(value.to_bits().to_le_bytes())
+        };
+        let surface_marker = (!self.surface_factors.is_empty()).then_some(0xff);
+        let penalty_marker = (!self.penalties.is_empty()).then_some(0xfe);
+        let marked = |marker: u8, value: Option<f64>| {
+            value.map(|v| [marker].into_iter().chain(v.to_bits().to_le_bytes())).into_iter().flatten()
+        };
+        // A missing value (any value) is 0xff, which no UTF-8 value contains
+        let penalty_bytes = |p: &Penalty| {
+            let value = p.value.as_ref().map_or(vec![0xff], |v| v.as_bytes().to_vec());
+            let rule = [p.tag.as_bytes(), &[0], &value, &[0]].concat();
+            rule.into_iter().chain(p.multiplier.to_bits().to_le_bytes())
+        };
+        fnv1a(
+            sorted(&self.speeds)
+                .into_iter()
+                .flat_map(entry_bytes)
+                .chain(surface_marker)
+                .chain(sorted(&self.surface_factors).into_iter().flat_map(entry_bytes))
+                .chain(penalty_marker)
+                .chain(self.penalties.iter().flat_map(penalty_bytes))
+                .chain(marked(0xfd, self.default_speed))
+                .chain(marked(0xfc, self.step_delay))
+                .chain(marked(0xfb, self.crossing_delay)),
+        )
+    }
 }
 
-struct Router {
-    data: RoutingData,
-    calculator: PathCalculator,
+/// Default speed multiplier for a surface=* value; surfaces slow cyclists more than walkers
+/// Motor modes and unknown surfaces are not scaled
+fn default_surface_factor(surface: &str, mode: &str) -> f64 {
+    match (mode, surface) {
+        ("bicycle", "paving_stones" | "compacted" | "fine_gravel" | "wood") => 0.9,
+        ("bicycle", "gravel" | "unpaved" | "ground" | "dirt" | "earth") => 0.8,
+        ("bicycle", "sett" | "cobblestone" | "unhewn_cobblestone" | "pebblestone") => 0.7,
+        ("bicycle", "grass") => 0.6,
+        ("bicycle", "sand" | "mud") => 0.5,
+        ("pedestrian", "gravel" | "pebblestone" | "unhewn_cobblestone" | "grass") => 0.9,
+        ("pedestrian", "sand" | "mud") => 0.7,
+        _ => 1.0,
+    }
 }
 
-static ROUTER_AUTO: Mutex<Option<Router>> = Mutex::new(None);
-static ROUTER_BICYCLE: Mutex<Option<Router>> = Mutex::new(None);
-static ROUTER_PEDESTRIAN: Mutex<Option<Router>> = Mutex::new(None);
-
-fn cache_path(pbf_path: &str, mode: &str) -> String {
-    format!("{}.{}.routing", pbf_path, mode)
+/// Speed multiplier for the way's surface, or None if the profile excludes that surface
+/// (a non-positive multiplier)
+fn surface_factor(tags: &Tags, mode: &str, profile: &Profile) -> Option<f64> {
+    let Some(surface) = tags.get("surface") else {
+        return Some(1.0);
+    };
+    match profile.surface_factors.get(surface.as_str()) {
+        Some(&factor) => (factor > 0.0).then_some(factor),
+        None => Some(default_surface_factor(surface, mode)),
+    }
 }
 
-fn build_graph_for_mode(pbf_path: &str, mode: &str) -> Result<RoutingData> {
-    let file = File::open(pbf_path).context("Could not open PBF file")?;
-    let mut pbf = OsmPbfReader::new(file);
-
-    let objs = pbf.get_objs_and_deps(|obj| {
-        obj.is_node() || (obj.is_way() && obj.tags().contains_key("highway"))
-    })?;
-
-    let mut osm_nodes: HashMap<i64, (f64, f64)> = HashMap::new();
-    for obj in objs.values() {
-        if let OsmObj::Node(n) = obj {
-            osm_nodes.insert(n.id.0, (n.lon(), n.lat()));
-        }
-    }
-
-    let mut edges: Vec<(i64, i64, u32)> = Vec::new();
-    let mut used_nodes: std::collections::HashSet<i64> = std::collections::HashSet::new();
-    let mut main_road_node_ids: std::collections::HashSet<i64> = std::collections::HashSet::new();
-
-    for obj in objs.values() {
-        if let OsmObj::Way(w) = obj {
-            let highway = w.tags.get("highway").map(|s| s.as_str()).unwrap_or("");
-            let is_main = is_main_road(highway);
-
-            if let Some(speed_kmh) = get_speed_kmh(highway, mode) {
-                let oneway = w.tags.get("oneway").map(|s| s.as_str()) == Some("yes");
-
-                for window in w.nodes.windows(2) {
-                    let from_id = window[0].0;
-                    let to_id = window[1].0;
-
-                    if let (Some(&(lon1, lat1)), Some(&(lon2, lat2))) =
-                        (osm_nodes.get(&from_id), osm_nodes.get(&to_id))
-                    {
-                        let p1 = Point::new(lon1, lat1);
-                        let p2 = Point::new(lon2, lat2);
-                        let dist_m = p1.haversine_distance(&p2);
-                        let time_ms = ((dist_m / 1000.0 / speed_kmh) * 3600.0 * 1000.0) as u32;
-
-                        if time_ms > 0 {
-                            edges.push((from_id, to_id, time_ms));
-                            used_nodes.insert(from_id);
-                            used_nodes.insert(to_id);
-                            if is_main {
-                                main_road_node_ids.insert(from_id);
-                                main_road_node_ids.insert(to_id);
-                            }
-                            if !oneway {
-                                edges.push((to_id, from_id, time_ms));
-                            }
-                        }
-                    }
-                }
+/// Product of the multipliers of the profile's penalty rules matching the way, or None if
+/// a matching rule excludes it (a non-positive multiplier)
+fn way_penalty(tags: &Tags, profile: &Profile) -> Option<f64> {
+    let mut factor = 1.0;
+    for penalty in &profile.penalties {
+        let matches = match (tags.get(penalty.tag.as_str()), &penalty.value) {
+            (Some(tagged), Some(value)) => tagged.as_str() == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if matches {
+            if penalty.multiplier <= 0.0 {
+                return None;
             }
+            factor *= penalty.multiplier;
         }
     }
+    Some(factor)
+}
 
-    let mut node_id_to_index: HashMap<i64, usize> = HashMap::new();
-    let mut node_positions: Vec<(f64, f64)> = Vec::new();
-    let mut rtree_points: Vec<IndexedPoint> = Vec::new();
-
-    for &node_id in &used_nodes {
-        if let Some(&pos) = osm_nodes.get(&node_id) {
-            let index = node_positions.len();
-            node_id_to_index.insert(node_id, index);
-            node_positions.push(pos);
-            // Only index main road nodes for reliable connectivity
-            if main_road_node_ids.contains(&node_id) {
-                rtree_points.push(IndexedPoint {
-                    lon: pos.0,
-                    lat: pos.1,
-                    idx: index,
-                });
-            }
-        }
-    }
+/// Delay per step of a highway=steps way tagged with its step_count, on top of the time its
+/// length takes at the steps speed; walkers lose the most time climbing
+fn step_delay_ms(mode: &str, profile: &Profile) -> f64 {
+    let default_s = match mode {
+        "pedestrian" => 0.5,
+        _ => 0.0,
+    };
+    profile.step_delay.unwrap_or(default_s).max(0.0) * 1000.0
+}
 
-    // Build adjacency list and input graph
-    let num_nodes = node_positions.len();
-    let mut adj_list: AdjList = vec![Vec::new(); num_nodes];
-    let mut input_graph = InputGraph::new();
+/// Delay for passing a highway=crossing node without traffic signals, i.e. waiting for a gap
+/// in the traffic of the road crossed
+fn crossing_delay_ms(mode: &str, profile: &Profile) -> u32 {
+    let default_s = match mode {
+        "pedestrian" => 5.0,
+        _ => 0.0,
+    };
+    (profile.crossing_delay.unwrap_or(default_s).max(0.0) * 1000.0) as u32
+}
 
-    for (from_id, to_id, weight) in edges {
-        if let (Some(&from_idx), Some(&to_idx)) =
-            (node_id_to_index.get(&from_id), node_id_to_index.get(&to_id))
-        {
-            input_graph.add_edge(from_idx, to_idx, weight as usize);
-            adj_list[from_idx].push((to_idx, weight));
-        }
+/// FNV-1a hash, stable across runs and platforms (unlike std's hasher)
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
     }
-    input_graph.freeze();
-
-    let fast_graph = fast_paths::prepare(&input_graph);
-    let spatial_index = RTree::bulk_load(rtree_points);
+    hash
+}
 
-    Ok(RoutingData {
-        node_positions,
-        fast_graph,
-        spatial_index,
-        adj_list,
-    })
+/// SHA-256 digest, for naming caches by content where a collision must not load another
+/// input's graph
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
 }
 
-fn save_graph(data: &RoutingData, path: &str) -> Result<()> {
-    let file = File::create(path)?;
-    let writer = BufWriter::new(file);
-    bincode::serialize_into(writer, data)?;
-    Ok(())
+/// Speed for a specific way, refining the highway-type speed with way tags
+/// Profile overrides take precedence over both
+fn way_speed_kmh(tags: &Tags, highway_type: &str, mode: &str, profile: &Profile) -> Option<f64> {
+    if let Some(&kmh) = profile.speeds.get(highway_type) {
+        return if kmh > 0.0 { Some(kmh) } else { None };
+    }
+    if mode == "agricultural" && highway_type == "track" {
+        // Track surface quality, grade1 (paved) to grade5 (soft)
+        return Some(match tags.get("tracktype").map(|s| s.as_str()) {
+            Some("grade1") => 30.0,
+            Some("grade2") => 25.0,
+            Some("grade3") => 20.0,
+            Some("grade4") => 15.0,
+            Some("grade5") => 10.0,
+            _ => 15.0,
+        });
+    }
+    let type_kmh = match get_speed_kmh(highway_type, mode) {
+        Some(kmh) => kmh,
+        None if is_access_granted(tags, mode) => permitted_speed_kmh(highway_type, mode)
+            .or_else(|| unknown_highway_kmh(highway_type, profile))?,
+        None => unknown_highway_kmh(highway_type, profile)?,
+    };
+    // Implausible limits are more likely tagging errors than real
+    let maxspeed = |key: &str| {
+        tags.get(key).and_then(|v| parse_maxspeed(v)).filter(|&kmh| kmh <= MAX_PLAUSIBLE_MAXSPEED_KMH)
+    };
+    match mode {
+        "auto" => Some(maxspeed("maxspeed").unwrap_or(type_kmh)),
+        // maxspeed:hgv is the truck limit; a general limit only caps the (lower) truck speed
+        "hgv" => Some(
+            maxspeed("maxspeed:hgv")
+                .or_else(|| maxspeed("maxspeed").map(|kmh| kmh.min(type_kmh)))
+                .unwrap_or(type_kmh),
+        ),
+        _ => Some(type_kmh),
+    }
 }
 
-fn load_graph(path: &str) -> Result<RoutingData> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let data: RoutingData = bincode::deserialize_from(reader)?;
-    Ok(data)
+// highway=* values that are not (or not yet, or no longer) roads
+const NON_ROAD_HIGHWAYS: &[&str] = &[
+    "proposed",
+    "planned",
+    "construction",
+    "abandoned",
+    "disused",
+    "razed",
+    "no",
+    "platform",
+    "bus_stop",
+    "rest_area",
+    "via_ferrata",
+];
+
+/// The profile's default speed for a highway type no mode has a speed for, e.g. a value
+/// introduced after this table was written. Types some mode knows (so a footway for cars)
+/// and non-road values are not affected.
+fn unknown_highway_kmh(highway_type: &str, profile: &Profile) -> Option<f64> {
+    let known = NON_ROAD_HIGHWAYS.contains(&highway_type)
+        || MODES
+            .iter()
+            .any(|mode| get_speed_kmh(highway_type, mode).or(permitted_speed_kmh(highway_type, mode)).is_some());
+    if known {
+        None
+    } else {
+        profile.default_speed
+    }
 }
 
-fn find_nearest_node(data: &RoutingData, lon: f64, lat: f64) -> Option<usize> {
-    data.spatial_index
-        .nearest_neighbor(&[lon, lat])
-        .map(|p| p.idx)
+const MAX_PLAUSIBLE_MAXSPEED_KMH: f64 = 200.0;
+
+/// Parse an OSM maxspeed value to km/h
+/// Handles plain numbers, an "mph" suffix, "walk" and common implicit country codes
+/// ("DE:urban", "RO:rural", "GB:nsl_single", ...); only the first of ";"-separated values is used.
+/// Returns None for "none", "signals" and anything unparseable, so the type speed applies
+fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.split(';').next()?.trim();
+    if value == "walk" {
+        return Some(6.0);
+    }
+    if let Some((country, zone)) = value.split_once(':') {
+        return implicit_maxspeed_kmh(country, zone);
+    }
+    let (number, factor) = match value.strip_suffix("mph") {
+        Some(number) => (number.trim(), 1.609_344),
+        None => (value.strip_suffix("km/h").unwrap_or(value).trim(), 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Some(n * factor),
+        _ => None,
+    }
 }
 
-fn get_router_for_mode(mode: &str) -> &'static Mutex<Option<Router>> {
-    match mode {
-        "bicycle" => &ROUTER_BICYCLE,
-        "pedestrian" => &ROUTER_PEDESTRIAN,
-        _ => &ROUTER_AUTO,
+/// Implicit speed limit for a "<country>:<zone>" maxspeed code
+fn implicit_maxspeed_kmh(country: &str, zone: &str) -> Option<f64> {
+    const MPH: f64 = 1.609_344;
+    match (country, zone) {
+        ("GB" | "UK", "nsl_single") => Some(60.0 * MPH),
+        ("GB" | "UK", "nsl_dual" | "motorway") => Some(70.0 * MPH),
+        ("DE", "rural") | ("AT", "rural") => Some(100.0),
+        ("DE", "motorway") => None, // No general limit
+        ("FR", "rural") => Some(80.0),
+        ("RO", "rural") | ("IT", "rural") => Some(90.0),
+        ("RO", "trunk") => Some(100.0),
+        ("IT", "trunk") => Some(110.0),
+        (_, "urban") => Some(50.0),
+        (_, "rural") => Some(90.0),
+        (_, "motorway") => Some(130.0),
+        (_, "living_street") => Some(7.0),
+        (_, "walk") => Some(6.0),
+        _ => None,
     }
 }
 
-/// Parse WKT geometry and return centroid as (lon, lat)
-/// For POINT, returns the point itself
-/// For other geometries, returns the centroid
-fn wkt_to_centroid(wkt_str: &str) -> Option<(f64, f64)> {
-    let geom: Geometry<f64> = Geometry::try_from_wkt_str(wkt_str).ok()?;
-    geometry_to_centroid(&geom)
+/// Whether the way's access tags allow the mode
+/// The most specific access key present decides (e.g. agricultural=yes overrides access=no).
+/// Restricted but legal values such as destination or delivery are allowed.
+fn is_access_allowed(tags: &Tags, mode: &str) -> bool {
+    match access_value(tags, mode) {
+        Some(value) => !matches!(value, "no" | "private"),
+        None => true,
+    }
 }
 
-/// Parse WKB geometry and return centroid as (lon, lat)
-fn wkb_to_centroid(wkb: &[u8]) -> Option<(f64, f64)> {
-    let wkb = Wkb(wkb.to_vec());
-    let geom: Geometry<f64> = wkb.to_geo().ok()?;
-    geometry_to_centroid(&geom)
+/// Value of the most specific access key present for the mode
+fn access_value<'a>(tags: &'a Tags, mode: &str) -> Option<&'a str> {
+    let keys: &[&str] = match mode {
+        "auto" => &["motorcar", "motor_vehicle", "vehicle", "access"],
+        "bicycle" => &["bicycle", "vehicle", "access"],
+        "pedestrian" => &["foot", "access"],
+        "agricultural" => &["agricultural", "forestry", "motor_vehicle", "vehicle", "access"],
+        "hgv" => &["hgv", "motor_vehicle", "vehicle", "access"],
+        _ => return None,
+    };
+    keys.iter().find_map(|key| tags.get(*key)).map(|value| value.as_str())
 }
 
-/// Extract centroid from a geo::Geometry
-fn geometry_to_centroid(geom: &Geometry<f64>) -> Option<(f64, f64)> {
-    match geom {
-        Geometry::Point(p) => Some((p.x(), p.y())),
-        Geometry::Line(l) => {
-            let c = l.centroid();
-            Some((c.x(), c.y()))
-        }
-        Geometry::LineString(ls) => ls.centroid().map(|c| (c.x(), c.y())),
-        Geometry::Polygon(p) => p.centroid().map(|c| (c.x(), c.y())),
-        Geometry::MultiPoint(mp) => mp.centroid().map(|c| (c.x(), c.y())),
-        Geometry::MultiLineString(mls) => mls.centroid().map(|c| (c.x(), c.y())),
-        Geometry::MultiPolygon(mp) => mp.centroid().map(|c| (c.x(), c.y())),
-        Geometry::GeometryCollection(gc) => gc.centroid().map(|c| (c.x(), c.y())),
-        Geometry::Rect(r) => {
-            let c = r.centroid();
-            Some((c.x(), c.y()))
+/// Whether the way can only be travelled along its node order in the given mode
+/// Roundabouts are one-way unless tagged oneway=no, except on foot. Cyclists follow oneway:bicycle when present,
+/// and ride both ways on car one-ways with a contraflow cycleway
+/// (cycleway=opposite*, or a cycleway:left/right:oneway=no|-1 lane)
+fn is_oneway(tags: &Tags, mode: &str) -> bool {
+    let tag = |key: &str| tags.get(key).map(|s| s.as_str());
+    if mode == "bicycle" {
+        match tag("oneway:bicycle") {
+            Some("yes") => return true,
+            Some("no") => return false,
+            _ => {}
         }
-        Geometry::Triangle(t) => {
-            let c = t.centroid();
-            Some((c.x(), c.y()))
+        let contraflow = ["cycleway", "cycleway:left", "cycleway:right", "cycleway:both"]
+            .iter()
+            .any(|&key| matches!(tag(key), Some("opposite" | "opposite_lane" | "opposite_track")))
+            || ["cycleway:left:oneway", "cycleway:right:oneway", "cycleway:both:oneway"]
+                .iter()
+                .any(|&key| matches!(tag(key), Some("no" | "-1")));
+        if contraflow {
+            return false;
         }
     }
+    match tag("oneway") {
+        Some("yes") => true,
+        Some("no") => false,
+        _ => mode != "pedestrian" && is_roundabout(tags),
+    }
 }
 
-// ============ C FFI ============
+// Pushing pace for bicycles on ways tagged bicycle=dismount
+const DISMOUNT_SPEED_KMH: f64 = 5.0;
 
-/// Load routing data - uses cache if available, builds and caches otherwise
-#[no_mangle]
-pub extern "C" fn routing_load(pbf_path: *const c_char, mode: *const c_char) -> i32 {
-    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
-        Ok(s) if !pbf_path.is_null() => s,
-        _ => return -1,
-    };
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+// Crossing speed of ferries without a duration tag, including boarding
+const FERRY_SPEED_KMH: f64 = 10.0;
 
-    let cache = cache_path(pbf_path, mode);
-    let data = if Path::new(&cache).exists() {
-        match load_graph(&cache) {
-            Ok(d) => d,
-            Err(_) => match build_graph_for_mode(pbf_path, mode) {
-                Ok(d) => {
-                    let _ = save_graph(&d, &cache);
-                    d
-                }
-                Err(_) => return -1,
-            },
-        }
-    } else {
-        match build_graph_for_mode(pbf_path, mode) {
-            Ok(d) => {
-                let _ = save_graph(&d, &cache);
-                d
-            }
-            Err(_) => return -1,
-        }
-    };
+fn is_ferry(tags: &Tags) -> bool {
+    tags.get("route").map(|s| s.as_str()) == Some("ferry")
+}
 
-    let calculator = fast_paths::create_calculator(&data.fast_graph);
-    let router = Router { data, calculator };
+/// Whether a way is read from the PBF at all: highways and ferry routes
+fn is_routable_way(tags: &Tags) -> bool {
+    tags.contains_key("highway") || is_ferry(tags)
+}
 
-    if let Ok(mut guard) = get_router_for_mode(mode).lock() {
-        *guard = Some(router);
-        0
-    } else {
-        -1
-    }
+// Road classes a graph can be built without (BuildOptions::exclude, routing_load_opts)
+const EXCLUDE_FERRIES: u32 = 1;
+const EXCLUDE_TOLLS: u32 = 2;
+const EXCLUDE_UNPAVED: u32 = 4;
+const EXCLUDE_ALL: u32 = EXCLUDE_FERRIES | EXCLUDE_TOLLS | EXCLUDE_UNPAVED;
+
+/// Tracks and ways with an unsealed surface=* value
+fn is_unpaved(tags: &Tags, highway: &str) -> bool {
+    highway == "track"
+        || matches!(
+            tags.get("surface").map(|s| s.as_str()),
+            Some(
+                "unpaved" | "compacted" | "fine_gravel" | "gravel" | "pebblestone" | "ground" | "dirt" | "earth"
+                    | "grass" | "sand" | "mud"
+            )
+        )
 }
 
-/// Calculate travel time in seconds between two points
-#[no_mangle]
-pub extern "C" fn routing_travel_time(
-    lat1: f64,
-    lon1: f64,
-    lat2: f64,
-    lon2: f64,
-    mode: *const c_char,
-) -> f64 {
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1.0,
-    };
+/// Whether a way is left out of the graph by the EXCLUDE_* bits in `exclude`
+fn is_excluded(tags: &Tags, highway: &str, exclude: u32) -> bool {
+    (exclude & EXCLUDE_FERRIES != 0 && is_ferry(tags))
+        || (exclude & EXCLUDE_TOLLS != 0 && tags.get("toll").map(|s| s.as_str()) == Some("yes"))
+        || (exclude & EXCLUDE_UNPAVED != 0 && !is_ferry(tags) && is_unpaved(tags, highway))
+}
 
-    let mutex = get_router_for_mode(mode);
-    let mut guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1.0,
-    };
+// Vehicle dimensions checked against the maxheight, maxwidth and maxweight tags of ways;
+// a dimension of 0 is not checked
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Vehicle {
+    height_m: f64,
+    width_m: f64,
+    weight_t: f64,
+}
 
-    let router = match guard.as_mut() {
-        Some(r) => r,
-        None => return -2.0,
-    };
+impl Vehicle {
+    /// Whether a way's tagged limits are below the vehicle's dimensions
+    /// Untagged and unparseable limits (e.g. "default", "none") don't restrict
+    fn exceeds_limits(&self, tags: &Tags) -> bool {
+        let below = |key: &str, size: f64, parse: fn(&str) -> Option<f64>| {
+            size > 0.0 && tags.get(key).and_then(|v| parse(v)).is_some_and(|limit| limit < size)
+        };
+        below("maxheight", self.height_m, parse_length_m)
+            || below("maxwidth", self.width_m, parse_length_m)
+            || below("maxweight", self.weight_t, parse_weight_t)
+    }
+}
 
-    let from_idx = match find_nearest_node(&router.data, lon1, lat1) {
-        Some(idx) => idx,
-        None => return -1.0,
+/// Parse an OSM length limit (maxheight, maxwidth) to meters: "3.5", "3.5 m" or feet and
+/// inches ("12'6\"")
+fn parse_length_m(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let meters = if let Some((feet, inches)) = value.split_once('\'') {
+        let inches = inches.trim().trim_end_matches('"').trim();
+        let inches = if inches.is_empty() { 0.0 } else { inches.parse::<f64>().ok()? };
+        (feet.trim().parse::<f64>().ok()? * 12.0 + inches) * 0.0254
+    } else {
+        value.strip_suffix('m').unwrap_or(value).trim().parse::<f64>().ok()?
     };
+    (meters.is_finite() && meters > 0.0).then_some(meters)
+}
 
-    let to_idx = match find_nearest_node(&router.data, lon2, lat2) {
-        Some(idx) => idx,
-        None => return -1.0,
+/// Parse an OSM maxweight value to metric tonnes: "7.5", "7.5 t", "7500 kg" or "10 st"
+/// (short tons)
+fn parse_weight_t(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (number, factor) = if let Some(number) = value.strip_suffix("kg") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix("st") {
+        (number, 0.907_185)
+    } else {
+        (value.strip_suffix('t').unwrap_or(value), 1.0)
     };
+    let tonnes = number.trim().parse::<f64>().ok()? * factor;
+    (tonnes.is_finite() && tonnes > 0.0).then_some(tonnes)
+}
 
-    match router
-        .calculator
-        .calc_path(&router.data.fast_graph, from_idx, to_idx)
-    {
-        Some(path) => path.get_weight() as f64 / 1000.0,
-        None => -1.0,
+/// Whether a ferry carries the given mode
+/// Motor vehicles need an explicit grant (motor_vehicle=yes, motorcar=yes, ...), as many ferries
+/// take passengers only; pedestrians and cyclists board unless foot/bicycle/access deny it.
+fn is_ferry_allowed(tags: &Tags, mode: &str) -> bool {
+    match mode {
+        "auto" | "agricultural" | "hgv" => is_access_granted(tags, mode),
+        _ => is_access_allowed(tags, mode),
     }
 }
 
-/// Check if routing data is loaded
-#[no_mangle]
-pub extern "C" fn routing_is_loaded(mode: *const c_char) -> i32 {
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return 0,
+/// Parse an OSM duration value ("HH:MM:SS", "HH:MM" or minutes) to seconds
+fn parse_duration_s(value: &str) -> Option<f64> {
+    let parts = value
+        .split(':')
+        .map(|part| part.trim().parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.0))
+        .collect::<Option<Vec<f64>>>()?;
+    let seconds = match parts[..] {
+        [minutes] => minutes * 60.0,
+        [hours, minutes] => hours * 3600.0 + minutes * 60.0,
+        [hours, minutes, seconds] => hours * 3600.0 + minutes * 60.0 + seconds,
+        _ => return None,
     };
+    (seconds > 0.0).then_some(seconds)
+}
 
-    match get_router_for_mode(mode).lock() {
-        Ok(guard) => i32::from(guard.is_some()),
-        Err(_) => 0,
-    }
+// DEM grades are clamped to this, as elevation noise on short segments gives absurd slopes
+const MAX_DEM_GRADE: f64 = 0.3;
+
+/// Elevation input of a build: incline tags, plus a terrain model when one was given
+#[derive(Debug)]
+struct Elevation {
+    dem_hash: u64, // fnv1a of the DEM file's contents (the cache key), 0 for incline tags only
+    grid: Option<ElevationGrid>,
 }
 
-/// Free routing data
-#[no_mangle]
-pub extern "C" fn routing_free(mode: *const c_char) {
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return,
-    };
+/// Terrain heights from an ESRI ASCII grid (.asc) in WGS84 degrees
+#[derive(Debug)]
+struct ElevationGrid {
+    ncols: usize,
+    nrows: usize,
+    min_lon: f64, // West edge
+    max_lat: f64, // North edge
+    cell_size: f64,
+    heights: Vec<f32>, // Row-major from the north, NaN for no data
+}
 
-    if let Ok(mut guard) = get_router_for_mode(mode).lock() {
-        *guard = None;
+impl ElevationGrid {
+    /// Parse an ESRI ASCII grid: ncols, nrows, xllcorner|xllcenter, yllcorner|yllcenter,
+    /// cellsize and an optional NODATA_value header, then nrows rows of heights from the north
+    fn from_ascii(text: &str) -> Result<ElevationGrid> {
+        let mut tokens = text.split_whitespace().peekable();
+        let mut header: HashMap<String, f64> = HashMap::new();
+        while let Some(key) = tokens.next_if(|t| t.parse::<f64>().is_err()) {
+            let value = tokens.next().and_then(|v| v.parse::<f64>().ok());
+            let value = value.with_context(|| format!("Missing value for elevation grid header {}", key))?;
+            header.insert(key.to_ascii_lowercase(), value);
+        }
+        let get = |key: &str| header.get(key).copied();
+        let (Some(ncols), Some(nrows), Some(cell_size)) = (get("ncols"), get("nrows"), get("cellsize")) else {
+            anyhow::bail!("Elevation grid header needs ncols, nrows and cellsize");
+        };
+        if ncols < 1.0 || nrows < 1.0 || cell_size.is_nan() || cell_size <= 0.0 {
+            anyhow::bail!("Invalid elevation grid dimensions");
+        }
+        let (ncols, nrows) = (ncols as usize, nrows as usize);
+        let half = cell_size / 2.0;
+        let min_lon = get("xllcorner").or_else(|| get("xllcenter").map(|x| x - half));
+        let min_lat = get("yllcorner").or_else(|| get("yllcenter").map(|y| y - half));
+        let (Some(min_lon), Some(min_lat)) = (min_lon, min_lat) else {
+            anyhow::bail!("Elevation grid header needs xllcorner/xllcenter and yllcorner/yllcenter");
+        };
+        let nodata = get("nodata_value");
+        let heights = tokens
+            .map(|t| match t.parse::<f64>() {
+                Ok(h) if Some(h) == nodata => Ok(f32::NAN),
+                Ok(h) => Ok(h as f32),
+                Err(_) => Err(anyhow::anyhow!("Invalid elevation value {}", t)),
+            })
+            .collect::<Result<Vec<f32>>>()?;
+        if heights.len() != ncols * nrows {
+            anyhow::bail!("Elevation grid has {} values, expected {}", heights.len(), ncols * nrows);
+        }
+        Ok(ElevationGrid {
+            ncols,
+            nrows,
+            min_lon,
+            max_lat: min_lat + nrows as f64 * cell_size,
+            cell_size,
+            heights,
+        })
+    }
+
+    /// Height in meters, interpolated bilinearly between cell centers
+    /// None outside the grid or next to a no-data cell
+    fn height(&self, lon: f64, lat: f64) -> Option<f64> {
+        let fx = (lon - self.min_lon) / self.cell_size - 0.5;
+        let fy = (self.max_lat - lat) / self.cell_size - 0.5;
+        let (max_x, max_y) = ((self.ncols - 1) as f64, (self.nrows - 1) as f64);
+        if !(-0.5..=max_x + 0.5).contains(&fx) || !(-0.5..=max_y + 0.5).contains(&fy) {
+            return None;
+        }
+        let (fx, fy) = (fx.clamp(0.0, max_x), fy.clamp(0.0, max_y));
+        let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.ncols - 1), (y0 + 1).min(self.nrows - 1));
+        let (tx, ty) = (fx - x0 as f64, fy - y0 as f64);
+        let h = |x: usize, y: usize| self.heights[y * self.ncols + x] as f64;
+        let top = h(x0, y0) * (1.0 - tx) + h(x1, y0) * tx;
+        let bottom = h(x0, y1) * (1.0 - tx) + h(x1, y1) * tx;
+        let height = top * (1.0 - ty) + bottom * ty;
+        (!height.is_nan()).then_some(height)
     }
 }
 
-/// Batch calculate travel times between pairs of points (parallel)
-/// results array must have space for `count` doubles
-/// Returns number of successful calculations, or -1 on error
-#[no_mangle]
-pub extern "C" fn routing_batch(
-    lats1: *const f64,
-    lons1: *const f64,
-    lats2: *const f64,
-    lons2: *const f64,
-    results: *mut f64,
-    count: i32,
-    mode: *const c_char,
-) -> i32 {
-    if lats1.is_null() || lons1.is_null() || lats2.is_null() || lons2.is_null() || results.is_null()
-    {
-        return -1;
+/// Parse an OSM incline value ("10%", "-5%", "4°") to a grade (rise over run)
+/// Plain numbers are percentages; "up"/"down" carry no grade and give None
+fn parse_incline(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let grade = if let Some(degrees) = value.strip_suffix('°') {
+        degrees.trim().parse::<f64>().ok()?.to_radians().tan()
+    } else {
+        value.strip_suffix('%').unwrap_or(value).trim().parse::<f64>().ok()? / 100.0
+    };
+    grade.is_finite().then_some(grade)
+}
+
+/// Grade of a segment in its node order, from the way's incline tag, else the terrain model
+/// Bridges and tunnels are flat unless tagged, as the terrain below them says nothing about
+/// the road
+fn segment_grade(
+    elevation: &Elevation,
+    tags: &Tags,
+    (lon1, lat1): (f64, f64),
+    (lon2, lat2): (f64, f64),
+    dist_m: f64,
+) -> f64 {
+    if let Some(grade) = tags.get("incline").and_then(|v| parse_incline(v)) {
+        return grade;
+    }
+    let on_structure = ["bridge", "tunnel"].iter().any(|key| tags.get(*key).is_some_and(|v| v != "no"));
+    match (&elevation.grid, on_structure) {
+        (Some(grid), false) if dist_m > 0.0 => match (grid.height(lon1, lat1), grid.height(lon2, lat2)) {
+            (Some(h1), Some(h2)) => ((h2 - h1) / dist_m).clamp(-MAX_DEM_GRADE, MAX_DEM_GRADE),
+            _ => 0.0,
+        },
+        _ => 0.0,
     }
+}
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+// Rider and bicycle for the cycling grade model: total mass, rolling resistance coefficient,
+// drag area and air density; riders push harder on climbs and brake on steep descents
+const CYCLIST_MASS_KG: f64 = 90.0;
+const CYCLIST_CRR: f64 = 0.008;
+const CYCLIST_CDA_M2: f64 = 0.6;
+const AIR_DENSITY_KG_M3: f64 = 1.2;
+const CLIMB_POWER_FACTOR: f64 = 1.5;
+const MAX_DESCENT_SPEED_FACTOR: f64 = 1.5;
+
+/// Speed multiplier on a grade (rise over run) for a mode whose flat speed is flat_kmh
+/// Pedestrians follow Tobler's hiking function, fastest on a slight descent. Cyclists keep the
+/// power that holds flat_kmh on the flat (CLIMB_POWER_FACTOR times that uphill) against rolling
+/// resistance, gravity and drag. Other modes are not affected.
+fn grade_speed_factor(mode: &str, grade: f64, flat_kmh: f64) -> f64 {
+    if grade == 0.0 {
+        return 1.0;
+    }
+    match mode {
+        "pedestrian" => (-3.5 * (grade + 0.05).abs()).exp() / (-3.5 * 0.05f64).exp(),
+        "bicycle" => {
+            let v0 = flat_kmh / 3.6;
+            let drag = 0.5 * AIR_DENSITY_KG_M3 * CYCLIST_CDA_M2;
+            let resistance = |grade: f64| CYCLIST_MASS_KG * 9.81 * (grade + CYCLIST_CRR);
+            let flat_power = resistance(0.0) * v0 + drag * v0.powi(3);
+            let power = if grade > 0.0 { flat_power * CLIMB_POWER_FACTOR } else { flat_power };
+            // Required power grows with speed beyond any slack from gravity, so bisect for it
+            let surplus = |v: f64| resistance(grade) * v + drag * v.powi(3) - power;
+            let (mut lo, mut hi) = (0.0, v0 * MAX_DESCENT_SPEED_FACTOR);
+            if surplus(hi) <= 0.0 {
+                return MAX_DESCENT_SPEED_FACTOR;
+            }
+            for _ in 0..60 {
+                let mid = (lo + hi) / 2.0;
+                if surplus(mid) < 0.0 {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            hi / v0
+        }
+        _ => 1.0,
+    }
+}
 
-    let mutex = get_router_for_mode(mode);
-    let guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
-    };
+/// Whether a barrier stops the given mode from passing
+/// A locked barrier stops every vehicle; otherwise the barrier's own access tags
+/// (e.g. access=private, bicycle=yes) override the default for its type.
+fn barrier_blocks(tags: &Tags, mode: &str) -> bool {
+    let barrier = tags.get("barrier").map(|s| s.as_str()).unwrap_or("");
+    if mode != "pedestrian" && tags.get("locked").map(|s| s.as_str()) == Some("yes") {
+        return true;
+    }
+    if let Some(value) = access_value(tags, mode) {
+        return matches!(value, "no" | "private");
+    }
+    match mode {
+        "pedestrian" => false,
+        "bicycle" => matches!(barrier, "stile" | "turnstile" | "full-height_turnstile"),
+        _ => matches!(
+            barrier,
+            "bollard"
+                | "block"
+                | "chain"
+                | "cycle_barrier"
+                | "full-height_turnstile"
+                | "jersey_barrier"
+                | "kissing_gate"
+                | "log"
+                | "motorcycle_barrier"
+                | "stile"
+                | "turnstile"
+        ),
+    }
+}
 
-    let router = match guard.as_ref() {
-        Some(r) => r,
-        None => return -2,
-    };
+/// Time in ms lost passing a barrier that doesn't block the mode (stopping to open a gate, ...)
+fn barrier_delay_ms(tags: &Tags, mode: &str) -> u32 {
+    let barrier = tags.get("barrier").map(|s| s.as_str()).unwrap_or("");
+    match (mode, barrier) {
+        ("pedestrian", _) => 0,
+        ("bicycle", "gate" | "swing_gate" | "sliding_gate" | "hampshire_gate" | "kissing_gate") => 10_000,
+        ("bicycle", "cycle_barrier") => 5_000,
+        ("bicycle", _) => 0,
+        (_, "gate" | "swing_gate" | "sliding_gate" | "hampshire_gate") => 30_000,
+        (_, "lift_gate" | "toll_booth") => 15_000,
+        _ => 0,
+    }
+}
 
-    let count = count as usize;
-    let lats1 = unsafe { std::slice::from_raw_parts(lats1, count) };
-    let lons1 = unsafe { std::slice::from_raw_parts(lons1, count) };
-    let lats2 = unsafe { std::slice::from_raw_parts(lats2, count) };
-    let lons2 = unsafe { std::slice::from_raw_parts(lons2, count) };
-    let results = unsafe { std::slice::from_raw_parts_mut(results, count) };
+/// What a barrier node does to a way passing through it
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BarrierEffect {
+    Block,
+    Delay(u32), // ms
+}
 
-    // Use thread-local calculators for parallel processing
-    use std::cell::RefCell;
-    thread_local! {
-        static CALC: RefCell<Option<PathCalculator>> = const { RefCell::new(None) };
+fn barrier_effect(tags: &Tags, mode: &str) -> Option<BarrierEffect> {
+    if barrier_blocks(tags, mode) {
+        return Some(BarrierEffect::Block);
     }
+    match barrier_delay_ms(tags, mode) {
+        0 => None,
+        ms => Some(BarrierEffect::Delay(ms)),
+    }
+}
 
-    // Parallel calculation using rayon
-    let success_count: i32 = (0..count)
-        .into_par_iter()
-        .map(|i| {
-            let from_idx = find_nearest_node(&router.data, lons1[i], lats1[i]);
-            let to_idx = find_nearest_node(&router.data, lons2[i], lats2[i]);
-
-            let result = match (from_idx, to_idx) {
-                (Some(from), Some(to)) => {
-                    CALC.with(|calc_cell| {
-                        let mut calc_ref = calc_cell.borrow_mut();
-                        if calc_ref.is_none() {
-                            *calc_ref = Some(fast_paths::create_calculator(&router.data.fast_graph));
-                        }
-                        match calc_ref.as_mut().unwrap().calc_path(&router.data.fast_graph, from, to) {
-                            Some(path) => (path.get_weight() as f64 / 1000.0, 1),
-                            None => (-1.0, 0),
-                        }
-                    })
-                }
-                _ => (-1.0, 0),
-            };
+/// Road class rank, lower is more important
+fn highway_rank(highway_type: &str) -> u8 {
+    match highway_type {
+        "motorway" | "motorway_link" => 0,
+        "trunk" | "trunk_link" => 1,
+        "primary" | "primary_link" => 2,
+        "secondary" | "secondary_link" => 3,
+        "tertiary" | "tertiary_link" => 4,
+        "unclassified" => 5,
+        "residential" => 6,
+        "living_street" => 7,
+        "service" => 8,
+        "track" => 9,
+        _ => 10,
+    }
+}
 
-            // SAFETY: each thread writes to a unique index
-            unsafe {
-                *results.as_ptr().add(i).cast_mut() = result.0;
-            }
-            result.1
-        })
-        .sum();
+/// Whether nodes on this highway type are indexed for snapping in the given mode
+/// Pedestrians and cyclists snap to any way they can use; motor modes keep to main roads
+/// unless `all_roads` is set, so points near driveways and tracks reach a through road
+fn is_snap_road(highway_type: &str, mode: &str, all_roads: bool) -> bool {
+    all_roads
+        || matches!(mode, "pedestrian" | "bicycle")
+        || is_main_road(highway_type)
+        || (mode == "agricultural" && highway_type == "track")
+}
 
-    success_count
+fn is_main_road(highway_type: &str) -> bool {
+    matches!(
+        highway_type,
+        "motorway"
+            | "motorway_link"
+            | "trunk"
+            | "trunk_link"
+            | "primary"
+            | "primary_link"
+            | "secondary"
+            | "secondary_link"
+            | "tertiary"
+            | "tertiary_link"
+            | "residential"
+            | "living_street"
+            | "service"
+            | "unclassified"
+            | "road"
+    )
 }
 
-/// Snap a coordinate to the nearest road network node
-/// Returns snapped lat/lon and distance in meters, or -1 values on error
-#[no_mangle]
-pub extern "C" fn routing_snap(
-    lat: f64,
+// R-tree point with node index
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct IndexedPoint {
     lon: f64,
-    mode: *const c_char,
-    out_lat: *mut f64,
-    out_lon: *mut f64,
-    out_distance_m: *mut f64,
-) -> i32 {
-    if out_lat.is_null() || out_lon.is_null() || out_distance_m.is_null() {
-        return -1;
-    }
+    lat: f64,
+    idx: usize,
+}
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+impl RTreeObject for IndexedPoint {
+    type Envelope = AABB<[f64; 2]>;
 
-    let mutex = get_router_for_mode(mode);
-    let guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
-    };
+    fn envelope(&self) -> Self::Envelope {
+        AABB::from_point([self.lon, self.lat])
+    }
+}
 
-    let router = match guard.as_ref() {
-        Some(r) => r,
-        None => return -2,
-    };
+impl PointDistance for IndexedPoint {
+    fn distance_2(&self, point: &[f64; 2]) -> f64 {
+        let dx = self.lon - point[0];
+        let dy = self.lat - point[1];
+        dx * dx + dy * dy
+    }
+}
 
-    match router.data.spatial_index.nearest_neighbor(&[lon, lat]) {
-        Some(point) => {
-            let (node_lon, node_lat) = router.data.node_positions[point.idx];
-            let p1 = Point::new(lon, lat);
-            let p2 = Point::new(node_lon, node_lat);
-            let dist = p1.haversine_distance(&p2);
+// Piece of the road between two snappable nodes, for edge snapping: (from, to, start, end)
+// where start..end is the share of the from -> to length it covers (0..1 unless the edge
+// is a contracted chain with several pieces)
+type IndexedSegment = GeomWithData<Line<[f64; 2]>, (usize, usize, f64, f64)>;
+
+/// Interior points of contracted chains per stable edge id, in CSR layout: edge e's points
+/// are points[offsets[e]..offsets[e + 1]]. Empty for graphs built without contraction
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct EdgeShapes {
+    offsets: Vec<usize>,
+    points: Vec<(f64, f64)>,
+}
 
-            unsafe {
-                *out_lat = node_lat;
-                *out_lon = node_lon;
-                *out_distance_m = dist;
-            }
-            0
-        }
-        None => {
-            unsafe {
-                *out_lat = -1.0;
-                *out_lon = -1.0;
-                *out_distance_m = -1.0;
-            }
-            -1
+impl EdgeShapes {
+    fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Interior points of an edge, from its start node towards its end node
+    fn interior(&self, edge_id: usize) -> &[(f64, f64)] {
+        match self.offsets.get(edge_id..edge_id + 2) {
+            Some(&[start, end]) => &self.points[start..end],
+            _ => &[],
         }
     }
+
+    /// Full geometry of an edge between the given end positions
+    fn coords(&self, edge_id: usize, from: (f64, f64), to: (f64, f64)) -> Vec<(f64, f64)> {
+        let interior = self.interior(edge_id);
+        let mut coords = Vec::with_capacity(interior.len() + 2);
+        coords.push(from);
+        coords.extend_from_slice(interior);
+        coords.push(to);
+        coords
+    }
 }
 
-/// Get count of nodes in the routing graph
-#[no_mangle]
-pub extern "C" fn routing_node_count(mode: *const c_char) -> i32 {
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+// Adjacency list entry: (to_node, weight_ms); the nested form used while building
+type AdjList = Vec<Vec<(usize, u32)>>;
 
-    let mutex = get_router_for_mode(mode);
-    match mutex.lock() {
-        Ok(guard) => match guard.as_ref() {
-            Some(r) => r.data.node_positions.len() as i32,
-            None => -2,
-        },
-        Err(_) => -1,
+/// Read-only array, either owned or a view into a memory-mapped cache file
+enum FlatVec<T> {
+    Owned(Vec<T>),
+    Mapped { map: Arc<Mmap>, offset: usize, len: usize },
+}
+
+impl<T> Deref for FlatVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            FlatVec::Owned(items) => items,
+            // SAFETY: Mapped is only created by read_raw_array for types listed in
+            // MAPPABLE_LAYOUT, at an offset aligned for T with len items inside the map
+            FlatVec::Mapped { map, offset, len } => unsafe {
+                std::slice::from_raw_parts(map.as_ptr().add(*offset) as *const T, *len)
+            },
+        }
     }
 }
 
-/// Isochrone result struct for FFI
-#[repr(C)]
-pub struct IsochroneResult {
-    pub lat: f64,
-    pub lon: f64,
-    pub seconds: f64,
+impl<T> Default for FlatVec<T> {
+    fn default() -> Self {
+        FlatVec::Owned(Vec::new())
+    }
 }
 
-/// Route point struct for FFI
-#[repr(C)]
-pub struct RoutePoint {
-    pub lat: f64,
-    pub lon: f64,
+impl<T> From<Vec<T>> for FlatVec<T> {
+    fn from(items: Vec<T>) -> Self {
+        FlatVec::Owned(items)
+    }
 }
 
-/// Route result struct for FFI
-#[repr(C)]
-pub struct RouteResult {
-    pub distance_m: f64,
-    pub duration_s: f64,
-    pub num_points: i32,
+impl<T: PartialEq> PartialEq for FlatVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
 }
 
-/// Calculate isochrone - all reachable points within max_seconds
-/// Returns count of results written, or -1 on error
-/// Results are written to out_results array (caller provides buffer)
-#[no_mangle]
-pub extern "C" fn routing_isochrone(
-    lat: f64,
-    lon: f64,
-    max_seconds: f64,
-    mode: *const c_char,
-    out_results: *mut IsochroneResult,
-    max_results: i32,
-) -> i32 {
-    if out_results.is_null() || max_results <= 0 {
-        return -1;
+impl<T: std::fmt::Debug> std::fmt::Debug for FlatVec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
     }
+}
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+/// Outgoing edges (to_node, weight_ms) of every node in compressed sparse row form
+/// Edges of node n are edges[offsets[n]..offsets[n + 1]]; this flattened order is the
+/// stable edge id order
+#[derive(Debug, Default, PartialEq)]
+struct Adjacency {
+    offsets: FlatVec<usize>,
+    edges: FlatVec<(usize, u32)>,
+}
 
-    let mutex = get_router_for_mode(mode);
-    let guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
-    };
+impl Adjacency {
+    /// Number of nodes
+    fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
 
-    let router = match guard.as_ref() {
-        Some(r) => r,
-        None => return -2,
-    };
+    fn num_edges(&self) -> usize {
+        self.edges.len()
+    }
 
-    // Find starting node
-    let start_idx = match find_nearest_node(&router.data, lon, lat) {
-        Some(idx) => idx,
-        None => return -1,
-    };
+    /// Start of each node's edges in stable edge id order, plus the total
+    fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
 
-    let max_cost_ms = (max_seconds * 1000.0) as u32;
-    let num_nodes = router.data.node_positions.len();
+    fn iter(&self) -> impl Iterator<Item = &[(usize, u32)]> + '_ {
+        (0..self.len()).map(move |node| &self[node])
+    }
+}
 
-    // Dijkstra with early termination
-    let mut dist: Vec<u32> = vec![u32::MAX; num_nodes];
-    let mut heap = BinaryHeap::new();
+impl From<AdjList> for Adjacency {
+    fn from(adj_list: AdjList) -> Self {
+        let mut offsets = Vec::with_capacity(adj_list.len() + 1);
+        offsets.push(0);
+        for edges in &adj_list {
+            offsets.push(offsets[offsets.len() - 1] + edges.len());
+        }
+        Adjacency {
+            offsets: offsets.into(),
+            edges: adj_list.into_iter().flatten().collect::<Vec<_>>().into(),
+        }
+    }
+}
 
-    dist[start_idx] = 0;
-    heap.push(DijkstraState { cost: 0, node: start_idx });
+impl Index<usize> for Adjacency {
+    type Output = [(usize, u32)];
 
-    let mut result_count = 0i32;
-    let max_results = max_results as usize;
-    let out_results = unsafe { std::slice::from_raw_parts_mut(out_results, max_results) };
+    fn index(&self, node: usize) -> &[(usize, u32)] {
+        &self.edges[self.offsets[node]..self.offsets[node + 1]]
+    }
+}
 
-    while let Some(DijkstraState { cost, node }) = heap.pop() {
-        // Skip if we've already found a better path
-        if cost > dist[node] {
-            continue;
-        }
+// Per-edge attributes, indexed by stable edge id (adj_list order)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct EdgeInfo {
+    way_id: i64, // Originating OSM way
+    name: u32,   // Index into RoutingData::names, or NO_NAME
+    highway: u8, // Index into HIGHWAY_CLASSES, or HIGHWAY_OTHER
+    roundabout: bool, // junction=roundabout or circular
+    toll: bool,       // toll=yes
+}
 
-        // Stop if beyond time limit
-        if cost > max_cost_ms {
-            continue;
-        }
+fn is_roundabout(tags: &Tags) -> bool {
+    matches!(tags.get("junction").map(|s| s.as_str()), Some("roundabout" | "circular"))
+}
 
-        // Record this reachable node
-        if (result_count as usize) < max_results {
-            let (node_lon, node_lat) = router.data.node_positions[node];
-            out_results[result_count as usize] = IsochroneResult {
-                lat: node_lat,
-                lon: node_lon,
-                seconds: cost as f64 / 1000.0,
-            };
-            result_count += 1;
-        }
+const NO_NAME: u32 = u32::MAX;
+
+// highway=* values tracked per edge; at most 63 so a node's classes fit a u64 mask with HIGHWAY_OTHER
+const HIGHWAY_CLASSES: &[&str] = &[
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+    "track",
+    "road",
+    "busway",
+    "cycleway",
+    "bridleway",
+    "footway",
+    "path",
+    "pedestrian",
+    "steps",
+];
+const HIGHWAY_OTHER: u8 = 63;
+
+fn highway_class(highway_type: &str) -> u8 {
+    HIGHWAY_CLASSES
+        .iter()
+        .position(|&h| h == highway_type)
+        .map_or(HIGHWAY_OTHER, |i| i as u8)
+}
 
-        // Explore neighbors
-        for &(next_node, edge_cost) in &router.data.adj_list[node] {
-            let next_cost = cost.saturating_add(edge_cost);
-            if next_cost <= max_cost_ms && next_cost < dist[next_node] {
-                dist[next_node] = next_cost;
-                heap.push(DijkstraState { cost: next_cost, node: next_node });
+/// Highway class bitmask of a comma-separated list of highway=* values
+/// A value without a class of its own is an error rather than HIGHWAY_OTHER, which would
+/// match every untracked highway type
+fn parse_highway_mask(csv: &str) -> Result<u64> {
+    let mut classes = Vec::new();
+    for name in csv.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+        match highway_class(name) {
+            HIGHWAY_OTHER => {
+                anyhow::bail!("Unknown highway type '{}' (expected one of {})", name, HIGHWAY_CLASSES.join(", "))
             }
+            class => classes.push(class),
         }
     }
+    Ok(highway_mask(classes))
+}
 
-    result_count
+/// Bitmask of highway classes, for matching against Router::node_highways
+fn highway_mask(classes: impl IntoIterator<Item = u8>) -> u64 {
+    classes.into_iter().fold(0, |mask, class| mask | 1u64 << class.min(HIGHWAY_OTHER))
 }
 
-/// Calculate route with full geometry
-/// Returns number of path points written, or -1 on error, -2 if not loaded
-#[no_mangle]
-pub extern "C" fn routing_route(
-    lat1: f64,
-    lon1: f64,
-    lat2: f64,
-    lon2: f64,
-    mode: *const c_char,
-    out_result: *mut RouteResult,
-    out_points: *mut RoutePoint,
-    max_points: i32,
-) -> i32 {
-    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
-        return -1;
-    }
+// node_positions and adj_list are written to the raw section of the cache file (see
+// write_graph) so they can be memory-mapped instead of deserialized
+#[derive(Serialize, Deserialize)]
+struct RoutingData {
+    #[serde(skip)]
+    node_positions: FlatVec<(f64, f64)>,
+    fast_graph: FastGraph,
+    distance_graph: FastGraph, // Same nodes and edges weighted by length in centimeters
+    spatial_index: RTree<IndexedPoint>,
+    #[serde(skip)]
+    adj_list: Adjacency, // For Dijkstra-based isochrone
+    component_ids: Vec<u32>, // Weakly connected component label per node
+    edge_info: Vec<EdgeInfo>,
+    names: Vec<String>, // Interned way names (name, falling back to ref)
+    turn_restrictions: Vec<TurnRestriction>, // Sorted by via node
+    source_component_count: usize, // Weakly connected components before small ones were dropped
+    edge_shapes: EdgeShapes, // Geometry of contracted chains (empty without contraction)
+    exclude: u32,            // BuildOptions::exclude the graph was built with
+}
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
+/// Turn restriction at a via node, from a `type=restriction` relation
+/// An `only` restriction allows nothing but `to_way` after arriving on `from_way`;
+/// otherwise the from_way -> to_way turn is forbidden
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct TurnRestriction {
+    via: usize,
+    from_way: i64,
+    to_way: i64,
+    only: bool,
+}
+
+/// A loaded routing graph of one mode, for use from Rust (see Router::load)
+/// The C functions query the per-mode routers installed by routing_load through the same methods.
+pub struct Router {
+    data: RoutingData,
+    mode: &'static str, // Canonical mode name, for the mode's speed factor
+    // Stable edge ids: edges of node n are edge_offsets[n]..edge_offsets[n + 1],
+    // in adj_list order
+    edge_offsets: Vec<usize>,
+    component_sizes: Vec<usize>, // Node count per component label
+    edge_delays_ms: HashMap<usize, u32>, // Query-time extra delay per edge id (way delays)
+    source_path: Option<String>,         // PBF the graph was built from (None for in-memory loads)
+    // What-if edges (from, to, weight_ms) seen only by dijkstra_path; edge id is
+    // base edge count + index. added_adj maps a node to its outgoing added edge indices
+    added_edges: Vec<(usize, usize, u32)>,
+    added_adj: HashMap<usize, Vec<usize>>,
+    node_highways: Vec<u64>, // highway_mask of the edges incident to each node (either direction)
+    // Segments between nodes of the point index (one per node pair), for find_nearest_edge
+    segment_index: RTree<IndexedSegment>,
+    // Incoming edges per node as (from, weight_ms, edge id), built on the first reverse search
+    reverse_adj: OnceLock<Vec<Vec<(usize, u32, usize)>>>,
+}
+
+impl Router {
+    fn new(data: RoutingData) -> Self {
+        let edge_offsets = data.adj_list.offsets().to_vec();
+        let num_components = data.component_ids.iter().map(|&c| c as usize + 1).max().unwrap_or(0);
+        let mut component_sizes = vec![0; num_components];
+        for &c in &data.component_ids {
+            component_sizes[c as usize] += 1;
+        }
+        let mut node_highways = vec![0u64; data.adj_list.len()];
+        let edges = data
+            .adj_list
+            .iter()
+            .enumerate()
+            .flat_map(|(from, adj)| adj.iter().map(move |&(to, _)| (from, to)));
+        for ((from, to), info) in edges.zip(&data.edge_info) {
+            let mask = highway_mask([info.highway]);
+            node_highways[from] |= mask;
+            node_highways[to] |= mask;
+        }
+        let segment_index = build_segment_index(&data);
+        Router {
+            data,
+            mode: "auto",
+            edge_offsets,
+            component_sizes,
+            edge_delays_ms: HashMap::new(),
+            source_path: None,
+            added_edges: Vec::new(),
+            added_adj: HashMap::new(),
+            node_highways,
+            segment_index,
+            reverse_adj: OnceLock::new(),
+        }
+    }
+
+    fn reverse_adj(&self) -> &[Vec<(usize, u32, usize)>] {
+        self.reverse_adj.get_or_init(|| {
+            let mut reverse = vec![Vec::new(); self.data.adj_list.len()];
+            for (from, edges) in self.data.adj_list.iter().enumerate() {
+                for (i, &(to, weight)) in edges.iter().enumerate() {
+                    reverse[to].push((from, weight, self.edge_offsets[from] + i));
+                }
+            }
+            reverse
+        })
+    }
+
+    /// Add a directed what-if edge, returning its edge id
+    fn add_edge(&mut self, from: usize, to: usize, weight_ms: u32) -> usize {
+        let index = self.added_edges.len();
+        self.added_edges.push((from, to, weight_ms));
+        self.added_adj.entry(from).or_default().push(index);
+        self.data.edge_info.len() + index
+    }
+
+    fn clear_added_edges(&mut self) {
+        self.added_edges.clear();
+        self.added_adj.clear();
+    }
+}
+
+// Queries share a router through read locks and use per-thread path calculators
+// (with_calculator); loading and what-if edits take the write lock
+static ROUTER_AUTO: RwLock<Option<Router>> = RwLock::new(None);
+static ROUTER_BICYCLE: RwLock<Option<Router>> = RwLock::new(None);
+static ROUTER_PEDESTRIAN: RwLock<Option<Router>> = RwLock::new(None);
+static ROUTER_AGRICULTURAL: RwLock<Option<Router>> = RwLock::new(None);
+static ROUTER_HGV: RwLock<Option<Router>> = RwLock::new(None);
+
+// Graphs loaded under a caller-chosen handle (routing_load_named), independent of the mode
+// routers; queries clone the Arc so loading or freeing another handle doesn't wait for them
+static NAMED_ROUTERS: RwLock<BTreeMap<String, Arc<Router>>> = RwLock::new(BTreeMap::new());
+
+// Isochrone origins in components with fewer nodes than this are reported as degenerate (0 = off)
+static MIN_ISOCHRONE_COMPONENT_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+// Blocking barriers split the ways through them at the node (true, default) or drop their edges at it
+static BARRIER_EDGE_SPLIT: AtomicBool = AtomicBool::new(true);
+
+// Components smaller than this are dropped when building; the largest is always kept
+// (usize::MAX keeps only the largest, 0 keeps everything)
+static MIN_BUILD_COMPONENT_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// Dead-end spurs shorter than this (meters, f64 bits) are dropped when building; 0 keeps them
+static MIN_BUILD_STUB_LENGTH_M: AtomicU64 = AtomicU64::new(0);
+
+// Contraction hierarchy preparation parameters of subsequent builds (routing_set_ch_params)
+static CH_PARAMS: RwLock<ChParams> = RwLock::new(ChParams::DEFAULT);
+
+// Motor modes index every usable node for snapping instead of main road nodes only
+static SNAP_ALL_ROADS: AtomicBool = AtomicBool::new(false);
+
+// Snapped routes try several nearby segments per endpoint (snapped_path)
+static MULTI_SNAP: AtomicBool = AtomicBool::new(false);
+
+// Collapse chains of interior way nodes into single shaped edges when building
+static CONTRACT_CHAINS: AtomicBool = AtomicBool::new(false);
+
+// Unit of the distance_m outputs of route functions (DistanceUnit as u8)
+static DISTANCE_UNIT: AtomicU8 = AtomicU8::new(DistanceUnit::Meters as u8);
+
+// Query-time travel time multiplier per mode (f64 bits), indexed like get_router_for_mode
+static DURATION_FACTORS: [AtomicU64; 5] = [const { AtomicU64::new(1.0f64.to_bits()) }; 5];
+
+fn duration_factor_slot(mode: &str) -> &'static AtomicU64 {
+    match mode {
+        "bicycle" => &DURATION_FACTORS[1],
+        "pedestrian" => &DURATION_FACTORS[2],
+        "agricultural" => &DURATION_FACTORS[3],
+        "hgv" => &DURATION_FACTORS[4],
+        _ => &DURATION_FACTORS[0],
+    }
+}
+
+/// Multiplier applied to the travel times reported for `mode` (routing_set_speed_factor)
+fn duration_factor(mode: &str) -> f64 {
+    f64::from_bits(duration_factor_slot(mode).load(AtomicOrdering::Relaxed))
+}
+
+/// Graph cost budget in ms for a travel time limit in seconds under `factor`
+fn budget_ms(seconds: f64, factor: f64) -> u32 {
+    (seconds * 1000.0 / factor) as u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+enum DistanceUnit {
+    Meters = 0,
+    Kilometers = 1,
+    Miles = 2,
+}
+
+impl DistanceUnit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "meters" | "m" => Some(DistanceUnit::Meters),
+            "kilometers" | "km" => Some(DistanceUnit::Kilometers),
+            "miles" | "mi" => Some(DistanceUnit::Miles),
+            _ => None,
+        }
+    }
+
+    fn current() -> Self {
+        match DISTANCE_UNIT.load(AtomicOrdering::Relaxed) {
+            1 => DistanceUnit::Kilometers,
+            2 => DistanceUnit::Miles,
+            _ => DistanceUnit::Meters,
+        }
+    }
+
+    fn convert_meters(self, meters: f64) -> f64 {
+        match self {
+            DistanceUnit::Meters => meters,
+            DistanceUnit::Kilometers => meters / 1000.0,
+            DistanceUnit::Miles => meters / 1609.344,
+        }
+    }
+}
+
+/// Name a set of PBF files is cached under: the file itself, or for several files the first
+/// in sorted order plus a hash of the sorted list, so the merged graph is found again
+/// whatever order the files are given in
+fn pbf_set_key(pbf_paths: &[&str]) -> String {
+    let mut sorted = pbf_paths.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    match sorted.as_slice() {
+        [] => String::new(),
+        [single] => single.to_string(),
+        [first, ..] => format!(
+            "{}.{:016x}",
+            first,
+            fnv1a(sorted.iter().flat_map(|path| path.bytes().chain([0])))
+        ),
+    }
+}
+
+fn cache_path(pbf_path: &str, mode: &str) -> String {
+    format!("{}.{}.routing", pbf_path, mode)
+}
+
+/// Cache path for a graph built with custom options; the default profile keeps the plain path
+/// A bounding box clip adds its corners, so different clips of one file are cached separately.
+fn cache_path_for(pbf_path: &str, mode: &str, options: &BuildOptions) -> String {
+    let plain = options.profile.is_empty()
+        && options.bbox.is_none()
+        && options.elevation.is_none()
+        && options.barrier_edge_split
+        && !options.contract_chains
+        && !options.snap_all_roads;
+    let pruned = options.min_component_size != usize::MAX || options.min_stub_length_m > 0.0;
+    if plain && !pruned && options.exclude == 0 && options.vehicle.is_none() {
+        return cache_path(pbf_path, mode);
+    }
+    let mut path = format!("{}.{}", pbf_path, mode);
+    if !options.profile.is_empty() {
+        path += &format!(".{:016x}", options.profile.hash());
+    }
+    if let Some((min_lon, min_lat, max_lon, max_lat)) = options.bbox {
+        path += &format!(".bbox_{}_{}_{}_{}", min_lon, min_lat, max_lon, max_lat);
+    }
+    if let Some(elevation) = &options.elevation {
+        path += &format!(".elev_{:016x}", elevation.dem_hash);
+    }
+    if options.exclude != 0 {
+        path += &format!(".x{}", options.exclude);
+    }
+    if let Some(vehicle) = options.vehicle {
+        path += &format!(".veh_{}_{}_{}", vehicle.height_m, vehicle.width_m, vehicle.weight_t);
+    }
+    if options.min_component_size != usize::MAX {
+        path += &format!(".comp_{}", options.min_component_size);
+    }
+    if options.min_stub_length_m > 0.0 {
+        path += &format!(".stub_{}", options.min_stub_length_m);
+    }
+    if !options.barrier_edge_split {
+        path += ".nosplit";
+    }
+    if options.contract_chains {
+        path += ".chains";
+    }
+    if options.snap_all_roads {
+        path += ".allnodes";
+    }
+    path + ".routing"
+}
+
+// Settings that change how a graph is built from OSM data
+#[derive(Clone, Debug)]
+struct BuildOptions {
+    barrier_edge_split: bool,
+    profile: Profile,
+    min_component_size: usize, // Smaller components are dropped, except the largest
+    min_stub_length_m: f64,    // Shorter dead-end spurs are dropped (stub_pruned_index); 0 keeps them
+    // (min_lon, min_lat, max_lon, max_lat); segments entirely outside are dropped
+    bbox: Option<(f64, f64, f64, f64)>,
+    snap_all_roads: bool, // Index every node for snapping in motor modes too
+    // Grade-dependent bicycle and pedestrian speeds; None builds a flat graph
+    elevation: Option<Arc<Elevation>>,
+    contract_chains: bool, // Collapse degree-2 chains into shaped edges (contract_chains)
+    exclude: u32,          // EXCLUDE_* bits of ways left out of the graph
+    vehicle: Option<Vehicle>, // Ways whose size or weight limits it exceeds are left out
+    compression_level: i32,   // zstd level for the written cache, 0 for uncompressed (not in the cache key)
+    ch_params: ChParams,      // Contraction tuning (not in the cache key: queries answer the same)
+    prior_order: Option<Arc<ChOrder>>, // Orders of the graph a rebuild replaces (set by cached_graph)
+    source_digest: Option<[u8; 32]>,   // SHA-256 of in-memory PBF bytes, checked against the cache header
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            barrier_edge_split: true,
+            profile: Profile::default(),
+            min_component_size: usize::MAX,
+            min_stub_length_m: 0.0,
+            bbox: None,
+            snap_all_roads: false,
+            elevation: None,
+            contract_chains: false,
+            exclude: 0,
+            vehicle: None,
+            compression_level: 0,
+            ch_params: ChParams::default(),
+            prior_order: None,
+            source_digest: None,
+        }
+    }
+}
+
+impl BuildOptions {
+    /// Options from the global build settings (routing_set_barrier_edge_split, ...)
+    fn from_settings() -> Self {
+        BuildOptions {
+            barrier_edge_split: BARRIER_EDGE_SPLIT.load(AtomicOrdering::Relaxed),
+            profile: Profile::default(),
+            min_component_size: MIN_BUILD_COMPONENT_SIZE.load(AtomicOrdering::Relaxed),
+            min_stub_length_m: f64::from_bits(MIN_BUILD_STUB_LENGTH_M.load(AtomicOrdering::Relaxed)),
+            bbox: None,
+            snap_all_roads: SNAP_ALL_ROADS.load(AtomicOrdering::Relaxed),
+            elevation: None,
+            contract_chains: CONTRACT_CHAINS.load(AtomicOrdering::Relaxed),
+            exclude: 0,
+            vehicle: None,
+            compression_level: 0,
+            ch_params: *CH_PARAMS.read().unwrap_or_else(|e| e.into_inner()),
+            prior_order: None,
+            source_digest: None,
+        }
+    }
+}
+
+/// Contraction hierarchy preparation parameters (fast_paths::Params and ParamsWithOrder)
+/// Witness searches that settle more nodes find more paths around a contracted node, so fewer
+/// shortcuts are added: a slower build for a smaller hierarchy and faster queries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChParams {
+    hierarchy_depth_factor: f32,
+    max_settled_initial: usize,    // Per witness search when first prioritizing the nodes
+    max_settled_neighbor: usize,   // Per witness search when reprioritizing after a contraction
+    max_settled_contraction: usize, // Per witness search when contracting a node
+    max_settled_with_order: usize,  // Per witness search when contracting in a reused order
+}
+
+impl ChParams {
+    // fast_paths' defaults
+    const DEFAULT: ChParams = ChParams {
+        hierarchy_depth_factor: 0.1,
+        max_settled_initial: 500,
+        max_settled_neighbor: 100,
+        max_settled_contraction: 500,
+        max_settled_with_order: 100,
     };
+}
 
-    let mutex = get_router_for_mode(mode);
-    let mut guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
+impl Default for ChParams {
+    fn default() -> Self {
+        ChParams::DEFAULT
+    }
+}
+
+/// Contraction orders of a previous build of a graph, with its node positions
+/// Contracting in a known order skips the node prioritization that dominates preparation.
+/// Any order gives correct results, but it only speeds things up for the same nodes, so
+/// it is reused only when the rebuilt graph has exactly the same node positions (e.g. after
+/// a speed profile change).
+#[derive(Debug)]
+struct ChOrder {
+    node_positions: Vec<(f64, f64)>,
+    time: Vec<usize>,
+    distance: Vec<usize>,
+}
+
+impl ChOrder {
+    fn of(data: &RoutingData) -> Self {
+        ChOrder {
+            node_positions: data.node_positions.to_vec(),
+            time: fast_paths::get_node_ordering(&data.fast_graph),
+            distance: fast_paths::get_node_ordering(&data.distance_graph),
+        }
+    }
+}
+
+/// Contract `input_graph` with `params`, in `order` if given (falling back to a fresh
+/// ordering should fast_paths reject it)
+fn prepare_ch(input_graph: &InputGraph, params: &ChParams, order: Option<&[usize]>) -> FastGraph {
+    if let Some(order) = order {
+        let with_order = fast_paths::ParamsWithOrder::new(params.max_settled_with_order);
+        if let Ok(graph) = fast_paths::prepare_with_order_with_params(input_graph, order, &with_order) {
+            return graph;
+        }
+    }
+    let params = fast_paths::Params::new(
+        params.hierarchy_depth_factor,
+        params.max_settled_initial,
+        params.max_settled_neighbor,
+        params.max_settled_contraction,
+    );
+    fast_paths::prepare_with_params(input_graph, &params)
+}
+
+/// Highway ways, the nodes they reference and turn restriction relations, as read from a PBF
+struct OsmData {
+    nodes: HashMap<i64, (f64, f64)>,
+    barriers: HashMap<i64, Tags>,
+    crossings: HashSet<i64>, // highway=crossing nodes without traffic signals
+    ways: Vec<osmpbfreader::Way>,
+    restrictions: Vec<osmpbfreader::Relation>,
+}
+
+fn read_osm(pbf_path: &str) -> Result<OsmData> {
+    let file = File::open(pbf_path).with_context(|| format!("Could not open PBF file {}", pbf_path))?;
+    read_osm_from(file).with_context(|| format!("Could not read PBF file {}", pbf_path))
+}
+
+/// read_osm for PBF data already in memory
+fn read_osm_bytes(bytes: &[u8]) -> Result<OsmData> {
+    read_osm_from(Cursor::new(bytes)).context("Could not read PBF buffer")
+}
+
+fn read_osm_from<R: Read + Seek>(reader: R) -> Result<OsmData> {
+    let mut pbf = OsmPbfReader::new(reader);
+
+    let objs = pbf.get_objs_and_deps(|obj| {
+        obj.is_node()
+            || (obj.is_way() && is_routable_way(obj.tags()))
+            || (obj.is_relation() && obj.tags().get("type").map(|s| s.as_str()) == Some("restriction"))
+    })?;
+
+    let mut data = OsmData {
+        nodes: HashMap::new(),
+        barriers: HashMap::new(),
+        crossings: HashSet::new(),
+        ways: Vec::new(),
+        restrictions: Vec::new(),
     };
+    for obj in objs.into_values() {
+        match obj {
+            OsmObj::Node(n) => {
+                data.nodes.insert(n.id.0, (n.lon(), n.lat()));
+                if is_unsignalled_crossing(&n.tags) {
+                    data.crossings.insert(n.id.0);
+                }
+                if n.tags.contains_key("barrier") {
+                    data.barriers.insert(n.id.0, n.tags);
+                }
+            }
+            // Restriction members are pulled in as dependencies; only highways and ferries are routable
+            OsmObj::Way(w) if is_routable_way(&w.tags) => data.ways.push(w),
+            OsmObj::Way(_) => {}
+            OsmObj::Relation(r) => data.restrictions.push(r),
+        }
+    }
+    Ok(data)
+}
 
-    let router = match guard.as_mut() {
-        Some(r) => r,
-        None => return -2,
+/// Read several PBF files into one data set
+/// Nodes are merged by OSM id, so a way referencing a node from another file connects to it;
+/// ways and relations present in more than one file (extract overlaps) are kept once.
+fn read_osm_files(pbf_paths: &[&str], progress: &Progress) -> Result<OsmData> {
+    let parts = pbf_paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            progress.report(c"reading", (PROGRESS_BUILDING * i / pbf_paths.len()) as i32);
+            read_osm(path).with_context(|| format!("Could not read {}", path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(merge_osm(parts))
+}
+
+fn merge_osm(parts: Vec<OsmData>) -> OsmData {
+    let mut parts = parts.into_iter();
+    let Some(mut merged) = parts.next() else {
+        return OsmData {
+            nodes: HashMap::new(),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: Vec::new(),
+            restrictions: Vec::new(),
+        };
     };
+    let mut way_ids: HashSet<i64> = merged.ways.iter().map(|w| w.id.0).collect();
+    let mut relation_ids: HashSet<i64> = merged.restrictions.iter().map(|r| r.id.0).collect();
+    for part in parts {
+        merged.nodes.extend(part.nodes);
+        merged.barriers.extend(part.barriers);
+        merged.crossings.extend(part.crossings);
+        merged.ways.extend(part.ways.into_iter().filter(|w| way_ids.insert(w.id.0)));
+        merged
+            .restrictions
+            .extend(part.restrictions.into_iter().filter(|r| relation_ids.insert(r.id.0)));
+    }
+    merged
+}
 
-    // Find nearest nodes
-    let from_idx = match find_nearest_node(&router.data, lon1, lat1) {
-        Some(idx) => idx,
-        None => return -1,
+/// Barrier effects for the given mode, keyed by (way id, node id)
+/// A barrier applies to every way through its node: OSM does not say which of them carries it.
+fn barrier_way_nodes(osm: &OsmData, mode: &str) -> HashMap<(i64, i64), BarrierEffect> {
+    let mut effects = HashMap::new();
+    for w in &osm.ways {
+        for node in &w.nodes {
+            if let Some(effect) = osm.barriers.get(&node.0).and_then(|tags| barrier_effect(tags, mode)) {
+                effects.insert((w.id.0, node.0), effect);
+            }
+        }
+    }
+    effects
+}
+
+fn is_unsignalled_crossing(tags: &Tags) -> bool {
+    let tag = |key: &str| tags.get(key).map(|s| s.as_str());
+    tag("highway") == Some("crossing")
+        && tag("crossing") != Some("traffic_signals")
+        && tag("crossing:signals") != Some("yes")
+}
+
+/// Add a crossing delay to the ways through unsignalled crossings, keyed like barrier_way_nodes
+/// The delay applies to the ways crossing the road, not to walking along the road itself: a
+/// crossing node belongs to the ways less important than the most important one through it.
+fn add_crossing_delays(osm: &OsmData, delay_ms: u32, effects: &mut HashMap<(i64, i64), BarrierEffect>) {
+    let mut ways_at: HashMap<i64, Vec<(i64, u8)>> = HashMap::new();
+    for w in &osm.ways {
+        let rank = highway_rank(w.tags.get("highway").map(|s| s.as_str()).unwrap_or(""));
+        for node in w.nodes.iter().filter(|node| osm.crossings.contains(&node.0)) {
+            ways_at.entry(node.0).or_default().push((w.id.0, rank));
+        }
+    }
+    for (node_id, ways) in ways_at {
+        let road_rank = ways.iter().map(|&(_, rank)| rank).min().unwrap_or(0);
+        for (way_id, rank) in ways {
+            if rank == road_rank {
+                continue;
+            }
+            match effects.entry((way_id, node_id)).or_insert(BarrierEffect::Delay(0)) {
+                BarrierEffect::Delay(ms) => *ms += delay_ms,
+                BarrierEffect::Block => {}
+            }
+        }
+    }
+}
+
+/// Whether a restriction relation binds the mode: Some(true) for only_*, Some(false) for no_*
+/// Mode-specific keys (restriction:motorcar, ...) win; a plain `restriction` honors `except`.
+/// Pedestrians aren't bound by turn restrictions.
+fn restriction_kind(tags: &Tags, mode: &str) -> Option<bool> {
+    if tags.get("type").map(|s| s.as_str()) != Some("restriction") {
+        return None;
+    }
+    let (keys, vehicles): (&[&str], &[&str]) = match mode {
+        "auto" => (&["restriction:motorcar", "restriction:motor_vehicle"], &["motorcar", "motor_vehicle"]),
+        "agricultural" => (
+            &["restriction:agricultural", "restriction:motor_vehicle"],
+            &["agricultural", "motor_vehicle"],
+        ),
+        "hgv" => (&["restriction:hgv", "restriction:motor_vehicle"], &["hgv", "motor_vehicle"]),
+        "bicycle" => (&["restriction:bicycle"], &["bicycle"]),
+        _ => return None,
+    };
+    let value = match keys.iter().find_map(|&k| tags.get(k)) {
+        Some(v) => v.as_str(),
+        None => {
+            let except = tags.get("except").map(|s| s.as_str()).unwrap_or("");
+            if except.split(';').any(|v| vehicles.contains(&v.trim())) {
+                return None;
+            }
+            tags.get("restriction")?.as_str()
+        }
     };
+    if value.starts_with("only_") {
+        Some(true)
+    } else if value.starts_with("no_") {
+        Some(false)
+    } else {
+        None
+    }
+}
 
-    let to_idx = match find_nearest_node(&router.data, lon2, lat2) {
-        Some(idx) => idx,
-        None => return -1,
+/// Turn restriction of a relation for the mode, with the via node mapped to its graph index
+/// Only via nodes are supported; relations with via ways or ambiguous members are skipped.
+fn turn_restriction(
+    relation: &osmpbfreader::Relation,
+    mode: &str,
+    node_index: &HashMap<i64, usize>,
+) -> Option<TurnRestriction> {
+    let only = restriction_kind(&relation.tags, mode)?;
+    let member = |role: &str| {
+        let mut refs = relation.refs.iter().filter(|r| r.role == role);
+        let first = refs.next()?;
+        refs.next().is_none().then_some(first.member)
     };
+    match (member("from")?, member("via")?, member("to")?) {
+        (OsmId::Way(from), OsmId::Node(via), OsmId::Way(to)) => Some(TurnRestriction {
+            via: *node_index.get(&via.0)?,
+            from_way: from.0,
+            to_way: to.0,
+            only,
+        }),
+        _ => None,
+    }
+}
 
-    // Calculate path
-    let path = match router
-        .calculator
-        .calc_path(&router.data.fast_graph, from_idx, to_idx)
-    {
-        Some(p) => p,
-        None => return -1,
+fn build_graph_for_mode(
+    pbf_paths: &[&str],
+    mode: &str,
+    options: &BuildOptions,
+    progress: &Progress,
+) -> Result<RoutingData> {
+    let osm = match pbf_paths {
+        [pbf_path] => {
+            progress.report(c"reading", 0);
+            read_osm(pbf_path)?
+        }
+        _ => read_osm_files(pbf_paths, progress)?,
     };
+    Ok(build_graph_with_progress(&osm, mode, options, progress))
+}
 
-    let path_nodes = path.get_nodes();
-    let duration_s = path.get_weight() as f64 / 1000.0;
+fn build_graph_from_osm(osm: &OsmData, mode: &str, options: &BuildOptions) -> RoutingData {
+    build_graph_with_progress(osm, mode, options, &Progress::NONE)
+}
 
-    // Calculate actual road distance and collect points
-    let mut total_distance_m = 0.0;
-    let out_points = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
-    let num_points = path_nodes.len().min(max_points as usize);
+// Overall percentages at which the load phases start
+const PROGRESS_BUILDING: usize = 30;
+const PROGRESS_CONTRACTING: i32 = 50;
+const PROGRESS_WRITING: i32 = 90;
+
+// C callback for routing_load_progress: phase label, overall percentage (0-100) and the
+// caller's user_data
+type ProgressCallback = extern "C" fn(phase: *const c_char, percent: i32, user_data: *mut c_void);
+
+/// Reports load phases to a ProgressCallback
+/// Only the loading thread reports, never the parallel workers, so the callback needs no
+/// synchronization
+struct Progress {
+    callback: Option<ProgressCallback>,
+    user_data: *mut c_void,
+}
 
-    for i in 0..num_points {
-        let node_idx = path_nodes[i];
-        let (lon, lat) = router.data.node_positions[node_idx];
-        out_points[i] = RoutePoint { lat, lon };
+impl Progress {
+    const NONE: Progress = Progress {
+        callback: None,
+        user_data: std::ptr::null_mut(),
+    };
 
-        // Calculate distance between consecutive points
-        if i > 0 {
-            let prev_idx = path_nodes[i - 1];
-            let (prev_lon, prev_lat) = router.data.node_positions[prev_idx];
-            let p1 = Point::new(prev_lon, prev_lat);
-            let p2 = Point::new(lon, lat);
-            total_distance_m += p1.haversine_distance(&p2);
+    fn report(&self, phase: &CStr, percent: i32) {
+        if let Some(callback) = self.callback {
+            callback(phase.as_ptr(), percent, self.user_data);
         }
     }
+}
 
-    // Write result
-    unsafe {
-        *out_result = RouteResult {
-            distance_m: total_distance_m,
-            duration_s,
-            num_points: num_points as i32,
+fn build_graph_with_progress(osm: &OsmData, mode: &str, options: &BuildOptions, progress: &Progress) -> RoutingData {
+    progress.report(c"building", PROGRESS_BUILDING as i32);
+    let osm_nodes = &osm.nodes;
+    let mut barriers = barrier_way_nodes(osm, mode);
+    match crossing_delay_ms(mode, &options.profile) {
+        0 => {}
+        delay_ms => add_crossing_delays(osm, delay_ms, &mut barriers),
+    }
+
+    // Segment lengths and travel times are computed per way in parallel; names and node sets
+    // are merged serially in way order so the result matches a serial build
+    let way_segments: Vec<Option<Vec<WaySegment>>> = in_thread_pool(|| {
+        osm.ways
+            .par_iter()
+            .map(|w| way_segments(w, mode, options, osm_nodes, &barriers))
+            .collect()
+    });
+
+    let mut edges: Vec<(i64, i64, u32, EdgeInfo)> = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    let mut name_ids: HashMap<String, u32> = HashMap::new();
+    let mut used_nodes: HashSet<i64> = HashSet::new();
+    let mut main_road_node_ids: HashSet<i64> = HashSet::new();
+    // With edge splitting every way end at a blocking barrier gets a node of its own, numbered
+    // below the OSM ids; this maps those nodes back to the barrier node
+    let mut split_nodes: HashMap<i64, i64> = HashMap::new();
+    let mut next_split_id = osm_nodes.keys().min().map_or(0, |&id| id.min(0)) - 1;
+
+    for (w, segments) in osm.ways.iter().zip(way_segments) {
+        let Some(segments) = segments else { continue };
+        let highway = w.tags.get("highway").map(|s| s.as_str()).unwrap_or("");
+        // Ferry terminals are indexed through the roads meeting them, not the crossing itself
+        let is_main = !is_ferry(&w.tags) && is_snap_road(highway, mode, options.snap_all_roads);
+        let oneway = is_oneway(&w.tags, mode);
+        let name = match w.tags.get("name").or_else(|| w.tags.get("ref")) {
+            Some(n) => *name_ids.entry(n.to_string()).or_insert_with(|| {
+                names.push(n.to_string());
+                names.len() as u32 - 1
+            }),
+            None => NO_NAME,
         };
+        let info = EdgeInfo {
+            way_id: w.id.0,
+            name,
+            highway: highway_class(highway),
+            roundabout: is_roundabout(&w.tags),
+            toll: w.tags.get("toll").map(|s| s.as_str()) == Some("yes"),
+        };
+
+        for WaySegment { mut from_id, mut to_id, forward_ms, backward_ms } in segments {
+            for node_id in [&mut from_id, &mut to_id] {
+                if barriers.get(&(w.id.0, *node_id)) == Some(&BarrierEffect::Block) {
+                    split_nodes.insert(next_split_id, *node_id);
+                    *node_id = next_split_id;
+                    next_split_id -= 1;
+                }
+            }
+            edges.push((from_id, to_id, forward_ms, info));
+            used_nodes.insert(from_id);
+            used_nodes.insert(to_id);
+            if is_main {
+                main_road_node_ids.insert(from_id);
+                main_road_node_ids.insert(to_id);
+            }
+            if !oneway {
+                edges.push((to_id, from_id, backward_ms, info));
+            }
+        }
     }
 
-    num_points as i32
+    let mut node_id_to_index: HashMap<i64, usize> = HashMap::new();
+    let mut node_positions: Vec<(f64, f64)> = Vec::new();
+    let mut rtree_points: Vec<IndexedPoint> = Vec::new();
+
+    // Number nodes in id order, so rebuilds of the same data match (ChOrder)
+    let mut used_nodes: Vec<i64> = used_nodes.into_iter().collect();
+    used_nodes.sort_unstable();
+    for &node_id in &used_nodes {
+        if let Some(&pos) = osm_nodes.get(split_nodes.get(&node_id).unwrap_or(&node_id)) {
+            let index = node_positions.len();
+            node_id_to_index.insert(node_id, index);
+            node_positions.push(pos);
+            // Only index snap road nodes, so motor modes snap to roads with through connectivity
+            if main_road_node_ids.contains(&node_id) {
+                rtree_points.push(IndexedPoint {
+                    lon: pos.0,
+                    lat: pos.1,
+                    idx: index,
+                });
+            }
+        }
+    }
+
+    // Build adjacency list
+    let num_nodes = node_positions.len();
+    let mut adj_list: AdjList = vec![Vec::new(); num_nodes];
+    let mut adj_info: Vec<Vec<EdgeInfo>> = vec![Vec::new(); num_nodes];
+
+    for (from_id, to_id, weight, info) in edges {
+        if let (Some(&from_idx), Some(&to_idx)) =
+            (node_id_to_index.get(&from_id), node_id_to_index.get(&to_id))
+        {
+            adj_list[from_idx].push((to_idx, weight));
+            adj_info[from_idx].push(info);
+        }
+    }
+
+    // Drop islands (places without a usable ferry, tagging errors) and re-index the remaining nodes
+    let mut component_ids = compute_components(&adj_list);
+    let source_component_count = component_ids.iter().map(|&c| c as usize + 1).max().unwrap_or(0);
+    if let Some(new_index) = kept_component_index(&component_ids, options.min_component_size) {
+        retain_nodes(
+            &new_index,
+            &mut node_positions,
+            &mut adj_list,
+            &mut adj_info,
+            &mut rtree_points,
+            &mut node_id_to_index,
+        );
+        component_ids = compute_components(&adj_list);
+    }
+
+    // Drop short dead-end spurs (driveways, parking aisles) so points snap to the road they leave
+    if options.min_stub_length_m > 0.0 {
+        if let Some(new_index) = stub_pruned_index(&node_positions, &adj_list, options.min_stub_length_m) {
+            retain_nodes(
+                &new_index,
+                &mut node_positions,
+                &mut adj_list,
+                &mut adj_info,
+                &mut rtree_points,
+                &mut node_id_to_index,
+            );
+            component_ids = compute_components(&adj_list);
+        }
+    }
+
+    let mut turn_restrictions: Vec<TurnRestriction> = osm
+        .restrictions
+        .iter()
+        .filter_map(|r| turn_restriction(r, mode, &node_id_to_index))
+        .collect();
+
+    // Collapse interior way nodes; restriction via nodes stay so restrictions keep applying
+    let mut edge_shapes = EdgeShapes::default();
+    if options.contract_chains {
+        let mut keep = vec![false; adj_list.len()];
+        for r in &turn_restrictions {
+            keep[r.via] = true;
+        }
+        let (new_index, contracted, contracted_info, shapes) =
+            contract_chains(&node_positions, &adj_list, &adj_info, &keep);
+        node_positions = retain_indexed(node_positions, &new_index);
+        (adj_list, adj_info, edge_shapes) = (contracted, contracted_info, shapes);
+        reindex_points(&mut rtree_points, &new_index);
+        for r in &mut turn_restrictions {
+            r.via = new_index[r.via].expect("restriction via nodes are kept");
+        }
+        component_ids = compute_components(&adj_list);
+    }
+
+    progress.report(c"contracting", PROGRESS_CONTRACTING);
+    let mut input_graph = InputGraph::new();
+    for (from, edges) in adj_list.iter().enumerate() {
+        for &(to, weight) in edges {
+            input_graph.add_edge(from, to, weight as usize);
+        }
+    }
+    input_graph.freeze();
+    let edge_info: Vec<EdgeInfo> = adj_info.into_iter().flatten().collect();
+
+    // The two contraction hierarchies are independent
+    let prior_order = options.prior_order.as_deref().filter(|prior| prior.node_positions == node_positions);
+    let params = &options.ch_params;
+    let (fast_graph, distance_graph) = in_thread_pool(|| {
+        rayon::join(
+            || prepare_ch(&input_graph, params, prior_order.map(|prior| &prior.time[..])),
+            || {
+                let order = prior_order.map(|prior| &prior.distance[..]);
+                prepare_distance_graph(&node_positions, &adj_list, &edge_shapes, params, order)
+            },
+        )
+    });
+    let spatial_index = RTree::bulk_load(rtree_points);
+    turn_restrictions.sort_by_key(|r| r.via);
+
+    RoutingData {
+        node_positions: node_positions.into(),
+        fast_graph,
+        distance_graph,
+        spatial_index,
+        adj_list: adj_list.into(),
+        component_ids,
+        edge_info,
+        names,
+        turn_restrictions,
+        source_component_count,
+        edge_shapes,
+        exclude: options.exclude,
+    }
 }
 
-/// Calculate route with full geometry using WKT geometries as input
-/// Uses centroid of each geometry as routing point
-/// Returns number of path points written, or -1 on error, -2 if not loaded
-#[no_mangle]
-pub extern "C" fn routing_route_geom(
-    from_wkt: *const c_char,
-    to_wkt: *const c_char,
-    mode: *const c_char,
-    out_result: *mut RouteResult,
-    out_points: *mut RoutePoint,
-    max_points: i32,
-) -> i32 {
-    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
-        return -1;
+// Consecutive node pair of a way with its travel time in each direction; the two differ
+// only on grades
+struct WaySegment {
+    from_id: i64,
+    to_id: i64,
+    forward_ms: u32,
+    backward_ms: u32,
+}
+
+/// Routable segments of a way for `mode`, or None if the way is not routable at all
+fn way_segments(
+    w: &osmpbfreader::Way,
+    mode: &str,
+    options: &BuildOptions,
+    osm_nodes: &HashMap<i64, (f64, f64)>,
+    barriers: &HashMap<(i64, i64), BarrierEffect>,
+) -> Option<Vec<WaySegment>> {
+    let highway = w.tags.get("highway").map(|s| s.as_str()).unwrap_or("");
+    if is_excluded(&w.tags, highway, options.exclude) || options.vehicle.is_some_and(|v| v.exceeds_limits(&w.tags)) {
+        return None;
+    }
+    let ferry = is_ferry(&w.tags);
+    let mut speed_kmh = if ferry {
+        if !is_ferry_allowed(&w.tags, mode) {
+            return None;
+        }
+        ferry_speed_kmh(w, osm_nodes)
+    } else {
+        if !is_access_allowed(&w.tags, mode) {
+            return None;
+        }
+        way_speed_kmh(&w.tags, highway, mode, &options.profile)? * surface_factor(&w.tags, mode, &options.profile)?
+    };
+    if mode == "bicycle" && access_value(&w.tags, mode) == Some("dismount") {
+        speed_kmh = speed_kmh.min(DISMOUNT_SPEED_KMH);
+    }
+    let penalty = way_penalty(&w.tags, &options.profile)?;
+    // The climbing delay of steps is spread over the way's segments by length
+    let steps_ms_per_m = match w.tags.get("step_count").and_then(|v| v.trim().parse::<u32>().ok()) {
+        Some(steps) if highway == "steps" && steps > 0 => match way_length_m(w, osm_nodes) {
+            length_m if length_m > 0.0 => steps as f64 * step_delay_ms(mode, &options.profile) / length_m,
+            _ => 0.0,
+        },
+        _ => 0.0,
+    };
+
+    // Half of a barrier's delay is paid entering its node and half leaving it,
+    // so passing along the way costs the full delay
+    let barrier = |node_id: i64| barriers.get(&(w.id.0, node_id)).copied();
+    let half_delay = |node_id: i64| match barrier(node_id) {
+        Some(BarrierEffect::Delay(ms)) => ms / 2,
+        _ => 0,
+    };
+    let mut segments = Vec::with_capacity(w.nodes.len().saturating_sub(1));
+    for window in w.nodes.windows(2) {
+        let from_id = window[0].0;
+        let to_id = window[1].0;
+        // With edge splitting the segments are kept and the build splits the way at the barrier
+        let blocked = |node_id| barrier(node_id) == Some(BarrierEffect::Block);
+        if !options.barrier_edge_split && (blocked(from_id) || blocked(to_id)) {
+            continue;
+        }
+        let mut delay_ms = half_delay(from_id) + half_delay(to_id);
+
+        if let (Some(&(lon1, lat1)), Some(&(lon2, lat2))) = (osm_nodes.get(&from_id), osm_nodes.get(&to_id)) {
+            if let Some(bbox) = options.bbox {
+                if !segment_touches_bbox(bbox, (lon1, lat1), (lon2, lat2)) {
+                    continue;
+                }
+            }
+            let p1 = Point::new(lon1, lat1);
+            let p2 = Point::new(lon2, lat2);
+            let dist_m = Haversine::distance(p1, p2);
+            let time_ms = |kmh: f64| ((dist_m / 1000.0 / kmh) * 3600.0 * 1000.0 * penalty) as u32;
+            delay_ms = delay_ms.saturating_add((steps_ms_per_m * dist_m) as u32);
+
+            if time_ms(speed_kmh) > 0 {
+                let grade = match &options.elevation {
+                    Some(elevation) if !ferry => segment_grade(elevation, &w.tags, (lon1, lat1), (lon2, lat2), dist_m),
+                    _ => 0.0,
+                };
+                let directed_ms = |grade: f64| {
+                    let ms = time_ms(speed_kmh * grade_speed_factor(mode, grade, speed_kmh));
+                    ms.max(1).saturating_add(delay_ms)
+                };
+                segments.push(WaySegment {
+                    from_id,
+                    to_id,
+                    forward_ms: directed_ms(grade),
+                    backward_ms: directed_ms(-grade),
+                });
+            }
+        }
+    }
+    Some(segments)
+}
+
+/// Speed that makes a ferry crossing take its tagged duration, or FERRY_SPEED_KMH without one
+fn ferry_speed_kmh(w: &osmpbfreader::Way, osm_nodes: &HashMap<i64, (f64, f64)>) -> f64 {
+    let Some(duration_s) = w.tags.get("duration").and_then(|v| parse_duration_s(v)) else {
+        return FERRY_SPEED_KMH;
+    };
+    let length_m = way_length_m(w, osm_nodes);
+    if length_m > 0.0 {
+        length_m / 1000.0 / (duration_s / 3600.0)
+    } else {
+        FERRY_SPEED_KMH
+    }
+}
+
+/// Length of the way over its nodes with known positions
+fn way_length_m(w: &osmpbfreader::Way, osm_nodes: &HashMap<i64, (f64, f64)>) -> f64 {
+    w.nodes
+        .windows(2)
+        .filter_map(|pair| match (osm_nodes.get(&pair[0].0), osm_nodes.get(&pair[1].0)) {
+            (Some(&(lon1, lat1)), Some(&(lon2, lat2))) => {
+                Some(Haversine::distance(Point::new(lon1, lat1), Point::new(lon2, lat2)))
+            }
+            _ => None,
+        })
+        .sum()
+}
+
+/// Whether the segment's envelope overlaps the (min_lon, min_lat, max_lon, max_lat) box
+/// Segments crossing the boundary are kept whole, with their outside node.
+fn segment_touches_bbox(bbox: (f64, f64, f64, f64), (lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> bool {
+    let (min_lon, min_lat, max_lon, max_lat) = bbox;
+    lon1.max(lon2) >= min_lon && lon1.min(lon2) <= max_lon && lat1.max(lat2) >= min_lat && lat1.min(lat2) <= max_lat
+}
+
+/// New index of every node when only the largest component and those with at least
+/// `min_size` nodes are kept (None for dropped nodes), or None if every node is kept
+fn kept_component_index(component_ids: &[u32], min_size: usize) -> Option<Vec<Option<usize>>> {
+    let mut sizes = vec![0usize; component_ids.iter().map(|&c| c as usize + 1).max().unwrap_or(0)];
+    for &c in component_ids {
+        sizes[c as usize] += 1;
+    }
+    let largest = (0..sizes.len()).max_by_key(|&c| (sizes[c], std::cmp::Reverse(c)))?;
+    let keep: Vec<bool> = sizes.iter().enumerate().map(|(c, &size)| c == largest || size >= min_size).collect();
+    if keep.iter().all(|&k| k) {
+        return None;
+    }
+    let mut next = 0;
+    Some(
+        component_ids
+            .iter()
+            .map(|&c| {
+                keep[c as usize].then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Items whose index maps to a new index, in order
+fn retain_indexed<T>(items: Vec<T>, new_index: &[Option<usize>]) -> Vec<T> {
+    items.into_iter().zip(new_index).filter_map(|(item, idx)| idx.map(|_| item)).collect()
+}
+
+/// Keep the nodes `new_index` maps and renumber them, with the edges and lookups between them
+/// Edges into dropped nodes are dropped too.
+fn retain_nodes(
+    new_index: &[Option<usize>],
+    node_positions: &mut Vec<(f64, f64)>,
+    adj_list: &mut AdjList,
+    adj_info: &mut Vec<Vec<EdgeInfo>>,
+    rtree_points: &mut Vec<IndexedPoint>,
+    node_id_to_index: &mut HashMap<i64, usize>,
+) {
+    *node_positions = retain_indexed(std::mem::take(node_positions), new_index);
+    let edges = retain_indexed(std::mem::take(adj_list), new_index);
+    let infos = retain_indexed(std::mem::take(adj_info), new_index);
+    for (edges, infos) in edges.into_iter().zip(infos) {
+        let (kept_edges, kept_infos) = edges
+            .into_iter()
+            .zip(infos)
+            .filter_map(|((to, w), info)| new_index[to].map(|to| ((to, w), info)))
+            .unzip();
+        adj_list.push(kept_edges);
+        adj_info.push(kept_infos);
+    }
+    reindex_points(rtree_points, new_index);
+    node_id_to_index.retain(|_, idx| match new_index[*idx] {
+        Some(new_idx) => {
+            *idx = new_idx;
+            true
+        }
+        None => false,
+    });
+}
+
+/// New indices after dropping dead-end spurs shorter than `min_length_m`: the nodes from a
+/// dead end (one neighbour, either direction) along a simple chain up to the first junction,
+/// which stays. A chain ending in another dead end is a component of its own and is left to
+/// the component filter. Returns None if no node is dropped.
+fn stub_pruned_index(
+    node_positions: &[(f64, f64)],
+    adj_list: &AdjList,
+    min_length_m: f64,
+) -> Option<Vec<Option<usize>>> {
+    let mut neighbours: Vec<Vec<usize>> = vec![Vec::new(); adj_list.len()];
+    for (from, edges) in adj_list.iter().enumerate() {
+        for &(to, _) in edges.iter().filter(|&&(to, _)| to != from) {
+            neighbours[from].push(to);
+            neighbours[to].push(from);
+        }
+    }
+    for n in &mut neighbours {
+        n.sort_unstable();
+        n.dedup();
+    }
+
+    let distance = |a: usize, b: usize| {
+        let ((lon1, lat1), (lon2, lat2)) = (node_positions[a], node_positions[b]);
+        Haversine::distance(Point::new(lon1, lat1), Point::new(lon2, lat2))
+    };
+    let mut dropped = vec![false; adj_list.len()];
+    for start in (0..adj_list.len()).filter(|&n| neighbours[n].len() == 1) {
+        let mut chain = vec![start];
+        let (mut prev, mut node) = (start, neighbours[start][0]);
+        let mut length_m = distance(prev, node);
+        while length_m < min_length_m && neighbours[node].len() == 2 {
+            let next = if neighbours[node][0] == prev { neighbours[node][1] } else { neighbours[node][0] };
+            chain.push(node);
+            (prev, node) = (node, next);
+            length_m += distance(prev, node);
+        }
+        if length_m < min_length_m && neighbours[node].len() > 2 {
+            for &n in &chain {
+                dropped[n] = true;
+            }
+        }
+    }
+    if !dropped.contains(&true) {
+        return None;
+    }
+
+    let mut next = 0;
+    Some(
+        dropped
+            .iter()
+            .map(|&d| {
+                (!d).then(|| {
+                    next += 1;
+                    next - 1
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Drop the snap index points of removed nodes and renumber the rest
+fn reindex_points(points: &mut Vec<IndexedPoint>, new_index: &[Option<usize>]) {
+    points.retain_mut(|p| match new_index[p.idx] {
+        Some(idx) => {
+            p.idx = idx;
+            true
+        }
+        None => false,
+    });
+}
+
+// Chain collapsed from a kept node: (end node, summed weight, edge info, interior positions,
+// lower index of its first and last interior node, the same walking a loop either way round)
+type Chain = (usize, u32, EdgeInfo, Vec<(f64, f64)>, usize);
+
+/// Follow each edge of `start` through interior nodes to the next kept node
+fn walk_chains(
+    start: usize,
+    node_positions: &[(f64, f64)],
+    adj_list: &AdjList,
+    adj_info: &[Vec<EdgeInfo>],
+    interior: &[bool],
+    covered: &mut [bool],
+) -> Vec<Chain> {
+    let mut chains = Vec::with_capacity(adj_list[start].len());
+    for (&(first, weight), &info) in adj_list[start].iter().zip(&adj_info[start]) {
+        let (mut prev, mut node, mut weight) = (start, first, weight);
+        let mut shape = Vec::new();
+        while interior[node] {
+            covered[node] = true;
+            shape.push(node_positions[node]);
+            let &(next, w) = adj_list[node].iter().find(|&&(next, _)| next != prev).unwrap_or(&adj_list[node][0]);
+            (prev, node, weight) = (node, next, weight.saturating_add(w));
+        }
+        chains.push((node, weight, info, shape, first.min(prev)));
+    }
+    chains
+}
+
+/// Collapse chains of interior nodes into single edges with summed weights, keeping the
+/// interior positions as edge shapes. A node is interior if all its edges belong to one
+/// way and lead to exactly two other nodes, both ways (two-way road) or in from one and
+/// out to the other (oneway), unless `keep` marks it. Closed loops keep an extra node so
+/// no chain starts and ends at the same node, and a chain sharing both ends with another
+/// edge keeps one so paths, which carry nodes only, still tell parallel edges apart.
+/// Returns the new index of every node (None for collapsed ones), the new adjacency with
+/// its edge info, and the shapes by stable edge id
+fn contract_chains(
+    node_positions: &[(f64, f64)],
+    adj_list: &AdjList,
+    adj_info: &[Vec<EdgeInfo>],
+    keep: &[bool],
+) -> (Vec<Option<usize>>, AdjList, Vec<Vec<EdgeInfo>>, EdgeShapes) {
+    let num_nodes = adj_list.len();
+    let mut incoming: Vec<Vec<(usize, usize)>> = vec![Vec::new(); num_nodes];
+    for (from, edges) in adj_list.iter().enumerate() {
+        for (i, &(to, _)) in edges.iter().enumerate() {
+            incoming[to].push((from, i));
+        }
+    }
+
+    let mut interior: Vec<bool> = (0..num_nodes)
+        .map(|n| {
+            let Some(&info) = adj_info[n].first() else { return false };
+            if keep[n]
+                || adj_info[n].iter().any(|&i| i != info)
+                || incoming[n].iter().any(|&(from, i)| adj_info[from][i] != info)
+            {
+                return false;
+            }
+            match (adj_list[n].as_slice(), incoming[n].as_slice()) {
+                (&[(a, _)], &[(b, _)]) => a != b && a != n && b != n,
+                (&[(a, _), (b, _)], &[(c, _), (d, _)]) => {
+                    a != b && a != n && b != n && ((a, b) == (c, d) || (a, b) == (d, c))
+                }
+                _ => false,
+            }
+        })
+        .collect();
+
+    // Walk from every kept node; a chain back to its own start or parallel to another edge keeps
+    // one of its nodes, and a ring of interior nodes no walk reaches keeps two, then walk again
+    let mut chains: Vec<Vec<Chain>> = vec![Vec::new(); num_nodes];
+    loop {
+        let mut covered = vec![false; num_nodes];
+        let mut promote = Vec::new();
+        for n in 0..num_nodes {
+            if !interior[n] {
+                chains[n] = walk_chains(n, node_positions, adj_list, adj_info, &interior, &mut covered);
+                // Of parallel chains the first stays collapsed unless a plain edge shares its ends
+                let ends = &chains[n];
+                let parallel = |(i, c): &(usize, &Chain)| {
+                    c.0 == n
+                        || ends[..*i].iter().any(|o| o.0 == c.0)
+                        || ends[i + 1..].iter().any(|o| o.0 == c.0 && o.3.is_empty())
+                };
+                let shaped = ends.iter().enumerate().filter(|(_, c)| !c.3.is_empty());
+                promote.extend(shaped.filter(parallel).map(|(_, c)| c.4));
+            }
+        }
+        for n in 0..num_nodes {
+            if interior[n] && !covered[n] {
+                let next = adj_list[n][0].0;
+                promote.extend([n, next]);
+                let (mut prev, mut node) = (n, next);
+                covered[n] = true;
+                while !covered[node] {
+                    covered[node] = true;
+                    let edges = &adj_list[node];
+                    let &(after, _) = edges.iter().find(|&&(after, _)| after != prev).unwrap_or(&edges[0]);
+                    (prev, node) = (node, after);
+                }
+            }
+        }
+        if promote.is_empty() {
+            break;
+        }
+        for n in promote {
+            interior[n] = false;
+        }
+    }
+
+    let mut new_index = vec![None; num_nodes];
+    let mut kept = 0;
+    for (n, slot) in new_index.iter_mut().enumerate() {
+        if !interior[n] {
+            *slot = Some(kept);
+            kept += 1;
+        }
+    }
+
+    let mut contracted: AdjList = Vec::with_capacity(kept);
+    let mut contracted_info: Vec<Vec<EdgeInfo>> = Vec::with_capacity(kept);
+    let mut shapes = EdgeShapes {
+        offsets: vec![0],
+        points: Vec::new(),
+    };
+    for (n, node_chains) in chains.into_iter().enumerate() {
+        if interior[n] {
+            continue;
+        }
+        let mut edges = Vec::with_capacity(node_chains.len());
+        let mut infos = Vec::with_capacity(node_chains.len());
+        for (end, weight, info, shape, _) in node_chains {
+            edges.push((new_index[end].expect("chains end at kept nodes"), weight));
+            infos.push(info);
+            shapes.points.extend(shape);
+            shapes.offsets.push(shapes.points.len());
+        }
+        contracted.push(edges);
+        contracted_info.push(infos);
+    }
+    (new_index, contracted, contracted_info, shapes)
+}
+
+/// Contraction hierarchy over the same edges as `adj_list`, weighted by haversine
+/// length in centimeters (at least 1, along the shape of contracted chains) for
+/// shortest-distance queries
+fn prepare_distance_graph(
+    node_positions: &[(f64, f64)],
+    adj_list: &AdjList,
+    shapes: &EdgeShapes,
+    params: &ChParams,
+    order: Option<&[usize]>,
+) -> FastGraph {
+    let mut input_graph = InputGraph::new();
+    let mut edge_id = 0;
+    for (from, edges) in adj_list.iter().enumerate() {
+        for &(to, _) in edges {
+            let coords = shapes.coords(edge_id, node_positions[from], node_positions[to]);
+            let dist_cm = (coords_length_m(&coords) * 100.0).round();
+            input_graph.add_edge(from, to, (dist_cm as usize).max(1));
+            edge_id += 1;
+        }
+    }
+    input_graph.freeze();
+    prepare_ch(&input_graph, params, order)
+}
+
+/// Label weakly connected components (edge direction ignored) with a union-find
+/// Labels are dense, 0..component_count
+fn compute_components(adj_list: &AdjList) -> Vec<u32> {
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+
+    let mut parent: Vec<usize> = (0..adj_list.len()).collect();
+    for (from, edges) in adj_list.iter().enumerate() {
+        for &(to, _) in edges {
+            let (a, b) = (find(&mut parent, from), find(&mut parent, to));
+            if a != b {
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut labels: HashMap<usize, u32> = HashMap::new();
+    (0..adj_list.len())
+        .map(|node| {
+            let root = find(&mut parent, node);
+            let next = labels.len() as u32;
+            *labels.entry(root).or_insert(next)
+        })
+        .collect()
+}
+
+const CACHE_MAGIC: &[u8; 8] = b"DDBROUTE";
+// Bump whenever the serialized layout of RoutingData changes, or what a build stores in it
+const CACHE_FORMAT_VERSION: u32 = 11;
+
+/// Identity of a cache file, written after CACHE_MAGIC and checked before the graph is read
+/// The crate version is included so upgrading the extension invalidates old caches.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CacheHeader {
+    format_version: u32,
+    crate_version: String,
+    mode: String,
+    profile_hash: u64, // 0 for the built-in speeds
+    source_digest: Option<[u8; 32]>, // Of the PBF bytes a routing_load_buffer cache was built from
+}
+
+impl CacheHeader {
+    fn new(mode: &str, options: &BuildOptions) -> Self {
+        CacheHeader {
+            format_version: CACHE_FORMAT_VERSION,
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            mode: mode.to_string(),
+            profile_hash: if options.profile.is_empty() { 0 } else { options.profile.hash() },
+            source_digest: options.source_digest,
+        }
+    }
+}
+
+/// Write the cache through a temporary file renamed into place, so a router still mapping
+/// the previous cache file keeps valid memory
+/// A non-zero compression_level writes the cache as one zstd frame of that level
+fn save_graph(data: &RoutingData, header: &CacheHeader, path: &str, compression_level: i32) -> Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let file = BufWriter::new(File::create(&tmp_path)?);
+    if compression_level == 0 {
+        write_graph(file, header, data)?;
+    } else {
+        let mut encoder = zstd::Encoder::new(file, compression_level)?;
+        write_graph(&mut encoder, header, data)?;
+        encoder.finish()?.flush()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+// First bytes of a zstd frame, marking a compressed cache
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Cache file layout: CACHE_MAGIC, bincode CacheHeader, bincode RoutingData length (u64 LE),
+// bincode RoutingData (without the raw arrays), then the raw section aligned to RAW_ALIGN:
+// node count, edge count, node positions, adjacency offsets and edges, each array padded to
+// RAW_ALIGN. The raw arrays dominate the file size and are memory-mapped on load.
+const RAW_ALIGN: usize = 8;
+
+fn raw_padding(pos: usize) -> usize {
+    (RAW_ALIGN - pos % RAW_ALIGN) % RAW_ALIGN
+}
+
+// Whether the raw encodings match this target's in-memory layout, so arrays can be mapped
+const MAPPABLE_LAYOUT: bool = cfg!(target_endian = "little")
+    && std::mem::size_of::<usize>() == 8
+    && std::mem::size_of::<(f64, f64)>() == 16
+    && std::mem::offset_of!((f64, f64), 0) == 0
+    && std::mem::offset_of!((f64, f64), 1) == 8
+    && std::mem::size_of::<(usize, u32)>() == 16
+    && std::mem::offset_of!((usize, u32), 0) == 0
+    && std::mem::offset_of!((usize, u32), 1) == 8;
+
+/// Element type of the raw cache section, with a fixed little-endian encoding of SIZE bytes
+/// On MAPPABLE_LAYOUT targets the encoding is the in-memory layout and any bytes form a
+/// valid value, so mapped arrays can be used in place
+trait RawItem: Sized {
+    const SIZE: usize;
+    fn write_raw<W: Write>(&self, writer: &mut W) -> std::io::Result<()>;
+    fn read_raw(bytes: &[u8]) -> Self;
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+impl RawItem for usize {
+    const SIZE: usize = 8;
+
+    fn write_raw<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(*self as u64).to_le_bytes())
+    }
+
+    fn read_raw(bytes: &[u8]) -> Self {
+        le_u64(bytes) as usize
+    }
+}
+
+impl RawItem for (f64, f64) {
+    const SIZE: usize = 16;
+
+    fn write_raw<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.0.to_le_bytes())?;
+        writer.write_all(&self.1.to_le_bytes())
+    }
+
+    fn read_raw(bytes: &[u8]) -> Self {
+        (f64::from_bits(le_u64(bytes)), f64::from_bits(le_u64(&bytes[8..])))
+    }
+}
+
+impl RawItem for (usize, u32) {
+    const SIZE: usize = 16;
+
+    fn write_raw<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.0 as u64).to_le_bytes())?;
+        writer.write_all(&self.1.to_le_bytes())?;
+        writer.write_all(&[0; 4])
+    }
+
+    fn read_raw(bytes: &[u8]) -> Self {
+        (le_u64(bytes) as usize, u32::from_le_bytes(bytes[8..12].try_into().unwrap()))
+    }
+}
+
+fn write_raw_array<W: Write, T: RawItem>(writer: &mut W, items: &[T]) -> Result<()> {
+    for item in items {
+        item.write_raw(writer)?;
+    }
+    writer.write_all(&[0; RAW_ALIGN][..raw_padding(items.len() * T::SIZE)])?;
+    Ok(())
+}
+
+/// Serialize routing data behind the cache magic and header
+fn write_graph<W: Write>(mut writer: W, header: &CacheHeader, data: &RoutingData) -> Result<()> {
+    let header_len = bincode::serialized_size(header)? as usize;
+    let data_len = bincode::serialized_size(data)? as usize;
+    writer.write_all(CACHE_MAGIC)?;
+    bincode::serialize_into(&mut writer, header)?;
+    writer.write_all(&(data_len as u64).to_le_bytes())?;
+    bincode::serialize_into(&mut writer, data)?;
+    writer.write_all(&[0; RAW_ALIGN][..raw_padding(CACHE_MAGIC.len() + header_len + 8 + data_len)])?;
+
+    write_raw_array(&mut writer, &[data.node_positions.len(), data.adj_list.num_edges()])?;
+    write_raw_array(&mut writer, &data.node_positions)?;
+    write_raw_array(&mut writer, data.adj_list.offsets())?;
+    write_raw_array(&mut writer, &data.adj_list.edges)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Cursor over the raw section of a cache, mapping arrays in place when they come from a
+/// memory-mapped file and the layout allows it, and decoding copies otherwise
+struct RawArrays<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    map: Option<&'a Arc<Mmap>>,
+}
+
+impl RawArrays<'_> {
+    fn array<T: RawItem>(&mut self, len: usize) -> Result<FlatVec<T>> {
+        let offset = self.pos;
+        let end = len
+            .checked_mul(T::SIZE)
+            .and_then(|size| offset.checked_add(size))
+            .filter(|&end| end <= self.bytes.len())
+            .context("Truncated routing cache")?;
+        self.pos = end + raw_padding(end);
+        match self.map {
+            Some(map) if MAPPABLE_LAYOUT && offset.is_multiple_of(std::mem::align_of::<T>()) => {
+                Ok(FlatVec::Mapped { map: Arc::clone(map), offset, len })
+            }
+            _ => Ok(FlatVec::Owned(self.bytes[offset..end].chunks_exact(T::SIZE).map(T::read_raw).collect())),
+        }
+    }
+}
+
+/// Load a cache file, memory-mapping its node positions and adjacency arrays
+fn load_graph(path: &str, expected: &CacheHeader) -> Result<RoutingData> {
+    let file = File::open(path)?;
+    // SAFETY: cache files are only replaced by renaming a new file over them (save_graph),
+    // never modified in place, so the mapping stays valid while the router uses it
+    let map = Arc::new(unsafe { Mmap::map(&file)? });
+    let (profile_hash, source_digest) = (Some(expected.profile_hash), expected.source_digest.as_ref());
+    parse_graph(&map, Some(&map), &expected.mode, profile_hash, source_digest)
+}
+
+/// Deserialize routing data from in-memory cache bytes (arrays are copied)
+fn read_graph(bytes: &[u8], mode: &str, profile_hash: Option<u64>) -> Result<RoutingData> {
+    parse_graph(bytes, None, mode, profile_hash, None)
+}
+
+/// Parse cache bytes; `map` is the mapping `bytes` comes from, if any
+/// The header must match this build's format and crate version and the given mode, and the
+/// profile hash and source digest unless they are None.
+fn parse_graph(
+    bytes: &[u8],
+    map: Option<&Arc<Mmap>>,
+    mode: &str,
+    profile_hash: Option<u64>,
+    source_digest: Option<&[u8; 32]>,
+) -> Result<RoutingData> {
+    // Compressed caches are decompressed into memory, so their arrays are never mapped
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        let decompressed = zstd::decode_all(bytes).context("Corrupt compressed routing cache")?;
+        return parse_graph(&decompressed, None, mode, profile_hash, source_digest);
+    }
+    let mut reader = bytes;
+    let mut magic = [0u8; 8];
+    if reader.read_exact(&mut magic).is_err() || &magic != CACHE_MAGIC {
+        anyhow::bail!("Not a routing cache (missing header; caches from before versioning must be rebuilt)");
+    }
+    let header: CacheHeader = bincode::deserialize_from(&mut reader).context("Corrupt routing cache header")?;
+    let expected = CacheHeader::new(mode, &BuildOptions::default());
+    if (header.format_version, &header.crate_version) != (expected.format_version, &expected.crate_version) {
+        anyhow::bail!(
+            "Routing cache was written by version {} (format {}), this is version {} (format {})",
+            header.crate_version,
+            header.format_version,
+            expected.crate_version,
+            expected.format_version
+        );
+    }
+    if header.mode != mode {
+        anyhow::bail!("Routing cache was built for mode '{}', not '{}'", header.mode, mode);
+    }
+    if profile_hash.is_some_and(|hash| hash != header.profile_hash) {
+        anyhow::bail!("Routing cache was built with a different speed profile");
+    }
+    if source_digest.is_some_and(|digest| header.source_digest.as_ref() != Some(digest)) {
+        anyhow::bail!("Routing cache was built from different PBF bytes");
+    }
+    let mut data_len = [0u8; 8];
+    reader.read_exact(&mut data_len).context("Truncated routing cache")?;
+    let data_len = u64::from_le_bytes(data_len) as usize;
+    let data_bytes = reader.get(..data_len).context("Truncated routing cache")?;
+    let mut data: RoutingData = bincode::deserialize(data_bytes)?;
+
+    let raw_start = bytes.len() - reader.len() + data_len;
+    let mut raw = RawArrays {
+        bytes,
+        pos: raw_start + raw_padding(raw_start),
+        map,
+    };
+    let counts: FlatVec<usize> = raw.array(2)?;
+    let (num_nodes, num_edges) = (counts[0], counts[1]);
+    data.node_positions = raw.array(num_nodes)?;
+    data.adj_list = Adjacency {
+        offsets: raw.array(num_nodes.checked_add(1).context("Corrupt routing cache")?)?,
+        edges: raw.array(num_edges)?,
+    };
+    check_graph(&data)?;
+    Ok(data)
+}
+
+/// Reject routing data whose per-node arrays disagree, so a damaged or foreign
+/// blob fails at load time instead of panicking in a later query
+fn check_graph(data: &RoutingData) -> Result<()> {
+    let num_nodes = data.node_positions.len();
+    if data.adj_list.len() != num_nodes
+        || data.component_ids.len() != num_nodes
+        || data.fast_graph.get_num_nodes() != num_nodes
+        || data.distance_graph.get_num_nodes() != num_nodes
+    {
+        anyhow::bail!("Inconsistent routing data: per-node arrays differ in length");
+    }
+    let offsets = data.adj_list.offsets();
+    if offsets.first().is_some_and(|&first| first != 0)
+        || offsets.windows(2).any(|w| w[0] > w[1])
+        || offsets.last().copied().unwrap_or(0) != data.adj_list.num_edges()
+    {
+        anyhow::bail!("Inconsistent routing data: adjacency offsets out of order");
+    }
+    if data.edge_info.len() != data.adj_list.num_edges() {
+        anyhow::bail!("Inconsistent routing data: per-edge arrays differ in length");
+    }
+    let shape_offsets = &data.edge_shapes.offsets;
+    if !shape_offsets.is_empty()
+        && (shape_offsets.len() != data.adj_list.num_edges() + 1
+            || shape_offsets[0] != 0
+            || shape_offsets.windows(2).any(|w| w[0] > w[1])
+            || shape_offsets.last() != Some(&data.edge_shapes.points.len()))
+    {
+        anyhow::bail!("Inconsistent routing data: edge shape offsets out of order");
+    }
+    if data.edge_info.iter().any(|e| e.name != NO_NAME && e.name as usize >= data.names.len()) {
+        anyhow::bail!("Inconsistent routing data: name index out of range");
+    }
+    if data.adj_list.iter().flatten().any(|&(to, _)| to >= num_nodes)
+        || data.spatial_index.iter().any(|p| p.idx >= num_nodes)
+        || data.turn_restrictions.iter().any(|r| r.via >= num_nodes)
+    {
+        anyhow::bail!("Inconsistent routing data: node index out of range");
+    }
+    Ok(())
+}
+
+/// Nearest indexed node, accounting for longitude wraparound at the antimeridian
+/// The R-tree is planar in degrees, so near ±180 the true nearest node may sit on
+/// the other side; in that case the query is repeated with the longitude shifted by 360
+fn find_nearest_node(data: &RoutingData, lon: f64, lat: f64) -> Option<usize> {
+    find_nearest_node_where(data, lon, lat, |_| true)
+}
+
+/// Nearest indexed node accepted by `accept`, with the same antimeridian handling
+fn find_nearest_node_where<F>(data: &RoutingData, lon: f64, lat: f64, accept: F) -> Option<usize>
+where
+    F: Fn(usize) -> bool,
+{
+    let nearest_where = |query_lon: f64| {
+        data.spatial_index
+            .nearest_neighbor_iter(&[query_lon, lat])
+            .find(|p| accept(p.idx))
+    };
+    let nearest = nearest_where(lon)?;
+    let reach_deg = nearest.distance_2(&[lon, lat]).sqrt();
+    if lon.abs() + reach_deg <= 180.0 {
+        return Some(nearest.idx);
+    }
+
+    let wrapped_lon = if lon < 0.0 { lon + 360.0 } else { lon - 360.0 };
+    let query = Point::new(lon, lat);
+    match nearest_where(wrapped_lon) {
+        Some(wrapped)
+            if Haversine::distance(query, Point::new(wrapped.lon, wrapped.lat))
+                < Haversine::distance(query, Point::new(nearest.lon, nearest.lat)) =>
+        {
+            Some(wrapped.idx)
+        }
+        _ => Some(nearest.idx),
+    }
+}
+
+/// Up to k indexed nodes nearest to a coordinate, as (node, meters) sorted by haversine distance
+/// The R-tree orders by planar degrees, which differs from ground distance away from the equator,
+/// so the search continues until no remaining node can be closer than the k-th candidate.
+/// Nodes across the antimeridian are included as in find_nearest_node.
+fn find_nearest_nodes(data: &RoutingData, lon: f64, lat: f64, k: usize) -> Vec<(usize, f64)> {
+    const EARTH_RADIUS_M: f64 = 6_371_000.0;
+    let query = Point::new(lon, lat);
+    // Planar reach in degrees that covers every point within `meters` of the query
+    let reach_deg = |meters: f64| {
+        let lat_deg = (meters / EARTH_RADIUS_M).to_degrees();
+        let cos_lat = (lat.abs() + lat_deg).min(89.9).to_radians().cos();
+        lat_deg / cos_lat
+    };
+    let mut found: Vec<(usize, f64)> = Vec::new();
+    if k == 0 {
+        return found;
+    }
+    let search = |query_lon: f64, found: &mut Vec<(usize, f64)>| {
+        for p in data.spatial_index.nearest_neighbor_iter(&[query_lon, lat]) {
+            if found.len() >= k && p.distance_2(&[query_lon, lat]).sqrt() > reach_deg(found[k - 1].1) {
+                break;
+            }
+            if found.iter().any(|&(idx, _)| idx == p.idx) {
+                continue;
+            }
+            let dist = Haversine::distance(query, Point::new(p.lon, p.lat));
+            let pos = found.partition_point(|&(_, d)| d <= dist);
+            if pos < k {
+                found.insert(pos, (p.idx, dist));
+                found.truncate(k);
+            }
+        }
+    };
+    search(lon, &mut found);
+    let reach = found.last().map(|&(_, d)| reach_deg(d)).unwrap_or(0.0);
+    if found.len() < k || lon.abs() + reach > 180.0 {
+        search(if lon < 0.0 { lon + 360.0 } else { lon - 360.0 }, &mut found);
+    }
+    found
+}
+
+/// R-tree of the segments whose endpoints are both in the point index
+/// Two-way roads get one segment per node pair; contracted chains one per piece of their shape
+fn build_segment_index(data: &RoutingData) -> RTree<IndexedSegment> {
+    let mut indexed = vec![false; data.node_positions.len()];
+    for p in data.spatial_index.iter() {
+        indexed[p.idx] = true;
+    }
+    let mut pairs = Vec::new();
+    for (a, edges) in data.adj_list.iter().enumerate() {
+        for &(b, _) in edges {
+            if !indexed[a] || !indexed[b] || a == b {
+                continue;
+            }
+            if a > b && edge_weight_ms(data, b, a).is_some() {
+                continue; // Added from the b side
+            }
+            pairs.push((a, b));
+        }
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+    let mut segments = Vec::with_capacity(pairs.len());
+    for (a, b) in pairs {
+        let coords = leg_coords(data, a, b);
+        let fractions = length_fractions(&coords);
+        for k in 1..coords.len() {
+            let ((lon1, lat1), (lon2, lat2)) = (coords[k - 1], coords[k]);
+            let data = (a, b, fractions[k - 1], fractions[k]);
+            segments.push(GeomWithData::new(Line::new([lon1, lat1], [lon2, lat2]), data));
+        }
+    }
+    RTree::bulk_load(segments)
+}
+
+// A query point projected onto a road segment
+#[derive(Clone, Copy, Debug)]
+struct EdgeSnap {
+    from: usize,     // Segment start node
+    to: usize,       // Segment end node
+    fraction: f64,   // Share of the from -> to length before the projected point, 0..=1
+    lon: f64,        // Projected point
+    lat: f64,
+    distance_m: f64, // Query point to projected point
+}
+
+/// Project a point onto the nearest indexed road segment
+/// Candidates come from the planar R-tree; the projection uses longitudes scaled by
+/// cos(latitude) and the best candidate is chosen by haversine distance
+fn find_nearest_edge(router: &Router, lon: f64, lat: f64) -> Option<EdgeSnap> {
+    find_nearest_edges(router, lon, lat, 1).into_iter().next()
+}
+
+/// Projections of a point onto up to k of the nearest indexed road segments, one per node
+/// pair, sorted by haversine distance
+/// Segments across the antimeridian are included as in find_nearest_node.
+fn find_nearest_edges(router: &Router, lon: f64, lat: f64, k: usize) -> Vec<EdgeSnap> {
+    let scale = lat.to_radians().cos();
+    // Projection onto the segment with the query at query_lon, the query's longitude in the
+    // segment's frame (shifted by 360 degrees across the antimeridian)
+    let project = |segment: &IndexedSegment, query_lon: f64| {
+        let (from, to, start, end) = segment.data;
+        let [a_lon, a_lat] = segment.geom().from;
+        let [b_lon, b_lat] = segment.geom().to;
+        let (dx, dy) = ((b_lon - a_lon) * scale, b_lat - a_lat);
+        let len_2 = dx * dx + dy * dy;
+        let fraction = if len_2 > 0.0 {
+            (((query_lon - a_lon) * scale * dx + (lat - a_lat) * dy) / len_2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let snap_lon = a_lon + (b_lon - a_lon) * fraction;
+        let snap_lat = a_lat + (b_lat - a_lat) * fraction;
+        EdgeSnap {
+            from,
+            to,
+            fraction: start + fraction * (end - start),
+            lon: snap_lon,
+            lat: snap_lat,
+            distance_m: Haversine::distance(Point::new(lon, lat), Point::new(snap_lon, snap_lat)),
+        }
+    };
+    let candidates = 16.max(4 * k);
+    let nearest: Vec<&IndexedSegment> =
+        router.segment_index.nearest_neighbor_iter(&[lon, lat]).take(candidates).collect();
+    let mut snaps: Vec<EdgeSnap> = nearest.iter().map(|segment| project(segment, lon)).collect();
+    let reach_deg = nearest.first().map_or(f64::INFINITY, |segment| segment.distance_2(&[lon, lat]).sqrt());
+    if lon.abs() + reach_deg > 180.0 {
+        let wrapped_lon = if lon < 0.0 { lon + 360.0 } else { lon - 360.0 };
+        let wrapped = router.segment_index.nearest_neighbor_iter(&[wrapped_lon, lat]).take(candidates);
+        snaps.extend(wrapped.map(|segment| project(segment, wrapped_lon)));
+    }
+    // Stable, so the first of equally near candidates stays first
+    snaps.sort_by(|x, y| x.distance_m.total_cmp(&y.distance_m));
+    let mut seen = HashSet::new();
+    snaps.retain(|snap| seen.insert((snap.from, snap.to)));
+    snaps.truncate(k);
+    snaps
+}
+
+// Segments tried per endpoint by snapped_path when multi-snap is enabled
+const SNAP_CANDIDATES: usize = 4;
+// Candidates farther than the nearest segment plus this are not tried
+const SNAP_CANDIDATE_TOLERANCE_M: f64 = 25.0;
+
+/// Edge snaps of two points and the route between them (route_between_snaps), trying up to
+/// `candidates` segments per point within SNAP_CANDIDATE_TOLERANCE_M of the nearest one and
+/// keeping the pair with the fastest route; ties go to the nearer snaps. With more than one
+/// candidate a poorly connected stub next to a point no longer forces a detour.
+fn snapped_path(
+    router: &Router,
+    lon1: f64,
+    lat1: f64,
+    lon2: f64,
+    lat2: f64,
+    candidates: usize,
+) -> Option<(EdgeSnap, EdgeSnap, Vec<usize>, u64)> {
+    let snaps = |lon: f64, lat: f64| {
+        let mut snaps = find_nearest_edges(router, lon, lat, candidates);
+        let max_m = snaps.first().map_or(0.0, |nearest| nearest.distance_m + SNAP_CANDIDATE_TOLERANCE_M);
+        snaps.retain(|snap| snap.distance_m <= max_m);
+        snaps
+    };
+    let (origins, dests) = (snaps(lon1, lat1), snaps(lon2, lat2));
+    origins
+        .iter()
+        .flat_map(|origin| dests.iter().map(move |dest| (origin, dest)))
+        .filter_map(|(origin, dest)| {
+            route_between_snaps(router, origin, dest).map(|(nodes, ms)| (*origin, *dest, nodes, ms))
+        })
+        .min_by_key(|&(_, _, _, ms)| ms)
+}
+
+/// Door-to-door route between two edge snaps as (path nodes, travel time in ms)
+/// The partial edge times from the origin to the first node and from the last node to the
+/// destination are included; one-way segments can only be left/entered along their direction.
+/// A route staying on a single segment has no path nodes. Turn restrictions are honored from
+/// the origin segment on; the turn onto the destination segment isn't checked.
+fn route_between_snaps(router: &Router, origin: &EdgeSnap, dest: &EdgeSnap) -> Option<(Vec<usize>, u64)> {
+    let data = &router.data;
+    let partial = |weight: u32, fraction: f64| (weight as f64 * fraction).round() as usize;
+    let way = |from: usize, to: usize| edge_between(router, from, to).map(|e| data.edge_info[e].way_id);
+
+    // (node, ms to reach it, way it is reached on)
+    let mut starts = Vec::new();
+    if let Some(w) = edge_weight_ms(data, origin.from, origin.to) {
+        starts.push((origin.to, partial(w, 1.0 - origin.fraction), way(origin.from, origin.to)));
+    }
+    if let Some(w) = edge_weight_ms(data, origin.to, origin.from) {
+        starts.push((origin.from, partial(w, origin.fraction), way(origin.to, origin.from)));
+    }
+    let mut ends = Vec::new();
+    if let Some(w) = edge_weight_ms(data, dest.from, dest.to) {
+        ends.push((dest.from, partial(w, dest.fraction)));
+    }
+    if let Some(w) = edge_weight_ms(data, dest.to, dest.from) {
+        ends.push((dest.to, partial(w, 1.0 - dest.fraction)));
+    }
+
+    // Both points on the same segment: possibly no node in between
+    let mut direct: Option<u64> = None;
+    if (origin.from, origin.to) == (dest.from, dest.to) {
+        let delta = dest.fraction - origin.fraction;
+        let weight = if delta >= 0.0 {
+            edge_weight_ms(data, origin.from, origin.to)
+        } else {
+            edge_weight_ms(data, origin.to, origin.from)
+        };
+        direct = weight.map(|w| partial(w, delta.abs()) as u64);
+    }
+
+    let via_nodes = if starts.is_empty() || ends.is_empty() {
+        None
+    } else {
+        let sources = starts.iter().map(|&(node, ms, _)| (node, ms)).collect();
+        with_calculator(&router.data.fast_graph, |calc| {
+            calc.calc_path_multiple_sources_and_targets(&router.data.fast_graph, sources, ends.clone())
+        })
+        .map(|path| (path.get_nodes().clone(), path.get_weight() as u64))
+    };
+    let via_nodes = match via_nodes {
+        Some((nodes, _)) if !path_turns_allowed(router, &nodes, start_way(&starts, nodes[0])) => {
+            let starts: Vec<_> = starts.iter().map(|&(node, ms, way)| (node, ms as u32, way)).collect();
+            let ends: Vec<_> = ends.iter().map(|&(node, ms)| (node, ms as u32)).collect();
+            turn_restricted_path(router, &starts, &ends).map(|p| (p.nodes, p.cost_ms as u64))
+        }
+        via_nodes => via_nodes,
+    };
+
+    match (via_nodes, direct) {
+        (Some((_, weight)), Some(ms)) if ms <= weight => Some((Vec::new(), ms)),
+        (Some(path), _) => Some(path),
+        (None, Some(ms)) => Some((Vec::new(), ms)),
+        (None, None) => None,
+    }
+}
+
+/// Way the route starts on at `node`, from route_between_snaps starts
+fn start_way(starts: &[(usize, usize, Option<i64>)], node: usize) -> Option<i64> {
+    starts.iter().find(|&&(start, _, _)| start == node).and_then(|&(_, _, way)| way)
+}
+
+// Modes with their own router; the C functions reject any other mode name (mode_arg), while
+// internal lookups of one fall back to the auto router
+const MODES: [&str; 5] = ["auto", "bicycle", "pedestrian", "agricultural", "hgv"];
+
+/// The MODES entry equal to `mode`, or None for an unknown mode
+fn canonical_mode(mode: &str) -> Option<&'static str> {
+    MODES.iter().find(|&&m| m == mode).copied()
+}
+
+fn get_router_for_mode(mode: &str) -> &'static RwLock<Option<Router>> {
+    match mode {
+        "bicycle" => &ROUTER_BICYCLE,
+        "pedestrian" => &ROUTER_PEDESTRIAN,
+        "agricultural" => &ROUTER_AGRICULTURAL,
+        "hgv" => &ROUTER_HGV,
+        _ => &ROUTER_AUTO,
+    }
+}
+
+thread_local! {
+    // Message describing the most recent failure on this thread, for routing_last_error
+    static LAST_ERROR: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+}
+
+fn set_last_error(message: impl Into<String>) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = message.into());
+}
+
+/// Record that no graph is loaded for `mode`, returning the not-loaded status (-2)
+fn not_loaded(mode: &str) -> i32 {
+    set_last_error(format!("No routing graph loaded for mode '{}'", mode));
+    -2
+}
+
+/// Checks C coordinate arguments, as (lat, lon) pairs
+/// Errs with the invalid-coordinate status (-5, recorded for routing_last_error) for the first
+/// pair out of range or NaN, so that swapped or projected coordinates fail instead of snapping
+/// to whatever node is nearest in the bogus space
+fn coords_arg(points: impl IntoIterator<Item = (f64, f64)>) -> Result<(), i32> {
+    match points.into_iter().find(|&(lat, lon)| !is_valid_coord(lat, lon)) {
+        Some((lat, lon)) => Err(Error::InvalidCoordinate { lat, lon }.record()),
+        None => Ok(()),
+    }
+}
+
+fn is_valid_coord(lat: f64, lon: f64) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+/// The MODES entry a C mode argument names
+/// Errs with the status to return: -1 for NULL or invalid UTF-8, or the unknown-mode status
+/// (-4, recorded for routing_last_error) for a name outside MODES, so that a typo such as
+/// "walking" fails instead of silently routing cars
+fn mode_arg(mode: *const c_char) -> Result<&'static str, i32> {
+    if mode.is_null() {
+        return Err(-1);
+    }
+    let name = unsafe { CStr::from_ptr(mode) }.to_str().map_err(|_| -1)?;
+    canonical_mode(name).ok_or_else(|| Error::UnknownMode(name.to_string()).record())
+}
+
+// Dedicated pool for parallel queries and graph builds (routing_set_threads); None runs on
+// rayon's global pool
+static THREAD_POOL: RwLock<Option<Arc<rayon::ThreadPool>>> = RwLock::new(None);
+
+/// Run `op` on the routing_set_threads pool, so the parallel iterators inside it use that
+/// pool's threads
+fn in_thread_pool<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = THREAD_POOL.read().ok().and_then(|guard| guard.clone());
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+// Path calculators kept per thread (at most one per mode's graph)
+const MAX_THREAD_CALCS: usize = 4;
+
+thread_local! {
+    // Per-thread calculators with the node count each was sized for, most recently used last
+    static THREAD_CALCS: std::cell::RefCell<Vec<(usize, PathCalculator)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Run `f` with the current thread's calculator for `graph`
+/// A calculator only depends on the node count, so graphs of equal size (a mode's time and
+/// distance hierarchies) share one; the least recently used is dropped beyond MAX_THREAD_CALCS.
+fn with_calculator<R>(graph: &FastGraph, f: impl FnOnce(&mut PathCalculator) -> R) -> R {
+    THREAD_CALCS.with(|calcs| {
+        let mut calcs = calcs.borrow_mut();
+        let num_nodes = graph.get_num_nodes();
+        match calcs.iter().position(|(n, _)| *n == num_nodes) {
+            Some(i) => {
+                let entry = calcs.remove(i);
+                calcs.push(entry);
+            }
+            None => {
+                if calcs.len() >= MAX_THREAD_CALCS {
+                    calcs.remove(0);
+                }
+                calcs.push((num_nodes, fast_paths::create_calculator(graph)));
+            }
+        }
+        f(&mut calcs.last_mut().unwrap().1)
+    })
+}
+
+/// CH travel time in seconds using the current thread's calculator (fastest_path)
+fn parallel_travel_time_s(router: &Router, from: usize, to: usize) -> Option<f64> {
+    fastest_path(router, from, to).map(|(_, ms)| ms as f64 / 1000.0)
+}
+
+/// Parse WKT geometry and return its routing point as (lon, lat)
+/// For POINT, returns the point itself
+/// For other geometries, returns the centroid or an interior point (geometry_routing_point)
+fn wkt_to_point(wkt_str: &str, interior: bool) -> Result<(f64, f64)> {
+    geometry_routing_point(&parse_wkt(wkt_str)?, interior).context("Geometry is empty")
+}
+
+/// Parse WKT or EWKT (`SRID=4326;POINT(...)`, as emitted by PostGIS/DuckDB)
+/// Coordinates must be WGS84 lon/lat, so any SRID other than 4326 is rejected
+fn parse_wkt(wkt_str: &str) -> Result<Geometry<f64>> {
+    let wkt_str = wkt_str.trim();
+    let body = match wkt_str.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("SRID=") => {
+            let (srid, body) = wkt_str[5..]
+                .split_once(';')
+                .context("EWKT SRID prefix is missing its ';' separator")?;
+            let srid: i32 = srid.trim().parse().context("EWKT SRID is not a number")?;
+            if srid != 4326 {
+                anyhow::bail!("Unsupported SRID {} (only 4326 lon/lat is supported)", srid);
+            }
+            body
+        }
+        _ => wkt_str,
+    };
+    let geom = Geometry::try_from_wkt_str(body).map_err(|e| anyhow::anyhow!("Invalid WKT: {}", e))?;
+    check_lon_lat(&geom)?;
+    Ok(geom)
+}
+
+/// Reject coordinates outside the lon/lat range, which come from a projected CRS (e.g. Web
+/// Mercator meters) given without an SRID and would otherwise snap to arbitrary nodes
+/// The error wraps Error::InvalidCoordinate for the first such coordinate (geometry_arg_error)
+fn check_lon_lat(geom: &Geometry<f64>) -> Result<()> {
+    use geo::CoordsIter;
+    match geom.coords_iter().find(|c| !is_valid_coord(c.y, c.x)) {
+        Some(c) => Err(anyhow::Error::new(Error::InvalidCoordinate { lat: c.y, lon: c.x })
+            .context("Coordinates outside the lon/lat range (projected CRS? only 4326 lon/lat is supported)")),
+        None => Ok(()),
+    }
+}
+
+/// Record why the start or end geometry argument of a route function failed to parse
+/// Returns -5 (ROUTING_INVALID_COORDINATE) for a coordinate out of range, otherwise -1
+fn geometry_arg_error(which: &str, e: anyhow::Error) -> i32 {
+    set_last_error(format!("{} geometry: {:#}", which, e));
+    match e.downcast_ref::<Error>() {
+        Some(Error::InvalidCoordinate { .. }) => -5,
+        _ => -1,
+    }
+}
+
+/// Polygons of a WKT/EWKT POLYGON or MULTIPOLYGON, e.g. closure zones to route around
+fn parse_avoid_area(wkt_str: &str) -> Result<geo::MultiPolygon<f64>> {
+    match parse_wkt(wkt_str)? {
+        Geometry::Polygon(polygon) => Ok(geo::MultiPolygon(vec![polygon])),
+        Geometry::MultiPolygon(polygons) => Ok(polygons),
+        _ => anyhow::bail!("Avoid area must be a POLYGON or MULTIPOLYGON"),
+    }
+}
+
+/// Parse WKB geometry and return its routing point as (lon, lat)
+fn wkb_to_point(wkb: &[u8], interior: bool) -> Result<(f64, f64)> {
+    geometry_routing_point(&parse_wkb(wkb)?, interior).context("Geometry is empty")
+}
+
+// EWKB geometry type flag: an SRID follows the type
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Parse WKB or EWKB (PostGIS extended WKB, with an SRID after the geometry type)
+/// Coordinates must be WGS84 lon/lat, so any SRID other than 4326 (or 0, unknown) is rejected
+fn parse_wkb(wkb: &[u8]) -> Result<Geometry<f64>> {
+    let header = wkb.get(..9).unwrap_or(wkb);
+    let read_u32 = |offset: usize| -> Result<u32> {
+        let bytes: [u8; 4] = header
+            .get(offset..offset + 4)
+            .and_then(|b| b.try_into().ok())
+            .context("WKB is truncated")?;
+        match header[0] {
+            0 => Ok(u32::from_be_bytes(bytes)),
+            1 => Ok(u32::from_le_bytes(bytes)),
+            order => anyhow::bail!("Invalid WKB byte order {}", order),
+        }
+    };
+    let geom = if read_u32(1)? & EWKB_SRID_FLAG != 0 {
+        let srid = read_u32(5)?;
+        if srid != 4326 && srid != 0 {
+            anyhow::bail!("Unsupported SRID {} (only 4326 lon/lat is supported)", srid);
+        }
+        Ewkb(wkb).to_geo()
+    } else {
+        Wkb(wkb).to_geo()
+    };
+    let geom = geom.map_err(|e| anyhow::anyhow!("Invalid WKB: {}", e))?;
+    check_lon_lat(&geom)?;
+    Ok(geom)
+}
+
+/// Point a geometry is routed from or to, as (lon, lat): its centroid, or with `interior` a
+/// point guaranteed to lie on the geometry (inside a polygon, on a line), for shapes such as
+/// a C-shaped area whose centroid falls outside them
+fn geometry_routing_point(geom: &Geometry<f64>, interior: bool) -> Option<(f64, f64)> {
+    if interior {
+        geom.interior_point().map(|p| (p.x(), p.y()))
+    } else {
+        geometry_to_centroid(geom)
+    }
+}
+
+/// Extract centroid from a geo::Geometry
+fn geometry_to_centroid(geom: &Geometry<f64>) -> Option<(f64, f64)> {
+    match geom {
+        Geometry::Point(p) => Some((p.x(), p.y())),
+        Geometry::Line(l) => {
+            let c = l.centroid();
+            Some((c.x(), c.y()))
+        }
+        Geometry::LineString(ls) => ls.centroid().map(|c| (c.x(), c.y())),
+        Geometry::Polygon(p) => p.centroid().map(|c| (c.x(), c.y())),
+        Geometry::MultiPoint(mp) => mp.centroid().map(|c| (c.x(), c.y())),
+        Geometry::MultiLineString(mls) => mls.centroid().map(|c| (c.x(), c.y())),
+        Geometry::MultiPolygon(mp) => mp.centroid().map(|c| (c.x(), c.y())),
+        Geometry::GeometryCollection(gc) => gc.centroid().map(|c| (c.x(), c.y())),
+        Geometry::Rect(r) => {
+            let c = r.centroid();
+            Some((c.x(), c.y()))
+        }
+        Geometry::Triangle(t) => {
+            let c = t.centroid();
+            Some((c.x(), c.y()))
+        }
+    }
+}
+
+/// Node a route from the coordinate starts at, for the functions routing between nodes
+/// Inside the nearest segment, if it can only be travelled one way, that is the node its legal
+/// direction leads to, so the route doesn't begin against a one-way; otherwise the nearest node
+fn find_origin_node(router: &Router, lon: f64, lat: f64) -> Option<usize> {
+    directed_snap_node(router, lon, lat, true)
+}
+
+/// Node a route to the coordinate ends at: on a one-way nearest segment the node it is
+/// entered from, so the route arrives in the legal direction; otherwise the nearest node
+fn find_dest_node(router: &Router, lon: f64, lat: f64) -> Option<usize> {
+    directed_snap_node(router, lon, lat, false)
+}
+
+fn directed_snap_node(router: &Router, lon: f64, lat: f64, leaving: bool) -> Option<usize> {
+    // A point projecting onto a segment end is already at a node and keeps it
+    if let Some(snap) = find_nearest_edge(router, lon, lat).filter(|s| s.fraction > 1e-9 && s.fraction < 1.0 - 1e-9) {
+        let forward = edge_weight_ms(&router.data, snap.from, snap.to).is_some();
+        let backward = edge_weight_ms(&router.data, snap.to, snap.from).is_some();
+        // Leaving along from -> to reaches `to`; arriving along it comes from `from`
+        match (forward, backward, leaving) {
+            (true, false, true) | (false, true, false) => return Some(snap.to),
+            (true, false, false) | (false, true, true) => return Some(snap.from),
+            _ => {}
+        }
+    }
+    find_nearest_node(&router.data, lon, lat)
+}
+
+/// Fastest path between two nodes as (path nodes, travel time in ms), from the contraction
+/// hierarchy; a CH path making a forbidden turn is re-routed with the turn-aware Dijkstra
+fn fastest_path(router: &Router, from: usize, to: usize) -> Option<(Vec<usize>, u32)> {
+    let graph = &router.data.fast_graph;
+    match with_calculator(graph, |calc| calc.calc_path(graph, from, to)) {
+        Some(path) if !path_turns_allowed(router, path.get_nodes(), None) => {
+            turn_restricted_path(router, &[(from, 0, None)], &[(to, 0)]).map(|p| (p.nodes, p.cost_ms))
+        }
+        path => path.map(|p| (p.get_nodes().clone(), p.get_weight() as u32)),
+    }
+}
+
+/// Shortest path between two coordinates as (path nodes, duration in seconds) (fastest_path)
+/// On failure the reason is recorded for routing_last_error
+fn calc_route(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<(Vec<usize>, f64)> {
+    let path = match (
+        find_origin_node(router, lon1, lat1),
+        find_dest_node(router, lon2, lat2),
+    ) {
+        (Some(from_idx), Some(to_idx)) => {
+            fastest_path(router, from_idx, to_idx).map(|(nodes, ms)| (nodes, ms as f64 / 1000.0))
+        }
+        _ => None,
+    };
+    if path.is_none() {
+        record_route_failure(&router.data, lon1, lat1, lon2, lat2);
+    }
+    path
+}
+
+/// Shortest-distance path nodes between two coordinates (distance_graph)
+/// A CH path making a forbidden turn is re-routed with the turn-aware Dijkstra over edge lengths.
+/// On failure the reason is recorded for routing_last_error
+fn calc_shortest_route(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<Vec<usize>> {
+    let graph = &router.data.distance_graph;
+    let path = match (
+        find_origin_node(router, lon1, lat1),
+        find_dest_node(router, lon2, lat2),
+    ) {
+        (Some(from_idx), Some(to_idx)) => match with_calculator(graph, |calc| calc.calc_path(graph, from_idx, to_idx)) {
+            Some(path) if !path_turns_allowed(router, path.get_nodes(), None) => {
+                // What-if edges are left out, as the CH doesn't see them either
+                let base_edges = router.data.edge_info.len();
+                dijkstra_path(router, from_idx, to_idx, |e, _| (e < base_edges).then(|| edge_length_cm(router, e)))
+                    .map(|p| p.nodes)
+            }
+            path => path.map(|p| p.get_nodes().clone()),
+        },
+        _ => None,
+    };
+    match path {
+        Some(path) => Some(path),
+        None => {
+            record_route_failure(&router.data, lon1, lat1, lon2, lat2);
+            None
+        }
+    }
+}
+
+// Snaps farther than this from the input point are reported as off network
+const OFF_NETWORK_M: f64 = 1000.0;
+
+fn format_distance(meters: f64) -> String {
+    if meters >= 1000.0 {
+        format!("{:.1} km", meters / 1000.0)
+    } else {
+        format!("{:.0} m", meters)
+    }
+}
+
+/// Why no route exists between two coordinates, for routing_last_error
+fn route_failure_reason(data: &RoutingData, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> String {
+    let snap = |lon: f64, lat: f64| {
+        find_nearest_node(data, lon, lat).map(|idx| {
+            let (node_lon, node_lat) = data.node_positions[idx];
+            (idx, Haversine::distance(Point::new(lon, lat), Point::new(node_lon, node_lat)))
+        })
+    };
+    let (from, to) = match (snap(lon1, lat1), snap(lon2, lat2)) {
+        (None, _) => return "origin snap failed (graph has no snappable nodes)".to_string(),
+        (_, None) => return "destination snap failed (graph has no snappable nodes)".to_string(),
+        (Some(from), Some(to)) => (from, to),
+    };
+
+    let mut reason = String::new();
+    for (label, (_, dist)) in [("origin", from), ("destination", to)] {
+        if dist > OFF_NETWORK_M {
+            reason += &format!("{} off network (nearest node {} away); ", label, format_distance(dist));
+        }
+    }
+    let (from_component, to_component) = (data.component_ids[from.0], data.component_ids[to.0]);
+    if from_component != to_component {
+        reason += &format!(
+            "no path: endpoints in different components ({}, {})",
+            from_component, to_component
+        );
+    } else {
+        reason += &format!(
+            "no path: destination not reachable from origin within component {} (one-way restrictions)",
+            from_component
+        );
+    }
+    reason += &format!(
+        "; snapped {} from origin, {} from destination",
+        format_distance(from.1),
+        format_distance(to.1)
+    );
+    reason
+}
+
+/// Record why a route failed and return -1
+fn record_route_failure(data: &RoutingData, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> i32 {
+    set_last_error(route_failure_reason(data, lon1, lat1, lon2, lat2));
+    -1
+}
+
+/// Bounding box of all nodes as (min_lon, min_lat, max_lon, max_lat)
+fn graph_bounds(data: &RoutingData) -> Option<(f64, f64, f64, f64)> {
+    let (&(lon, lat), rest) = data.node_positions.split_first()?;
+    Some(rest.iter().fold((lon, lat, lon, lat), |(x0, y0, x1, y1), &(x, y)| {
+        (x0.min(x), y0.min(y), x1.max(x), y1.max(y))
+    }))
+}
+
+// Small deterministic PRNG (SplitMix64) for reproducible synthetic workloads
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Lower bound for every query-time edge weight in dijkstra_path
+const MIN_EDGE_WEIGHT_MS: u32 = 1;
+
+/// Scale a base weight by a penalty or discount factor, clamped to [MIN_EDGE_WEIGHT_MS, u32::MAX]
+/// Non-finite or non-positive factors give the minimum weight
+fn scale_weight(base_ms: u32, factor: f64) -> u32 {
+    let scaled = base_ms as f64 * factor;
+    if scaled.is_nan() || scaled < MIN_EDGE_WEIGHT_MS as f64 {
+        MIN_EDGE_WEIGHT_MS
+    } else {
+        scaled.min(u32::MAX as f64) as u32
+    }
+}
+
+/// Point-to-point Dijkstra over `adj_list` with query-time edge weights
+/// `weight` maps (edge id, base weight in ms) to the query weight, or None to skip the edge.
+/// Used where contraction hierarchy weights can't express the query (penalties, closures).
+/// What-if edges added with routing_add_edge are included after the base edges.
+/// Query weights are clamped to at least MIN_EDGE_WEIGHT_MS: discounts can't create free or
+/// negative edges, so Dijkstra stays correct and paths never loop.
+fn dijkstra_path<F>(router: &Router, from: usize, to: usize, weight: F) -> Option<DynamicPath>
+where
+    F: Fn(usize, u32) -> Option<u32>,
+{
+    dijkstra_path_between(router, &[(from, 0, None)], &[(to, 0)], weight)
+}
+
+/// dijkstra_path from several starts to several ends, each with a cost in ms paid before
+/// leaving / after reaching it (cost_ms includes both). A start's way is the way the trip
+/// arrives on, so the first turn is checked against turn restrictions too.
+fn dijkstra_path_between<F>(
+    router: &Router,
+    starts: &[(usize, u32, Option<i64>)],
+    ends: &[(usize, u32)],
+    weight: F,
+) -> Option<DynamicPath>
+where
+    F: Fn(usize, u32) -> Option<u32>,
+{
+    let data = &router.data;
+    let num_nodes = data.node_positions.len();
+    let mut states = TurnStates::new(data);
+    let mut dist: Vec<u32> = vec![u32::MAX; num_nodes];
+    // Predecessor (state, edge id) for path reconstruction
+    let mut prev: Vec<(usize, usize)> = vec![(usize::MAX, usize::MAX); num_nodes];
+    let mut heap = BinaryHeap::new();
+
+    for &(node, cost, way) in starts {
+        let state = states.state(node, way);
+        grow_labels(&mut dist, state, u32::MAX);
+        grow_labels(&mut prev, state, (usize::MAX, usize::MAX));
+        if cost < dist[state] {
+            dist[state] = cost;
+            heap.push(DijkstraState { cost, node: state });
+        }
+    }
+
+    // (end state, total cost) of the best end reached so far
+    let mut best: Option<(usize, u32)> = None;
+    while let Some(DijkstraState { cost, node: state }) = heap.pop() {
+        if best.is_some_and(|(_, total)| cost >= total) {
+            break;
+        }
+        if cost > dist[state] {
+            continue;
+        }
+        let node = states.node(state);
+        if let Some(&(_, end_cost)) = ends.iter().find(|&&(end, _)| end == node) {
+            let total = cost.saturating_add(end_cost);
+            if best.is_none_or(|(_, best_total)| total < best_total) {
+                best = Some((state, total));
+            }
+        }
+
+        let first_edge = router.edge_offsets[node];
+        let base_edges = data.adj_list[node]
+            .iter()
+            .enumerate()
+            .map(|(i, &(next_node, w))| (first_edge + i, next_node, w));
+        let added_edges = router.added_adj.get(&node).into_iter().flatten().map(|&k| {
+            let (_, next_node, w) = router.added_edges[k];
+            (data.edge_info.len() + k, next_node, w)
+        });
+        for (edge_id, next_node, base_weight) in base_edges.chain(added_edges) {
+            // What-if edges belong to no way and are never restricted
+            let way = data.edge_info.get(edge_id).map(|e| e.way_id);
+            if !states.allows(state, way) {
+                continue;
+            }
+            let edge_cost = match weight(edge_id, base_weight) {
+                Some(w) => w.max(MIN_EDGE_WEIGHT_MS),
+                None => continue,
+            };
+            let next_cost = cost.saturating_add(edge_cost);
+            let next = states.state(next_node, way);
+            grow_labels(&mut dist, next, u32::MAX);
+            grow_labels(&mut prev, next, (usize::MAX, usize::MAX));
+            if next_cost < dist[next] {
+                dist[next] = next_cost;
+                prev[next] = (state, edge_id);
+                heap.push(DijkstraState { cost: next_cost, node: next });
+            }
+        }
+    }
+
+    let (end_state, cost_ms) = best?;
+    let mut nodes = vec![states.node(end_state)];
+    let mut edges = Vec::new();
+    let mut current = end_state;
+    while prev[current].0 != usize::MAX {
+        let (prev_state, edge_id) = prev[current];
+        nodes.push(states.node(prev_state));
+        edges.push(edge_id);
+        current = prev_state;
+    }
+    nodes.reverse();
+    edges.reverse();
+
+    Some(DynamicPath { nodes, edges, cost_ms })
+}
+
+/// Turn restrictions at a via node
+fn restrictions_at(data: &RoutingData, via: usize) -> &[TurnRestriction] {
+    let restrictions = &data.turn_restrictions;
+    let start = restrictions.partition_point(|r| r.via < via);
+    let end = restrictions.partition_point(|r| r.via <= via);
+    &restrictions[start..end]
+}
+
+/// Whether leaving `via` on `out_way` is allowed after arriving on `in_way`
+/// Unknown ways (the search start, what-if edges) are never restricted
+fn turn_allowed(data: &RoutingData, via: usize, in_way: Option<i64>, out_way: Option<i64>) -> bool {
+    let (Some(in_way), Some(out_way)) = (in_way, out_way) else {
+        return true;
+    };
+    let mut has_only = false;
+    for r in restrictions_at(data, via).iter().filter(|r| r.from_way == in_way) {
+        if r.to_way == out_way {
+            return r.only;
+        }
+        has_only |= r.only;
+    }
+    !has_only
+}
+
+/// Search states of a turn-aware Dijkstra: one per node, plus one per (node, incoming way) at
+/// via nodes of turn restrictions, where the way a node was reached on decides where it may go.
+/// A reversed search walks edges backwards, so its states hold the way a node is left on.
+/// States 0..num_nodes are the plain nodes; the others are allocated as they are reached.
+struct TurnStates<'a> {
+    data: &'a RoutingData,
+    edge_offsets: &'a [usize], // Empty when the graph has no restrictions
+    arrivals: Vec<(usize, i64)>,
+    index: HashMap<(usize, i64), usize>,
+    reversed: bool,
+}
+
+impl<'a> TurnStates<'a> {
+    fn new(data: &'a RoutingData) -> Self {
+        let edge_offsets = if data.turn_restrictions.is_empty() {
+            &[]
+        } else {
+            data.adj_list.offsets()
+        };
+        TurnStates {
+            data,
+            edge_offsets,
+            arrivals: Vec::new(),
+            index: HashMap::new(),
+            reversed: false,
+        }
+    }
+
+    fn reversed(data: &'a RoutingData) -> Self {
+        TurnStates { reversed: true, ..TurnStates::new(data) }
+    }
+
+    /// State for arriving at `node` on `way`
+    fn state(&mut self, node: usize, way: Option<i64>) -> usize {
+        match way {
+            Some(way) if !restrictions_at(self.data, node).is_empty() => {
+                let next = self.data.node_positions.len() + self.arrivals.len();
+                *self.index.entry((node, way)).or_insert_with(|| {
+                    self.arrivals.push((node, way));
+                    next
+                })
+            }
+            _ => node,
+        }
+    }
+
+    fn node(&self, state: usize) -> usize {
+        match state.checked_sub(self.data.node_positions.len()) {
+            Some(i) => self.arrivals[i].0,
+            None => state,
+        }
+    }
+
+    /// Whether the edge on `way` may be taken from `state` (backwards when reversed)
+    fn allows(&self, state: usize, way: Option<i64>) -> bool {
+        match state.checked_sub(self.data.node_positions.len()) {
+            Some(i) if self.reversed => {
+                let (node, out_way) = self.arrivals[i];
+                turn_allowed(self.data, node, way, Some(out_way))
+            }
+            Some(i) => {
+                let (node, in_way) = self.arrivals[i];
+                turn_allowed(self.data, node, Some(in_way), way)
+            }
+            None => true,
+        }
+    }
+
+    /// Way of the i-th adj_list edge of `node`, or None if the graph has no restrictions
+    fn edge_way(&self, node: usize, i: usize) -> Option<i64> {
+        self.edge_offsets.get(node).map(|&first| self.data.edge_info[first + i].way_id)
+    }
+}
+
+/// Extend per-state labels to cover `state` (turn states are allocated during a search)
+fn grow_labels<T: Clone>(labels: &mut Vec<T>, state: usize, fill: T) {
+    if state >= labels.len() {
+        labels.resize(state + 1, fill);
+    }
+}
+
+/// Whether a contraction hierarchy path (which ignores turn restrictions) only makes allowed
+/// turns; `in_way` is the way the trip arrives at the first node on. Each hop is taken to use
+/// the cheapest edge between its nodes, as the CH does.
+fn path_turns_allowed(router: &Router, nodes: &[usize], in_way: Option<i64>) -> bool {
+    let data = &router.data;
+    if data.turn_restrictions.is_empty() {
+        return true;
+    }
+    let mut way = in_way;
+    for hop in nodes.windows(2) {
+        let next_way = edge_between(router, hop[0], hop[1]).map(|e| data.edge_info[e].way_id);
+        if !turn_allowed(data, hop[0], way, next_way) {
+            return false;
+        }
+        way = next_way;
+    }
+    true
+}
+
+/// Re-route with dijkstra_path_between when a CH path made a forbidden turn
+/// What-if edges are left out, as the CH doesn't see them either
+fn turn_restricted_path(
+    router: &Router,
+    starts: &[(usize, u32, Option<i64>)],
+    ends: &[(usize, u32)],
+) -> Option<DynamicPath> {
+    let base_edges = router.data.edge_info.len();
+    dijkstra_path_between(router, starts, ends, |e, w| (e < base_edges).then_some(w))
+}
+
+/// Length in centimeters of a stable edge id of the graph, its weight in the distance_graph
+fn edge_length_cm(router: &Router, edge_id: usize) -> u32 {
+    let data = &router.data;
+    let node = router.edge_offsets.partition_point(|&offset| offset <= edge_id) - 1;
+    let to = data.adj_list[node][edge_id - router.edge_offsets[node]].0;
+    let coords = data.edge_shapes.coords(edge_id, data.node_positions[node], data.node_positions[to]);
+    ((coords_length_m(&coords) * 100.0).round() as u32).max(1)
+}
+
+/// Base travel time in milliseconds of a stable edge id (what-if edges included)
+fn edge_base_weight_ms(router: &Router, edge_id: usize) -> u32 {
+    if let Some(added) = edge_id.checked_sub(router.data.edge_info.len()) {
+        return router.added_edges[added].2;
+    }
+    let node = router.edge_offsets.partition_point(|&offset| offset <= edge_id) - 1;
+    router.data.adj_list[node][edge_id - router.edge_offsets[node]].1
+}
+
+// Alternatives: weight multiplier per previous use of an edge, and the allowed
+// detour relative to the primary route
+const ALTERNATIVE_PENALTY: f64 = 1.4;
+const MAX_ALTERNATIVE_STRETCH: f64 = 1.5;
+
+/// Up to k distinct paths from `from` to `to`, the optimal one first (iterative penalty method)
+/// Each round inflates the weights of edges used by earlier candidates; a candidate is kept
+/// if at most `max_shared` of its travel time runs over edges of an already kept path and it
+/// is at most MAX_ALTERNATIVE_STRETCH times the optimum. Fewer than k paths are returned
+/// when the network offers nothing distinct
+fn alternative_paths(router: &Router, from: usize, to: usize, k: usize, max_shared: f64) -> Vec<DynamicPath> {
+    let base_ms = |path: &DynamicPath| -> u64 { path.edges.iter().map(|&e| edge_base_weight_ms(router, e) as u64).sum() };
+
+    let mut accepted: Vec<DynamicPath> = Vec::new();
+    let primary = match dijkstra_path(router, from, to, |_, w| Some(w)) {
+        Some(p) => p,
+        None => return accepted,
+    };
+    let primary_ms = base_ms(&primary);
+    let mut uses: HashMap<usize, i32> = HashMap::new();
+    let mut kept_edges: std::collections::HashSet<usize> = primary.edges.iter().copied().collect();
+    for &e in &primary.edges {
+        *uses.entry(e).or_insert(0) += 1;
+    }
+    accepted.push(primary);
+
+    for _ in 0..k.saturating_sub(1) * 4 {
+        if accepted.len() >= k {
+            break;
+        }
+        let candidate = match dijkstra_path(router, from, to, |e, w| match uses.get(&e) {
+            Some(&n) => Some(scale_weight(w, ALTERNATIVE_PENALTY.powi(n))),
+            None => Some(w),
+        }) {
+            Some(p) => p,
+            None => break,
+        };
+        for &e in &candidate.edges {
+            *uses.entry(e).or_insert(0) += 1;
+        }
+
+        let candidate_ms = base_ms(&candidate);
+        let shared_ms: u64 = candidate
+            .edges
+            .iter()
+            .filter(|e| kept_edges.contains(e))
+            .map(|&e| edge_base_weight_ms(router, e) as u64)
+            .sum();
+        let shared = if candidate_ms > 0 { shared_ms as f64 / candidate_ms as f64 } else { 1.0 };
+        if shared <= max_shared && candidate_ms as f64 <= primary_ms as f64 * MAX_ALTERNATIVE_STRETCH {
+            kept_edges.extend(candidate.edges.iter().copied());
+            accepted.push(candidate);
+        }
+    }
+    accepted
+}
+
+/// Bounded Dijkstra over `adj_list` from `start_idx`
+/// Returns every node settled within `max_cost_ms` as (node, cost in ms), in settle order
+fn isochrone_nodes(data: &RoutingData, start_idx: usize, max_cost_ms: u32) -> Vec<(usize, u32)> {
+    isochrone_nodes_capped(data, start_idx, max_cost_ms, usize::MAX, None).0
+}
+
+/// How often the bounded isochrone search checks its wall-clock deadline, in settled nodes
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+/// isochrone_nodes that stops after settling `max_settled` nodes, or once `deadline` passes
+/// (checked every DEADLINE_CHECK_INTERVAL settled nodes, so the result is always a prefix
+/// of the full settle order). The flag is true if the search was cut short with nodes
+/// still within reach
+fn isochrone_nodes_capped(
+    data: &RoutingData,
+    start_idx: usize,
+    max_cost_ms: u32,
+    max_settled: usize,
+    deadline: Option<std::time::Instant>,
+) -> (Vec<(usize, u32)>, bool) {
+    let num_nodes = data.node_positions.len();
+
+    // Dijkstra with early termination; a node is reached when its first state settles
+    let mut states = TurnStates::new(data);
+    let mut dist: Vec<u32> = vec![u32::MAX; num_nodes];
+    let mut seen = vec![false; num_nodes];
+    let mut heap = BinaryHeap::new();
+    let mut reached = Vec::new();
+
+    dist[start_idx] = 0;
+    heap.push(DijkstraState { cost: 0, node: start_idx });
+
+    while let Some(DijkstraState { cost, node: state }) = heap.pop() {
+        // Skip if we've already found a better path
+        if cost > dist[state] {
+            continue;
+        }
+
+        // Stop if beyond time limit
+        if cost > max_cost_ms {
+            continue;
+        }
+
+        // Record this reachable node
+        let node = states.node(state);
+        if !seen[node] {
+            if reached.len() >= max_settled {
+                return (reached, true);
+            }
+            if reached.len() % DEADLINE_CHECK_INTERVAL == 0
+                && deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+            {
+                return (reached, true);
+            }
+            seen[node] = true;
+            reached.push((node, cost));
+        }
+
+        // Explore neighbors
+        for (i, &(next_node, edge_cost)) in data.adj_list[node].iter().enumerate() {
+            let way = states.edge_way(node, i);
+            if !states.allows(state, way) {
+                continue;
+            }
+            let next_cost = cost.saturating_add(edge_cost);
+            let next = states.state(next_node, way);
+            grow_labels(&mut dist, next, u32::MAX);
+            if next_cost <= max_cost_ms && next_cost < dist[next] {
+                dist[next] = next_cost;
+                heap.push(DijkstraState { cost: next_cost, node: next });
+            }
+        }
+    }
+
+    (reached, false)
+}
+
+/// Network distances from `from` to each of `targets`, with their node paths, for targets
+/// within max_m meters along the road (None for the others)
+/// Edge lengths are haversine distances; turn restrictions are not applied, as a matched
+/// trace records where the vehicle actually went.
+fn paths_within_distance(
+    router: &Router,
+    from: usize,
+    targets: &[usize],
+    max_m: f64,
+) -> Vec<Option<(f64, Vec<usize>)>> {
+    let data = &router.data;
+    let max_cm = (max_m * 100.0).min(u32::MAX as f64) as u32;
+    let edge_cm = |a: usize, b: usize| ((path_distance_m(data, &[a, b]) * 100.0).round() as u32).max(1);
+    let mut dist: HashMap<usize, u32> = HashMap::from([(from, 0)]);
+    let mut prev: HashMap<usize, usize> = HashMap::new();
+    let mut settled: HashSet<usize> = HashSet::new();
+    let mut remaining: HashSet<usize> = targets.iter().copied().collect();
+    let mut heap = BinaryHeap::from([DijkstraState { cost: 0, node: from }]);
+
+    while let Some(DijkstraState { cost, node }) = heap.pop() {
+        if !settled.insert(node) {
+            continue;
+        }
+        remaining.remove(&node);
+        if remaining.is_empty() {
+            break;
+        }
+        for &(next, _) in data.adj_list[node].iter() {
+            let next_cost = cost.saturating_add(edge_cm(node, next));
+            if next_cost <= max_cm && dist.get(&next).is_none_or(|&d| next_cost < d) {
+                dist.insert(next, next_cost);
+                prev.insert(next, node);
+                heap.push(DijkstraState { cost: next_cost, node: next });
+            }
+        }
+    }
+
+    targets
+        .iter()
+        .map(|&target| {
+            if !settled.contains(&target) {
+                return None;
+            }
+            let mut path = vec![target];
+            while let Some(&p) = prev.get(path.last().unwrap()) {
+                path.push(p);
+            }
+            path.reverse();
+            Some((dist[&target] as f64 / 100.0, path))
+        })
+        .collect()
+}
+
+// Map matching (hidden Markov model after Newson & Krumm): GPS error standard deviation,
+// candidate search radius and count per point, scale of the mismatch between road and
+// straight-line distance of a transition, and the longest detour considered
+const MATCH_GPS_SIGMA_M: f64 = 20.0;
+const MATCH_RADIUS_M: f64 = 100.0;
+const MATCH_MAX_CANDIDATES: usize = 8;
+const MATCH_BETA_M: f64 = 50.0;
+const MATCH_MAX_DETOUR: f64 = 4.0;
+// With timestamps, a transition is impossible if the graph's travel time along it exceeds
+// this multiple of the elapsed time plus the slack
+const MATCH_MAX_TIME_FACTOR: f64 = 2.0;
+const MATCH_TIME_SLACK_S: f64 = 30.0;
+
+/// Match a GPS trace of (lon, lat) points onto the network as one path of nodes
+/// Candidates per point are the nodes within MATCH_RADIUS_M (the nearest one if none is);
+/// emissions score the snap distance, transitions the difference between road and
+/// straight-line distance, and a Viterbi pass picks the most likely sequence. Where no
+/// candidate is reachable from the previous point's candidates the model restarts and the
+/// path jumps to the new candidate. Timestamps (seconds) are optional.
+/// Returns the path and its travel time in ms, or None for an empty trace or graph
+fn match_trace(router: &Router, points: &[(f64, f64)], timestamps: Option<&[f64]>) -> Option<(Vec<usize>, u64)> {
+    let data = &router.data;
+    let candidates: Vec<Vec<(usize, f64)>> = points
+        .iter()
+        .map(|&(lon, lat)| {
+            let nearest = find_nearest_nodes(data, lon, lat, MATCH_MAX_CANDIDATES);
+            let within: Vec<(usize, f64)> = nearest.iter().copied().filter(|&(_, d)| d <= MATCH_RADIUS_M).collect();
+            if within.is_empty() {
+                nearest.into_iter().take(1).collect()
+            } else {
+                within
+            }
+        })
+        .collect();
+    if candidates.first().is_none_or(|c| c.is_empty()) {
+        return None;
+    }
+    let emission = |d: f64| -0.5 * (d / MATCH_GPS_SIGMA_M).powi(2);
+    let path_ms = |path: &[usize]| -> u64 {
+        path.windows(2)
+            .filter_map(|w| edge_between(router, w[0], w[1]))
+            .map(|e| edge_base_weight_ms(router, e) as u64)
+            .sum()
+    };
+    let best = |scores: &[f64]| (0..scores.len()).max_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+
+    // Per point and candidate: log probability of the best sequence ending there, and the
+    // predecessor candidate with the path from it
+    type Transition = Option<(usize, Vec<usize>)>;
+    let mut scores: Vec<Vec<f64>> = vec![candidates[0].iter().map(|&(_, d)| emission(d)).collect()];
+    let mut back: Vec<Vec<Transition>> = vec![vec![None; candidates[0].len()]];
+    for t in 1..points.len() {
+        let ((lon1, lat1), (lon2, lat2)) = (points[t - 1], points[t]);
+        let straight_m = Haversine::distance(Point::new(lon1, lat1), Point::new(lon2, lat2));
+        let max_m = straight_m * MATCH_MAX_DETOUR + 2.0 * MATCH_RADIUS_M;
+        let max_ms = timestamps
+            .map(|ts| ((ts[t] - ts[t - 1]).max(0.0) * MATCH_MAX_TIME_FACTOR + MATCH_TIME_SLACK_S) * 1000.0);
+        let targets: Vec<usize> = candidates[t].iter().map(|&(node, _)| node).collect();
+        let mut next_scores = vec![f64::NEG_INFINITY; targets.len()];
+        let mut next_back: Vec<Transition> = vec![None; targets.len()];
+        for (i, &(from, _)) in candidates[t - 1].iter().enumerate() {
+            let score = scores[t - 1][i];
+            if score == f64::NEG_INFINITY {
+                continue;
+            }
+            for (j, reached) in paths_within_distance(router, from, &targets, max_m).into_iter().enumerate() {
+                let Some((road_m, path)) = reached else { continue };
+                if max_ms.is_some_and(|max_ms| path_ms(&path) as f64 > max_ms) {
+                    continue;
+                }
+                let next = score - (road_m - straight_m).abs() / MATCH_BETA_M + emission(candidates[t][j].1);
+                if next > next_scores[j] {
+                    next_scores[j] = next;
+                    next_back[j] = Some((i, path));
+                }
+            }
+        }
+        if next_scores.iter().all(|&s| s == f64::NEG_INFINITY) {
+            // Break in the model: start over from this point's candidates
+            next_scores = candidates[t].iter().map(|&(_, d)| emission(d)).collect();
+        }
+        scores.push(next_scores);
+        back.push(next_back);
+    }
+
+    // Walk back from the best final candidate, collecting transition paths in reverse;
+    // across a break, continue from the best candidate before it
+    let mut j = best(scores.last()?)?;
+    let mut pieces: Vec<Vec<usize>> = Vec::new();
+    for t in (0..points.len()).rev() {
+        match back[t][j].take() {
+            Some((i, path)) => {
+                pieces.push(path);
+                j = i;
+            }
+            None => {
+                pieces.push(vec![candidates[t][j].0]);
+                if t > 0 {
+                    j = best(&scores[t - 1])?;
+                }
+            }
+        }
+    }
+    let mut nodes: Vec<usize> = Vec::new();
+    for piece in pieces.into_iter().rev() {
+        for node in piece {
+            if nodes.last() != Some(&node) {
+                nodes.push(node);
+            }
+        }
+    }
+    let duration_ms = path_ms(&nodes);
+    Some((nodes, duration_ms))
+}
+
+/// isochrone_nodes over reversed edges: every node that reaches `target_idx` within
+/// `max_cost_ms`, as (node, cost in ms to the target), in settle order
+fn reverse_isochrone_nodes(router: &Router, target_idx: usize, max_cost_ms: u32) -> Vec<(usize, u32)> {
+    let data = &router.data;
+    let reverse_adj = router.reverse_adj();
+    let num_nodes = data.node_positions.len();
+
+    let mut states = TurnStates::reversed(data);
+    let mut dist: Vec<u32> = vec![u32::MAX; num_nodes];
+    let mut seen = vec![false; num_nodes];
+    let mut heap = BinaryHeap::new();
+    let mut reached = Vec::new();
+
+    dist[target_idx] = 0;
+    heap.push(DijkstraState { cost: 0, node: target_idx });
+
+    while let Some(DijkstraState { cost, node: state }) = heap.pop() {
+        if cost > dist[state] || cost > max_cost_ms {
+            continue;
+        }
+
+        let node = states.node(state);
+        if !seen[node] {
+            seen[node] = true;
+            reached.push((node, cost));
+        }
+
+        for &(prev_node, edge_cost, edge_id) in &reverse_adj[node] {
+            let way = (!data.turn_restrictions.is_empty()).then(|| data.edge_info[edge_id].way_id);
+            if !states.allows(state, way) {
+                continue;
+            }
+            let next_cost = cost.saturating_add(edge_cost);
+            let next = states.state(prev_node, way);
+            grow_labels(&mut dist, next, u32::MAX);
+            if next_cost <= max_cost_ms && next_cost < dist[next] {
+                dist[next] = next_cost;
+                heap.push(DijkstraState { cost: next_cost, node: next });
+            }
+        }
+    }
+
+    reached
+}
+
+// Random deviation in degrees added to each candidate's bearing mismatch by round_trip_path
+const ROUND_TRIP_JITTER_DEG: f64 = 30.0;
+
+/// Loop from `start` back to itself of about `target_ms`, as (path nodes, cost in ms)
+/// The outbound leg walks adj_list from a heading picked by `seed`, turning the heading a full
+/// circle (in a seeded direction) over the target cost so the walk curves into a loop. At each
+/// node it takes the unvisited neighbour, reachable by an allowed turn, whose bearing best
+/// matches the heading plus some seeded jitter. The walk stops once its cost plus the fastest
+/// way back reaches the target (or at a dead end), and the fastest way back closes the loop,
+/// so the cost overshoots the target by at most about two edges.
+/// None if the start has no edge that leads back to it within the target
+fn round_trip_path(router: &Router, start: usize, target_ms: u32, seed: u64) -> Option<(Vec<usize>, u32)> {
+    let data = &router.data;
+    let mut back_ms = vec![u32::MAX; data.node_positions.len()];
+    for (node, ms) in reverse_isochrone_nodes(router, start, target_ms) {
+        back_ms[node] = ms;
+    }
+
+    let mut rng = SplitMix64(seed);
+    let initial_deg = rng.next_f64() * 360.0;
+    let turn_deg = if rng.next_f64() < 0.5 { 360.0 } else { -360.0 };
+    let mut nodes = vec![start];
+    let mut visited = HashSet::from([start]);
+    let (mut node, mut out_ms, mut in_way) = (start, 0u32, None);
+    while out_ms.saturating_add(back_ms[node]) < target_ms {
+        let heading = initial_deg + turn_deg * out_ms as f64 / target_ms as f64;
+        let mismatch = |next: usize| {
+            let coords = leg_coords(data, node, next);
+            let bearing = Haversine::bearing(Point::from(coords[0]), Point::from(coords[1]));
+            let angle = (bearing - heading).rem_euclid(360.0);
+            angle.min(360.0 - angle)
+        };
+        let step = data.adj_list[node]
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(next, _))| !visited.contains(&next) && back_ms[next] != u32::MAX)
+            .map(|(i, &(next, weight))| (next, weight, data.edge_info[router.edge_offsets[node] + i].way_id))
+            .filter(|&(_, _, way)| turn_allowed(data, node, in_way, Some(way)))
+            .map(|(next, weight, way)| (mismatch(next) + rng.next_f64() * ROUND_TRIP_JITTER_DEG, next, weight, way))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        let Some((_, next, weight, way)) = step else {
+            break;
+        };
+        nodes.push(next);
+        visited.insert(next);
+        (node, out_ms, in_way) = (next, out_ms.saturating_add(weight), Some(way));
+    }
+    if node == start {
+        return None;
+    }
+
+    let back = with_calculator(&data.fast_graph, |calc| calc.calc_path(&data.fast_graph, node, start))
+        .map(|path| (path.get_nodes().clone(), path.get_weight() as u32));
+    let (back_nodes, back_cost) = match back {
+        Some((path, _)) if !path_turns_allowed(router, &path, in_way) => {
+            turn_restricted_path(router, &[(node, 0, in_way)], &[(start, 0)]).map(|p| (p.nodes, p.cost_ms))
+        }
+        back => back,
+    }?;
+    nodes.extend(&back_nodes[1..]);
+    Some((nodes, out_ms.saturating_add(back_cost)))
+}
+
+/// Node minimising the total (or, with `minimize_max`, the worst) travel time from all origins
+/// Only nodes reachable from every origin qualify. Returns (node, cost in ms)
+fn meeting_node(data: &RoutingData, origins: &[usize], minimize_max: bool) -> Option<(usize, u64)> {
+    let num_nodes = data.node_positions.len();
+    let trees: Vec<Vec<(usize, u32)>> = in_thread_pool(|| {
+        origins
+            .par_iter()
+            .map(|&origin| isochrone_nodes(data, origin, u32::MAX))
+            .collect()
+    });
+
+    let mut reached_by = vec![0usize; num_nodes];
+    let mut cost = vec![0u64; num_nodes];
+    for tree in &trees {
+        for &(node, ms) in tree {
+            reached_by[node] += 1;
+            cost[node] = if minimize_max {
+                cost[node].max(ms as u64)
+            } else {
+                cost[node] + ms as u64
+            };
+        }
+    }
+
+    (0..num_nodes)
+        .filter(|&node| reached_by[node] == origins.len())
+        .map(|node| (node, cost[node]))
+        .min_by_key(|&(node, c)| (c, node))
+}
+
+// Fewer distinct points than this get a convex hull instead of a concave one
+const MIN_CONCAVE_HULL_POINTS: usize = 5;
+// geo concave hull parameter: higher is closer to the convex hull
+const ISOCHRONE_CONCAVITY: f64 = 2.0;
+
+/// Hull around reachable node positions: concave where there are enough points, convex otherwise
+fn isochrone_hull(points: &[(f64, f64)]) -> geo::Polygon<f64> {
+    use geo::{ConcaveHull, ConvexHull};
+    let mut coords: Vec<geo::Coord<f64>> = points.iter().map(|&(x, y)| geo::coord! { x: x, y: y }).collect();
+    coords.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    coords.dedup();
+    let multi: geo::MultiPoint<f64> = coords.into_iter().map(Point::from).collect();
+    if multi.0.len() < MIN_CONCAVE_HULL_POINTS {
+        multi.convex_hull()
+    } else {
+        multi.concave_hull(ISOCHRONE_CONCAVITY)
+    }
+}
+
+/// Non-overlapping bands of the isochrone hulls of `limits` (one hull per limit): each hull
+/// minus the hulls of the smaller limits, so a band is a ring with the inner area as a hole.
+/// Bands are returned in the order of `limits`; a band may be several polygons, or none when its
+/// limit reaches no further than a smaller one.
+fn isochrone_bands(hulls: &[geo::Polygon<f64>], limits: &[f64]) -> Vec<geo::Polygon<f64>> {
+    use geo::BooleanOps;
+    let mut order: Vec<usize> = (0..limits.len()).collect();
+    order.sort_by(|&a, &b| limits[a].total_cmp(&limits[b]).then(a.cmp(&b)));
+    let mut bands = vec![geo::MultiPolygon::<f64>(Vec::new()); limits.len()];
+    let mut inner = geo::MultiPolygon::<f64>(Vec::new());
+    for i in order {
+        bands[i] = hulls[i].difference(&inner);
+        inner = inner.union(&hulls[i]);
+    }
+    bands.into_iter().flat_map(|band| band.0).collect()
+}
+
+/// Points where the budget runs out part-way along an edge: for each node reached within
+/// `max_cost_ms` and each edge leaving it that ends beyond the budget, the point at the share
+/// of the edge's travel time the remaining budget covers (speed taken as uniform along it).
+/// Edges whose far end is itself reached within the budget lie inside the area and add no point.
+/// Turn restrictions are not applied to these last partial edges.
+fn isochrone_boundary_points(data: &RoutingData, reached: &[(usize, u32)], max_cost_ms: u32) -> Vec<(f64, f64)> {
+    let inside: HashSet<usize> =
+        reached.iter().filter(|&&(_, cost)| cost <= max_cost_ms).map(|&(node, _)| node).collect();
+    let mut points = Vec::new();
+    for &(node, cost) in reached.iter().filter(|&&(_, cost)| cost <= max_cost_ms) {
+        for &(next, edge_cost) in &data.adj_list[node] {
+            if cost.saturating_add(edge_cost) > max_cost_ms && !inside.contains(&next) {
+                let ratio = (max_cost_ms - cost) as f64 / edge_cost as f64;
+                points.push(point_along(&leg_coords(data, node, next), ratio));
+            }
+        }
+    }
+    points
+}
+
+// (start, end, arrival cost in ms at the end) of a piece of road, positions as (lon, lat)
+type EdgeSegment = ((f64, f64), (f64, f64), u32);
+
+/// Road segments fully traversed within `max_cost_ms`: for each node reached within the budget
+/// and each edge leaving it that also ends within it, the pieces of the edge's geometry as
+/// (start, end, arrival cost in ms at the end), the arrival time shared out along the piece
+/// lengths. A road reached from both ends is kept once, in the direction of its earlier-reached
+/// end (`reached` is in settle order). Turn restrictions are not applied to these edges.
+fn isochrone_edges(data: &RoutingData, reached: &[(usize, u32)], max_cost_ms: u32) -> Vec<EdgeSegment> {
+    let mut segments = Vec::new();
+    let mut seen: HashSet<(usize, usize)> = HashSet::new();
+    for &(node, cost) in reached.iter().filter(|&&(_, cost)| cost <= max_cost_ms) {
+        for &(next, edge_cost) in &data.adj_list[node] {
+            let arrival = cost.saturating_add(edge_cost);
+            if arrival > max_cost_ms || !seen.insert((node.min(next), node.max(next))) {
+                continue;
+            }
+            let coords = leg_coords(data, node, next);
+            let fractions = length_fractions(&coords);
+            for k in 1..coords.len() {
+                let at = cost + (edge_cost as f64 * fractions[k]).round() as u32;
+                segments.push((coords[k - 1], coords[k], at.min(arrival)));
+            }
+        }
+    }
+    segments
+}
+
+/// Travel times in ms from `source` to each target with one Dijkstra over `adj_list`
+/// The search stops once every distinct reachable target is settled
+fn one_to_many_ms(data: &RoutingData, source: usize, targets: &[Option<usize>]) -> Vec<Option<u32>> {
+    let num_nodes = data.node_positions.len();
+    let mut is_target = vec![false; num_nodes];
+    let mut remaining = 0;
+    for &t in targets.iter().flatten() {
+        if !is_target[t] {
+            is_target[t] = true;
+            remaining += 1;
+        }
+    }
+
+    // Per state labels (turn-aware); a node's time is its first settled state
+    let mut states = TurnStates::new(data);
+    let mut dist: Vec<u32> = vec![u32::MAX; num_nodes];
+    let mut settled: Vec<u32> = vec![u32::MAX; num_nodes];
+    let mut heap = BinaryHeap::new();
+    dist[source] = 0;
+    heap.push(DijkstraState { cost: 0, node: source });
+
+    while let Some(DijkstraState { cost, node: state }) = heap.pop() {
+        if remaining == 0 {
+            break;
+        }
+        if cost > dist[state] {
+            continue;
+        }
+        let node = states.node(state);
+        if settled[node] == u32::MAX {
+            settled[node] = cost;
+            if is_target[node] {
+                is_target[node] = false;
+                remaining -= 1;
+            }
+        }
+        for (i, &(next_node, edge_cost)) in data.adj_list[node].iter().enumerate() {
+            let way = states.edge_way(node, i);
+            if !states.allows(state, way) {
+                continue;
+            }
+            let next_cost = cost.saturating_add(edge_cost);
+            let next = states.state(next_node, way);
+            grow_labels(&mut dist, next, u32::MAX);
+            if next_cost < dist[next] {
+                dist[next] = next_cost;
+                heap.push(DijkstraState { cost: next_cost, node: next });
+            }
+        }
+    }
+
+    targets
+        .iter()
+        .map(|t| t.and_then(|t| (settled[t] != u32::MAX).then_some(settled[t])))
+        .collect()
+}
+
+/// Number of POIs whose node settles within `max_cost_ms` of `source`
+/// `pois_at` maps each snapped node to the number of POIs on it
+fn reachable_poi_count(data: &RoutingData, source: usize, pois_at: &HashMap<usize, u32>, max_cost_ms: u32) -> u32 {
+    isochrone_nodes(data, source, max_cost_ms)
+        .iter()
+        .filter_map(|(node, _)| pois_at.get(node))
+        .sum()
+}
+
+/// Mean (lon, lat) of a set of nodes
+/// Longitudes are averaged relative to the first node so sets straddling the
+/// antimeridian don't average out to the opposite side of the globe
+fn mean_position(data: &RoutingData, nodes: &[usize]) -> Option<(f64, f64)> {
+    let &first = nodes.first()?;
+    let ref_lon = data.node_positions[first].0;
+    let (mut sum_dlon, mut sum_lat) = (0.0, 0.0);
+    for &node in nodes {
+        let (lon, lat) = data.node_positions[node];
+        sum_dlon += (lon - ref_lon + 540.0).rem_euclid(360.0) - 180.0;
+        sum_lat += lat;
+    }
+    let n = nodes.len() as f64;
+    let lon = (ref_lon + sum_dlon / n + 540.0).rem_euclid(360.0) - 180.0;
+    Some((lon, sum_lat / n))
+}
+
+/// Spread per-way delays over the way's edges in proportion to their travel time,
+/// so traversing a whole way (in one direction) incurs its full extra delay
+fn way_delays_to_edges(router: &Router, way_delays_s: &HashMap<i64, f64>) -> HashMap<usize, u32> {
+    let mut way_edges: HashMap<i64, Vec<usize>> = HashMap::new();
+    for (edge_id, info) in router.data.edge_info.iter().enumerate() {
+        if way_delays_s.contains_key(&info.way_id) {
+            way_edges.entry(info.way_id).or_default().push(edge_id);
+        }
+    }
+
+    let mut edge_delays = HashMap::new();
+    for (way_id, edge_ids) in way_edges {
+        let extra_ms = way_delays_s[&way_id] * 1000.0;
+        let edge_ends: Vec<(usize, usize)> = edge_ids.iter().map(|&e| edge_endpoints(router, e)).collect();
+        // A two-way way has every segment in both directions; one traversal covers half its edges
+        let two_way = edge_ends.iter().all(|&(a, b)| edge_ends.contains(&(b, a)));
+        let total_ms: f64 = edge_ids.iter().map(|&e| edge_base_weight_ms(router, e) as f64).sum();
+        let one_way_ms = if two_way { total_ms / 2.0 } else { total_ms };
+        if one_way_ms <= 0.0 {
+            continue;
+        }
+        for &e in &edge_ids {
+            let share = edge_base_weight_ms(router, e) as f64 / one_way_ms;
+            edge_delays.insert(e, (extra_ms * share).round().clamp(0.0, u32::MAX as f64) as u32);
+        }
+    }
+    edge_delays
+}
+
+/// (from node, to node) of a stable edge id
+fn edge_endpoints(router: &Router, edge_id: usize) -> (usize, usize) {
+    let node = router.edge_offsets.partition_point(|&offset| offset <= edge_id) - 1;
+    (node, router.data.adj_list[node][edge_id - router.edge_offsets[node]].0)
+}
+
+/// Weight in milliseconds of the cheapest direct edge from `from` to `to`
+fn edge_weight_ms(data: &RoutingData, from: usize, to: usize) -> Option<u32> {
+    data.adj_list[from]
+        .iter()
+        .filter(|&&(next, _)| next == to)
+        .map(|&(_, weight)| weight)
+        .min()
+}
+
+/// Cumulative road distance in meters at each path node (first entry is 0)
+fn path_cumulative_m(data: &RoutingData, path_nodes: &[usize]) -> Vec<f64> {
+    let mut total = 0.0;
+    std::iter::once(0.0)
+        .chain(path_nodes.windows(2).map(|pair| {
+            total += path_distance_m(data, pair);
+            total
+        }))
+        .take(path_nodes.len())
+        .collect()
+}
+
+/// Cumulative travel time in milliseconds at each path node (first entry is 0)
+fn path_cumulative_ms(data: &RoutingData, path_nodes: &[usize]) -> Vec<u32> {
+    let mut cumulative = Vec::with_capacity(path_nodes.len());
+    let mut total = 0u32;
+    for (i, &node) in path_nodes.iter().enumerate() {
+        if i > 0 {
+            let weight = edge_weight_ms(data, path_nodes[i - 1], node).unwrap_or(0);
+            total = total.saturating_add(weight);
+        }
+        cumulative.push(total);
+    }
+    cumulative
+}
+
+/// Positions along a path every `interval_s` seconds as (lon, lat, seconds), at most
+/// `max_samples` of them (at least 1)
+/// Positions are interpolated along each segment by its share of the edge time;
+/// the arrival point is always included as the last sample, so sampling stops one short of
+/// the cap however small the interval
+fn sample_path(data: &RoutingData, path_nodes: &[usize], interval_s: f64, max_samples: usize) -> Vec<(f64, f64, f64)> {
+    let cumulative = path_cumulative_ms(data, path_nodes);
+    let total_ms = match cumulative.last() {
+        Some(&t) => t,
+        None => return Vec::new(),
+    };
+    let interval_ms = interval_s * 1000.0;
+
+    let mut samples = Vec::new();
+    let mut segment = 0;
+    let mut step = 0u64;
+    while samples.len() + 1 < max_samples {
+        let t_ms = step as f64 * interval_ms;
+        if t_ms >= total_ms as f64 {
+            break;
+        }
+        while segment + 1 < cumulative.len() - 1 && (cumulative[segment + 1] as f64) <= t_ms {
+            segment += 1;
+        }
+        let span = (cumulative[segment + 1] - cumulative[segment]) as f64;
+        let ratio = if span > 0.0 {
+            ((t_ms - cumulative[segment] as f64) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (lon, lat) = point_along(&leg_coords(data, path_nodes[segment], path_nodes[segment + 1]), ratio);
+        samples.push((lon, lat, t_ms / 1000.0));
+        step += 1;
+    }
+
+    let (lon, lat) = data.node_positions[*path_nodes.last().unwrap()];
+    samples.push((lon, lat, total_ms as f64 / 1000.0));
+    samples
+}
+
+/// Write path geometry and summary to caller buffers (truncating to max_points)
+/// The distance covers the full path even when the geometry is truncated
+/// Returns number of points written
+fn write_route(
+    data: &RoutingData,
+    path_nodes: &[usize],
+    duration_s: f64,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    let out_points = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
+    let coords = path_coords(data, path_nodes);
+    let num_points = coords.len().min(max_points as usize);
+
+    for (out, &(lon, lat)) in out_points.iter_mut().zip(&coords) {
+        *out = RoutePoint { lat, lon };
+    }
+
+    unsafe {
+        *out_result = RouteResult {
+            distance_m: DistanceUnit::current().convert_meters(path_distance_m(data, path_nodes)),
+            duration_s,
+            num_points: num_points as i32,
+        };
+    }
+
+    num_points as i32
+}
+
+/// Road distance in meters along a path (sum of haversine segment lengths)
+fn path_distance_m(data: &RoutingData, path_nodes: &[usize]) -> f64 {
+    path_nodes.windows(2).map(|w| coords_length_m(&leg_coords(data, w[0], w[1]))).sum()
+}
+
+/// Geometry from node `from` to node `to`: both positions with the shape of the cheapest
+/// edge between them in between (of the cheapest `to` -> `from` edge, reversed, if there is
+/// no forward edge). Without an edge (e.g. what-if edges) it is a straight line
+fn leg_coords(data: &RoutingData, from: usize, to: usize) -> Vec<(f64, f64)> {
+    let (start, end) = (data.node_positions[from], data.node_positions[to]);
+    if data.edge_shapes.is_empty() {
+        return vec![start, end];
+    }
+    let cheapest = |a: usize, b: usize| {
+        data.adj_list[a]
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(next, _))| next == b)
+            .min_by_key(|&(_, &(_, weight))| weight)
+            .map(|(i, _)| data.adj_list.offsets()[a] + i)
+    };
+    match (cheapest(from, to), cheapest(to, from)) {
+        (Some(edge), _) => data.edge_shapes.coords(edge, start, end),
+        (None, Some(edge)) => {
+            let mut coords = data.edge_shapes.coords(edge, end, start);
+            coords.reverse();
+            coords
+        }
+        (None, None) => vec![start, end],
+    }
+}
+
+/// Full geometry of a path, including the shapes of contracted chains
+fn path_coords(data: &RoutingData, path_nodes: &[usize]) -> Vec<(f64, f64)> {
+    let mut coords: Vec<(f64, f64)> = path_nodes.first().map(|&n| data.node_positions[n]).into_iter().collect();
+    for pair in path_nodes.windows(2) {
+        coords.extend(leg_coords(data, pair[0], pair[1]).into_iter().skip(1));
+    }
+    coords
+}
+
+/// Share of a polyline's length at each of its coordinates (0 at the first, 1 at the last)
+fn length_fractions(coords: &[(f64, f64)]) -> Vec<f64> {
+    let total = coords_length_m(coords);
+    let mut at = 0.0;
+    let mut fractions = Vec::with_capacity(coords.len());
+    fractions.push(0.0);
+    for pair in coords.windows(2) {
+        at += coords_length_m(pair);
+        fractions.push(if total > 0.0 { at / total } else { 0.0 });
+    }
+    if coords.len() > 1 {
+        fractions[coords.len() - 1] = 1.0;
+    }
+    fractions
+}
+
+/// Point at `ratio` of the length along a polyline
+fn point_along(coords: &[(f64, f64)], ratio: f64) -> (f64, f64) {
+    let fractions = length_fractions(coords);
+    let k = fractions.partition_point(|&f| f <= ratio).clamp(1, coords.len() - 1);
+    let span = fractions[k] - fractions[k - 1];
+    let local = if span > 0.0 { ((ratio - fractions[k - 1]) / span).clamp(0.0, 1.0) } else { 0.0 };
+    let point = |(lon, lat): (f64, f64)| Point::new(lon, lat);
+    let p = Haversine::point_at_ratio_between(point(coords[k - 1]), point(coords[k]), local);
+    (p.x(), p.y())
+}
+
+/// Shape points of the from -> to leg strictly between two shares of its length, ordered
+/// from `start` towards `end` (e.g. between an edge snap and the node a route leaves by)
+fn leg_points_between(data: &RoutingData, from: usize, to: usize, start: f64, end: f64) -> Vec<(f64, f64)> {
+    let coords = leg_coords(data, from, to);
+    let fractions = length_fractions(&coords);
+    let (lo, hi) = (start.min(end), start.max(end));
+    let mut points: Vec<(f64, f64)> =
+        coords.into_iter().zip(fractions).filter(|&(_, f)| f > lo && f < hi).map(|(c, _)| c).collect();
+    if start > end {
+        points.reverse();
+    }
+    points
+}
+
+/// Stable edge id of the cheapest direct edge from `from` to `to`
+fn edge_between(router: &Router, from: usize, to: usize) -> Option<usize> {
+    router.data.adj_list[from]
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(next, _))| next == to)
+        .min_by_key(|&(_, &(_, weight))| weight)
+        .map(|(i, _)| router.edge_offsets[from] + i)
+}
+
+/// Signed turn angle in degrees at `via` when travelling from -> via -> to
+/// Positive is a right turn, negative a left turn, in (-180, 180]
+/// Bearings are taken along the shapes next to `via`, so a curving chain turns where it meets `via`
+fn turn_angle_deg(data: &RoutingData, from: usize, via: usize, to: usize) -> f64 {
+    let point = |(lon, lat): (f64, f64)| Point::new(lon, lat);
+    let (into, out) = (leg_coords(data, from, via), leg_coords(data, via, to));
+    let bearing_in = Haversine::bearing(point(into[into.len() - 2]), point(into[into.len() - 1]));
+    let bearing_out = Haversine::bearing(point(out[0]), point(out[1]));
+    let angle = (bearing_out - bearing_in).rem_euclid(360.0);
+    if angle > 180.0 {
+        angle - 360.0
+    } else {
+        angle
+    }
+}
+
+/// Classify a signed turn angle into a maneuver type
+fn classify_turn(angle_deg: f64) -> &'static str {
+    let side = |left, right| if angle_deg < 0.0 { left } else { right };
+    match angle_deg.abs() {
+        a if a < 20.0 => "continue",
+        a if a < 45.0 => side("slight_left", "slight_right"),
+        a if a < 135.0 => side("left", "right"),
+        a if a < 170.0 => side("sharp_left", "sharp_right"),
+        _ => "uturn",
+    }
+}
+
+// One turn-by-turn instruction; distance and duration cover the stretch up to the next maneuver
+struct Maneuver {
+    kind: &'static str,
+    name: u32,
+    node: usize,
+    distance_m: f64,
+    duration_s: f64,
+    exit: Option<u32>, // Roundabout exit taken, counting from the entry
+}
+
+/// Group a path into maneuvers: a new maneuver starts where the street name changes
+/// or the path turns by 45 degrees or more; depart and arrive bracket the list.
+/// A roundabout is one maneuver from its entry, named after the street taken at the exit;
+/// turns and name changes on the ring are not reported.
+fn build_maneuvers(router: &Router, path_nodes: &[usize]) -> Vec<Maneuver> {
+    let data = &router.data;
+    let edge_ids: Vec<Option<usize>> = path_nodes.windows(2).map(|w| edge_between(router, w[0], w[1])).collect();
+    let edge_name = |i: usize| edge_ids[i].map(|e| data.edge_info[e].name).unwrap_or(NO_NAME);
+    let on_roundabout = |i: usize| edge_ids[i].is_some_and(|e| data.edge_info[e].roundabout);
+    // Whether a ring node has a way out of the roundabout
+    let is_exit = |node: usize| {
+        let first = router.edge_offsets[node];
+        (first..router.edge_offsets[node + 1]).any(|e| !data.edge_info[e].roundabout)
+    };
+
+    let mut maneuvers = vec![Maneuver {
+        kind: "depart",
+        name: if edge_ids.is_empty() { NO_NAME } else { edge_name(0) },
+        node: path_nodes[0],
+        distance_m: 0.0,
+        duration_s: 0.0,
+        exit: None,
+    }];
+
+    for i in 0..edge_ids.len() {
+        if i > 0 {
+            let current = maneuvers.last_mut().unwrap();
+            let angle = turn_angle_deg(data, path_nodes[i - 1], path_nodes[i], path_nodes[i + 1]);
+            if on_roundabout(i) && !on_roundabout(i - 1) {
+                maneuvers.push(Maneuver {
+                    kind: "roundabout",
+                    name: edge_name(i),
+                    node: path_nodes[i],
+                    distance_m: 0.0,
+                    duration_s: 0.0,
+                    exit: Some(0),
+                });
+            } else if let (true, Some(exit)) = (on_roundabout(i - 1), current.exit.as_mut()) {
+                if is_exit(path_nodes[i]) {
+                    *exit += 1;
+                }
+                if !on_roundabout(i) {
+                    current.name = edge_name(i);
+                }
+            } else if edge_name(i) != edge_name(i - 1) || angle.abs() >= 45.0 {
+                maneuvers.push(Maneuver {
+                    kind: classify_turn(angle),
+                    name: edge_name(i),
+                    node: path_nodes[i],
+                    distance_m: 0.0,
+                    duration_s: 0.0,
+                    exit: None,
+                });
+            }
+        }
+        let current = maneuvers.last_mut().unwrap();
+        current.distance_m += path_distance_m(data, &path_nodes[i..i + 2]);
+        current.duration_s += edge_ids[i].map(|e| edge_base_weight_ms(router, e)).unwrap_or(0) as f64 / 1000.0;
+    }
+
+    maneuvers.push(Maneuver {
+        kind: "arrive",
+        name: NO_NAME,
+        node: *path_nodes.last().unwrap(),
+        distance_m: 0.0,
+        duration_s: 0.0,
+        exit: None,
+    });
+    maneuvers
+}
+
+/// Render maneuvers as the routing_directions JSON document, distances in `unit`
+fn maneuvers_to_json(
+    data: &RoutingData,
+    maneuvers: &[Maneuver],
+    distance_m: f64,
+    duration_s: f64,
+    unit: DistanceUnit,
+) -> String {
+    let items: Vec<serde_json::Value> = maneuvers
+        .iter()
+        .map(|m| {
+            let (lon, lat) = data.node_positions[m.node];
+            serde_json::json!({
+                "type": m.kind,
+                "street": data.names.get(m.name as usize),
+                "distance_m": unit.convert_meters(m.distance_m),
+                "duration_s": m.duration_s,
+                "exit": m.exit,
+                "lat": lat,
+                "lon": lon,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "distance_m": unit.convert_meters(distance_m),
+        "duration_s": duration_s,
+        "maneuvers": items,
+    })
+    .to_string()
+}
+
+/// Render each edge of a path as {from/to lat/lon, distance_m, duration_s, speed_kmh, highway, way_id}
+/// for the routing_route_segments JSON array; durations are scaled by `factor`, distances in `unit`.
+/// speed_kmh is implied by the edge's length and travel time, so it includes barrier delays.
+fn route_segments_to_json(router: &Router, path_nodes: &[usize], factor: f64, unit: DistanceUnit) -> String {
+    let data = &router.data;
+    let segments: Vec<serde_json::Value> = path_nodes
+        .windows(2)
+        .map(|pair| {
+            let edge_id = edge_between(router, pair[0], pair[1]);
+            let info = edge_id.and_then(|e| data.edge_info.get(e));
+            let distance_m = path_distance_m(data, pair);
+            let duration_s = edge_id.map(|e| edge_base_weight_ms(router, e)).unwrap_or(0) as f64 / 1000.0 * factor;
+            let speed_kmh = (duration_s > 0.0).then(|| distance_m / 1000.0 / (duration_s / 3600.0));
+            let (from_lon, from_lat) = data.node_positions[pair[0]];
+            let (to_lon, to_lat) = data.node_positions[pair[1]];
+            serde_json::json!({
+                "from_lat": from_lat,
+                "from_lon": from_lon,
+                "to_lat": to_lat,
+                "to_lon": to_lon,
+                "distance_m": unit.convert_meters(distance_m),
+                "duration_s": duration_s,
+                "speed_kmh": speed_kmh,
+                "highway": info.and_then(|i| HIGHWAY_CLASSES.get(i.highway as usize)),
+                "way_id": info.map(|i| i.way_id),
+            })
+        })
+        .collect();
+    serde_json::Value::Array(segments).to_string()
+}
+
+/// OSM ways a path traverses, in order, each listed once per stretch along it
+/// Edges added at query time (routing_add_edge) have no way and are skipped.
+fn path_way_ids(router: &Router, path_nodes: &[usize]) -> Vec<i64> {
+    let mut way_ids: Vec<i64> = path_nodes
+        .windows(2)
+        .filter_map(|pair| edge_between(router, pair[0], pair[1]))
+        .filter_map(|e| router.data.edge_info.get(e).map(|info| info.way_id))
+        .collect();
+    way_ids.dedup();
+    way_ids
+}
+
+/// OSM way ids along the edge-snapped route between two coordinates (snapped_path), including
+/// the ways of the segments the route starts and ends part-way along
+/// On failure the reason is recorded for routing_last_error
+fn snapped_way_ids(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<Vec<i64>> {
+    let candidates = if MULTI_SNAP.load(AtomicOrdering::Relaxed) { SNAP_CANDIDATES } else { 1 };
+    let Some((origin, dest, path_nodes, _)) = snapped_path(router, lon1, lat1, lon2, lat2, candidates) else {
+        record_route_failure(&router.data, lon1, lat1, lon2, lat2);
+        return None;
+    };
+    let segment_way = |snap: &EdgeSnap| {
+        edge_between(router, snap.from, snap.to)
+            .or_else(|| edge_between(router, snap.to, snap.from))
+            .map(|e| router.data.edge_info[e].way_id)
+    };
+    // A snap right on the path's end node travels none of its segment
+    let leaves = match path_nodes.first() {
+        Some(&first) if first == origin.from => origin.fraction > 0.0,
+        Some(_) => origin.fraction < 1.0,
+        None => true,
+    };
+    let enters = match path_nodes.last() {
+        Some(&last) if last == dest.from => dest.fraction > 0.0,
+        Some(_) => dest.fraction < 1.0,
+        None => true,
+    };
+    let mut way_ids: Vec<i64> = segment_way(&origin)
+        .filter(|_| leaves)
+        .into_iter()
+        .chain(path_way_ids(router, &path_nodes))
+        .chain(segment_way(&dest).filter(|_| enters))
+        .collect();
+    way_ids.dedup();
+    Some(way_ids)
+}
+
+/// Format path nodes as a WKT LINESTRING with `precision` decimal places
+/// A single-node path repeats its point so the LINESTRING stays valid
+fn path_to_wkt(data: &RoutingData, path_nodes: &[usize], precision: usize) -> String {
+    let mut coords: Vec<String> = path_coords(data, path_nodes)
+        .iter()
+        .map(|&(lon, lat)| format!("{:.*} {:.*}", precision, lon, precision, lat))
+        .collect();
+    if coords.len() == 1 {
+        coords.push(coords[0].clone());
+    }
+    format!("LINESTRING({})", coords.join(", "))
+}
+
+/// Format path nodes as a WKT LINESTRING M with one measure per node
+/// Shape points of contracted chains get measures interpolated by length.
+/// Coordinates use `precision` decimal places, measures 3 (mm or ms resolution)
+fn path_to_wkt_m(data: &RoutingData, path_nodes: &[usize], measures: &[f64], precision: usize) -> String {
+    let mut points: Vec<((f64, f64), f64)> = Vec::with_capacity(path_nodes.len());
+    if let (Some(&first), Some(&m)) = (path_nodes.first(), measures.first()) {
+        points.push((data.node_positions[first], m));
+    }
+    for (pair, m) in path_nodes.windows(2).zip(measures.windows(2)) {
+        let coords = leg_coords(data, pair[0], pair[1]);
+        let fractions = length_fractions(&coords);
+        let last = coords.len() - 1;
+        for (k, (coord, f)) in coords.into_iter().zip(fractions).enumerate().skip(1) {
+            points.push((coord, if k == last { m[1] } else { m[0] + (m[1] - m[0]) * f }));
+        }
+    }
+    let mut coords: Vec<String> = points
+        .iter()
+        .map(|&((lon, lat), m)| format!("{:.*} {:.*} {:.3}", precision, lon, precision, lat, m))
+        .collect();
+    if coords.len() == 1 {
+        coords.push(coords[0].clone());
+    }
+    format!("LINESTRING M ({})", coords.join(", "))
+}
+
+/// Copy a string into a caller buffer as a NUL-terminated C string
+/// Returns the string length in bytes; nothing is written if the buffer is too small
+/// (callers detect this by a return value >= buf_len)
+fn write_c_string(s: &str, out_buf: *mut c_char, buf_len: i32) -> i32 {
+    let bytes = s.as_bytes();
+    if !out_buf.is_null() && buf_len > 0 && bytes.len() < buf_len as usize {
+        let out = unsafe { std::slice::from_raw_parts_mut(out_buf as *mut u8, buf_len as usize) };
+        out[..bytes.len()].copy_from_slice(bytes);
+        out[bytes.len()] = 0;
+    }
+    bytes.len() as i32
+}
+
+// ============ Rust API ============
+
+/// Error of the Rust API; the C functions report it through routing_last_error
+#[derive(Debug)]
+pub enum Error {
+    /// Not one of the supported modes ("auto", "bicycle", "pedestrian", "agricultural", "hgv")
+    UnknownMode(String),
+    /// Invalid argument, e.g. a negative vehicle dimension
+    InvalidArgument(String),
+    /// Reading, building or caching the graph failed
+    Load(anyhow::Error),
+    /// The graph has no node to snap a coordinate to
+    NoSnap,
+    /// No route between two coordinates, with the reason
+    NoRoute(String),
+    /// The isochrone origin snapped into a component below the minimum component size
+    /// (routing_set_min_component_size)
+    SmallComponent,
+    /// A latitude outside [-90, 90] or longitude outside [-180, 180], often swapped lat/lon
+    InvalidCoordinate { lat: f64, lon: f64 },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownMode(mode) => write!(f, "Unknown mode '{}' (expected one of {})", mode, MODES.join(", ")),
+            Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Error::Load(e) => write!(f, "{:#}", e),
+            Error::NoSnap => write!(f, "No node to snap to (empty graph)"),
+            Error::NoRoute(reason) => write!(f, "{}", reason),
+            Error::SmallComponent => write!(f, "Origin snapped into a component below the minimum size"),
+            Error::InvalidCoordinate { lat, lon } => write!(
+                f,
+                "Coordinate out of range: lat {}, lon {} (expected lat in [-90, 90] and lon in [-180, 180]; \
+                 are lat and lon swapped?)",
+                lat, lon
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Load(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// Record the error for routing_last_error and return the C status code
+    /// (-3 for SmallComponent, -4 for UnknownMode, -5 for InvalidCoordinate, -1 otherwise)
+    fn record(self) -> i32 {
+        set_last_error(self.to_string());
+        match self {
+            Error::SmallComponent => -3,
+            Error::UnknownMode(_) => -4,
+            Error::InvalidCoordinate { .. } => -5,
+            _ => -1,
+        }
+    }
+}
+
+/// Options for loading a Router, from Router::builder
+/// Starts from the default build settings; the routing_set_* build settings of the C API
+/// don't apply.
+#[derive(Clone, Debug)]
+pub struct RouterBuilder {
+    mode: String,
+    options: BuildOptions,
+    rebuild: bool,
+}
+
+impl RouterBuilder {
+    /// Build from the PBF even if a valid cache exists, overwriting it (routing_reload)
+    pub fn rebuild(mut self, rebuild: bool) -> Self {
+        self.rebuild = rebuild;
+        self
+    }
+
+    /// Leave ferry routes out of the graph (see routing_load_opts)
+    pub fn exclude_ferries(self, exclude: bool) -> Self {
+        self.exclude(EXCLUDE_FERRIES, exclude)
+    }
+
+    /// Leave toll roads out of the graph
+    pub fn exclude_tolls(self, exclude: bool) -> Self {
+        self.exclude(EXCLUDE_TOLLS, exclude)
+    }
+
+    /// Leave unpaved roads out of the graph
+    pub fn exclude_unpaved(self, exclude: bool) -> Self {
+        self.exclude(EXCLUDE_UNPAVED, exclude)
+    }
+
+    fn exclude(mut self, bit: u32, exclude: bool) -> Self {
+        if exclude {
+            self.options.exclude |= bit;
+        } else {
+            self.options.exclude &= !bit;
+        }
+        self
+    }
+
+    /// Leave out ways whose height, width or weight limits the vehicle exceeds
+    /// (see routing_load_vehicle); a dimension of 0 is not checked
+    pub fn vehicle(mut self, height_m: f64, width_m: f64, weight_t: f64) -> Self {
+        let vehicle = Vehicle {
+            height_m,
+            width_m,
+            weight_t,
+        };
+        self.options.vehicle = (vehicle != Vehicle::default()).then_some(vehicle);
+        self
+    }
+
+    /// Drop dead-end spurs shorter than `meters` (see routing_set_build_min_stub_length); 0,
+    /// the default, keeps them. Graphs pruned this way are cached separately.
+    pub fn min_stub_length(mut self, meters: f64) -> Self {
+        self.options.min_stub_length_m = meters;
+        self
+    }
+
+    /// zstd level (1-22) of a newly written cache, 0 for uncompressed (the default)
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.options.compression_level = level;
+        self
+    }
+
+    /// Load the graph built from a PBF, from its cache if present and valid, otherwise
+    /// building and caching it next to the PBF like routing_load
+    pub fn load(self, pbf_path: &str) -> Result<Router, Error> {
+        if canonical_mode(&self.mode).is_none() {
+            return Err(Error::UnknownMode(self.mode));
+        }
+        if let Some(v) = self.options.vehicle {
+            // Also rejects NaN
+            if ![v.height_m, v.width_m, v.weight_t].iter().all(|&d| d.is_finite() && d >= 0.0) {
+                return Err(Error::InvalidArgument("vehicle dimensions must be finite and >= 0".into()));
+            }
+        }
+        if !(0..=22).contains(&self.options.compression_level) {
+            return Err(Error::InvalidArgument("compression level must be 0-22".into()));
+        }
+        if !(self.options.min_stub_length_m.is_finite() && self.options.min_stub_length_m >= 0.0) {
+            return Err(Error::InvalidArgument("minimum stub length must be finite and >= 0".into()));
+        }
+        self.load_with_progress(pbf_path, &Progress::NONE)
+    }
+
+    fn load_with_progress(&self, pbf_path: &str, progress: &Progress) -> Result<Router, Error> {
+        let (mode, options) = (self.mode.as_str(), &self.options);
+        let data = cached_graph(&pbf_set_key(&[pbf_path]), mode, options, self.rebuild, progress, |options, progress| {
+            build_graph_for_mode(&[pbf_path], mode, options, progress)
+        })
+        .map_err(Error::Load)?;
+        let mut router = Router::new(data);
+        router.mode = canonical_mode(mode).unwrap_or("auto");
+        router.source_path = Some(pbf_path.to_string());
+        Ok(router)
+    }
+}
+
+/// Route from Router::route
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub distance_m: f64,
+    pub duration_s: f64,
+    pub points: Vec<RoutePoint>,
+}
+
+impl Router {
+    /// Builder for loading a graph of `mode` with non-default options
+    pub fn builder(mode: &str) -> RouterBuilder {
+        RouterBuilder {
+            mode: mode.to_string(),
+            options: BuildOptions::default(),
+            rebuild: false,
+        }
+    }
+
+    /// Load the graph of `mode` built from a PBF, using its cache next to the PBF if present
+    /// and valid, otherwise building and writing it
+    pub fn load(pbf_path: &str, mode: &str) -> Result<Router, Error> {
+        Router::builder(mode).load(pbf_path)
+    }
+
+    /// The router's mode
+    pub fn mode(&self) -> &str {
+        self.mode
+    }
+
+    /// Travel time in seconds between two points, as routing_travel_time
+    /// Durations are scaled by the mode's speed factor (routing_set_speed_factor).
+    pub fn travel_time(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Result<f64, Error> {
+        self.travel_time_ex(lat1, lon1, lat2, lon2, |_, _| {})
+    }
+
+    /// travel_time that passes the origin and destination snap distances in meters to
+    /// `on_snap` once both points have snapped, even if no route is found
+    fn travel_time_ex(
+        &self,
+        lat1: f64,
+        lon1: f64,
+        lat2: f64,
+        lon2: f64,
+        on_snap: impl FnOnce(f64, f64),
+    ) -> Result<f64, Error> {
+        let no_route = || Error::NoRoute(route_failure_reason(&self.data, lon1, lat1, lon2, lat2));
+        let (origin, dest) = match (find_nearest_edge(self, lon1, lat1), find_nearest_edge(self, lon2, lat2)) {
+            (Some(o), Some(d)) => (o, d),
+            _ => return Err(no_route()),
+        };
+        on_snap(origin.distance_m, dest.distance_m);
+
+        match route_between_snaps(self, &origin, &dest) {
+            Some((_, ms)) => Ok(ms as f64 / 1000.0 * duration_factor(self.mode)),
+            None => Err(no_route()),
+        }
+    }
+
+    /// Whether two points snap to roads in the same connected component, as
+    /// routing_same_component. Points in different components never have a route.
+    pub fn same_component(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Result<bool, Error> {
+        match (find_nearest_edge(self, lon1, lat1), find_nearest_edge(self, lon2, lat2)) {
+            // Both ends of a snapped segment share its component
+            (Some(o), Some(d)) => Ok(self.data.component_ids[o.from] == self.data.component_ids[d.from]),
+            _ => Err(Error::NoSnap),
+        }
+    }
+
+    /// Route with full geometry between two points, as routing_route
+    /// The distance is in meters whatever routing_set_distance_unit chose.
+    pub fn route(&self, lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Result<Route, Error> {
+        let (coords, duration_ms) = snapped_route(self, lon1, lat1, lon2, lat2)
+            .ok_or_else(|| Error::NoRoute(route_failure_reason(&self.data, lon1, lat1, lon2, lat2)))?;
+        Ok(Route {
+            distance_m: coords_length_m(&coords),
+            duration_s: duration_ms as f64 / 1000.0 * duration_factor(self.mode),
+            points: coords.into_iter().map(|(lon, lat)| RoutePoint { lat, lon }).collect(),
+        })
+    }
+
+    /// Nodes reachable from a point within max_seconds with their travel times, as
+    /// routing_isochrone
+    pub fn isochrone(&self, lat: f64, lon: f64, max_seconds: f64) -> Result<Vec<IsochroneResult>, Error> {
+        self.isochrone_bounded(lat, lon, max_seconds, usize::MAX, None).map(|(reached, _)| reached)
+    }
+
+    /// isochrone that stops after settling max_settled nodes or at the deadline, also
+    /// returning whether a bound cut the search short
+    fn isochrone_bounded(
+        &self,
+        lat: f64,
+        lon: f64,
+        max_seconds: f64,
+        max_settled: usize,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<(Vec<IsochroneResult>, bool), Error> {
+        let start_idx = find_nearest_node(&self.data, lon, lat).ok_or(Error::NoSnap)?;
+
+        let min_component_size = MIN_ISOCHRONE_COMPONENT_SIZE.load(AtomicOrdering::Relaxed);
+        let component = self.data.component_ids[start_idx] as usize;
+        if self.component_sizes[component] < min_component_size {
+            return Err(Error::SmallComponent);
+        }
+
+        let factor = duration_factor(self.mode);
+        let max_cost_ms = budget_ms(max_seconds, factor);
+        let (reached, incomplete) = isochrone_nodes_capped(&self.data, start_idx, max_cost_ms, max_settled, deadline);
+        let reached = reached
+            .into_iter()
+            .map(|(node, cost)| {
+                let (lon, lat) = self.data.node_positions[node];
+                IsochroneResult {
+                    lat,
+                    lon,
+                    seconds: cost as f64 / 1000.0 * factor,
+                }
+            })
+            .collect();
+        Ok((reached, incomplete))
+    }
+
+    /// Travel times in seconds from every origin to every destination, both as (lat, lon),
+    /// as routing_matrix: one row per origin, None where unroutable
+    pub fn matrix(&self, origins: &[(f64, f64)], destinations: &[(f64, f64)]) -> Result<Vec<Vec<Option<f64>>>, Error> {
+        if destinations.is_empty() {
+            return Ok(vec![Vec::new(); origins.len()]);
+        }
+        let mut cells = vec![-1.0; origins.len() * destinations.len()];
+        self.matrix_into(origins, destinations, &mut cells, &mut [], &mut []);
+        let rows = cells.chunks(destinations.len());
+        Ok(rows.map(|row| row.iter().map(|&s| (s >= 0.0).then_some(s)).collect()).collect())
+    }
+
+    /// matrix into a row-major slice, -1.0 where unroutable; returns the number of routed cells
+    /// Each point's snap distance in meters (-1.0 if unsnapped) goes to its slot of
+    /// origin_snap_m or dest_snap_m, where the slice is long enough (empty to skip).
+    fn matrix_into(
+        &self,
+        origins: &[(f64, f64)],
+        destinations: &[(f64, f64)],
+        results: &mut [f64],
+        origin_snap_m: &mut [f64],
+        dest_snap_m: &mut [f64],
+    ) -> i32 {
+        let snap = |points: &[(f64, f64)], snap_m: &mut [f64]| -> Vec<Option<usize>> {
+            let mut slots = snap_m.iter_mut();
+            points
+                .iter()
+                .map(|&(lat, lon)| {
+                    let node = find_nearest_node(&self.data, lon, lat);
+                    if let Some(slot) = slots.next() {
+                        *slot = node.map_or(-1.0, |idx| {
+                            let (node_lon, node_lat) = self.data.node_positions[idx];
+                            Haversine::distance(Point::new(lon, lat), Point::new(node_lon, node_lat))
+                        });
+                    }
+                    node
+                })
+                .collect()
+        };
+        let (sources, targets) = (snap(origins, origin_snap_m), snap(destinations, dest_snap_m));
+        fill_matrix(&self.data, &sources, &targets, duration_factor(self.mode), results)
+    }
+}
+
+// ============ C FFI ============
+
+/// Load routing data - uses cache if available, builds and caches otherwise
+#[no_mangle]
+pub extern "C" fn routing_load(pbf_path: *const c_char, mode: *const c_char) -> i32 {
+    routing_load_ex(pbf_path, mode, 0)
+}
+
+/// Rebuild routing data from the PBF, ignoring and overwriting any cache (e.g. after an
+/// OSM update). Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_reload(pbf_path: *const c_char, mode: *const c_char) -> i32 {
+    routing_load_ex(pbf_path, mode, 1)
+}
+
+/// routing_load that rebuilds from the PBF, ignoring and overwriting any cache, if
+/// force_rebuild is non-zero. Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_load_ex(pbf_path: *const c_char, mode: *const c_char, force_rebuild: i32) -> i32 {
+    load_pbf(pbf_path, mode, force_rebuild != 0, &Progress::NONE)
+}
+
+/// routing_load that calls `callback` (if not NULL) on the calling thread as loading
+/// progresses, with the phase ("loading", "reading", "building", "contracting", "writing",
+/// "done") and an overall percentage. Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_load_progress(
+    pbf_path: *const c_char,
+    mode: *const c_char,
+    callback: Option<ProgressCallback>,
+    user_data: *mut c_void,
+) -> i32 {
+    load_pbf(pbf_path, mode, false, &Progress { callback, user_data })
+}
+
+fn load_pbf(pbf_path: *const c_char, mode: *const c_char, rebuild: bool, progress: &Progress) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let builder = RouterBuilder {
+        mode: mode.to_string(),
+        options: BuildOptions::from_settings(),
+        rebuild,
+    };
+    match builder.load_with_progress(pbf_path, progress) {
+        Ok(router) => {
+            let installed = install(mode, router);
+            progress.report(c"done", 100);
+            installed
+        }
+        Err(e) => e.record(),
+    }
+}
+
+/// routing_load that keeps the graph under `handle` instead of the mode's router, so graphs of
+/// several regions (or several of one mode) stay loaded side by side. Loading an existing
+/// handle replaces its graph. Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_load_named(handle: *const c_char, pbf_path: *const c_char, mode: *const c_char) -> i32 {
+    let handle = match unsafe { CStr::from_ptr(handle) }.to_str() {
+        Ok(s) if !handle.is_null() => s,
+        _ => return -1,
+    };
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let builder = RouterBuilder {
+        mode: mode.to_string(),
+        options: BuildOptions::from_settings(),
+        rebuild: false,
+    };
+    match builder.load_with_progress(pbf_path, &Progress::NONE) {
+        Ok(router) => install_named(handle, router),
+        Err(e) => e.record(),
+    }
+}
+
+/// Keep a router under a handle, replacing any graph already there
+/// Returns 0 on success, -1 on error
+fn install_named(handle: &str, router: Router) -> i32 {
+    if let Ok(mut routers) = NAMED_ROUTERS.write() {
+        routers.insert(handle.to_string(), Arc::new(router));
+        0
+    } else {
+        set_last_error("Named routers are poisoned by an earlier panic");
+        -1
+    }
+}
+
+/// The router loaded under a handle, or the not-loaded status (-2, recorded for
+/// routing_last_error); -1 for a NULL or invalid handle
+fn named_router(handle: *const c_char) -> Result<Arc<Router>, i32> {
+    if handle.is_null() {
+        return Err(-1);
+    }
+    let name = unsafe { CStr::from_ptr(handle) }.to_str().map_err(|_| -1)?;
+    let routers = NAMED_ROUTERS.read().map_err(|_| -1)?;
+    routers.get(name).cloned().ok_or_else(|| {
+        set_last_error(format!("No routing graph loaded under handle '{}'", name));
+        -2
+    })
+}
+
+/// Load one graph built from several PBF files (e.g. neighbouring regional extracts)
+/// Nodes are merged by OSM id so ways crossing between files connect. The merged graph is
+/// cached next to the first file (in sorted order) under a hash of the sorted file list.
+/// Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_load_multi(pbf_paths: *const *const c_char, count: i32, mode: *const c_char) -> i32 {
+    if pbf_paths.is_null() || count <= 0 {
+        return -1;
+    }
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let pointers = unsafe { std::slice::from_raw_parts(pbf_paths, count as usize) };
+    let mut paths = Vec::with_capacity(pointers.len());
+    for &path in pointers {
+        match unsafe { CStr::from_ptr(path) }.to_str() {
+            Ok(s) if !path.is_null() => paths.push(s),
+            _ => return -1,
+        }
+    }
+
+    load_with_options(&paths, mode, &BuildOptions::from_settings())
+}
+
+/// Load routing data with a custom speed profile (JSON object of highway type -> km/h,
+/// merged on top of the built-in speeds, with optional "surface" multipliers and
+/// "penalties" rules).
+/// Graphs are cached per profile.
+/// Returns 0 on success, -1 on error (including an unreadable or invalid profile)
+#[no_mangle]
+pub extern "C" fn routing_load_profile(
+    pbf_path: *const c_char,
+    mode: *const c_char,
+    profile_json_path: *const c_char,
+) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    let profile_json_path = match unsafe { CStr::from_ptr(profile_json_path) }.to_str() {
+        Ok(s) if !profile_json_path.is_null() => s,
+        _ => return -1,
+    };
+
+    let profile = match std::fs::read_to_string(profile_json_path)
+        .context("Could not read profile file")
+        .and_then(|text| Profile::from_json(&text))
+    {
+        Ok(p) => p,
+        Err(e) => {
+            set_last_error(format!("{:#}", e));
+            return -1;
+        }
+    };
+
+    let options = BuildOptions {
+        profile,
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// Load routing data for the part of a PBF inside a bounding box
+/// Road segments entirely outside the box are dropped; segments crossing its edge are kept.
+/// Each box gets its own cache file. Returns 0 on success, -1 on error (including an empty box)
+#[no_mangle]
+pub extern "C" fn routing_load_bbox(
+    pbf_path: *const c_char,
+    mode: *const c_char,
+    min_lon: f64,
+    min_lat: f64,
+    max_lon: f64,
+    max_lat: f64,
+) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    // Also rejects NaN
+    if !(min_lon <= max_lon && min_lat <= max_lat) {
+        return -1;
+    }
+    if let Err(code) = coords_arg([(min_lat, min_lon), (max_lat, max_lon)]) {
+        return code;
+    }
+
+    let options = BuildOptions {
+        bbox: Some((min_lon, min_lat, max_lon, max_lat)),
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// Load routing data with grade-dependent bicycle and pedestrian speeds
+/// Grades come from incline tags and, if dem_path is not NULL, an ESRI ASCII grid (.asc) of
+/// terrain heights in WGS84. Graphs are cached per DEM contents.
+/// Returns 0 on success, -1 on error (including an unreadable or invalid DEM)
+#[no_mangle]
+pub extern "C" fn routing_load_elevation(pbf_path: *const c_char, mode: *const c_char, dem_path: *const c_char) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    let dem_path = if dem_path.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(dem_path) }.to_str() {
+            Ok(s) => Some(s),
+            Err(_) => return -1,
+        }
+    };
+
+    let dem = dem_path.map(|path| {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read elevation model {}", path))
+            .and_then(|text| Ok((fnv1a(text.bytes()), ElevationGrid::from_ascii(&text)?)))
+    });
+    let (dem_hash, grid) = match dem.transpose() {
+        Ok(Some((dem_hash, grid))) => (dem_hash, Some(grid)),
+        Ok(None) => (0, None),
+        Err(e) => {
+            set_last_error(format!("{:#}", e));
+            return -1;
+        }
+    };
+
+    let options = BuildOptions {
+        elevation: Some(Arc::new(Elevation { dem_hash, grid })),
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// Load routing data built without the road classes in `exclude`, a bitmask of
+/// 1 (ferries), 2 (toll roads) and 4 (unpaved: tracks and unsealed surfaces)
+/// Each combination gets its own cache file. Returns 0 on success, -1 on error (including
+/// unknown bits)
+#[no_mangle]
+pub extern "C" fn routing_load_opts(pbf_path: *const c_char, mode: *const c_char, exclude: i32) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    if exclude < 0 || exclude as u32 & !EXCLUDE_ALL != 0 {
+        set_last_error(format!("Unknown exclude bits in {}", exclude));
+        return -1;
+    }
+
+    let options = BuildOptions {
+        exclude: exclude as u32,
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// Load routing data for a vehicle of the given size, leaving out ways whose maxheight,
+/// maxwidth or maxweight is below it (a dimension of 0 is not checked)
+/// Each vehicle gets its own cache file. Returns 0 on success, -1 on error (including
+/// negative or non-finite dimensions)
+#[no_mangle]
+pub extern "C" fn routing_load_vehicle(
+    pbf_path: *const c_char,
+    mode: *const c_char,
+    height_m: f64,
+    width_m: f64,
+    weight_t: f64,
+) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    // Also rejects NaN
+    if ![height_m, width_m, weight_t].iter().all(|&d| d.is_finite() && d >= 0.0) {
+        return -1;
+    }
+
+    let vehicle = Vehicle {
+        height_m,
+        width_m,
+        weight_t,
+    };
+    let options = BuildOptions {
+        vehicle: (vehicle != Vehicle::default()).then_some(vehicle),
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// routing_load that writes a newly built cache compressed with zstd at `level` (1-22,
+/// 0 for uncompressed). Compressed caches load into memory instead of being mapped.
+/// Returns 0 on success, -1 on error (including an invalid level)
+#[no_mangle]
+pub extern "C" fn routing_load_compressed(pbf_path: *const c_char, mode: *const c_char, level: i32) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    if !(0..=22).contains(&level) {
+        set_last_error(format!("Invalid compression level {} (expected 0 to 22)", level));
+        return -1;
+    }
+
+    let options = BuildOptions {
+        compression_level: level,
+        ..BuildOptions::from_settings()
+    };
+    load_with_options(&[pbf_path], mode, &options)
+}
+
+/// Load several modes from one PBF in one call, e.g. at service startup so no query waits on
+/// a first load. `modes_csv` lists the modes separated by commas ("auto,bicycle,pedestrian").
+/// Modes with a valid cache load from it; the others are built in parallel from a single
+/// read of the PBF.
+/// Returns a bitmask of the modes loaded, bit i for MODES[i] (auto 1, bicycle 2,
+/// pedestrian 4, agricultural 8, hgv 16), or -1 on error (including an unknown mode).
+/// Modes that failed are recorded for routing_last_error
+#[no_mangle]
+pub extern "C" fn routing_preload(pbf_path: *const c_char, modes_csv: *const c_char) -> i32 {
+    let pbf_path = match unsafe { CStr::from_ptr(pbf_path) }.to_str() {
+        Ok(s) if !pbf_path.is_null() => s,
+        _ => return -1,
+    };
+    let modes_csv = match unsafe { CStr::from_ptr(modes_csv) }.to_str() {
+        Ok(s) if !modes_csv.is_null() => s,
+        _ => return -1,
+    };
+    // Indices into MODES, which are also the modes' bits in the result
+    let mut bits: Vec<usize> = Vec::new();
+    for name in modes_csv.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match MODES.iter().position(|&m| m == name) {
+            Some(bit) if !bits.contains(&bit) => bits.push(bit),
+            Some(_) => {}
+            None => return Error::UnknownMode(name.to_string()).record(),
+        }
+    }
+    if bits.is_empty() {
+        set_last_error("No modes to preload".to_string());
+        return -1;
+    }
+
+    let options = BuildOptions::from_settings();
+    let modes: Vec<&str> = bits.iter().map(|&bit| MODES[bit]).collect();
+    let mut loaded = 0;
+    let mut errors = Vec::new();
+    for ((&bit, mode), data) in bits.iter().zip(&modes).zip(load_modes(pbf_path, &modes, &options)) {
+        match data.map(|data| install_router(mode, data, Some(pbf_path))) {
+            Ok(0) => loaded |= 1 << bit,
+            Ok(_) => errors.push(format!("{}: could not install the router", mode)),
+            Err(e) => errors.push(format!("{}: {:#}", mode, e)),
+        }
+    }
+    if !errors.is_empty() {
+        set_last_error(errors.join("; "));
+    }
+    loaded
+}
+
+/// Load from the options' cache file if present and valid, otherwise build and cache
+/// Returns 0 on success, -1 on error (the reason is recorded for routing_last_error)
+fn load_with_options(pbf_paths: &[&str], mode: &str, options: &BuildOptions) -> i32 {
+    let source_path = pbf_paths.join(";");
+    let key = pbf_set_key(pbf_paths);
+    load_cached(&key, mode, options, Some(&source_path), false, &Progress::NONE, |options, progress| {
+        build_graph_for_mode(pbf_paths, mode, options, progress)
+    })
+}
+
+/// Load the cached graph for `cache_key` (see cache_path_for), building and caching it if
+/// there is no usable cache or `rebuild` is set
+fn load_cached(
+    cache_key: &str,
+    mode: &str,
+    options: &BuildOptions,
+    source_path: Option<&str>,
+    rebuild: bool,
+    progress: &Progress,
+    build: impl FnOnce(&BuildOptions, &Progress) -> Result<RoutingData>,
+) -> i32 {
+    let data = match cached_graph(cache_key, mode, options, rebuild, progress, build) {
+        Ok(d) => d,
+        Err(e) => {
+            set_last_error(format!("{:#}", e));
+            return -1;
+        }
+    };
+
+    let installed = install_router(mode, data, source_path);
+    progress.report(c"done", 100);
+    installed
+}
+
+/// The graph load_cached installs: read from the cache, or built (by `build`, with the options
+/// to build with) and written to it
+/// A rebuild over a valid cache reuses its contraction orders (ChOrder).
+fn cached_graph(
+    cache_key: &str,
+    mode: &str,
+    options: &BuildOptions,
+    rebuild: bool,
+    progress: &Progress,
+    build: impl FnOnce(&BuildOptions, &Progress) -> Result<RoutingData>,
+) -> Result<RoutingData> {
+    let cache = cache_path_for(cache_key, mode, options);
+    let header = CacheHeader::new(mode, options);
+    // A stale, foreign or corrupt cache is rebuilt and overwritten
+    let cached = if !rebuild && Path::new(&cache).exists() {
+        progress.report(c"loading", 0);
+        load_graph(&cache, &header).ok()
+    } else {
+        None
+    };
+    match cached {
+        Some(d) => Ok(d),
+        None => {
+            let prior_order = match &options.prior_order {
+                None if rebuild && Path::new(&cache).exists() => {
+                    load_graph(&cache, &header).ok().map(|old| Arc::new(ChOrder::of(&old)))
+                }
+                prior_order => prior_order.clone(),
+            };
+            let options = BuildOptions {
+                prior_order,
+                ..options.clone()
+            };
+            let d = build(&options, progress)?;
+            progress.report(c"writing", PROGRESS_WRITING);
+            let _ = save_graph(&d, &header, &cache, options.compression_level);
+            Ok(d)
+        }
+    }
+}
+
+/// cached_graph for each of `modes` of one PBF, in the same order
+/// Each mode loads on a thread of its own, so a mode reading its cache never waits behind
+/// another's build, and the builds' parallel work still runs on the routing_set_threads pool
+/// (in_thread_pool) rather than occupying its workers with whole builds. The PBF is read at
+/// most once, by the first mode that needs it, and shared by the others.
+fn load_modes(pbf_path: &str, modes: &[&str], options: &BuildOptions) -> Vec<Result<RoutingData>> {
+    let osm: OnceLock<Result<OsmData, String>> = OnceLock::new();
+    let cache_key = pbf_set_key(&[pbf_path]);
+    std::thread::scope(|scope| {
+        let loads: Vec<_> = modes
+            .iter()
+            .map(|&mode| {
+                let (osm, cache_key) = (&osm, &cache_key);
+                scope.spawn(move || {
+                    // Progress is per thread, and these loads report to no callback anyway
+                    cached_graph(cache_key, mode, options, false, &Progress::NONE, |options, progress| {
+                        let osm = osm.get_or_init(|| read_osm(pbf_path).map_err(|e| format!("{:#}", e)));
+                        match osm {
+                            Ok(osm) => Ok(build_graph_with_progress(osm, mode, options, progress)),
+                            Err(e) => Err(anyhow::anyhow!("{}", e)),
+                        }
+                    })
+                })
+            })
+            .collect();
+        loads
+            .into_iter()
+            .map(|load| load.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Graph build panicked"))))
+            .collect()
+    })
+}
+
+/// Directory for the caches of PBFs held in memory, private to the user so no one else can
+/// plant a graph there: $XDG_CACHE_HOME/duckdb-routing, ~/.cache/duckdb-routing or
+/// %LOCALAPPDATA%\duckdb-routing. Nothing in it is removed automatically.
+/// None if there is no such base directory or it can't be created
+fn buffer_cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    private_cache_dir(&base)
+}
+
+/// The duckdb-routing directory under `base`, created readable by the user only
+fn private_cache_dir(base: &Path) -> Option<PathBuf> {
+    let dir = base.join("duckdb-routing");
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir).ok()?;
+    Some(dir)
+}
+
+/// Cache key for a PBF held in memory: a file in `dir` (buffer_cache_dir) named by the SHA-256
+/// of the contents and their length, so loading identical bytes again reuses the cached graph
+fn buffer_cache_key(dir: &Path, digest: &[u8; 32], len: usize) -> String {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    dir.join(format!("routing-buffer-{}-{}", hex, len)).to_string_lossy().into_owned()
+}
+
+/// Load routing data from PBF bytes in memory, e.g. received over the network
+/// The graph is cached in the user's cache directory (buffer_cache_dir) under a digest of the
+/// bytes, which the cache header repeats, so repeated loads of the same data skip the build.
+/// Without a cache directory the graph is built every time. Returns 0 on success, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_load_buffer(data: *const u8, len: i64, mode: *const c_char) -> i32 {
+    if data.is_null() || len <= 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let bytes = unsafe { std::slice::from_raw_parts(data, len as usize) };
+    let digest = sha256(bytes);
+    let options = BuildOptions {
+        source_digest: Some(digest),
+        ..BuildOptions::from_settings()
+    };
+    let build = |options: &BuildOptions, _: &Progress| Ok(build_graph_from_osm(&read_osm_bytes(bytes)?, mode, options));
+    match buffer_cache_dir() {
+        Some(dir) => {
+            let key = buffer_cache_key(&dir, &digest, bytes.len());
+            load_cached(&key, mode, &options, None, false, &Progress::NONE, build)
+        }
+        None => match build(&options, &Progress::NONE) {
+            Ok(data) => install_router(mode, data, None),
+            Err(e) => {
+                set_last_error(format!("{:#}", e));
+                -1
+            }
+        },
+    }
+}
+
+/// Make routing data the active router for a mode
+/// Returns 0 on success, -1 on error
+fn install_router(mode: &str, data: RoutingData, source_path: Option<&str>) -> i32 {
+    let mut router = Router::new(data);
+    router.source_path = source_path.map(str::to_string);
+    install(mode, router)
+}
+
+/// Make a router the active router for a mode
+/// Returns 0 on success, -1 on error
+fn install(mode: &str, mut router: Router) -> i32 {
+    // Unknown modes share the auto router (see get_router_for_mode)
+    router.mode = canonical_mode(mode).unwrap_or("auto");
+    if let Ok(mut guard) = get_router_for_mode(mode).write() {
+        *guard = Some(router);
+        0
+    } else {
+        set_last_error(format!("Router for mode '{}' is poisoned by an earlier panic", mode));
+        -1
+    }
+}
+
+/// Load routing data from serialized `.routing` cache bytes (no filesystem access)
+/// The cache must have been written by this version for the same mode; any speed profile
+/// is accepted. Returns 0 on success, -1 on error (invalid, stale or corrupt data; the
+/// reason is recorded for routing_last_error)
+#[no_mangle]
+pub extern "C" fn routing_load_cache_bytes(bytes: *const u8, len: i32, mode: *const c_char) -> i32 {
+    if bytes.is_null() || len <= 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let bytes = unsafe { std::slice::from_raw_parts(bytes, len as usize) };
+    match read_graph(bytes, mode, None) {
+        Ok(data) => install_router(mode, data, None),
+        Err(e) => {
+            set_last_error(format!("{:#}", e));
+            -1
+        }
+    }
+}
+
+/// Calculate travel time in seconds between two points
+/// Both points are snapped onto the nearest road segment and the partial segment
+/// times to and from the route's first and last node are included
+#[no_mangle]
+pub extern "C" fn routing_travel_time(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+) -> f64 {
+    routing_travel_time_ex(lat1, lon1, lat2, lon2, mode, std::ptr::null_mut(), std::ptr::null_mut())
+}
+
+/// routing_travel_time that also reports how far each point is from the road it snapped to
+/// The snap distances (meters) are written when both points snap, even if no route is found,
+/// and are -1 otherwise; either output pointer may be NULL
+#[no_mangle]
+pub extern "C" fn routing_travel_time_ex(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_origin_snap_m: *mut f64,
+    out_dest_snap_m: *mut f64,
+) -> f64 {
+    let write_snaps = |origin_m: f64, dest_m: f64| unsafe {
+        if !out_origin_snap_m.is_null() {
+            *out_origin_snap_m = origin_m;
+        }
+        if !out_dest_snap_m.is_null() {
+            *out_dest_snap_m = dest_m;
+        }
+    };
+    write_snaps(-1.0, -1.0);
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code as f64;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as f64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1.0,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode) as f64,
+    };
+
+    router
+        .travel_time_ex(lat1, lon1, lat2, lon2, write_snaps)
+        .unwrap_or_else(|e| e.record() as f64)
+}
+
+/// routing_travel_time that falls back to a great-circle estimate (great_circle_estimate_s)
+/// when there is no route, setting *out_estimated (may be NULL) to 1 for an estimate and 0
+/// for a routed time
+/// Returns the travel time in seconds, or -1.0 on error, -2.0 if not loaded, -4.0 for an
+/// unknown mode
+#[no_mangle]
+pub extern "C" fn routing_travel_time_or_estimate(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_estimated: *mut i32,
+) -> f64 {
+    let write_estimated = |estimated: bool| {
+        if !out_estimated.is_null() {
+            unsafe { *out_estimated = i32::from(estimated) };
+        }
+    };
+    write_estimated(false);
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code as f64;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as f64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1.0,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode) as f64,
+    };
+
+    match router.travel_time(lat1, lon1, lat2, lon2) {
+        Ok(seconds) => seconds,
+        Err(Error::NoRoute(_) | Error::NoSnap) => {
+            write_estimated(true);
+            great_circle_estimate_s(mode, lon1, lat1, lon2, lat2) * duration_factor(mode)
+        }
+        Err(e) => e.record() as f64,
+    }
+}
+
+/// routing_travel_time on the graph loaded under `handle` (routing_load_named), in the mode
+/// it was loaded for
+/// Returns the travel time in seconds, or -1.0 on error, -2.0 if nothing is loaded under the
+/// handle
+#[no_mangle]
+pub extern "C" fn routing_travel_time_named(
+    handle: *const c_char,
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+) -> f64 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code as f64;
+    }
+    let router = match named_router(handle) {
+        Ok(r) => r,
+        Err(code) => return code as f64,
+    };
+    router
+        .travel_time(lat1, lon1, lat2, lon2)
+        .unwrap_or_else(|e| e.record() as f64)
+}
+
+/// Road distance in meters and duration in ms of the snapped route between two coordinates,
+/// measured the same way as routing_route but without handing out the geometry
+fn snapped_cost(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<(f64, u64)> {
+    snapped_route(router, lon1, lat1, lon2, lat2).map(|(coords, duration_ms)| (coords_length_m(&coords), duration_ms))
+}
+
+/// Calculate both travel time and road distance between two points
+/// Same snapping and route as routing_route, for callers that need only the two numbers
+/// Returns 0 on success, -1 on error or if no route found, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_cost(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_seconds: *mut f64,
+    out_meters: *mut f64,
+) -> i32 {
+    if out_seconds.is_null() || out_meters.is_null() {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    match snapped_cost(router, lon1, lat1, lon2, lat2) {
+        Some((meters, duration_ms)) => {
+            unsafe {
+                *out_seconds = duration_ms as f64 / 1000.0 * duration_factor(mode);
+                *out_meters = meters;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Write the supported mode names, comma-separated in MODES order ("auto,bicycle,...")
+/// Returns the string length (nothing is written if >= buf_len)
+#[no_mangle]
+pub extern "C" fn routing_supported_modes(out_buf: *mut c_char, buf_len: i32) -> i32 {
+    write_c_string(&MODES.join(","), out_buf, buf_len)
+}
+
+/// Check if routing data is loaded
+#[no_mangle]
+pub extern "C" fn routing_is_loaded(mode: *const c_char) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(_) => return 0,
+    };
+
+    match get_router_for_mode(mode).read() {
+        Ok(guard) => i32::from(guard.is_some()),
+        Err(_) => 0,
+    }
+}
+
+/// Free routing data
+#[no_mangle]
+pub extern "C" fn routing_free(mode: *const c_char) {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+
+    if let Ok(mut guard) = get_router_for_mode(mode).write() {
+        *guard = None;
+    }
+}
+
+/// Free routing data of every mode
+#[no_mangle]
+pub extern "C" fn routing_free_all() {
+    for mode in MODES {
+        if let Ok(mut guard) = get_router_for_mode(mode).write() {
+            *guard = None;
+        }
+    }
+}
+
+/// Free the graph loaded under `handle`; queries still running on it finish first
+/// Returns 0 if a graph was freed, -2 if nothing is loaded under the handle, -1 on error
+#[no_mangle]
+pub extern "C" fn routing_free_named(handle: *const c_char) -> i32 {
+    if handle.is_null() {
+        return -1;
+    }
+    let Ok(name) = unsafe { CStr::from_ptr(handle) }.to_str() else {
+        return -1;
+    };
+    match NAMED_ROUTERS.write() {
+        Ok(mut routers) => match routers.remove(name) {
+            Some(_) => 0,
+            None => -2,
+        },
+        Err(_) => -1,
+    }
+}
+
+/// Path of the PBF file the mode's graph was built from
+/// Graphs loaded from in-memory cache bytes have no source path (empty string)
+/// Returns the path length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_source_path(mode: *const c_char, out_buf: *mut c_char, buf_len: i32) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    match get_router_for_mode(mode).read() {
+        Ok(guard) => match guard.as_ref() {
+            Some(r) => write_c_string(r.source_path.as_deref().unwrap_or(""), out_buf, buf_len),
+            None => not_loaded(mode),
+        },
+        Err(_) => -1,
+    }
+}
+
+/// routing_batch that gives pairs without a route a great-circle estimate instead of a
+/// sentinel (great_circle_estimate_s), so every result is a travel time; out_estimated (may
+/// be NULL, else `count` ints) marks estimated pairs with 1 and routed ones with 0
+/// Returns number of routed (not estimated) pairs, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_batch_or_estimate(
+    lats1: *const f64,
+    lons1: *const f64,
+    lats2: *const f64,
+    lons2: *const f64,
+    results: *mut f64,
+    count: i32,
+    mode: *const c_char,
+    out_estimated: *mut i32,
+) -> i32 {
+    let routed = routing_batch(lats1, lons1, lats2, lons2, results, count, mode);
+    if routed < 0 {
+        return routed;
+    }
+
+    // routing_batch validated the arguments and the mode
+    let Ok(mode) = mode_arg(mode) else {
+        return -1;
+    };
+    let count = count as usize;
+    let lats1 = unsafe { std::slice::from_raw_parts(lats1, count) };
+    let lons1 = unsafe { std::slice::from_raw_parts(lons1, count) };
+    let lats2 = unsafe { std::slice::from_raw_parts(lats2, count) };
+    let lons2 = unsafe { std::slice::from_raw_parts(lons2, count) };
+    let results = unsafe { std::slice::from_raw_parts_mut(results, count) };
+    let mut estimated =
+        (!out_estimated.is_null()).then(|| unsafe { std::slice::from_raw_parts_mut(out_estimated, count) });
+
+    let factor = duration_factor(mode);
+    for (i, result) in results.iter_mut().enumerate() {
+        // Routed times are >= 0; the BATCH_* sentinels are negative. Out-of-range pairs have
+        // nothing to estimate from
+        let estimate = *result < 0.0 && *result != BATCH_INVALID_COORD;
+        if estimate {
+            *result = great_circle_estimate_s(mode, lons1[i], lats1[i], lons2[i], lats2[i]) * factor;
+        }
+        if let Some(estimated) = estimated.as_deref_mut() {
+            estimated[i] = i32::from(estimate);
+        }
+    }
+    routed
+}
+
+/// Batch calculate travel times between pairs of points (parallel)
+/// results array must have space for `count` doubles; failed pairs get a BATCH_* sentinel
+/// Returns number of successful calculations, or -1 on error
+#[no_mangle]
+pub extern "C" fn routing_batch(
+    lats1: *const f64,
+    lons1: *const f64,
+    lats2: *const f64,
+    lons2: *const f64,
+    results: *mut f64,
+    count: i32,
+    mode: *const c_char,
+) -> i32 {
+    if lats1.is_null() || lons1.is_null() || lats2.is_null() || lons2.is_null() || results.is_null()
+    {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let count = count as usize;
+    let lats1 = unsafe { std::slice::from_raw_parts(lats1, count) };
+    let lons1 = unsafe { std::slice::from_raw_parts(lons1, count) };
+    let lats2 = unsafe { std::slice::from_raw_parts(lats2, count) };
+    let lons2 = unsafe { std::slice::from_raw_parts(lons2, count) };
+    let results = unsafe { std::slice::from_raw_parts_mut(results, count) };
+
+    let factor = duration_factor(mode);
+    // Parallel calculation using rayon
+    let success_count: i32 = in_thread_pool(|| {
+        (0..count)
+            .into_par_iter()
+            .map(|i| {
+                let seconds = batch_travel_time_s(router, lons1[i], lats1[i], lons2[i], lats2[i]);
+                let result = match seconds {
+                    Ok(seconds) => (seconds * factor, 1),
+                    Err(sentinel) => (sentinel, 0),
+                };
+
+                // SAFETY: each thread writes to a unique index
+                unsafe {
+                    *results.as_ptr().add(i).cast_mut() = result.0;
+                }
+                result.1
+            })
+            .sum()
+    });
+
+    success_count
+}
+
+// routing_batch results for pairs that failed, by reason
+const BATCH_NO_ORIGIN_SNAP: f64 = -1.0;
+const BATCH_NO_DEST_SNAP: f64 = -2.0;
+const BATCH_NO_PATH: f64 = -3.0;
+const BATCH_INVALID_COORD: f64 = -5.0;
+
+/// Unscaled travel time in seconds of one routing_batch pair, or the BATCH_* sentinel for
+/// why it failed (an out-of-range coordinate wins, then an unsnapped origin over an unsnapped
+/// destination)
+fn batch_travel_time_s(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Result<f64, f64> {
+    let data = &router.data;
+    if !is_valid_coord(lat1, lon1) || !is_valid_coord(lat2, lon2) {
+        return Err(BATCH_INVALID_COORD);
+    }
+    let from = find_nearest_node(data, lon1, lat1).ok_or(BATCH_NO_ORIGIN_SNAP)?;
+    let to = find_nearest_node(data, lon2, lat2).ok_or(BATCH_NO_DEST_SNAP)?;
+    parallel_travel_time_s(router, from, to).ok_or(BATCH_NO_PATH)
+}
+
+/// Overall speed in km/h at which the great-circle estimates cover the straight line:
+/// below the mode's road speeds, as real routes are longer than the straight line
+fn estimate_speed_kmh(mode: &str) -> f64 {
+    match mode {
+        "bicycle" => 14.0,
+        "pedestrian" => 4.5,
+        "agricultural" => 15.0,
+        "hgv" => 35.0,
+        _ => 40.0,
+    }
+}
+
+/// Unscaled travel time in seconds estimated from the great-circle distance between two
+/// points, for pairs without a route (routing_travel_time_or_estimate)
+fn great_circle_estimate_s(mode: &str, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let meters = Haversine::distance(Point::new(lon1, lat1), Point::new(lon2, lat2));
+    meters / (estimate_speed_kmh(mode) / 3.6)
+}
+
+/// Full travel-time matrix from every source to every destination
+/// results must hold n_src * n_dst doubles, row-major (row = source); unroutable cells are -1.0.
+/// Each point is snapped once and each source row is one Dijkstra (rows run in parallel)
+/// Returns number of routed cells, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_matrix(
+    src_lats: *const f64,
+    src_lons: *const f64,
+    n_src: i32,
+    dst_lats: *const f64,
+    dst_lons: *const f64,
+    n_dst: i32,
+    results: *mut f64,
+    mode: *const c_char,
+) -> i32 {
+    let no_snaps = std::ptr::null_mut();
+    routing_matrix_ex(src_lats, src_lons, n_src, dst_lats, dst_lons, n_dst, results, mode, no_snaps, no_snaps)
+}
+
+/// routing_matrix that also writes how far each point is from the node it snapped to, in
+/// meters (-1.0 if it did not snap), so cells with a far-off endpoint can be filtered out:
+/// out_src_snap_m holds n_src values and out_dst_snap_m n_dst; either may be NULL
+#[no_mangle]
+pub extern "C" fn routing_matrix_ex(
+    src_lats: *const f64,
+    src_lons: *const f64,
+    n_src: i32,
+    dst_lats: *const f64,
+    dst_lons: *const f64,
+    n_dst: i32,
+    results: *mut f64,
+    mode: *const c_char,
+    out_src_snap_m: *mut f64,
+    out_dst_snap_m: *mut f64,
+) -> i32 {
+    if src_lats.is_null() || src_lons.is_null() || dst_lats.is_null() || dst_lons.is_null() || results.is_null() {
+        return -1;
+    }
+    if n_src < 0 || n_dst < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (n_src, n_dst) = (n_src as usize, n_dst as usize);
+    let points = |lats: *const f64, lons: *const f64, n: usize| -> Vec<(f64, f64)> {
+        let (lats, lons) = unsafe { (std::slice::from_raw_parts(lats, n), std::slice::from_raw_parts(lons, n)) };
+        lats.iter().copied().zip(lons.iter().copied()).collect()
+    };
+    let (sources, targets) = (points(src_lats, src_lons, n_src), points(dst_lats, dst_lons, n_dst));
+    if let Err(code) = coords_arg(sources.iter().chain(&targets).copied()) {
+        return code;
+    }
+    let results = unsafe { std::slice::from_raw_parts_mut(results, n_src * n_dst) };
+    let snaps = |out: *mut f64, n: usize| -> &mut [f64] {
+        if out.is_null() {
+            &mut []
+        } else {
+            unsafe { std::slice::from_raw_parts_mut(out, n) }
+        }
+    };
+
+    router.matrix_into(
+        &sources,
+        &targets,
+        results,
+        snaps(out_src_snap_m, n_src),
+        snaps(out_dst_snap_m, n_dst),
+    )
+}
+
+/// Fill a row-major sources x targets matrix of travel times in seconds, -1.0 where
+/// unroutable (or a point is unsnapped); each row is one Dijkstra, rows run in parallel
+/// Returns number of routed cells
+fn fill_matrix(
+    data: &RoutingData,
+    sources: &[Option<usize>],
+    targets: &[Option<usize>],
+    factor: f64,
+    results: &mut [f64],
+) -> i32 {
+    let n_dst = targets.len();
+    if n_dst == 0 {
+        return 0;
+    }
+    in_thread_pool(|| {
+        results
+            .par_chunks_mut(n_dst)
+            .zip(sources.par_iter())
+            .map(|(row, source)| {
+                let times = match source {
+                    Some(source) => one_to_many_ms(data, *source, targets),
+                    None => vec![None; n_dst],
+                };
+                let mut routed = 0;
+                for (cell, ms) in row.iter_mut().zip(times) {
+                    *cell = match ms {
+                        Some(ms) => {
+                            routed += 1;
+                            ms as f64 / 1000.0 * factor
+                        }
+                        None => -1.0,
+                    };
+                }
+                routed
+            })
+            .sum()
+    })
+}
+
+/// Snap coordinates once to node ids for the *_by_id functions
+/// Ids are indices into the loaded graph of `mode` (the same order as routing_dump_nodes);
+/// they are only valid until that mode is reloaded or freed.
+/// out_node_ids must hold count ints; points that cannot be snapped get -1
+/// Returns number of points snapped, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_snap_ids(
+    lats: *const f64,
+    lons: *const f64,
+    count: i32,
+    mode: *const c_char,
+    out_node_ids: *mut i64,
+) -> i32 {
+    if lats.is_null() || lons.is_null() || out_node_ids.is_null() || count < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let count = count as usize;
+    let lats = unsafe { std::slice::from_raw_parts(lats, count) };
+    let lons = unsafe { std::slice::from_raw_parts(lons, count) };
+    if let Err(code) = coords_arg(lats.iter().copied().zip(lons.iter().copied())) {
+        return code;
+    }
+    let out_node_ids = unsafe { std::slice::from_raw_parts_mut(out_node_ids, count) };
+
+    in_thread_pool(|| {
+        out_node_ids
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, id)| match find_nearest_node(&router.data, lons[i], lats[i]) {
+                Some(node) => {
+                    *id = node as i64;
+                    1
+                }
+                None => {
+                    *id = -1;
+                    0
+                }
+            })
+            .sum()
+    })
+}
+
+/// Node index for an id from routing_snap_ids, if it is valid for this graph
+fn node_from_id(data: &RoutingData, id: i64) -> Option<usize> {
+    usize::try_from(id).ok().filter(|&node| node < data.node_positions.len())
+}
+
+/// Travel time in seconds between two node ids from routing_snap_ids, skipping snapping
+/// Returns -1.0 if an id is invalid for the loaded graph or no route exists, -2.0 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_travel_time_by_id(id1: i64, id2: i64, mode: *const c_char) -> f64 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as f64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1.0,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode) as f64,
+    };
+
+    let (from, to) = match (node_from_id(&router.data, id1), node_from_id(&router.data, id2)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => {
+            set_last_error(format!(
+                "node id out of range for the loaded '{}' graph ({} nodes); snap ids again after reloading",
+                mode,
+                router.data.node_positions.len()
+            ));
+            return -1.0;
+        }
+    };
+
+    match parallel_travel_time_s(router, from, to) {
+        Some(seconds) => seconds * duration_factor(mode),
+        None => -1.0,
+    }
+}
+
+/// routing_matrix over node ids from routing_snap_ids, skipping snapping
+/// results must hold n_src * n_dst doubles, row-major (row = source); cells with an invalid
+/// id or no route are -1.0
+/// Returns number of routed cells, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_matrix_by_id(
+    src_ids: *const i64,
+    n_src: i32,
+    dst_ids: *const i64,
+    n_dst: i32,
+    results: *mut f64,
+    mode: *const c_char,
+) -> i32 {
+    if src_ids.is_null() || dst_ids.is_null() || results.is_null() || n_src < 0 || n_dst < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (n_src, n_dst) = (n_src as usize, n_dst as usize);
+    let src_ids = unsafe { std::slice::from_raw_parts(src_ids, n_src) };
+    let dst_ids = unsafe { std::slice::from_raw_parts(dst_ids, n_dst) };
+    let results = unsafe { std::slice::from_raw_parts_mut(results, n_src * n_dst) };
+
+    let sources: Vec<Option<usize>> = src_ids.iter().map(|&id| node_from_id(&router.data, id)).collect();
+    let targets: Vec<Option<usize>> = dst_ids.iter().map(|&id| node_from_id(&router.data, id)).collect();
+    fill_matrix(&router.data, &sources, &targets, duration_factor(mode), results)
+}
+
+/// Travel-time matrix among one set of points, computing only the upper triangle
+/// Assumes travel times are symmetric (t(i, j) == t(j, i)), which only holds approximately,
+/// e.g. for pedestrian graphs; oneway-heavy auto graphs should use a full matrix instead.
+/// out_triangle must hold n * (n + 1) / 2 doubles, packed row-major: row i holds
+/// columns i..n, so cell (i, j) with i <= j is at i * n - i * (i - 1) / 2 + (j - i).
+/// The diagonal is 0.0, unroutable pairs are -1.0.
+/// Returns number of routed pairs (i < j), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_matrix_symmetric(
+    lats: *const f64,
+    lons: *const f64,
+    n: i32,
+    mode: *const c_char,
+    out_triangle: *mut f64,
+) -> i32 {
+    if lats.is_null() || lons.is_null() || out_triangle.is_null() || n < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let n = n as usize;
+    let lats = unsafe { std::slice::from_raw_parts(lats, n) };
+    let lons = unsafe { std::slice::from_raw_parts(lons, n) };
+    if let Err(code) = coords_arg(lats.iter().copied().zip(lons.iter().copied())) {
+        return code;
+    }
+    let out_triangle = unsafe { std::slice::from_raw_parts_mut(out_triangle, n * (n + 1) / 2) };
+
+    // Snap each point once
+    let snapped: Vec<Option<usize>> = (0..n)
+        .map(|i| find_nearest_node(&router.data, lons[i], lats[i]))
+        .collect();
+
+    let factor = duration_factor(mode);
+    // One packed row per task; rows are disjoint slices of the output
+    let mut rows: Vec<&mut [f64]> = Vec::with_capacity(n);
+    let mut rest = out_triangle;
+    for i in 0..n {
+        let (row, tail) = rest.split_at_mut(n - i);
+        rows.push(row);
+        rest = tail;
+    }
+
+    in_thread_pool(|| {
+        rows.into_par_iter()
+            .enumerate()
+            .map(|(i, row)| {
+                row[0] = 0.0;
+                let mut routed = 0;
+                for j in (i + 1)..n {
+                    let seconds = match (snapped[i], snapped[j]) {
+                        (Some(from), Some(to)) => parallel_travel_time_s(router, from, to),
+                        _ => None,
+                    };
+                    row[j - i] = match seconds {
+                        Some(s) => {
+                            routed += 1;
+                            s * factor
+                        }
+                        None => -1.0,
+                    };
+                }
+                routed
+            })
+            .sum()
+    })
+}
+
+/// For each origin, the number of POIs reachable within max_seconds (accessibility scores)
+/// POIs are snapped once; each origin is one bounded Dijkstra over `adj_list` (origins run
+/// in parallel), far cheaper than a full matrix when only counts are needed.
+/// out_counts must hold n ints; origins that cannot be snapped get -1
+/// Returns number of origins counted, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_reachable_count(
+    origin_lats: *const f64,
+    origin_lons: *const f64,
+    n: i32,
+    poi_lats: *const f64,
+    poi_lons: *const f64,
+    m: i32,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_counts: *mut i32,
+) -> i32 {
+    if origin_lats.is_null() || origin_lons.is_null() || poi_lats.is_null() || poi_lons.is_null() || out_counts.is_null()
+    {
+        return -1;
+    }
+    if n < 0 || m < 0 || max_seconds.is_nan() || max_seconds < 0.0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (n, m) = (n as usize, m as usize);
+    let origin_lats = unsafe { std::slice::from_raw_parts(origin_lats, n) };
+    let origin_lons = unsafe { std::slice::from_raw_parts(origin_lons, n) };
+    let poi_lats = unsafe { std::slice::from_raw_parts(poi_lats, m) };
+    let poi_lons = unsafe { std::slice::from_raw_parts(poi_lons, m) };
+    let origins = origin_lats.iter().copied().zip(origin_lons.iter().copied());
+    if let Err(code) = coords_arg(origins.chain(poi_lats.iter().copied().zip(poi_lons.iter().copied()))) {
+        return code;
+    }
+    let out_counts = unsafe { std::slice::from_raw_parts_mut(out_counts, n) };
+
+    let mut pois_at: HashMap<usize, u32> = HashMap::new();
+    for j in 0..m {
+        if let Some(node) = find_nearest_node(&router.data, poi_lons[j], poi_lats[j]) {
+            *pois_at.entry(node).or_insert(0) += 1;
+        }
+    }
+
+    let max_cost_ms = budget_ms(max_seconds, duration_factor(mode));
+    in_thread_pool(|| {
+        out_counts
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, count)| match find_nearest_node(&router.data, origin_lons[i], origin_lats[i]) {
+                Some(origin) => {
+                    *count = reachable_poi_count(&router.data, origin, &pois_at, max_cost_ms) as i32;
+                    1
+                }
+                None => {
+                    *count = -1;
+                    0
+                }
+            })
+            .sum()
+    })
+}
+
+/// Snap a coordinate to the nearest node with an incident edge of an allowed highway type
+/// allowed_highway_csv is a comma-separated list of highway=* values, e.g. "primary,secondary,tertiary"
+/// Returns 0 on success, -1 on error (including an unknown highway type) or if no node matches,
+/// -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_snap_filtered(
+    lat: f64,
+    lon: f64,
+    mode: *const c_char,
+    allowed_highway_csv: *const c_char,
+    out_lat: *mut f64,
+    out_lon: *mut f64,
+    out_distance_m: *mut f64,
+) -> i32 {
+    if out_lat.is_null() || out_lon.is_null() || out_distance_m.is_null() || allowed_highway_csv.is_null() {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let allowed = match unsafe { CStr::from_ptr(allowed_highway_csv) }.to_str() {
+        Ok(s) => match parse_highway_mask(s) {
+            Ok(mask) => mask,
+            Err(e) => {
+                set_last_error(format!("{:#}", e));
+                return -1;
+            }
+        },
+        Err(_) => return -1,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let idx = match find_nearest_node_where(&router.data, lon, lat, |n| router.node_highways[n] & allowed != 0) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let (node_lon, node_lat) = router.data.node_positions[idx];
+    unsafe {
+        *out_lat = node_lat;
+        *out_lon = node_lon;
+        *out_distance_m = Haversine::distance(Point::new(lon, lat), Point::new(node_lon, node_lat));
+    }
+    0
+}
+
+/// Snap a coordinate to the nearest road network node
+/// Returns snapped lat/lon and distance in meters, or -1 values on error
+#[no_mangle]
+pub extern "C" fn routing_snap(
+    lat: f64,
+    lon: f64,
+    mode: *const c_char,
+    out_lat: *mut f64,
+    out_lon: *mut f64,
+    out_distance_m: *mut f64,
+) -> i32 {
+    if out_lat.is_null() || out_lon.is_null() || out_distance_m.is_null() {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => {
+            let (node_lon, node_lat) = router.data.node_positions[idx];
+            let p1 = Point::new(lon, lat);
+            let p2 = Point::new(node_lon, node_lat);
+            let dist = Haversine::distance(p1, p2);
+
+            unsafe {
+                *out_lat = node_lat;
+                *out_lon = node_lon;
+                *out_distance_m = dist;
+            }
+            0
+        }
+        None => {
+            unsafe {
+                *out_lat = -1.0;
+                *out_lon = -1.0;
+                *out_distance_m = -1.0;
+            }
+            -1
+        }
+    }
+}
+
+/// Snap a coordinate to the k nearest road network nodes, closest first
+/// Writes min(k, max_k) candidates at most; returns the number written, -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_snap_k(
+    lat: f64,
+    lon: f64,
+    k: i32,
+    mode: *const c_char,
+    out_lats: *mut f64,
+    out_lons: *mut f64,
+    out_dists: *mut f64,
+    max_k: i32,
+) -> i32 {
+    if out_lats.is_null() || out_lons.is_null() || out_dists.is_null() || k < 0 || max_k < 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let n = k.min(max_k) as usize;
+    let out_lats = unsafe { std::slice::from_raw_parts_mut(out_lats, n) };
+    let out_lons = unsafe { std::slice::from_raw_parts_mut(out_lons, n) };
+    let out_dists = unsafe { std::slice::from_raw_parts_mut(out_dists, n) };
+    let nearest = find_nearest_nodes(&router.data, lon, lat, n);
+    for (i, &(idx, dist)) in nearest.iter().enumerate() {
+        let (node_lon, node_lat) = router.data.node_positions[idx];
+        out_lats[i] = node_lat;
+        out_lons[i] = node_lon;
+        out_dists[i] = dist;
+    }
+    nearest.len() as i32
+}
+
+/// Snap a coordinate to the nearest road network node, rejecting nodes farther than max_distance_m
+/// Returns 0 on success, -1 on error, -2 if not loaded, -3 if the nearest node is too far away
+/// (the outputs then still hold that node and its distance)
+#[no_mangle]
+pub extern "C" fn routing_snap_within(
+    lat: f64,
+    lon: f64,
+    mode: *const c_char,
+    max_distance_m: f64,
+    out_lat: *mut f64,
+    out_lon: *mut f64,
+    out_distance_m: *mut f64,
+) -> i32 {
+    if max_distance_m.is_nan() || max_distance_m < 0.0 {
+        return -1;
+    }
+    match routing_snap(lat, lon, mode, out_lat, out_lon, out_distance_m) {
+        0 if unsafe { *out_distance_m } > max_distance_m => -3,
+        result => result,
+    }
+}
+
+/// Choose whether subsequent auto and agricultural builds index every node for snapping
+/// Zero (default) indexes only main road nodes; pedestrian and bicycle graphs always index
+/// every node. Graphs indexing every node are cached in files of their own.
+#[no_mangle]
+pub extern "C" fn routing_set_snap_all_roads(enabled: i32) {
+    SNAP_ALL_ROADS.store(enabled != 0, AtomicOrdering::Relaxed);
+}
+
+/// Choose whether routing_route and the other snapped route functions try several nearby
+/// segments per endpoint. Non-zero tries the SNAP_CANDIDATES nearest segments within
+/// SNAP_CANDIDATE_TOLERANCE_M of the nearest one for origin and destination and routes between
+/// the pair with the fastest route, avoiding detours from snapping onto a poorly connected
+/// stub at the cost of up to 16 searches per route. Zero (default) uses the nearest segment.
+#[no_mangle]
+pub extern "C" fn routing_set_multi_snap(enabled: i32) {
+    MULTI_SNAP.store(enabled != 0, AtomicOrdering::Relaxed);
+}
+
+/// Run batch and matrix queries and graph builds on a dedicated pool of `threads` threads
+/// Zero or negative returns to rayon's global pool (one thread per core, the default).
+/// Returns 0 on success, -1 if the pool could not be created
+#[no_mangle]
+pub extern "C" fn routing_set_threads(threads: i32) -> i32 {
+    let pool = if threads > 0 {
+        let built = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .thread_name(|i| format!("routing-{}", i))
+            .build();
+        match built {
+            Ok(pool) => Some(Arc::new(pool)),
+            Err(e) => {
+                set_last_error(format!("Could not create a pool of {} threads: {}", threads, e));
+                return -1;
+            }
+        }
+    } else {
+        None
+    };
+    match THREAD_POOL.write() {
+        Ok(mut guard) => {
+            *guard = pool;
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Collapse chains of interior way nodes into single edges when building graphs (default off)
+/// Affects subsequent builds from a PBF; contracted graphs are cached in files of their own
+#[no_mangle]
+pub extern "C" fn routing_set_contract_chains(enabled: i32) {
+    CONTRACT_CHAINS.store(enabled != 0, AtomicOrdering::Relaxed);
+}
+
+/// Exclude bitmask the mode's graph was built with (see routing_load_opts)
+/// Returns the bitmask, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_graph_options(mode: *const c_char) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    match get_router_for_mode(mode).read() {
+        Ok(guard) => match guard.as_ref() {
+            Some(r) => r.data.exclude as i32,
+            None => not_loaded(mode),
+        },
+        Err(_) => -1,
+    }
+}
+
+/// Get count of nodes in the routing graph
+#[no_mangle]
+pub extern "C" fn routing_node_count(mode: *const c_char) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    match lock.read() {
+        Ok(guard) => match guard.as_ref() {
+            Some(r) => r.data.node_positions.len() as i32,
+            None => not_loaded(mode),
+        },
+        Err(_) => -1,
+    }
+}
+
+/// Get count of directed edges in the routing graph (a two-way road segment counts twice)
+/// Returns -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_edge_count(mode: *const c_char) -> i64 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as i64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    match lock.read() {
+        Ok(guard) => match guard.as_ref() {
+            Some(r) => r.data.adj_list.num_edges() as i64,
+            None => not_loaded(mode) as i64,
+        },
+        Err(_) => -1,
+    }
+}
+
+/// Bounding box of the graph's nodes, to reject coordinates outside the extract
+/// instead of snapping them to its edge
+/// Returns 0 on success, -1 on error or for an empty graph, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_graph_bounds(
+    mode: *const c_char,
+    out_min_lon: *mut f64,
+    out_min_lat: *mut f64,
+    out_max_lon: *mut f64,
+    out_max_lat: *mut f64,
+) -> i32 {
+    if out_min_lon.is_null() || out_min_lat.is_null() || out_max_lon.is_null() || out_max_lat.is_null() {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    match graph_bounds(&router.data) {
+        Some((min_lon, min_lat, max_lon, max_lat)) => {
+            unsafe {
+                *out_min_lon = min_lon;
+                *out_min_lat = min_lat;
+                *out_max_lon = max_lon;
+                *out_max_lat = max_lat;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Number of weakly connected components in the input network before small ones were dropped
+/// Returns the count, -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_component_count(mode: *const c_char) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    match lock.read() {
+        Ok(guard) => match guard.as_ref() {
+            Some(r) => r.data.source_component_count as i32,
+            None => not_loaded(mode),
+        },
+        Err(_) => -1,
+    }
+}
+
+/// Whether two coordinates snap to roads in the same connected component (weakly connected,
+/// labelled at build time), as a cheap check before routing: points in different components
+/// never have a route, while points in one component may still have none because of one-ways
+/// Returns 1 if they do, 0 if not, -1 on error (including a failed snap), -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_same_component(lat1: f64, lon1: f64, lat2: f64, lon2: f64, mode: *const c_char) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    match router.same_component(lat1, lon1, lat2, lon2) {
+        Ok(same) => i32::from(same),
+        Err(e) => e.record(),
+    }
+}
+
+/// Out-degree of the node a coordinate snaps to
+/// A degree of 1 or less marks a dead-end stub, which is often a poor snap target
+/// Returns 0 on success, -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_snap_degree(lat: f64, lon: f64, mode: *const c_char, out_degree: *mut i32) -> i32 {
+    if out_degree.is_null() {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => {
+            unsafe {
+                *out_degree = router.data.adj_list[idx].len() as i32;
+            }
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Contraction hierarchy level (contraction rank) of every node, for diagnostics
+/// out_levels[node] is the position of the node in the contraction order;
+/// higher levels were contracted later and are more "important"
+/// Returns total node count (write is truncated to max_nodes), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_ch_levels(mode: *const c_char, out_levels: *mut i32, max_nodes: i32) -> i32 {
+    if out_levels.is_null() || max_nodes < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let ordering = fast_paths::get_node_ordering(&router.data.fast_graph);
+    let out_levels = unsafe { std::slice::from_raw_parts_mut(out_levels, max_nodes as usize) };
+    for (level, &node) in ordering.iter().enumerate() {
+        if node < out_levels.len() {
+            out_levels[node] = level as i32;
+        }
+    }
+
+    ordering.len() as i32
+}
+
+/// Contraction hierarchy statistics: node count, CH edge count (forward + backward,
+/// shortcuts included) and base graph edge count. fast_paths doesn't expose shortcut
+/// flags, so ch_edges - base_edges is only an estimate of the shortcut count
+/// (parallel base edges are merged during preparation)
+/// Returns 0 on success, -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_ch_stats(
+    mode: *const c_char,
+    out_nodes: *mut i64,
+    out_ch_edges: *mut i64,
+    out_base_edges: *mut i64,
+) -> i32 {
+    if out_nodes.is_null() || out_ch_edges.is_null() || out_base_edges.is_null() {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let graph = &router.data.fast_graph;
+    unsafe {
+        *out_nodes = graph.get_num_nodes() as i64;
+        *out_ch_edges = (graph.get_num_out_edges() + graph.get_num_in_edges()) as i64;
+        *out_base_edges = router.data.edge_info.len() as i64;
+    }
+    0
+}
+
+/// Dump the directed edge list in columnar form: source node, target node, weight (ms)
+/// Edges are written in edge-id order (the order used by routing_route_cover), up to max_edges
+/// Returns the total edge count, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_dump_adjacency(
+    mode: *const c_char,
+    out_edge_from: *mut i32,
+    out_edge_to: *mut i32,
+    out_edge_weight: *mut u32,
+    max_edges: i64,
+) -> i64 {
+    if out_edge_from.is_null() || out_edge_to.is_null() || out_edge_weight.is_null() || max_edges < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as i64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode) as i64,
+    };
+
+    let edges = router
+        .data
+        .adj_list
+        .iter()
+        .enumerate()
+        .flat_map(|(from, adj)| adj.iter().map(move |&(to, weight)| (from, to, weight)));
+    for (i, (from, to, weight)) in edges.take(max_edges as usize).enumerate() {
+        unsafe {
+            *out_edge_from.add(i) = from as i32;
+            *out_edge_to.add(i) = to as i32;
+            *out_edge_weight.add(i) = weight;
+        }
+    }
+
+    router.data.edge_info.len() as i64
+}
+
+/// Dump node coordinates indexed by node id, up to max_nodes
+/// Returns the total node count, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_dump_nodes(
+    mode: *const c_char,
+    out_lats: *mut f64,
+    out_lons: *mut f64,
+    max_nodes: i64,
+) -> i64 {
+    if out_lats.is_null() || out_lons.is_null() || max_nodes < 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code as i64,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode) as i64,
+    };
+
+    let positions = &router.data.node_positions;
+    for (i, &(lon, lat)) in positions.iter().take(max_nodes as usize).enumerate() {
+        unsafe {
+            *out_lats.add(i) = lat;
+            *out_lons.add(i) = lon;
+        }
+    }
+
+    positions.len() as i64
+}
+
+/// Isochrone result struct for FFI
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct IsochroneResult {
+    pub lat: f64,
+    pub lon: f64,
+    pub seconds: f64,
+}
+
+/// Route point struct for FFI
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct RoutePoint {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Isochrone edge struct for FFI: a road segment and the travel time to its end
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct IsochroneEdge {
+    pub from_lat: f64,
+    pub from_lon: f64,
+    pub to_lat: f64,
+    pub to_lon: f64,
+    pub seconds: f64,
+}
+
+/// Route result struct for FFI
+#[repr(C)]
+pub struct RouteResult {
+    pub distance_m: f64,
+    pub duration_s: f64,
+    pub num_points: i32,
+}
+
+/// Set the unit of the distance_m outputs of route functions ("meters", "kilometers", "miles")
+/// The fields keep their _m names but hold distances in the chosen unit. Snap distances stay in meters.
+/// Returns 0 on success, -1 for an unknown unit
+#[no_mangle]
+pub extern "C" fn routing_set_distance_unit(unit: *const c_char) -> i32 {
+    if unit.is_null() {
+        return -1;
+    }
+    match unsafe { CStr::from_ptr(unit) }.to_str().ok().and_then(DistanceUnit::from_name) {
+        Some(u) => {
+            DISTANCE_UNIT.store(u as u8, AtomicOrdering::Relaxed);
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Scale the travel times of a mode at query time, e.g. 1.4 for rush-hour traffic that makes
+/// every trip 40% slower. Reported durations are multiplied and isochrone limits divided by
+/// the factor; paths are unchanged, since the contraction hierarchy weights are fixed at
+/// build time (use a speed profile for per-road-class changes). Applies to loaded and
+/// later loaded graphs of the mode. Returns 0 on success, -1 unless factor is positive and finite
+#[no_mangle]
+pub extern "C" fn routing_set_speed_factor(mode: *const c_char, factor: f64) -> i32 {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+    if !(factor.is_finite() && factor > 0.0) {
+        return -1;
+    }
+    duration_factor_slot(mode).store(factor.to_bits(), AtomicOrdering::Relaxed);
+    0
+}
+
+/// Choose how blocking barriers are applied by subsequent routing_load builds
+/// A blocking barrier closes every way through its node. Non-zero (default) splits the ways
+/// at the node, so each side stays routable up to the barrier. Zero drops every edge at the
+/// barrier node. Graphs built without edge splitting are cached in files of their own.
+#[no_mangle]
+pub extern "C" fn routing_set_barrier_edge_split(enabled: i32) {
+    BARRIER_EDGE_SPLIT.store(enabled != 0, AtomicOrdering::Relaxed);
+}
+
+/// Set the minimum size (in nodes) of components kept by subsequent routing_load builds
+/// Smaller components are dropped, except the largest one. 0 keeps every component;
+/// a negative value restores the default of keeping only the largest component.
+/// Each size other than the default builds and caches its own graph.
+#[no_mangle]
+pub extern "C" fn routing_set_build_min_component_size(min_nodes: i32) {
+    let min_nodes = if min_nodes < 0 { usize::MAX } else { min_nodes as usize };
+    MIN_BUILD_COMPONENT_SIZE.store(min_nodes, AtomicOrdering::Relaxed);
+}
+
+/// Set the length (meters) below which subsequent routing_load builds drop dead-end spurs:
+/// the nodes from a dead end up to the first junction, e.g. driveways and parking aisles
+/// that points would otherwise snap onto. 0 or less keeps them (default).
+/// Each length above 0 builds and caches its own graph.
+#[no_mangle]
+pub extern "C" fn routing_set_build_min_stub_length(meters: f64) {
+    let meters = if meters.is_finite() { meters.max(0.0) } else { 0.0 };
+    MIN_BUILD_STUB_LENGTH_M.store(meters.to_bits(), AtomicOrdering::Relaxed);
+}
+
+/// Set the contraction hierarchy preparation parameters of subsequent routing_load builds
+/// (ChParams; fast_paths' defaults are 0.1, 500, 100, 500 and 100). Larger witness search
+/// limits build slower but add fewer shortcuts, for faster queries; smaller ones build faster.
+/// Graphs already cached on disk keep the hierarchy they were built with.
+/// Returns 0 on success, -1 for a negative limit or a depth factor that is negative or not finite
+#[no_mangle]
+pub extern "C" fn routing_set_ch_params(
+    hierarchy_depth_factor: f64,
+    max_settled_initial: i32,
+    max_settled_neighbor: i32,
+    max_settled_contraction: i32,
+    max_settled_with_order: i32,
+) -> i32 {
+    let limits = [max_settled_initial, max_settled_neighbor, max_settled_contraction, max_settled_with_order];
+    if !(hierarchy_depth_factor.is_finite() && hierarchy_depth_factor >= 0.0) || limits.iter().any(|&l| l < 0) {
+        set_last_error("Invalid contraction hierarchy parameters (expected a factor and limits >= 0)");
+        return -1;
+    }
+    let params = ChParams {
+        hierarchy_depth_factor: hierarchy_depth_factor as f32,
+        max_settled_initial: max_settled_initial as usize,
+        max_settled_neighbor: max_settled_neighbor as usize,
+        max_settled_contraction: max_settled_contraction as usize,
+        max_settled_with_order: max_settled_with_order as usize,
+    };
+    match CH_PARAMS.write() {
+        Ok(mut guard) => {
+            *guard = params;
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Set the minimum component size (in nodes) for isochrone origins
+/// Origins snapping into a smaller component make routing_isochrone and
+/// routing_isochrone_polygon return -3 instead of a misleadingly tiny result.
+/// 0 disables the check (default).
+#[no_mangle]
+pub extern "C" fn routing_set_min_component_size(min_nodes: i32) {
+    MIN_ISOCHRONE_COMPONENT_SIZE.store(min_nodes.max(0) as usize, AtomicOrdering::Relaxed);
+}
+
+/// Calculate isochrone - all reachable points within max_seconds
+/// Returns count of results written, or -1 on error, -2 if not loaded,
+/// -3 if the origin snapped into a component below the minimum component size
+/// Results are written to out_results array (caller provides buffer)
+#[no_mangle]
+pub extern "C" fn routing_isochrone(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_results: *mut IsochroneResult,
+    max_results: i32,
+) -> i32 {
+    routing_isochrone_capped(
+        lat,
+        lon,
+        max_seconds,
+        mode,
+        0,
+        out_results,
+        max_results,
+        std::ptr::null_mut(),
+    )
+}
+
+/// routing_isochrone with a safety cap on the search itself: the traversal stops after
+/// settling max_nodes_explored nodes (0 = no cap), bounding memory on dense graphs.
+/// out_incomplete (may be null) is set to 1 if the cap cut the search short, else 0
+/// Returns count of results written, or -1 on error, -2 if not loaded,
+/// -3 if the origin snapped into a component below the minimum component size
+#[no_mangle]
+pub extern "C" fn routing_isochrone_capped(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    max_nodes_explored: i32,
+    out_results: *mut IsochroneResult,
+    max_results: i32,
+    out_incomplete: *mut i32,
+) -> i32 {
+    let mut count = 0;
+    let status = routing_isochrone_bounded(
+        lat,
+        lon,
+        max_seconds,
+        mode,
+        max_nodes_explored,
+        0,
+        out_results,
+        max_results,
+        &mut count,
+    );
+    if status < 0 {
+        return status;
+    }
+    if !out_incomplete.is_null() {
+        unsafe { *out_incomplete = status };
+    }
+    count
+}
+
+/// routing_isochrone with bounds on latency for interactive use: the traversal stops after
+/// settling max_nodes_explored nodes (0 = no cap) or after deadline_ms milliseconds of wall
+/// clock (0 = no deadline), keeping the nodes settled so far. The max_seconds budget still
+/// ends the search early as usual.
+/// out_count receives the number of results written
+/// Returns 0 if the search completed, 1 if a bound cut it short (partial results), or -1 on
+/// error, -2 if not loaded, -3 if the origin snapped into a component below the minimum
+/// component size
+#[no_mangle]
+pub extern "C" fn routing_isochrone_bounded(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    max_nodes_explored: i32,
+    deadline_ms: i32,
+    out_results: *mut IsochroneResult,
+    max_results: i32,
+    out_count: *mut i32,
+) -> i32 {
+    if out_results.is_null() || out_count.is_null() || max_results <= 0 || max_nodes_explored < 0 || deadline_ms < 0 {
+        return -1;
+    }
+    let deadline = (deadline_ms > 0)
+        .then(|| std::time::Instant::now() + std::time::Duration::from_millis(deadline_ms as u64));
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let max_settled = if max_nodes_explored > 0 { max_nodes_explored as usize } else { usize::MAX };
+    let (reached, incomplete) = match router.isochrone_bounded(lat, lon, max_seconds, max_settled, deadline) {
+        Ok(r) => r,
+        Err(e) => return e.record(),
+    };
+
+    let max_results = max_results as usize;
+    let out_results = unsafe { std::slice::from_raw_parts_mut(out_results, max_results) };
+    let result_count = reached.len().min(max_results);
+    out_results[..result_count].copy_from_slice(&reached[..result_count]);
+
+    unsafe { *out_count = result_count as i32 };
+    incomplete as i32
+}
+
+/// Reverse isochrone - all points that can reach (lat, lon) within max_seconds
+/// Results have the same layout as routing_isochrone; seconds is the travel time to the target.
+/// Returns count of results written, or -1 on error, -2 if not loaded,
+/// -3 if the target snapped into a component below the minimum component size
+#[no_mangle]
+pub extern "C" fn routing_isochrone_reverse(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_results: *mut IsochroneResult,
+    max_results: i32,
+) -> i32 {
+    if out_results.is_null() || max_results <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let target_idx = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let min_component_size = MIN_ISOCHRONE_COMPONENT_SIZE.load(AtomicOrdering::Relaxed);
+    let component = router.data.component_ids[target_idx] as usize;
+    if router.component_sizes[component] < min_component_size {
+        return -3;
+    }
+
+    let factor = duration_factor(mode);
+    let reached = reverse_isochrone_nodes(router, target_idx, budget_ms(max_seconds, factor));
+
+    let max_results = max_results as usize;
+    let out_results = unsafe { std::slice::from_raw_parts_mut(out_results, max_results) };
+    let result_count = reached.len().min(max_results);
+
+    for (i, &(node, cost)) in reached.iter().take(result_count).enumerate() {
+        let (node_lon, node_lat) = router.data.node_positions[node];
+        out_results[i] = IsochroneResult {
+            lat: node_lat,
+            lon: node_lon,
+            seconds: cost as f64 / 1000.0 * factor,
+        };
+    }
+
+    result_count as i32
+}
+
+/// Calculate the isochrone as a WKT polygon (concave hull of the reachable nodes and of the
+/// points where the budget runs out along edges leaving them; convex hull when there are too
+/// few points for a concave shape).
+/// With n_buckets > 0, buckets lists travel times in seconds (e.g. 300, 600, 900) and the
+/// result is a MULTIPOLYGON of the non-overlapping bands between them, in the given order
+/// (isochrone_bands); the buckets replace max_seconds, which is then ignored. A bucket that is
+/// not a positive number is an error
+/// Returns the WKT length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded, -3 if the origin snapped into
+/// a component smaller than the minimum component size
+#[no_mangle]
+pub extern "C" fn routing_isochrone_polygon(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    buckets: *const f64,
+    n_buckets: i32,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if n_buckets < 0 || (n_buckets > 0 && buckets.is_null()) {
+        return -1;
+    }
+    let limits: Vec<f64> = if n_buckets > 0 {
+        unsafe { std::slice::from_raw_parts(buckets, n_buckets as usize) }.to_vec()
+    } else {
+        vec![max_seconds]
+    };
+    if n_buckets > 0 && !limits.iter().all(|&seconds| seconds.is_finite() && seconds > 0.0) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let start_idx = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let min_component_size = MIN_ISOCHRONE_COMPONENT_SIZE.load(AtomicOrdering::Relaxed);
+    let component = router.data.component_ids[start_idx] as usize;
+    if router.component_sizes[component] < min_component_size {
+        return -3;
+    }
+
+    let max_limit = limits.iter().copied().fold(0.0, f64::max);
+    let factor = duration_factor(mode);
+    let reached = isochrone_nodes(&router.data, start_idx, budget_ms(max_limit, factor));
+
+    let hulls: Vec<geo::Polygon<f64>> = limits
+        .iter()
+        .map(|&seconds| {
+            let max_cost_ms = budget_ms(seconds, factor);
+            let mut points: Vec<(f64, f64)> = reached
+                .iter()
+                .filter(|&&(_, cost)| cost <= max_cost_ms)
+                .map(|&(node, _)| router.data.node_positions[node])
+                .collect();
+            points.extend(isochrone_boundary_points(&router.data, &reached, max_cost_ms));
+            isochrone_hull(&points)
+        })
+        .collect();
+
+    let wkt = if n_buckets > 0 {
+        geo::MultiPolygon(isochrone_bands(&hulls, &limits)).wkt_string()
+    } else {
+        hulls[0].wkt_string()
+    };
+    write_c_string(&wkt, out_buf, buf_len)
+}
+
+/// Points where the isochrone's travel time budget runs out part-way along an edge, i.e. the
+/// exact reach along every road leaving the reachable nodes
+/// Writes up to max_points points; returns the total number of points, or -1 on error,
+/// -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_isochrone_boundary(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if max_points < 0 || (max_points > 0 && out_points.is_null()) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let start_idx = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let max_cost_ms = budget_ms(max_seconds, duration_factor(mode));
+    let reached = isochrone_nodes(&router.data, start_idx, max_cost_ms);
+    let points = isochrone_boundary_points(&router.data, &reached, max_cost_ms);
+    if max_points > 0 {
+        let out = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
+        for (slot, &(lon, lat)) in out.iter_mut().zip(&points) {
+            *slot = RoutePoint { lat, lon };
+        }
+    }
+    points.len() as i32
+}
+
+/// Road segments fully reachable within max_seconds, each with the travel time to its end,
+/// for drawing the reachable network as lines instead of a hull
+/// Writes up to max_edges segments; returns the total number of segments, or -1 on error,
+/// -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_isochrone_edges(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_edges: *mut IsochroneEdge,
+    max_edges: i32,
+) -> i32 {
+    if max_edges < 0 || (max_edges > 0 && out_edges.is_null()) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let start_idx = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let factor = duration_factor(mode);
+    let max_cost_ms = budget_ms(max_seconds, factor);
+    let reached = isochrone_nodes(&router.data, start_idx, max_cost_ms);
+    let segments = isochrone_edges(&router.data, &reached, max_cost_ms);
+    if max_edges > 0 {
+        let out = unsafe { std::slice::from_raw_parts_mut(out_edges, max_edges as usize) };
+        for (slot, &((from_lon, from_lat), (to_lon, to_lat), cost)) in out.iter_mut().zip(&segments) {
+            *slot = IsochroneEdge {
+                from_lat,
+                from_lon,
+                to_lat,
+                to_lon,
+                seconds: cost as f64 / 1000.0 * factor,
+            };
+        }
+    }
+    segments.len() as i32
+}
+
+/// Calculate the center of mass of the area reachable within max_seconds
+/// (uniform mean of reached node coordinates)
+/// Returns number of reached nodes averaged, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_isochrone_centroid(
+    lat: f64,
+    lon: f64,
+    max_seconds: f64,
+    mode: *const c_char,
+    out_lat: *mut f64,
+    out_lon: *mut f64,
+) -> i32 {
+    if out_lat.is_null() || out_lon.is_null() {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let start_idx = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let max_cost_ms = budget_ms(max_seconds, duration_factor(mode));
+    let reached = isochrone_nodes(&router.data, start_idx, max_cost_ms);
+    let nodes: Vec<usize> = reached.iter().map(|&(node, _)| node).collect();
+    let (c_lon, c_lat) = match mean_position(&router.data, &nodes) {
+        Some(c) => c,
+        None => return -1,
+    };
+
+    unsafe {
+        *out_lat = c_lat;
+        *out_lon = c_lon;
+    }
+    nodes.len() as i32
+}
+
+/// Route between edge snaps of two coordinates as (lon, lat) coordinates and duration in ms
+/// The coordinates run from the projected origin through the path nodes to the projected
+/// destination; projections onto a node are not repeated. With routing_set_multi_snap the
+/// snaps are the best of several nearby segments (snapped_path).
+/// On failure the reason is recorded for routing_last_error
+fn snapped_route(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<(Vec<(f64, f64)>, u64)> {
+    snapped_route_named(router, lon1, lat1, lon2, lat2).map(|route| (route.coords, route.duration_ms))
+}
+
+// Route geometry with the street name index (or NO_NAME) of each coordinate: the name of
+// the segment starting there, NO_NAME for the last coordinate
+struct NamedRoute {
+    coords: Vec<(f64, f64)>,
+    names: Vec<u32>,
+    duration_ms: u64,
+}
+
+/// snapped_route with the street names along the geometry
+fn snapped_route_named(router: &Router, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<NamedRoute> {
+    let candidates = if MULTI_SNAP.load(AtomicOrdering::Relaxed) { SNAP_CANDIDATES } else { 1 };
+    let (origin, dest, path_nodes, duration_ms) = match snapped_path(router, lon1, lat1, lon2, lat2, candidates) {
+        Some(r) => r,
+        None => {
+            record_route_failure(&router.data, lon1, lat1, lon2, lat2);
+            return None;
+        }
+    };
+
+    let data = &router.data;
+    let name = |a: usize, b: usize| {
+        edge_between(router, a, b)
+            .or_else(|| edge_between(router, b, a))
+            .map_or(NO_NAME, |e| data.edge_info[e].name)
+    };
+    // Shape points of contracted chains between the snaps and the path take the chain's name
+    let mut points = Vec::with_capacity(path_nodes.len() + 2);
+    let origin_name = name(origin.from, origin.to);
+    points.push(((origin.lon, origin.lat), origin_name));
+    let leave_at = match path_nodes.first() {
+        Some(&first) if first == origin.from => 0.0,
+        Some(_) => 1.0,
+        None => dest.fraction,
+    };
+    let leaving = leg_points_between(data, origin.from, origin.to, origin.fraction, leave_at);
+    points.extend(leaving.into_iter().map(|coord| (coord, origin_name)));
+    for (i, &node) in path_nodes.iter().enumerate() {
+        match path_nodes.get(i + 1) {
+            Some(&next) => {
+                let leg_name = name(node, next);
+                let coords = leg_coords(data, node, next);
+                points.extend(coords[..coords.len() - 1].iter().map(|&coord| (coord, leg_name)));
+            }
+            None => {
+                let dest_name = name(dest.from, dest.to);
+                points.push((data.node_positions[node], dest_name));
+                let enter_at = if node == dest.from { 0.0 } else { 1.0 };
+                let entering = leg_points_between(data, dest.from, dest.to, enter_at, dest.fraction);
+                points.extend(entering.into_iter().map(|coord| (coord, dest_name)));
+            }
+        }
+    }
+    points.push(((dest.lon, dest.lat), NO_NAME));
+
+    // A repeated point starts the segment after it, so it takes that segment's name
+    let mut coords: Vec<(f64, f64)> = Vec::with_capacity(points.len());
+    let mut names: Vec<u32> = Vec::with_capacity(points.len());
+    for (coord, name) in points {
+        if coords.last() == Some(&coord) {
+            *names.last_mut().unwrap() = name;
+        } else {
+            coords.push(coord);
+            names.push(name);
+        }
+    }
+    Some(NamedRoute {
+        coords,
+        names,
+        duration_ms,
+    })
+}
+
+/// Street name changes along a route as a JSON array of {"index", "name"}, where index is
+/// the coordinate where the named stretch starts and name is null for unnamed ways
+/// The final coordinate (the destination) starts no stretch.
+fn name_changes_to_json(names: &[u32], all_names: &[String]) -> String {
+    let stretches = &names[..names.len().saturating_sub(1)];
+    let events: Vec<serde_json::Value> = stretches
+        .iter()
+        .enumerate()
+        .filter(|&(i, name)| i == 0 || stretches[i - 1] != *name)
+        .map(|(i, &name)| serde_json::json!({"index": i, "name": all_names.get(name as usize)}))
+        .collect();
+    serde_json::Value::Array(events).to_string()
+}
+
+/// Haversine length in meters of a (lon, lat) polyline
+fn coords_length_m(coords: &[(f64, f64)]) -> f64 {
+    coords
+        .windows(2)
+        .map(|pair| Haversine::distance(Point::new(pair[0].0, pair[0].1), Point::new(pair[1].0, pair[1].1)))
+        .sum()
+}
+
+/// Calculate route with full geometry
+/// Both points are snapped onto the nearest road segment; the geometry runs from the
+/// projected origin through the route nodes to the projected destination, and distance
+/// and duration include the partial segments
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let route = match router.route(lat1, lon1, lat2, lon2) {
+        Ok(r) => r,
+        Err(e) => return e.record(),
+    };
+
+    let out_points = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
+    let num_points = route.points.len().min(max_points as usize);
+    out_points[..num_points].copy_from_slice(&route.points[..num_points]);
+
+    unsafe {
+        *out_result = RouteResult {
+            distance_m: DistanceUnit::current().convert_meters(route.distance_m),
+            duration_s: route.duration_s,
+            num_points: num_points as i32,
+        };
+    }
+
+    num_points as i32
+}
+
+/// Calculate route with full geometry using WKT geometries as input
+/// Uses centroid of each geometry as routing point
+/// Returns number of path points written, or -1 on error, -2 if not loaded, -5 if a
+/// geometry has a coordinate out of range
+#[no_mangle]
+pub extern "C" fn routing_route_geom(
+    from_wkt: *const c_char,
+    to_wkt: *const c_char,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    let (no_from, no_to) = (std::ptr::null_mut(), std::ptr::null_mut());
+    routing_route_geom_ex(from_wkt, to_wkt, mode, 0, no_from, no_to, out_result, out_points, max_points)
+}
+
+/// routing_route_geom that routes from an interior point of each geometry instead of its
+/// centroid if `interior` is non-zero (see geometry_routing_point), and writes the points
+/// used to out_from / out_to (either may be NULL) once both geometries are parsed
+/// Returns number of path points written, or -1 on error, -2 if not loaded, -5 if a
+/// geometry has a coordinate out of range
+#[no_mangle]
+pub extern "C" fn routing_route_geom_ex(
+    from_wkt: *const c_char,
+    to_wkt: *const c_char,
+    mode: *const c_char,
+    interior: i32,
+    out_from: *mut RoutePoint,
+    out_to: *mut RoutePoint,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    let from_wkt = match unsafe { CStr::from_ptr(from_wkt) }.to_str() {
+        Ok(s) if !from_wkt.is_null() => s,
+        _ => return -1,
+    };
+
+    let to_wkt = match unsafe { CStr::from_ptr(to_wkt) }.to_str() {
+        Ok(s) if !to_wkt.is_null() => s,
+        _ => return -1,
+    };
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    // Parse WKT and get the routing points
+    let (lon1, lat1) = match wkt_to_point(from_wkt, interior != 0) {
+        Ok(c) => c,
+        Err(e) => return geometry_arg_error("Start", e),
+    };
+
+    let (lon2, lat2) = match wkt_to_point(to_wkt, interior != 0) {
+        Ok(c) => c,
+        Err(e) => return geometry_arg_error("End", e),
+    };
+    write_routing_points(out_from, (lon1, lat1), out_to, (lon2, lat2));
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    write_geometry_route(router, (lon1, lat1), (lon2, lat2), out_result, out_points, max_points)
+}
+
+/// Calculate route with full geometry using WKB geometries as input
+/// Uses centroid of each geometry as routing point
+/// Returns number of path points written, or -1 on error, -2 if not loaded, -5 if a
+/// geometry has a coordinate out of range
+#[no_mangle]
+pub extern "C" fn routing_route_wkb(
+    from_wkb: *const u8,
+    from_wkb_len: i32,
+    to_wkb: *const u8,
+    to_wkb_len: i32,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    let (no_from, no_to) = (std::ptr::null_mut(), std::ptr::null_mut());
+    routing_route_wkb_ex(
+        from_wkb,
+        from_wkb_len,
+        to_wkb,
+        to_wkb_len,
+        mode,
+        0,
+        no_from,
+        no_to,
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// routing_route_wkb with the interior point option and routing point outputs of
+/// routing_route_geom_ex
+/// Returns number of path points written, or -1 on error, -2 if not loaded, -5 if a
+/// geometry has a coordinate out of range
+#[no_mangle]
+pub extern "C" fn routing_route_wkb_ex(
+    from_wkb: *const u8,
+    from_wkb_len: i32,
+    to_wkb: *const u8,
+    to_wkb_len: i32,
+    mode: *const c_char,
+    interior: i32,
+    out_from: *mut RoutePoint,
+    out_to: *mut RoutePoint,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if from_wkb.is_null() || to_wkb.is_null() || out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    let from_bytes = unsafe { std::slice::from_raw_parts(from_wkb, from_wkb_len as usize) };
+    let to_bytes = unsafe { std::slice::from_raw_parts(to_wkb, to_wkb_len as usize) };
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    // Parse WKB and get the routing points
+    let (lon1, lat1) = match wkb_to_point(from_bytes, interior != 0) {
+        Ok(c) => c,
+        Err(e) => return geometry_arg_error("Start", e),
+    };
+
+    let (lon2, lat2) = match wkb_to_point(to_bytes, interior != 0) {
+        Ok(c) => c,
+        Err(e) => return geometry_arg_error("End", e),
+    };
+    write_routing_points(out_from, (lon1, lat1), out_to, (lon2, lat2));
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    write_geometry_route(router, (lon1, lat1), (lon2, lat2), out_result, out_points, max_points)
+}
+
+/// Route between the (lon, lat) routing points of two geometries and write it as routing_route
+/// Returns number of path points written, or -1 if there is no route
+fn write_geometry_route(
+    router: &Router,
+    (lon1, lat1): (f64, f64),
+    (lon2, lat2): (f64, f64),
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    let Some((path_nodes, seconds)) = calc_route(router, lon1, lat1, lon2, lat2) else {
+        return -1;
+    };
+    let duration_s = seconds * duration_factor(router.mode);
+    write_route(&router.data, &path_nodes, duration_s, out_result, out_points, max_points)
+}
+
+/// Write the (lon, lat) routing points of a geometry route to its optional outputs
+fn write_routing_points(out_from: *mut RoutePoint, from: (f64, f64), out_to: *mut RoutePoint, to: (f64, f64)) {
+    for (out, (lon, lat)) in [(out_from, from), (out_to, to)] {
+        if !out.is_null() {
+            unsafe { *out = RoutePoint { lat, lon } };
+        }
+    }
+}
+
+/// Calculate a route through a sequence of waypoints (origin, stops..., destination)
+/// dwell_seconds (optional, count entries) adds a service time at each intermediate
+/// waypoint to the duration; entries for the origin and destination are ignored.
+/// Geometry and distance are those of the concatenated legs
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_via(
+    lats: *const f64,
+    lons: *const f64,
+    dwell_seconds: *const f64,
+    count: i32,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if lats.is_null() || lons.is_null() || out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+    if count < 2 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let count = count as usize;
+    let lats = unsafe { std::slice::from_raw_parts(lats, count) };
+    let lons = unsafe { std::slice::from_raw_parts(lons, count) };
+    if let Err(code) = coords_arg(lats.iter().copied().zip(lons.iter().copied())) {
+        return code;
+    }
+    let dwell_seconds = if dwell_seconds.is_null() {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(dwell_seconds, count) }
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let mut path_nodes: Vec<usize> = Vec::new();
+    let mut duration_s = 0.0;
+    for leg in 0..count - 1 {
+        let (leg_nodes, leg_s) = match calc_route(router, lons[leg], lats[leg], lons[leg + 1], lats[leg + 1]) {
+            Some(r) => r,
+            None => return -1,
+        };
+        // Consecutive legs share the waypoint node
+        let skip = usize::from(!path_nodes.is_empty());
+        path_nodes.extend(leg_nodes.into_iter().skip(skip));
+        duration_s += leg_s * duration_factor(mode);
+        if leg > 0 {
+            duration_s += dwell_seconds.get(leg).copied().filter(|d| d.is_finite() && *d > 0.0).unwrap_or(0.0);
+        }
+    }
+
+    write_route(&router.data, &path_nodes, duration_s, out_result, out_points, max_points)
+}
+
+/// Round trip from a point back to it taking about target_seconds, e.g. a recreational ride
+/// The route heads off in a direction picked by `seed` and curves back into a loop (see
+/// round_trip_path); the same seed gives the same route on the same graph. out_result holds
+/// the achieved distance and duration, so callers can drop trips that missed the target
+/// (e.g. from a dead end). Returns number of path points written, or -1 on error (including
+/// no loop from the start), -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_round_trip(
+    lat: f64,
+    lon: f64,
+    target_seconds: f64,
+    seed: i64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+    if !(target_seconds.is_finite() && target_seconds > 0.0) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat, lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let start = match find_nearest_node(&router.data, lon, lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let factor = duration_factor(mode);
+    let (path_nodes, cost_ms) = match round_trip_path(router, start, budget_ms(target_seconds, factor), seed as u64) {
+        Some(r) => r,
+        None => {
+            set_last_error("no round trip: no road from the start leads back to it within the target");
+            return -1;
+        }
+    };
+    let duration_s = cost_ms as f64 / 1000.0 * factor;
+
+    write_route(&router.data, &path_nodes, duration_s, out_result, out_points, max_points)
+}
+
+/// Calculate route and write its geometry as a WKT LINESTRING
+/// `precision` is the number of decimal places per coordinate (clamped to 0..=15)
+/// Returns the WKT length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_wkt(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    precision: i32,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, _) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let wkt = path_to_wkt(&router.data, &path_nodes, precision.clamp(0, 15) as usize);
+    write_c_string(&wkt, out_buf, buf_len)
+}
+
+/// Calculate route and write it as a GeoJSON Feature with a LineString geometry and
+/// distance_m / duration_s properties; coordinates match routing_route
+/// Returns the JSON length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_geojson(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (coords, duration_ms) = match snapped_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let geojson = route_to_geojson(&coords, coords_length_m(&coords), duration_ms as f64 / 1000.0 * duration_factor(mode));
+    write_c_string(&geojson, out_buf, buf_len)
+}
+
+/// Calculate route and write the street name changes along it as JSON:
+/// [{"index": 0, "name": "Via Roma"}, {"index": 7, "name": null}, ...]
+/// index refers to the points routing_route returns for the same query; each entry names
+/// the ways from that point up to the next entry (null for unnamed ways, using ref if present)
+/// Returns the JSON length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_named(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let route = match snapped_route_named(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    write_c_string(&name_changes_to_json(&route.names, &router.data.names), out_buf, buf_len)
+}
+
+/// Calculate route and write its geometry as an encoded polyline (Google / Valhalla format)
+/// `precision` is 5 (Google) or 6 (Valhalla, OSRM polyline6) decimal places; coordinates
+/// match routing_route. Distance, duration and point count go to out_result.
+/// Returns the polyline length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_polyline(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    precision: i32,
+    out_result: *mut RouteResult,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if out_result.is_null() || !matches!(precision, 5 | 6) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (coords, duration_ms) = match snapped_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    unsafe {
+        *out_result = RouteResult {
+            distance_m: DistanceUnit::current().convert_meters(coords_length_m(&coords)),
+            duration_s: duration_ms as f64 / 1000.0 * duration_factor(mode),
+            num_points: coords.len() as i32,
+        };
+    }
+    write_c_string(&encode_polyline(&coords, precision as u32), out_buf, buf_len)
+}
+
+/// Encode (lon, lat) coordinates with the polyline algorithm at `precision` decimal places
+/// Values are written lat, lon as deltas from the previous point, zigzag encoded in 5-bit chunks
+fn encode_polyline(coords: &[(f64, f64)], precision: u32) -> String {
+    let factor = 10f64.powi(precision as i32);
+    let mut encoded = String::new();
+    let mut previous = (0i64, 0i64);
+    for &(lon, lat) in coords {
+        let point = ((lat * factor).round() as i64, (lon * factor).round() as i64);
+        for delta in [point.0 - previous.0, point.1 - previous.1] {
+            let mut value = if delta < 0 { !(delta << 1) } else { delta << 1 };
+            while value >= 0x20 {
+                encoded.push((((value & 0x1f) | 0x20) as u8 + 63) as char);
+                value >>= 5;
+            }
+            encoded.push((value as u8 + 63) as char);
+        }
+        previous = point;
+    }
+    encoded
+}
+
+/// GeoJSON Feature for a route; distance_m is in the configured distance unit
+fn route_to_geojson(coords: &[(f64, f64)], distance_m: f64, duration_s: f64) -> String {
+    let coordinates: Vec<[f64; 2]> = coords.iter().map(|&(lon, lat)| [lon, lat]).collect();
+    serde_json::json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "LineString",
+            "coordinates": coordinates,
+        },
+        "properties": {
+            "distance_m": DistanceUnit::current().convert_meters(distance_m),
+            "duration_s": duration_s,
+        },
+    })
+    .to_string()
+}
+
+/// Calculate route and write its geometry as a WKT LINESTRING M for linear referencing
+/// The M value of each vertex is the cumulative distance in meters from the origin
+/// (default, measure_time = 0) or the cumulative travel time in seconds (measure_time != 0)
+/// Returns the WKT length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_wkt_m(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    precision: i32,
+    measure_time: i32,
+    out_buf: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, _) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let measures: Vec<f64> = if measure_time != 0 {
+        let factor = duration_factor(mode);
+        path_cumulative_ms(&router.data, &path_nodes)
+            .iter()
+            .map(|&ms| ms as f64 / 1000.0 * factor)
+            .collect()
+    } else {
+        path_cumulative_m(&router.data, &path_nodes)
+    };
+    let wkt = path_to_wkt_m(&router.data, &path_nodes, &measures, precision.clamp(0, 15) as usize);
+    write_c_string(&wkt, out_buf, buf_len)
+}
+
+/// Sample positions along a route at fixed time intervals (animation keyframes)
+/// Writes up to max_samples positions and their elapsed seconds; the arrival
+/// point is always the final sample, also when max_samples cuts the sampling short
+/// Returns number of samples written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_samples(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    interval_s: f64,
+    out_lats: *mut f64,
+    out_lons: *mut f64,
+    out_times: *mut f64,
+    max_samples: i32,
+) -> i32 {
+    if out_lats.is_null() || out_lons.is_null() || out_times.is_null() || max_samples <= 0 {
+        return -1;
+    }
+    if interval_s.is_nan() || interval_s <= 0.0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, _) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let factor = duration_factor(mode);
+    let max_samples = max_samples as usize;
+    let samples = sample_path(&router.data, &path_nodes, interval_s / factor, max_samples);
+    let out_lats = unsafe { std::slice::from_raw_parts_mut(out_lats, max_samples) };
+    let out_lons = unsafe { std::slice::from_raw_parts_mut(out_lons, max_samples) };
+    let out_times = unsafe { std::slice::from_raw_parts_mut(out_times, max_samples) };
+
+    for (i, &(lon, lat, t)) in samples.iter().enumerate() {
+        out_lats[i] = lat;
+        out_lons[i] = lon;
+        out_times[i] = t * factor;
+    }
+
+    samples.len() as i32
+}
+
+/// Calculate a route that avoids re-using already covered edges (patrol/coverage routing)
+/// Edges listed in used_edges have their weight multiplied by penalty_factor (>= 1.0).
+/// out_edges must hold max_points - 1 entries; out_edges[i] is the stable edge id
+/// from out_points[i] to out_points[i + 1], ready to feed back as used_edges.
+/// The reported duration is the unpenalized travel time of the chosen path.
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_cover(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    used_edges: *const i32,
+    used_count: i32,
+    penalty_factor: f64,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    out_edges: *mut i32,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || out_edges.is_null() || max_points <= 0 {
+        return -1;
+    }
+    if (used_edges.is_null() && used_count > 0) || used_count < 0 {
+        return -1;
+    }
+    if !penalty_factor.is_finite() || penalty_factor < 1.0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_origin_node(router, lon1, lat1) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let to_idx = match find_dest_node(router, lon2, lat2) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let used: std::collections::HashSet<usize> = if used_count > 0 {
+        unsafe { std::slice::from_raw_parts(used_edges, used_count as usize) }
+            .iter()
+            .filter(|&&e| e >= 0)
+            .map(|&e| e as usize)
+            .collect()
+    } else {
+        std::collections::HashSet::new()
+    };
+
+    let path = match dijkstra_path(router, from_idx, to_idx, |edge_id, base| {
+        if used.contains(&edge_id) {
+            Some(scale_weight(base, penalty_factor))
+        } else {
+            Some(base)
+        }
+    }) {
+        Some(p) => p,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let duration_ms: u64 = path
+        .edges
+        .iter()
+        .map(|&e| edge_base_weight_ms(router, e) as u64)
+        .sum();
+
+    let num_points = write_route(
+        &router.data,
+        &path.nodes,
+        duration_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    );
+
+    let out_edges = unsafe { std::slice::from_raw_parts_mut(out_edges, max_points as usize - 1) };
+    for (out, &edge_id) in out_edges.iter_mut().zip(&path.edges) {
+        *out = edge_id as i32;
+    }
+
+    num_points
+}
+
+/// Load query-time extra delays keyed by OSM way id (e.g. construction zones)
+/// Replaces any previously loaded delays for the mode; count 0 clears them.
+/// Each way's delay is spread over its edges so a full traversal incurs it once.
+/// Honored by routing_route_with_delays only (the CH graph is unchanged).
+/// Returns number of delayed edges, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_load_way_delays(
+    mode: *const c_char,
+    way_ids: *const i64,
+    delays_s: *const f64,
+    count: i32,
+) -> i32 {
+    if count < 0 || (count > 0 && (way_ids.is_null() || delays_s.is_null())) {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let mut guard = match lock.write() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_mut() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let mut way_delays_s: HashMap<i64, f64> = HashMap::new();
+    if count > 0 {
+        let way_ids = unsafe { std::slice::from_raw_parts(way_ids, count as usize) };
+        let delays_s = unsafe { std::slice::from_raw_parts(delays_s, count as usize) };
+        for (&way_id, &delay) in way_ids.iter().zip(delays_s) {
+            if delay.is_finite() && delay > 0.0 {
+                *way_delays_s.entry(way_id).or_insert(0.0) += delay;
+            }
+        }
+    }
+
+    router.edge_delays_ms = way_delays_to_edges(router, &way_delays_s);
+    router.edge_delays_ms.len() as i32
+}
+
+/// Clear query-time way delays for a mode, restoring baseline weights
+#[no_mangle]
+pub extern "C" fn routing_clear_way_delays(mode: *const c_char) {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+
+    if let Ok(mut guard) = get_router_for_mode(mode).write() {
+        if let Some(router) = guard.as_mut() {
+            router.edge_delays_ms.clear();
+        }
+    }
+}
+
+/// Calculate route honoring the way delays loaded with routing_load_way_delays
+/// The reported duration includes the delays along the chosen path
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_with_delays(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_origin_node(router, lon1, lat1) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let to_idx = match find_dest_node(router, lon2, lat2) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let path = match dijkstra_path(router, from_idx, to_idx, |edge_id, base| {
+        Some(base.saturating_add(router.edge_delays_ms.get(&edge_id).copied().unwrap_or(0)))
+    }) {
+        Some(p) => p,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    write_route(
+        &router.data,
+        &path.nodes,
+        path.cost_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// Add a two-way what-if edge (e.g. a proposed road) between the nodes nearest to both points
+/// The edge is seen by the adj_list queries (routing_route_dynamic, cover, delays),
+/// not by the contraction hierarchy. Travel time is the straight-line distance at speed_kmh.
+/// Returns 0 on success, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_add_edge(
+    from_lat: f64,
+    from_lon: f64,
+    to_lat: f64,
+    to_lon: f64,
+    speed_kmh: f64,
+    mode: *const c_char,
+) -> i32 {
+    if !speed_kmh.is_finite() || speed_kmh <= 0.0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(from_lat, from_lon), (to_lat, to_lon)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let mut guard = match lock.write() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_mut() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_nearest_node(&router.data, from_lon, from_lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let to_idx = match find_nearest_node(&router.data, to_lon, to_lat) {
+        Some(idx) => idx,
+        None => return -1,
+    };
+
+    let dist_m = path_distance_m(&router.data, &[from_idx, to_idx]);
+    let weight_ms = ((dist_m / 1000.0 / speed_kmh) * 3600.0 * 1000.0).min(u32::MAX as f64) as u32;
+    router.add_edge(from_idx, to_idx, weight_ms);
+    router.add_edge(to_idx, from_idx, weight_ms);
+    0
+}
+
+/// Remove all what-if edges for a mode, restoring the baseline graph
+#[no_mangle]
+pub extern "C" fn routing_clear_added_edges(mode: *const c_char) {
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+
+    if let Ok(mut guard) = get_router_for_mode(mode).write() {
+        if let Some(router) = guard.as_mut() {
+            router.clear_added_edges();
+        }
+    }
+}
+
+/// Calculate route with the plain Dijkstra query, including edges added with routing_add_edge
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_dynamic(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_origin_node(router, lon1, lat1) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let to_idx = match find_dest_node(router, lon2, lat2) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let path = match dijkstra_path(router, from_idx, to_idx, |_, base| Some(base)) {
+        Some(p) => p,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    write_route(
+        &router.data,
+        &path.nodes,
+        path.cost_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+// Extra cost of every tolled edge when avoiding tolls; large enough that toll roads are only
+// used where no toll-free route exists at all
+const TOLL_PENALTY_MS: u32 = 3_600_000;
+
+/// Dijkstra path that avoids tolled edges unless no toll-free route exists
+/// cost_ms is the actual travel time along the path, without the penalties
+fn toll_free_path(router: &Router, from: usize, to: usize) -> Option<DynamicPath> {
+    let tolled = |edge_id: usize| router.data.edge_info.get(edge_id).is_some_and(|info| info.toll);
+    let mut path = dijkstra_path(router, from, to, |edge_id, base| {
+        Some(if tolled(edge_id) { base.saturating_add(TOLL_PENALTY_MS) } else { base })
+    })?;
+    path.cost_ms = path.edges.iter().fold(0u32, |total, &e| total.saturating_add(edge_base_weight_ms(router, e)));
+    Some(path)
+}
+
+/// Match a GPS trace onto the road network (see match_trace) and write the matched path
+/// timestamps (seconds, increasing) may be NULL. The result holds the matched distance and
+/// the graph's travel time along the matched path.
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_match(
+    lats: *const f64,
+    lons: *const f64,
+    timestamps: *const f64,
+    count: i32,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if lats.is_null() || lons.is_null() || out_result.is_null() || out_points.is_null() {
+        return -1;
+    }
+    if count <= 0 || max_points <= 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let lats = unsafe { std::slice::from_raw_parts(lats, count as usize) };
+    let lons = unsafe { std::slice::from_raw_parts(lons, count as usize) };
+    if let Err(code) = coords_arg(lats.iter().copied().zip(lons.iter().copied())) {
+        return code;
+    }
+    let timestamps = (!timestamps.is_null()).then(|| unsafe { std::slice::from_raw_parts(timestamps, count as usize) });
+    let points: Vec<(f64, f64)> = lons.iter().copied().zip(lats.iter().copied()).collect();
+
+    let (path_nodes, duration_ms) = match match_trace(router, &points, timestamps) {
+        Some(m) => m,
+        None => return -1,
+    };
+
+    write_route(
+        &router.data,
+        &path_nodes,
+        duration_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// Stable ids of the edges touching `area`: with an endpoint inside it, or crossing it
+fn edges_in_area(router: &Router, area: &geo::MultiPolygon<f64>) -> HashSet<usize> {
+    use geo::{BoundingRect, Intersects};
+    let data = &router.data;
+    let mut blocked = HashSet::new();
+    let Some(bounds) = area.bounding_rect() else {
+        return blocked;
+    };
+    let (min, max) = (bounds.min(), bounds.max());
+    for node in 0..data.node_positions.len() {
+        for (i, &(next, _)) in data.adj_list[node].iter().enumerate() {
+            let edge_id = router.edge_offsets[node] + i;
+            let line = geo::LineString::from(data.edge_shapes.coords(
+                edge_id,
+                data.node_positions[node],
+                data.node_positions[next],
+            ));
+            // Cheap envelope test first; most edges are nowhere near the area
+            let Some(envelope) = line.bounding_rect() else { continue };
+            let (lo, hi) = (envelope.min(), envelope.max());
+            if hi.x < min.x || lo.x > max.x || hi.y < min.y || lo.y > max.y {
+                continue;
+            }
+            if area.intersects(&line) {
+                blocked.insert(edge_id);
+            }
+        }
+    }
+    blocked
+}
+
+/// Calculate route around an area (e.g. a temporary closure) given as a WKT/EWKT POLYGON or
+/// MULTIPOLYGON: edges with an endpoint inside it or crossing it are not used, and the
+/// endpoints snap to the nearest nodes outside it
+/// Returns number of path points written, or -1 on error (invalid area, or no route around
+/// it; see routing_last_error), -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_avoid_area(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    avoid_wkt: *const c_char,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if avoid_wkt.is_null() || out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let area = unsafe { CStr::from_ptr(avoid_wkt) }.to_str().context("Avoid area is not UTF-8");
+    let area = match area.and_then(parse_avoid_area) {
+        Ok(a) => a,
+        Err(e) => {
+            set_last_error(format!("{:#}", e));
+            return -1;
+        }
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    use geo::Intersects;
+    let outside = |node: usize| {
+        let (lon, lat) = router.data.node_positions[node];
+        !area.intersects(&Point::new(lon, lat))
+    };
+    let (from_idx, to_idx) = match (
+        find_nearest_node_where(&router.data, lon1, lat1, outside),
+        find_nearest_node_where(&router.data, lon2, lat2, outside),
+    ) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let blocked = edges_in_area(router, &area);
+    let open = |edge_id: usize, base: u32| (!blocked.contains(&edge_id)).then_some(base);
+    let path = match dijkstra_path(router, from_idx, to_idx, open) {
+        Some(p) => p,
+        None if dijkstra_path(router, from_idx, to_idx, |_, base| Some(base)).is_some() => {
+            set_last_error("no path: the avoid area disconnects origin and destination".to_string());
+            return -1;
+        }
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    write_route(
+        &router.data,
+        &path.nodes,
+        path.cost_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// Calculate route avoiding toll roads (toll=yes) where a toll-free route exists
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_avoid_tolls(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_origin_node(router, lon1, lat1) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let to_idx = match find_dest_node(router, lon2, lat2) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let path = match toll_free_path(router, from_idx, to_idx) {
+        Some(p) => p,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    write_route(
+        &router.data,
+        &path.nodes,
+        path.cost_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// Calculate the shortest-distance route (instead of the fastest)
+/// The duration is the travel time along the returned path
+/// Returns number of path points written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_shortest(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_result: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_result.is_null() || out_points.is_null() || max_points <= 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let path_nodes = match calc_shortest_route(router, lon1, lat1, lon2, lat2) {
+        Some(nodes) => nodes,
+        None => return -1,
+    };
+
+    let duration_ms = path_cumulative_ms(&router.data, &path_nodes).last().copied().unwrap_or(0);
+    write_route(
+        &router.data,
+        &path_nodes,
+        duration_ms as f64 / 1000.0 * duration_factor(mode),
+        out_result,
+        out_points,
+        max_points,
+    )
+}
+
+/// Calculate up to k alternative routes, the optimal route first
+/// Alternatives share at most max_shared_fraction (0..1) of their travel time with previously
+/// returned routes and are at most 1.5x slower than the optimum; fewer than k routes are
+/// returned when the network offers nothing distinct.
+/// out_results must hold k results; route i's points go to out_points[i * max_points..]
+/// (so out_points must hold k * max_points points)
+/// Returns number of routes written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_alternatives(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    k: i32,
+    max_shared_fraction: f64,
+    out_results: *mut RouteResult,
+    out_points: *mut RoutePoint,
+    max_points: i32,
+) -> i32 {
+    if out_results.is_null() || out_points.is_null() || max_points <= 0 || k <= 0 {
+        return -1;
+    }
+    if !(0.0..=1.0).contains(&max_shared_fraction) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let from_idx = match find_origin_node(router, lon1, lat1) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let to_idx = match find_dest_node(router, lon2, lat2) {
+        Some(idx) => idx,
+        None => return record_route_failure(&router.data, lon1, lat1, lon2, lat2),
+    };
+
+    let paths = alternative_paths(router, from_idx, to_idx, k as usize, max_shared_fraction);
+    if paths.is_empty() {
+        return record_route_failure(&router.data, lon1, lat1, lon2, lat2);
+    }
+
+    let factor = duration_factor(mode);
+    for (i, path) in paths.iter().enumerate() {
+        let duration_ms: u64 = path.edges.iter().map(|&e| edge_base_weight_ms(router, e) as u64).sum();
+        write_route(
+            &router.data,
+            &path.nodes,
+            duration_ms as f64 / 1000.0 * factor,
+            unsafe { out_results.add(i) },
+            unsafe { out_points.add(i * max_points as usize) },
+            max_points,
+        );
+    }
+    paths.len() as i32
+}
+
+/// Signed turn angle (degrees, positive = right) at each interior node of a route
+/// Returns number of angles written, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_turn_angles(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_angles: *mut f64,
+    max_angles: i32,
+) -> i32 {
+    if out_angles.is_null() || max_angles < 0 {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, _) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let out_angles = unsafe { std::slice::from_raw_parts_mut(out_angles, max_angles as usize) };
+    let mut count = 0;
+    for (out, w) in out_angles.iter_mut().zip(path_nodes.windows(3)) {
+        *out = turn_angle_deg(&router.data, w[0], w[1], w[2]);
+        count += 1;
+    }
+
+    count
+}
+
+/// Calculate route and write turn-by-turn directions as JSON:
+/// {"distance_m", "duration_s", "maneuvers": [{"type", "street", "distance_m",
+/// "duration_s", "lat", "lon"}]}. Maneuver types are depart, continue,
+/// slight_left/right, left/right, sharp_left/right, uturn and arrive; each maneuver's
+/// distance and duration cover the stretch up to the next one
+/// Returns the JSON length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_directions(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_json: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, duration_s) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let factor = duration_factor(mode);
+    let mut maneuvers = build_maneuvers(router, &path_nodes);
+    for maneuver in &mut maneuvers {
+        maneuver.duration_s *= factor;
+    }
+    let distance_m = path_distance_m(&router.data, &path_nodes);
+    let json = maneuvers_to_json(&router.data, &maneuvers, distance_m, duration_s * factor, DistanceUnit::current());
+    write_c_string(&json, out_json, buf_len)
+}
+
+/// Calculate route and write its per-edge breakdown as a JSON array (see route_segments_to_json)
+/// Returns JSON length in bytes (nothing is written if >= buf_len), or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_segments(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_json: *mut c_char,
+    buf_len: i32,
+) -> i32 {
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (path_nodes, _) = match calc_route(router, lon1, lat1, lon2, lat2) {
+        Some(r) => r,
+        None => return -1,
+    };
+
+    let json = route_segments_to_json(router, &path_nodes, duration_factor(mode), DistanceUnit::current());
+    write_c_string(&json, out_json, buf_len)
+}
+
+/// OSM way ids along the route between two coordinates, e.g. to trace a surprising travel
+/// time back to the ways it used; consecutive edges of one way give a single entry
+/// Writes up to max_ids ids; returns the total number of ways, -1 on error or if no route
+/// found, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_route_way_ids(
+    lat1: f64,
+    lon1: f64,
+    lat2: f64,
+    lon2: f64,
+    mode: *const c_char,
+    out_ids: *mut i64,
+    max_ids: i32,
+) -> i32 {
+    if max_ids < 0 || (max_ids > 0 && out_ids.is_null()) {
+        return -1;
+    }
+
+    if let Err(code) = coords_arg([(lat1, lon1), (lat2, lon2)]) {
+        return code;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let way_ids = match snapped_way_ids(router, lon1, lat1, lon2, lat2) {
+        Some(ids) => ids,
+        None => return -1,
+    };
+
+    if max_ids > 0 {
+        let out = unsafe { std::slice::from_raw_parts_mut(out_ids, max_ids as usize) };
+        for (slot, &way_id) in out.iter_mut().zip(&way_ids) {
+            *slot = way_id;
+        }
+    }
+    way_ids.len() as i32
+}
+
+/// Find the meeting point for a group: the network node minimising the summed travel time
+/// from all origins, or the worst single travel time when minimize_max is non-zero.
+/// One shortest-path tree per origin, computed in parallel.
+/// Returns 0 on success, -1 on error or if no node is reachable from every origin, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_meeting_point(
+    lats: *const f64,
+    lons: *const f64,
+    n_origins: i32,
+    mode: *const c_char,
+    minimize_max: i32,
+    out_lat: *mut f64,
+    out_lon: *mut f64,
+    out_cost: *mut f64,
+) -> i32 {
+    if lats.is_null() || lons.is_null() || out_lat.is_null() || out_lon.is_null() || out_cost.is_null() {
+        return -1;
+    }
+    if n_origins <= 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let n = n_origins as usize;
+    let lats = unsafe { std::slice::from_raw_parts(lats, n) };
+    let lons = unsafe { std::slice::from_raw_parts(lons, n) };
+    if let Err(code) = coords_arg(lats.iter().copied().zip(lons.iter().copied())) {
+        return code;
+    }
+
+    let mut origins = Vec::with_capacity(n);
+    for i in 0..n {
+        match find_nearest_node(&router.data, lons[i], lats[i]) {
+            Some(idx) => origins.push(idx),
+            None => return -1,
+        }
+    }
+
+    let (node, cost_ms) = match meeting_node(&router.data, &origins, minimize_max != 0) {
+        Some(m) => m,
+        None => return -1,
+    };
+
+    let (lon, lat) = router.data.node_positions[node];
+    unsafe {
+        *out_lat = lat;
+        *out_lon = lon;
+        *out_cost = cost_ms as f64 / 1000.0 * duration_factor(mode);
+    }
+    0
+}
+
+/// Benchmark: route n_queries random origin/destination pairs drawn uniformly from the
+/// graph's bounding box (fixed seed, so runs are reproducible) through the regular
+/// snap + contraction hierarchy query path, and report the throughput
+/// Returns number of queries that found a route, or -1 on error, -2 if not loaded
+#[no_mangle]
+pub extern "C" fn routing_benchmark(mode: *const c_char, n_queries: i32, out_queries_per_sec: *mut f64) -> i32 {
+    if out_queries_per_sec.is_null() || n_queries <= 0 {
+        return -1;
+    }
+
+    let mode = match mode_arg(mode) {
+        Ok(m) => m,
+        Err(code) => return code,
+    };
+
+    let lock = get_router_for_mode(mode);
+    let guard = match lock.read() {
+        Ok(g) => g,
+        Err(_) => return -1,
+    };
+
+    let router = match guard.as_ref() {
+        Some(r) => r,
+        None => return not_loaded(mode),
+    };
+
+    let (min_lon, min_lat, max_lon, max_lat) = match graph_bounds(&router.data) {
+        Some(b) => b,
+        None => return -1,
+    };
+
+    let mut rng = SplitMix64(n_queries as u64);
+    let mut random_point = || {
+        (
+            min_lon + rng.next_f64() * (max_lon - min_lon),
+            min_lat + rng.next_f64() * (max_lat - min_lat),
+        )
+    };
+    let pairs: Vec<((f64, f64), (f64, f64))> = (0..n_queries).map(|_| (random_point(), random_point())).collect();
+
+    let start = std::time::Instant::now();
+    let mut routed = 0;
+    for &((lon1, lat1), (lon2, lat2)) in &pairs {
+        if calc_route(router, lon1, lat1, lon2, lat2).is_some() {
+            routed += 1;
+        }
+    }
+    let elapsed_s = start.elapsed().as_secs_f64();
+
+    unsafe {
+        *out_queries_per_sec = if elapsed_s > 0.0 { n_queries as f64 / elapsed_s } else { f64::INFINITY };
+    }
+    routed
+}
+
+/// Copy the message describing the most recent failure on the calling thread
+/// (e.g. why a route function returned -1). Messages are not cleared on success.
+/// Returns the message length in bytes (>= buf_len means the buffer was too small
+/// and nothing was written); 0 if nothing failed yet
+#[no_mangle]
+pub extern "C" fn routing_last_error(out_buf: *mut c_char, buf_len: i32) -> i32 {
+    LAST_ERROR.with(|e| write_c_string(&e.borrow(), out_buf, buf_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small graph fixture: every node is indexed for snapping
+    fn make_data(node_positions: Vec<(f64, f64)>, edges: &[(usize, usize, u32)]) -> RoutingData {
+        let mut adj_list: AdjList = vec![Vec::new(); node_positions.len()];
+        let mut input_graph = InputGraph::new();
+        for &(from, to, weight) in edges {
+            input_graph.add_edge(from, to, weight as usize);
+            adj_list[from].push((to, weight));
+        }
+        input_graph.freeze();
+        let rtree_points = node_positions
+            .iter()
+            .enumerate()
+            .map(|(idx, &(lon, lat))| IndexedPoint { lon, lat, idx })
+            .collect();
+        let component_ids = compute_components(&adj_list);
+        RoutingData {
+            source_component_count: component_ids.iter().map(|&c| c as usize + 1).max().unwrap_or(0),
+            exclude: 0,
+            fast_graph: fast_paths::prepare(&input_graph),
+            distance_graph: prepare_distance_graph(
+                &node_positions,
+                &adj_list,
+                &EdgeShapes::default(),
+                &ChParams::default(),
+                None,
+            ),
+            spatial_index: RTree::bulk_load(rtree_points),
+            node_positions: node_positions.into(),
+            component_ids,
+            edge_info: adj_list
+                .iter()
+                .flatten()
+                .enumerate()
+                .map(|(i, _)| EdgeInfo {
+                    way_id: i as i64 + 1,
+                    name: NO_NAME,
+                    highway: HIGHWAY_OTHER,
+                    roundabout: false,
+                    toll: false,
+                })
+                .collect(),
+            adj_list: adj_list.into(),
+            names: Vec::new(),
+            turn_restrictions: Vec::new(),
+            edge_shapes: EdgeShapes::default(),
+        }
+    }
+
+    #[test]
+    fn test_speed_lookup() {
+        assert_eq!(get_speed_kmh("motorway", "auto"), Some(120.0));
+        assert_eq!(get_speed_kmh("cycleway", "bicycle"), Some(20.0));
+        assert_eq!(get_speed_kmh("footway", "pedestrian"), Some(5.0));
+        assert_eq!(get_speed_kmh("railway", "auto"), None);
+    }
+
+    #[test]
+    fn test_speed_lookup_newer_types() {
+        for mode in ["auto", "bicycle", "pedestrian", "agricultural", "hgv"] {
+            assert_eq!(get_speed_kmh("road", mode), get_speed_kmh("unclassified", mode), "{}", mode);
+            assert_eq!(get_speed_kmh("services", mode), get_speed_kmh("service", mode), "{}", mode);
+        }
+        let profile = Profile::default();
+        let speed = |highway: &str, mode: &str, pairs: &[(&str, &str)]| {
+            way_speed_kmh(&tags(pairs), highway, mode, &profile)
+        };
+
+        // Busways and raceways need an explicit permission for every mode
+        for mode in ["auto", "bicycle", "pedestrian"] {
+            assert_eq!(speed("busway", mode, &[]), None, "{}", mode);
+            assert_eq!(speed("raceway", mode, &[]), None, "{}", mode);
+        }
+        assert_eq!(speed("busway", "auto", &[("psv", "yes")]), None);
+        assert_eq!(speed("busway", "auto", &[("motorcar", "yes")]), Some(40.0));
+        assert_eq!(speed("busway", "bicycle", &[("bicycle", "designated")]), Some(18.0));
+        assert_eq!(speed("busway", "agricultural", &[("access", "yes")]), None);
+
+        // Cars use tracks and paths only where motor vehicles are allowed
+        assert_eq!(speed("track", "auto", &[]), None);
+        assert_eq!(speed("track", "auto", &[("motor_vehicle", "yes")]), Some(15.0));
+        assert_eq!(speed("track", "auto", &[("motor_vehicle", "agricultural")]), None);
+        assert_eq!(speed("path", "auto", &[("motor_vehicle", "destination")]), Some(10.0));
+        assert_eq!(speed("path", "auto", &[("motorcar", "no"), ("motor_vehicle", "yes")]), None);
+    }
+
+    #[test]
+    fn test_is_main_road() {
+        assert!(is_main_road("motorway"));
+        assert!(is_main_road("residential"));
+        assert!(!is_main_road("footway"));
+        assert!(!is_main_road("cycleway"));
+    }
+
+    #[test]
+    fn test_rtree_nearest() {
+        let points = vec![
+            IndexedPoint { lon: 0.0, lat: 0.0, idx: 0 },
+            IndexedPoint { lon: 1.0, lat: 1.0, idx: 1 },
+            IndexedPoint { lon: 2.0, lat: 2.0, idx: 2 },
+        ];
+        let tree = RTree::bulk_load(points);
+
+        let nearest = tree.nearest_neighbor(&[0.1, 0.1]).unwrap();
+        assert_eq!(nearest.idx, 0);
+
+        let nearest = tree.nearest_neighbor(&[1.9, 1.9]).unwrap();
+        assert_eq!(nearest.idx, 2);
+    }
+
+    #[test]
+    fn test_cache_path() {
+        assert_eq!(
+            cache_path("/data/italy.osm.pbf", "auto"),
+            "/data/italy.osm.pbf.auto.routing"
+        );
+        let chains = BuildOptions {
+            contract_chains: true,
+            ..Default::default()
+        };
+        assert_eq!(cache_path_for("/data/italy.osm.pbf", "auto", &chains), "/data/italy.osm.pbf.auto.chains.routing");
+        let all_nodes = BuildOptions {
+            snap_all_roads: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            cache_path_for("/data/italy.osm.pbf", "auto", &all_nodes),
+            "/data/italy.osm.pbf.auto.allnodes.routing"
+        );
+    }
+
+    #[test]
+    fn test_pbf_set_key() {
+        assert_eq!(pbf_set_key(&["/data/it.osm.pbf"]), "/data/it.osm.pbf");
+        let key = pbf_set_key(&["/data/it.osm.pbf", "/data/ch.osm.pbf"]);
+        assert!(key.starts_with("/data/ch.osm.pbf."));
+        assert_eq!(key, pbf_set_key(&["/data/ch.osm.pbf", "/data/it.osm.pbf", "/data/ch.osm.pbf"]));
+        assert_ne!(key, pbf_set_key(&["/data/ch.osm.pbf", "/data/at.osm.pbf"]));
+    }
+
+    #[test]
+    fn test_path_to_wkt() {
+        let data = make_data(vec![(12.5, 41.9), (12.51, 41.91)], &[(0, 1, 1000)]);
+        assert_eq!(
+            path_to_wkt(&data, &[0, 1], 2),
+            "LINESTRING(12.50 41.90, 12.51 41.91)"
+        );
+        // Single-node paths still produce a valid two-point LINESTRING
+        assert_eq!(path_to_wkt(&data, &[1], 1), "LINESTRING(12.5 41.9, 12.5 41.9)");
+    }
+
+    #[test]
+    fn test_write_c_string() {
+        let mut buf = [0 as c_char; 8];
+        assert_eq!(write_c_string("POINT", buf.as_mut_ptr(), 8), 5);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap(), "POINT");
+        // Too small: returns needed length, leaves buffer untouched
+        let mut small = [0 as c_char; 4];
+        assert_eq!(write_c_string("POINT", small.as_mut_ptr(), 4), 5);
+        assert_eq!(small, [0; 4]);
+    }
+
+    #[test]
+    fn test_antimeridian_distance() {
+        // 0.02 degrees of longitude at the equator, straddling ±180
+        let east = Point::new(179.99, 0.0);
+        let west = Point::new(-179.99, 0.0);
+        let across: f64 = Haversine::distance(east, west);
+        let same_side = Haversine::distance(Point::new(10.0, 0.0), Point::new(10.02, 0.0));
+        assert!((across - same_side).abs() < 1e-6);
+        assert!(across < 2300.0);
+    }
+
+    #[test]
+    fn test_antimeridian_snap() {
+        // Planar nearest to -179.99 is -170.0, but 179.99 is only ~2 km away
+        let data = make_data(vec![(179.99, 0.0), (-170.0, 0.0)], &[(0, 1, 1000)]);
+        assert_eq!(find_nearest_node(&data, -179.99, 0.0), Some(0));
+        assert_eq!(find_nearest_node(&data, -170.5, 0.0), Some(1));
+        assert_eq!(find_nearest_node(&data, 179.5, 0.0), Some(0));
+
+        // Edge snapping too: the segment ending at 179.99 is the nearest, projected across the line
+        let data = make_data(
+            vec![(179.98, 0.0), (179.99, 0.0), (-170.0, 0.0), (-169.99, 0.0)],
+            &[(0, 1, 1000), (2, 3, 1000)],
+        );
+        let router = Router::new(data);
+        let snap = find_nearest_edge(&router, -179.995, 0.0005).unwrap();
+        assert_eq!((snap.from, snap.to, snap.lon, snap.lat), (0, 1, 179.99, 0.0));
+        assert!(snap.distance_m < 2000.0);
+    }
+
+    #[test]
+    fn test_sample_path() {
+        // Two 10 s segments along the equator
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)],
+            &[(0, 1, 10_000), (1, 2, 10_000)],
+        );
+        let samples = sample_path(&data, &[0, 1, 2], 5.0, 100);
+        let times: Vec<f64> = samples.iter().map(|s| s.2).collect();
+        assert_eq!(times, vec![0.0, 5.0, 10.0, 15.0, 20.0]);
+        assert!((samples[1].0 - 0.005).abs() < 1e-9);
+        assert!((samples[3].0 - 0.015).abs() < 1e-9);
+        assert_eq!((samples[4].0, samples[4].1), (0.02, 0.0));
+
+        // Interval longer than the route: departure and arrival only
+        assert_eq!(sample_path(&data, &[0, 1, 2], 60.0, 100).len(), 2);
+
+        // A cap stops the sampling, keeping the arrival last, even for a vanishing interval
+        let capped = sample_path(&data, &[0, 1, 2], 5.0, 3);
+        assert_eq!(capped.iter().map(|s| s.2).collect::<Vec<_>>(), vec![0.0, 5.0, 20.0]);
+        assert_eq!(sample_path(&data, &[0, 1, 2], 1e-12, 4).len(), 4);
+        assert_eq!(sample_path(&data, &[0, 1, 2], 5.0, 1), vec![(0.02, 0.0, 20.0)]);
+    }
+
+    #[test]
+    fn test_dijkstra_path_penalty() {
+        // Square 0-1-3 (fast) and 0-2-3 (slightly slower)
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.01), (0.01, -0.01), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 3, 1000), (0, 2, 1100), (2, 3, 1100)],
+        );
+        let router = Router::new(data);
+
+        let path = dijkstra_path(&router, 0, 3, |_, w| Some(w)).unwrap();
+        assert_eq!(path.nodes, vec![0, 1, 3]);
+        assert_eq!(path.cost_ms, 2000);
+        assert_eq!(path.edges.len(), 2);
+        assert_eq!(edge_base_weight_ms(&router, path.edges[0]), 1000);
+
+        // Penalizing the covered edges steers the route onto fresh roads
+        let used = path.edges.clone();
+        let detour = dijkstra_path(&router, 0, 3, |e, w| {
+            Some(if used.contains(&e) { w * 2 } else { w })
+        })
+        .unwrap();
+        assert_eq!(detour.nodes, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn test_compute_components() {
+        // 0 -> 1 <- 2 is one weak component, 3 <-> 4 another, 5 is isolated
+        let data = make_data(
+            vec![(0.0, 0.0), (0.1, 0.0), (0.2, 0.0), (1.0, 1.0), (1.1, 1.0), (5.0, 5.0)],
+            &[(0, 1, 100), (2, 1, 100), (3, 4, 100), (4, 3, 100)],
+        );
+        assert_eq!(data.component_ids, vec![0, 0, 0, 1, 1, 2]);
+        let router = Router::new(data);
+        assert_eq!(router.component_sizes, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_read_graph_roundtrip() {
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000), (1, 0, 1000)]);
+        let mut bytes = Vec::new();
+        write_graph(&mut bytes, &CacheHeader::new("auto", &BuildOptions::default()), &data).unwrap();
+        let loaded = read_graph(bytes.as_slice(), "auto", Some(0)).unwrap();
+        assert_eq!(loaded.node_positions, data.node_positions);
+        assert_eq!(loaded.adj_list, data.adj_list);
+
+        assert!(read_graph(&bytes[..bytes.len() / 2], "auto", None).is_err());
+        assert!(read_graph(&b"not a graph"[..], "auto", None).is_err());
+    }
+
+    #[test]
+    fn test_mapped_cache() {
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.005)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 700)],
+        );
+        let path = std::env::temp_dir().join(format!("routing-mapped-{}.routing", std::process::id()));
+        let path = path.to_str().unwrap();
+        let header = CacheHeader::new("auto", &BuildOptions::default());
+        save_graph(&data, &header, path, 0).unwrap();
+
+        let loaded = load_graph(path, &header).unwrap();
+        if MAPPABLE_LAYOUT {
+            assert!(matches!(loaded.node_positions, FlatVec::Mapped { .. }));
+            assert!(matches!(loaded.adj_list.edges, FlatVec::Mapped { .. }));
+        }
+        assert_eq!(loaded.node_positions, data.node_positions);
+        assert_eq!(loaded.adj_list, data.adj_list);
+        assert_eq!(&loaded.adj_list[1], &[(0, 1000), (2, 700)]);
+
+        // Rewriting the cache leaves an existing mapping intact
+        save_graph(&make_data(vec![(5.0, 5.0), (5.01, 5.0)], &[(0, 1, 500)]), &header, path, 0).unwrap();
+        let router = Router::new(loaded);
+        assert_eq!(calc_route(&router, 0.0, 0.0, 0.02, 0.005).unwrap().1, 1.7);
+        assert_eq!(load_graph(path, &header).unwrap().node_positions.len(), 2);
+
+        // Compressed caches are detected by their magic and loaded into memory
+        save_graph(&data, &header, path, 3).unwrap();
+        let compressed = std::fs::read(path).unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+        let loaded = load_graph(path, &header).unwrap();
+        assert!(matches!(loaded.node_positions, FlatVec::Owned(_)));
+        assert_eq!(loaded.node_positions, data.node_positions);
+        assert_eq!(loaded.adj_list, data.adj_list);
+        assert_eq!(read_graph(&compressed, "auto", None).unwrap().adj_list, data.adj_list);
+        assert!(read_graph(&compressed[..compressed.len() / 2], "auto", None).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_cache_header() {
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000), (1, 0, 1000)]);
+        let write = |header: &CacheHeader| {
+            let mut bytes = Vec::new();
+            write_graph(&mut bytes, header, &data).unwrap();
+            bytes
+        };
+        let error = |bytes: &[u8], mode, profile_hash| match read_graph(bytes, mode, profile_hash) {
+            Ok(_) => String::new(),
+            Err(e) => e.to_string(),
+        };
+
+        let options = BuildOptions {
+            profile: Profile::from_json(r#"{"residential": 20}"#).unwrap(),
+            ..Default::default()
+        };
+        let bytes = write(&CacheHeader::new("bicycle", &options));
+        assert!(read_graph(bytes.as_slice(), "bicycle", Some(options.profile.hash())).is_ok());
+        assert!(read_graph(bytes.as_slice(), "bicycle", None).is_ok());
+        assert!(error(&bytes, "auto", None).contains("built for mode 'bicycle'"));
+        assert!(error(&bytes, "bicycle", Some(0)).contains("different speed profile"));
+
+        let mut stale = CacheHeader::new("auto", &BuildOptions::default());
+        stale.crate_version = "0.0.0-old".to_string();
+        assert!(error(&write(&stale), "auto", None).contains("written by version 0.0.0-old"));
+        stale = CacheHeader::new("auto", &BuildOptions::default());
+        stale.format_version += 1;
+        assert!(error(&write(&stale), "auto", None).contains("written by version"));
+
+        // Caches from before the header existed are rejected up front
+        let legacy = bincode::serialize(&data).unwrap();
+        assert!(error(&legacy, "auto", None).starts_with("Not a routing cache"));
+    }
+
+    #[test]
+    fn test_isochrone_nodes_and_centroid() {
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000)],
+        );
+        let reached = isochrone_nodes(&data, 0, 2000);
+        assert_eq!(reached, vec![(0, 0), (1, 1000), (2, 2000)]);
+
+        let (lon, lat) = mean_position(&data, &[0, 1, 2]).unwrap();
+        assert!((lon - 0.01).abs() < 1e-12 && lat == 0.0);
+        assert_eq!(mean_position(&data, &[]), None);
+
+        // Straddling the antimeridian averages to ±180, not 0
+        let pacific = make_data(vec![(179.9, 0.0), (-179.9, 0.0)], &[]);
+        let (lon, _) = mean_position(&pacific, &[0, 1]).unwrap();
+        assert!((lon.abs() - 180.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_way_delays_to_edges() {
+        // Way 7 is two-way 0 <-> 1 <-> 2 (2 s per segment), way 8 is oneway 2 -> 3
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 2000), (1, 0, 2000), (1, 2, 2000), (1, 2, 2000), (2, 1, 2000), (2, 3, 1000)],
+        );
+        data.edge_info = [7, 7, 7, 9, 7, 8]
+            .iter()
+            .map(|&way_id| EdgeInfo {
+                way_id,
+                name: NO_NAME,
+                highway: HIGHWAY_OTHER,
+                roundabout: false,
+                toll: false,
+            })
+            .collect();
+        let router = Router::new(data);
+
+        let delays = way_delays_to_edges(&router, &HashMap::from([(7, 60.0), (8, 10.0)]));
+        // Traversing way 7 end to end (two segments) adds 60 s in total
+        assert_eq!(delays[&0] + delays[&2], 60_000);
+        assert_eq!(delays[&5], 10_000);
+        assert!(!delays.contains_key(&3));
+    }
+
+    #[test]
+    fn test_turn_classification() {
+        assert_eq!(classify_turn(5.0), "continue");
+        assert_eq!(classify_turn(-30.0), "slight_left");
+        assert_eq!(classify_turn(90.0), "right");
+        assert_eq!(classify_turn(-150.0), "sharp_left");
+        assert_eq!(classify_turn(180.0), "uturn");
+    }
+
+    #[test]
+    fn test_build_maneuvers() {
+        // East along "Main St" (two segments), then north onto "Side St"
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0), (0.002, 0.001)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000)],
+        );
+        data.names = vec!["Main St".to_string(), "Side St".to_string()];
+        for (edge, name) in data.edge_info.iter_mut().zip([0, 0, 1]) {
+            edge.name = name;
+        }
+        let router = Router::new(data);
+
+        assert!((turn_angle_deg(&router.data, 1, 2, 3) + 90.0).abs() < 0.1);
+        let maneuvers = build_maneuvers(&router, &[0, 1, 2, 3]);
+        let kinds: Vec<&str> = maneuvers.iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, vec!["depart", "left", "arrive"]);
+        assert_eq!(maneuvers[0].duration_s, 2.0);
+        assert_eq!(maneuvers[1].node, 2);
+        assert_eq!(maneuvers[1].name, 1);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&maneuvers_to_json(&router.data, &maneuvers, 1.0, 3.0, DistanceUnit::Meters)).unwrap();
+        assert_eq!(json["maneuvers"][1]["street"], "Side St");
+        assert!(json["maneuvers"][2]["street"].is_null());
+    }
+
+    #[test]
+    fn test_roundabout_maneuvers() {
+        // Approach 0 -> 1, then counter-clockwise around the ring 1 -> 2 -> 3 -> 4 -> 1,
+        // with spokes out at 2 (5), 3 (6) and 4 (7)
+        let mut data = make_data(
+            vec![
+                (0.0, -0.002),
+                (0.0, -0.001),
+                (0.001, 0.0),
+                (0.0, 0.001),
+                (-0.001, 0.0),
+                (0.002, 0.0),
+                (0.0, 0.002),
+                (-0.002, 0.0),
+            ],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000), (3, 4, 1000), (4, 1, 1000), (2, 5, 1000), (3, 6, 1000), (4, 7, 1000)],
+        );
+        data.names = vec!["Ring".to_string(), "North Rd".to_string()];
+        let ends: Vec<(usize, usize)> =
+            (0..data.adj_list.len()).flat_map(|from| data.adj_list[from].iter().map(move |&(to, _)| (from, to))).collect();
+        for (info, (from, to)) in data.edge_info.iter_mut().zip(ends) {
+            info.roundabout = (1..=4).contains(&from) && (1..=4).contains(&to);
+            info.name = match (from, to) {
+                _ if info.roundabout => 0,
+                (3, 6) => 1,
+                _ => NO_NAME,
+            };
+        }
+        let router = Router::new(data);
+
+        // Second exit: the spoke at 2 is passed, the one at 3 is taken
+        let maneuvers = build_maneuvers(&router, &[0, 1, 2, 3, 6]);
+        let kinds: Vec<&str> = maneuvers.iter().map(|m| m.kind).collect();
+        assert_eq!(kinds, vec!["depart", "roundabout", "arrive"]);
+        assert_eq!((maneuvers[1].node, maneuvers[1].exit, maneuvers[1].name), (1, Some(2), 1));
+        assert_eq!(maneuvers[1].duration_s, 3.0);
+
+        let maneuvers = build_maneuvers(&router, &[0, 1, 2, 5]);
+        assert_eq!(maneuvers[1].exit, Some(1));
+        let json: serde_json::Value =
+            serde_json::from_str(&maneuvers_to_json(&router.data, &maneuvers, 1.0, 3.0, DistanceUnit::Meters)).unwrap();
+        assert_eq!(json["maneuvers"][1]["exit"], 1);
+        assert!(json["maneuvers"][0]["exit"].is_null());
+
+        // Roundabouts are one-way by default
+        assert!(is_oneway(&tags(&[("junction", "roundabout")]), "auto"));
+        assert!(!is_oneway(&tags(&[("junction", "roundabout"), ("oneway", "no")]), "auto"));
+        assert!(!is_oneway(&tags(&[("junction", "roundabout"), ("oneway:bicycle", "no")]), "bicycle"));
+        assert!(!is_oneway(&tags(&[("junction", "roundabout")]), "pedestrian"));
+    }
+
+    #[test]
+    fn test_parse_wkt_and_ewkt() {
+        assert_eq!(wkt_to_point("POINT(12.45 43.94)", false).ok(), Some((12.45, 43.94)));
+        assert_eq!(wkt_to_point("SRID=4326;POINT(12.45 43.94)", false).ok(), Some((12.45, 43.94)));
+        assert_eq!(wkt_to_point("srid=4326; POINT(12.45 43.94)", false).ok(), Some((12.45, 43.94)));
+
+        let err = parse_wkt("SRID=3857;POINT(1385863 5458560)").unwrap_err();
+        assert!(err.to_string().contains("Unsupported SRID 3857"));
+        assert!(parse_wkt("SRID=4326 POINT(1 2)").is_err());
+        assert!(parse_wkt("POINT(").is_err());
+    }
+
+    fn tags(pairs: &[(&str, &str)]) -> Tags {
+        pairs.iter().map(|&(k, v)| (k.into(), v.into())).collect()
+    }
+
+    #[test]
+    fn test_agricultural_tracktype_speeds() {
+        let track = |grade: &str| way_speed_kmh(&tags(&[("tracktype", grade)]), "track", "agricultural", &Profile::default());
+        assert_eq!(track("grade1"), Some(30.0));
+        assert_eq!(track("grade3"), Some(20.0));
+        assert_eq!(track("grade5"), Some(10.0));
+        assert_eq!(way_speed_kmh(&tags(&[]), "track", "agricultural", &Profile::default()), Some(15.0));
+        assert_eq!(way_speed_kmh(&tags(&[]), "motorway", "agricultural", &Profile::default()), None);
+        // Tracks stay out of auto mode
+        assert_eq!(way_speed_kmh(&tags(&[("tracktype", "grade1")]), "track", "auto", &Profile::default()), None);
+    }
+
+    #[test]
+    fn test_agricultural_access() {
+        assert!(is_access_allowed(&tags(&[]), "agricultural"));
+        assert!(!is_access_allowed(&tags(&[("access", "private")]), "agricultural"));
+        assert!(is_access_allowed(&tags(&[("access", "no"), ("agricultural", "yes")]), "agricultural"));
+        assert!(is_access_allowed(&tags(&[("access", "agricultural")]), "agricultural"));
+        assert!(!is_access_allowed(&tags(&[("motor_vehicle", "no")]), "agricultural"));
+        assert!(is_snap_road("track", "agricultural", false) && !is_snap_road("track", "auto", false));
+    }
+
+    fn barrier_junction(crossing_highway: &str) -> OsmData {
+        let way = |id: i64, highway: &str, nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags: tags(&[("highway", highway)]),
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        OsmData {
+            nodes: HashMap::from([
+                (1, (0.0, 0.0)),
+                (2, (0.001, 0.0)),
+                (3, (0.002, 0.0)),
+                (4, (0.001, -0.001)),
+                (5, (0.001, 0.001)),
+            ]),
+            barriers: HashMap::from([(2, tags(&[("barrier", "bollard")]))]),
+            crossings: HashSet::new(),
+            ways: vec![way(10, "residential", &[1, 2, 3]), way(11, crossing_highway, &[4, 2, 5])],
+            restrictions: Vec::new(),
+        }
+    }
+
+    fn connected(data: &RoutingData, a: (f64, f64), b: (f64, f64)) -> bool {
+        let idx = |p| data.node_positions.iter().position(|&q| q == p);
+        match (idx(a), idx(b)) {
+            (Some(a), Some(b)) => data.component_ids[a] == data.component_ids[b],
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_barrier_edge_split() {
+        // A bollard at a junction closes both roads through it; split at the bollard, each of
+        // the four road ends stays routable up to it
+        let osm = barrier_junction("service");
+        let edge_split = BuildOptions {
+            min_component_size: 0,
+            ..Default::default()
+        };
+        let node_block = BuildOptions {
+            barrier_edge_split: false,
+            ..edge_split.clone()
+        };
+        let at_bollard = |data: &RoutingData| data.node_positions.iter().filter(|&&p| p == (0.001, 0.0)).count();
+        let data = build_graph_from_osm(&osm, "auto", &edge_split);
+        assert_eq!((data.node_positions.len(), at_bollard(&data), data.source_component_count), (8, 4, 4));
+        assert!(!connected(&data, (0.0, 0.0), (0.002, 0.0)));
+        assert!(!connected(&data, (0.001, -0.001), (0.001, 0.001)));
+        let data = build_graph_from_osm(&osm, "auto", &node_block);
+        assert_eq!(at_bollard(&data), 0);
+        assert!(!connected(&data, (0.0, 0.0), (0.002, 0.0)));
+
+        // A bollard on a cycleway does not stop bicycles
+        let osm = barrier_junction("cycleway");
+        let data = build_graph_from_osm(&osm, "bicycle", &edge_split);
+        assert_eq!(at_bollard(&data), 1);
+        assert!(connected(&data, (0.001, -0.001), (0.001, 0.001)));
+        let node_block = BuildOptions {
+            barrier_edge_split: false,
+            ..Default::default()
+        };
+        assert_eq!(cache_path_for("a.pbf", "auto", &node_block), "a.pbf.auto.nosplit.routing");
+    }
+
+    #[test]
+    fn test_dynamic_weights_stay_positive() {
+        assert_eq!(scale_weight(1000, 2.5), 2500);
+        assert_eq!(scale_weight(1000, 1e-9), MIN_EDGE_WEIGHT_MS);
+        assert_eq!(scale_weight(1000, -3.0), MIN_EDGE_WEIGHT_MS);
+        assert_eq!(scale_weight(1000, f64::NAN), MIN_EDGE_WEIGHT_MS);
+        assert_eq!(scale_weight(u32::MAX, 4.0), u32::MAX);
+
+        // A cycle 1 <-> 2 <-> 4 <-> 1 next to the direct edge 1 -> 3; with every edge
+        // discounted to nothing the route must still be the simple path
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0), (0.015, 0.01)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 1, 1000), (2, 4, 1000), (4, 1, 1000), (1, 3, 1000)],
+        );
+        let router = Router::new(data);
+        let path = dijkstra_path(&router, 0, 3, |_, w| Some(scale_weight(w, 0.0))).unwrap();
+        assert_eq!(path.nodes, vec![0, 1, 3]);
+        assert_eq!(path.cost_ms, 2 * MIN_EDGE_WEIGHT_MS);
+        let path = dijkstra_path(&router, 0, 3, |_, _| Some(0)).unwrap();
+        assert_eq!(path.nodes, vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn test_meeting_node() {
+        // Chain 0 - 1 - 2 - 3 with a spur 3 - 4, all two-way
+        let mut edges = Vec::new();
+        for &(a, b, w) in &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000), (3, 4, 5000)] {
+            edges.push((a, b, w));
+            edges.push((b, a, w));
+        }
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0), (0.04, 0.0)],
+            &edges,
+        );
+
+        // Sum: node 2 costs 2000 + 1000 + 6000, node 3 costs 3000 + 0 + 5000
+        assert_eq!(meeting_node(&data, &[0, 3, 4], false), Some((3, 8000)));
+        // Max: node 3 (worst 5000) beats node 2 (worst 6000)
+        assert_eq!(meeting_node(&data, &[0, 3, 4], true), Some((3, 5000)));
+        assert_eq!(meeting_node(&data, &[0, 2], true), Some((1, 1000)));
+
+        // Origins in different components have no common node
+        let data = make_data(vec![(0.0, 0.0), (1.0, 1.0)], &[]);
+        assert_eq!(meeting_node(&data, &[0, 1], false), None);
+    }
+
+    #[test]
+    fn test_distance_units() {
+        assert_eq!(DistanceUnit::from_name("miles"), Some(DistanceUnit::Miles));
+        assert_eq!(DistanceUnit::from_name("km"), Some(DistanceUnit::Kilometers));
+        assert_eq!(DistanceUnit::from_name("furlongs"), None);
+        assert_eq!(DistanceUnit::Meters.convert_meters(1609.344), 1609.344);
+        assert_eq!(DistanceUnit::Kilometers.convert_meters(1500.0), 1.5);
+        assert!((DistanceUnit::Miles.convert_meters(1609.344) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_added_edges() {
+        // 0 -> 1 -> 2 -> 3 is slow; a what-if edge 0 -> 3 short-circuits it
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 5000), (1, 2, 5000), (2, 3, 5000)],
+        );
+        let mut router = Router::new(data);
+        let base_edges = router.data.edge_info.len();
+
+        let added = router.add_edge(0, 3, 4000);
+        assert_eq!(added, base_edges);
+        let path = dijkstra_path(&router, 0, 3, |_, w| Some(w)).unwrap();
+        assert_eq!(path.nodes, vec![0, 3]);
+        assert_eq!(path.edges, vec![added]);
+        assert_eq!(path.cost_ms, 4000);
+        assert_eq!(edge_base_weight_ms(&router, added), 4000);
+
+        // Penalties keyed by edge id apply to added edges too
+        let path = dijkstra_path(&router, 0, 3, |e, w| Some(if e == added { w * 10 } else { w })).unwrap();
+        assert_eq!(path.nodes, vec![0, 1, 2, 3]);
+
+        router.clear_added_edges();
+        let path = dijkstra_path(&router, 0, 3, |_, w| Some(w)).unwrap();
+        assert_eq!(path.cost_ms, 15000);
+    }
+
+    #[test]
+    fn test_snap_filtered_by_highway() {
+        // Footway 0 - 1 right next to the query point, primary 2 - 3 farther away
+        let mut data = make_data(
+            vec![(0.0, 0.0001), (0.001, 0.0001), (0.0, 0.01), (0.001, 0.01)],
+            &[(0, 1, 1000), (1, 0, 1000), (2, 3, 1000)],
+        );
+        let classes = [highway_class("footway"), highway_class("footway"), highway_class("primary")];
+        for (info, class) in data.edge_info.iter_mut().zip(classes) {
+            info.highway = class;
+        }
+        let router = Router::new(data);
+
+        assert_eq!(find_nearest_node(&router.data, 0.0, 0.0), Some(0));
+        let road_only = highway_mask([highway_class("primary"), highway_class("secondary")]);
+        let snapped = find_nearest_node_where(&router.data, 0.0, 0.0, |n| router.node_highways[n] & road_only != 0);
+        assert_eq!(snapped, Some(2));
+        // Node 3 is only reached by an incoming edge, which still counts
+        assert_ne!(router.node_highways[3] & road_only, 0);
+        let unknown = highway_mask([highway_class("runway")]);
+        let snapped = find_nearest_node_where(&router.data, 0.0, 0.0, |n| router.node_highways[n] & unknown != 0);
+        assert_eq!(snapped, None);
+
+        // Highway types without a class are rejected rather than matching all untracked types
+        assert_eq!(parse_highway_mask(" primary, secondary,").unwrap(), road_only);
+        let err = parse_highway_mask("primary,runway").unwrap_err();
+        assert!(err.to_string().starts_with("Unknown highway type 'runway' (expected one of motorway, "));
+        let (mut lat, mut lon, mut meters) = (0.0, 0.0, 0.0);
+        let runway = c"runway".as_ptr();
+        assert_eq!(routing_snap_filtered(0.0, 0.0, c"auto".as_ptr(), runway, &mut lat, &mut lon, &mut meters), -1);
+        assert!(LAST_ERROR.with(|e| e.borrow().clone()).contains("'runway'"));
+    }
+
+    #[test]
+    fn test_benchmark_helpers() {
+        let data = make_data(vec![(1.0, 5.0), (-2.0, 7.0), (3.0, 6.0)], &[]);
+        assert_eq!(graph_bounds(&data), Some((-2.0, 5.0, 3.0, 7.0)));
+        assert_eq!(graph_bounds(&make_data(Vec::new(), &[])), None);
+
+        let mut a = SplitMix64(42);
+        let mut b = SplitMix64(42);
+        for _ in 0..1000 {
+            let x = a.next_f64();
+            assert_eq!(x, b.next_f64());
+            assert!((0.0..1.0).contains(&x));
+        }
+    }
+
+    #[test]
+    fn test_path_to_wkt_m() {
+        let data = make_data(vec![(0.0, 0.0), (0.0, 0.001), (0.0, 0.002)], &[(0, 1, 500), (1, 2, 700)]);
+        let meters = path_cumulative_m(&data, &[0, 1, 2]);
+        assert_eq!(meters[0], 0.0);
+        assert!((meters[1] - 111.19).abs() < 0.1);
+        assert!((meters[2] - 2.0 * meters[1]).abs() < 1e-6);
+
+        assert_eq!(
+            path_to_wkt_m(&data, &[0, 1, 2], &[0.0, 0.5, 1.2], 3),
+            "LINESTRING M (0.000 0.000 0.000, 0.000 0.001 0.500, 0.000 0.002 1.200)"
+        );
+        assert_eq!(
+            path_to_wkt_m(&data, &[1], &[0.0], 1),
+            "LINESTRING M (0.0 0.0 0.000, 0.0 0.0 0.000)"
+        );
+    }
+
+    #[test]
+    fn test_isochrone_node_cap() {
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000)],
+        );
+        let (reached, incomplete) = isochrone_nodes_capped(&data, 0, 10_000, 2, None);
+        assert_eq!(reached, vec![(0, 0), (1, 1000)]);
+        assert!(incomplete);
+
+        // A cap equal to the full result is not a truncation
+        let (reached, incomplete) = isochrone_nodes_capped(&data, 0, 10_000, 4, None);
+        assert_eq!(reached.len(), 4);
+        assert!(!incomplete);
+        let (reached, incomplete) = isochrone_nodes_capped(&data, 0, 1500, 2, None);
+        assert_eq!(reached.len(), 2);
+        assert!(!incomplete);
+
+        // An expired deadline stops the search before it settles anything
+        let now = std::time::Instant::now();
+        let (reached, incomplete) = isochrone_nodes_capped(&data, 0, 10_000, usize::MAX, Some(now));
+        assert!(reached.is_empty());
+        assert!(incomplete);
+        let later = now + std::time::Duration::from_secs(3600);
+        let (reached, incomplete) = isochrone_nodes_capped(&data, 0, 10_000, usize::MAX, Some(later));
+        assert_eq!(reached.len(), 4);
+        assert!(!incomplete);
+    }
+
+    #[test]
+    fn test_route_failure_reasons() {
+        let empty = make_data(Vec::new(), &[]);
+        assert_eq!(
+            route_failure_reason(&empty, 0.0, 0.0, 1.0, 1.0),
+            "origin snap failed (graph has no snappable nodes)"
+        );
+
+        // 0 -> 1 is one-way; 2 <-> 3 is a separate island about 111 km away
+        let data = make_data(
+            vec![(0.0, 0.0), (0.001, 0.0), (1.0, 0.0), (1.001, 0.0)],
+            &[(0, 1, 1000), (2, 3, 1000), (3, 2, 1000)],
+        );
+
+        let reason = route_failure_reason(&data, 0.0, 0.0, 1.001, 0.0);
+        assert!(reason.starts_with("no path: endpoints in different components (0, 1)"), "{}", reason);
+        assert!(reason.ends_with("snapped 0 m from origin, 0 m from destination"), "{}", reason);
+
+        let reason = route_failure_reason(&data, 0.001, 0.0, 0.0, 0.0);
+        assert!(reason.starts_with("no path: destination not reachable from origin within component 0"));
+
+        // Destination far from any node: about 8.9 km north of node 3
+        let reason = route_failure_reason(&data, 0.0, 0.0, 1.001, 0.08);
+        assert!(reason.starts_with("destination off network (nearest node 8.9 km away); no path"), "{}", reason);
+
+        record_route_failure(&data, 0.0, 0.0, 1.001, 0.0);
+        let mut buf = [0 as c_char; 256];
+        let len = routing_last_error(buf.as_mut_ptr(), buf.len() as i32);
+        let message = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap();
+        assert_eq!(len as usize, message.len());
+        assert!(message.contains("different components"));
+    }
+
+    #[test]
+    fn test_edge_snapping() {
+        // Two-way line 0 - 1 - 2 along the equator, 1000 ms per segment
+        let line = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 1000), (2, 1, 1000)],
+        );
+        let router = Router::new(line);
+        assert_eq!(router.segment_index.size(), 2);
+
+        let origin = find_nearest_edge(&router, 0.004, 0.0001).unwrap();
+        assert_eq!((origin.from, origin.to), (0, 1));
+        assert!((origin.fraction - 0.4).abs() < 1e-9);
+        assert!((origin.lon - 0.004).abs() < 1e-12 && origin.lat.abs() < 1e-12);
+        assert!((origin.distance_m - 11.1).abs() < 0.1);
+
+        // 600 ms to node 1, then 600 ms into the next segment (node snapping would give 2000)
+        let dest = find_nearest_edge(&router, 0.016, 0.0).unwrap();
+        let (nodes, ms) = route_between_snaps(&router, &origin, &dest).unwrap();
+        assert_eq!(nodes, vec![1]);
+        assert_eq!(ms, 1200);
+
+        // Same segment, in either direction
+        let near = find_nearest_edge(&router, 0.008, 0.0).unwrap();
+        assert_eq!(route_between_snaps(&router, &origin, &near), Some((Vec::new(), 400)));
+        assert_eq!(route_between_snaps(&router, &near, &origin), Some((Vec::new(), 400)));
+
+        // One-way 0 -> 1 -> 2: going backwards along the segment is impossible
+        let oneway = make_data(vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)], &[(0, 1, 1000), (1, 2, 1000)]);
+        let router = Router::new(oneway);
+        let origin = find_nearest_edge(&router, 0.004, 0.0).unwrap();
+        let dest = find_nearest_edge(&router, 0.016, 0.0).unwrap();
+        assert_eq!(route_between_snaps(&router, &origin, &dest), Some((vec![1], 1200)));
+        let back = find_nearest_edge(&router, 0.002, 0.0).unwrap();
+        assert_eq!(route_between_snaps(&router, &origin, &back), None);
+    }
+
+    #[test]
+    fn test_shortest_distance_route() {
+        // 0 -> 1 -> 3 is short but slow, 0 -> 2 -> 3 is a fast detour
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.001), (0.01, 0.02), (0.02, 0.0)],
+            &[(0, 1, 60_000), (1, 3, 60_000), (0, 2, 10_000), (2, 3, 10_000)],
+        );
+        let router = Router::new(data);
+
+        let (fastest, seconds) = calc_route(&router, 0.0, 0.0, 0.02, 0.0).unwrap();
+        assert_eq!(fastest, vec![0, 2, 3]);
+        assert_eq!(seconds, 20.0);
+
+        let shortest = calc_shortest_route(&router, 0.0, 0.0, 0.02, 0.0).unwrap();
+        assert_eq!(shortest, vec![0, 1, 3]);
+        assert_eq!(path_cumulative_ms(&router.data, &shortest), vec![0, 60_000, 120_000]);
+
+        // One-way edges stay one-way in the distance graph
+        assert!(calc_shortest_route(&router, 0.02, 0.0, 0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_speed_profile() {
+        let profile = Profile::from_json(r#"{"residential": 20, "busway": 35.5, "motorway": 0}"#).unwrap();
+        let none = tags(&[]);
+        assert_eq!(way_speed_kmh(&none, "residential", "auto", &profile), Some(20.0));
+        assert_eq!(way_speed_kmh(&none, "busway", "auto", &profile), Some(35.5));
+        assert_eq!(way_speed_kmh(&none, "motorway", "auto", &profile), None);
+        assert_eq!(way_speed_kmh(&none, "primary", "auto", &profile), get_speed_kmh("primary", "auto"));
+
+        assert!(Profile::from_json(r#"{"residential": "fast"}"#).is_err());
+        assert!(Profile::from_json("[1, 2]").is_err());
+
+        // Profiles get their own cache file; the default keeps the plain path
+        let same = Profile::from_json(r#"{"motorway": 0, "busway": 35.5, "residential": 20}"#).unwrap();
+        assert_eq!(profile.hash(), same.hash());
+        let options = BuildOptions {
+            profile,
+            ..Default::default()
+        };
+        let path = cache_path_for("/data/it.osm.pbf", "auto", &options);
+        assert_eq!(path, format!("/data/it.osm.pbf.auto.{:016x}.routing", same.hash()));
+        assert_eq!(
+            cache_path_for("/data/it.osm.pbf", "auto", &BuildOptions::default()),
+            cache_path("/data/it.osm.pbf", "auto")
+        );
+        let other = Profile::from_json(r#"{"residential": 21}"#).unwrap();
+        assert_ne!(other.hash(), same.hash());
+
+        let clipped = BuildOptions {
+            bbox: Some((12.4, 41.8, 12.6, 42.0)),
+            ..Default::default()
+        };
+        assert_eq!(
+            cache_path_for("/data/it.osm.pbf", "auto", &clipped),
+            "/data/it.osm.pbf.auto.bbox_12.4_41.8_12.6_42.routing"
+        );
+    }
+
+    #[test]
+    fn test_parse_maxspeed() {
+        assert_eq!(parse_maxspeed("70"), Some(70.0));
+        assert_eq!(parse_maxspeed("50 km/h"), Some(50.0));
+        assert!((parse_maxspeed("50 mph").unwrap() - 80.467).abs() < 0.001);
+        assert!((parse_maxspeed("30mph").unwrap() - 48.28).abs() < 0.01);
+        assert_eq!(parse_maxspeed("walk"), Some(6.0));
+        assert_eq!(parse_maxspeed("RO:urban"), Some(50.0));
+        assert_eq!(parse_maxspeed("DE:rural"), Some(100.0));
+        assert!((parse_maxspeed("GB:nsl_single").unwrap() - 96.56).abs() < 0.01);
+        assert_eq!(parse_maxspeed("DE:motorway"), None);
+        assert_eq!(parse_maxspeed("60;80"), Some(60.0));
+        assert_eq!(parse_maxspeed("none"), None);
+        assert_eq!(parse_maxspeed("signals"), None);
+        assert_eq!(parse_maxspeed("-5"), None);
+
+        let none = Profile::default();
+        let way = |maxspeed: &str| tags(&[("maxspeed", maxspeed)]);
+        assert_eq!(way_speed_kmh(&way("70"), "primary", "auto", &none), Some(70.0));
+        assert_eq!(way_speed_kmh(&way("none"), "motorway", "auto", &none), Some(120.0));
+        // Absurd limits fall back to the type default
+        assert_eq!(way_speed_kmh(&way("300"), "motorway", "auto", &none), Some(120.0));
+        // Other modes and non-routable types ignore maxspeed
+        assert_eq!(way_speed_kmh(&way("70"), "primary", "bicycle", &none), get_speed_kmh("primary", "bicycle"));
+        assert_eq!(way_speed_kmh(&way("70"), "footway", "auto", &none), None);
+    }
+
+    #[test]
+    fn test_hgv_mode() {
+        let none = Profile::default();
+        let speed = |highway: &str, pairs: &[(&str, &str)]| way_speed_kmh(&tags(pairs), highway, "hgv", &none);
+        assert_eq!(speed("motorway", &[]), Some(80.0));
+        assert_eq!(speed("motorway", &[("maxspeed", "130")]), Some(80.0));
+        assert_eq!(speed("primary", &[("maxspeed", "50")]), Some(50.0));
+        assert_eq!(speed("motorway", &[("maxspeed", "130"), ("maxspeed:hgv", "90")]), Some(90.0));
+        assert_eq!(speed("primary", &[("maxspeed:hgv", "variable")]), Some(70.0));
+        assert_eq!(speed("track", &[]), None);
+        assert_eq!(speed("track", &[("hgv", "yes")]), Some(10.0));
+
+        assert!(!is_access_allowed(&tags(&[("hgv", "no")]), "hgv"));
+        assert!(is_access_allowed(&tags(&[("hgv", "no")]), "auto"));
+        assert!(is_access_allowed(&tags(&[("motor_vehicle", "no"), ("hgv", "delivery")]), "hgv"));
+        assert!(!is_access_allowed(&tags(&[("motor_vehicle", "no")]), "hgv"));
+        let relation = tags(&[("type", "restriction"), ("restriction:hgv", "no_left_turn")]);
+        assert_eq!(restriction_kind(&relation, "hgv"), Some(false));
+        assert_eq!(restriction_kind(&relation, "auto"), None);
+
+        assert_eq!(parse_length_m("3.5"), Some(3.5));
+        assert_eq!(parse_length_m("3.8 m"), Some(3.8));
+        assert!((parse_length_m("12'6\"").unwrap() - 3.81).abs() < 0.001);
+        assert!((parse_length_m("14'").unwrap() - 4.267).abs() < 0.001);
+        assert_eq!(parse_length_m("default"), None);
+        assert_eq!(parse_weight_t("7.5"), Some(7.5));
+        assert_eq!(parse_weight_t("3.5 t"), Some(3.5));
+        assert_eq!(parse_weight_t("7500 kg"), Some(7.5));
+        assert_eq!(parse_weight_t("none"), None);
+
+        let truck = Vehicle {
+            height_m: 4.0,
+            width_m: 0.0,
+            weight_t: 18.0,
+        };
+        assert!(truck.exceeds_limits(&tags(&[("maxheight", "3.5")])));
+        assert!(!truck.exceeds_limits(&tags(&[("maxheight", "4.2")])));
+        assert!(truck.exceeds_limits(&tags(&[("maxweight", "7.5")])));
+        assert!(!truck.exceeds_limits(&tags(&[("maxwidth", "2")])));
+        assert!(!truck.exceeds_limits(&tags(&[("maxheight", "below_default")])));
+
+        let options = BuildOptions {
+            vehicle: Some(truck),
+            ..Default::default()
+        };
+        assert_eq!(cache_path_for("a.pbf", "hgv", &options), "a.pbf.hgv.veh_4_0_18.routing");
+    }
+
+    #[test]
+    fn test_isochrone_hull() {
+        use geo::{Area, Intersects};
+        // An L-shaped set of points: the concave hull leaves out the empty corner
+        let mut points = Vec::new();
+        for i in 0..=10 {
+            points.push((i as f64 * 0.001, 0.0));
+            points.push((0.0, i as f64 * 0.001));
+            points.push((i as f64 * 0.001, 0.001));
+            points.push((0.001, i as f64 * 0.001));
+        }
+        let concave = isochrone_hull(&points);
+        assert!(concave.unsigned_area() < 0.5 * 0.01 * 0.01);
+        assert!(concave.unsigned_area() > 0.0);
+
+        // Too few points: convex hull (a triangle here), duplicates ignored
+        let triangle = isochrone_hull(&[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (0.0, 1.0)]);
+        assert_eq!(triangle.unsigned_area(), 0.5);
+        assert!(triangle.wkt_string().starts_with("POLYGON(("));
+
+        // Buckets out of order: the outer band is the larger square with the smaller one as a hole;
+        // a repeated bucket adds no band
+        let square = |a: f64, b: f64| isochrone_hull(&[(a, a), (b, a), (b, b), (a, b)]);
+        let bands = isochrone_bands(&[square(0.0, 3.0), square(1.0, 2.0), square(1.0, 2.0)], &[600.0, 300.0, 300.0]);
+        assert_eq!(bands.len(), 2);
+        assert_eq!((bands[0].unsigned_area(), bands[0].interiors().len()), (8.0, 1));
+        assert_eq!(bands[1].unsigned_area(), 1.0);
+        let inside = |band: &geo::Polygon<f64>| band.intersects(&Point::new(1.5, 1.5));
+        assert!(!inside(&bands[0]) && inside(&bands[1]));
+
+        // Buckets must be positive travel times
+        let mut buf = [0 as c_char; 64];
+        for bad in [0.0, -300.0, f64::NAN, f64::INFINITY] {
+            let buckets = [300.0, bad];
+            let (mode, out) = (c"auto".as_ptr(), buf.as_mut_ptr());
+            assert_eq!(routing_isochrone_polygon(0.0, 0.0, 600.0, mode, buckets.as_ptr(), 2, out, 64), -1, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_one_to_many() {
+        // 0 -> 1 -> 2, 0 -> 2 direct but slow, 3 unreachable
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (1.0, 1.0)],
+            &[(0, 1, 1000), (1, 2, 1000), (0, 2, 5000)],
+        );
+        let times = one_to_many_ms(&data, 0, &[Some(2), Some(0), Some(3), None, Some(2)]);
+        assert_eq!(times, vec![Some(2000), Some(0), None, None, Some(2000)]);
+        assert_eq!(one_to_many_ms(&data, 2, &[Some(0)]), vec![None]);
+
+        // Matches the contraction hierarchy for every pair (the CH has no trailing edgeless node)
+        let router = Router::new(data);
+        let targets: Vec<Option<usize>> = (0..3).map(Some).collect();
+        for source in 0..3 {
+            for (target, ms) in one_to_many_ms(&router.data, source, &targets).into_iter().enumerate() {
+                let ch = with_calculator(&router.data.fast_graph, |calc| {
+                    calc.calc_path(&router.data.fast_graph, source, target)
+                })
+                .map(|p| p.get_weight() as u32);
+                assert_eq!(ms, ch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternative_paths() {
+        // Three parallel corridors 0 -> {1, 2, 3} -> 4 of 2000, 2200 and 5000 ms
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.01), (0.01, 0.0), (0.01, -0.01), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 4, 1000), (0, 2, 1100), (2, 4, 1100), (0, 3, 2500), (3, 4, 2500)],
+        );
+        let router = Router::new(data);
+
+        let paths = alternative_paths(&router, 0, 4, 3, 0.3);
+        let nodes: Vec<Vec<usize>> = paths.iter().map(|p| p.nodes.clone()).collect();
+        // The 5000 ms corridor exceeds the 1.5x stretch limit
+        assert_eq!(nodes, vec![vec![0, 1, 4], vec![0, 2, 4]]);
+
+        assert_eq!(alternative_paths(&router, 0, 4, 1, 0.3).len(), 1);
+        // A single corridor has no distinct alternative
+        let line = make_data(vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)], &[(0, 1, 1000), (1, 2, 1000)]);
+        let router = Router::new(line);
+        assert_eq!(alternative_paths(&router, 0, 2, 3, 0.5).len(), 1);
+        assert!(alternative_paths(&router, 2, 0, 3, 0.5).is_empty());
+    }
+
+    #[test]
+    fn test_turn_restrictions() {
+        // 0 -> 1 -> 2 directly, or around through 3; all two-way
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.01, 0.01), (0.02, 0.005)],
+            &[
+                (0, 1, 1000),
+                (1, 0, 1000),
+                (1, 2, 1000),
+                (2, 1, 1000),
+                (1, 3, 1000),
+                (3, 1, 1000),
+                (3, 2, 1000),
+                (2, 3, 1000),
+            ],
+        );
+        let mut router = Router::new(data);
+        let way = |router: &Router, a, b| router.data.edge_info[edge_between(router, a, b).unwrap()].way_id;
+        assert_eq!(dijkstra_path(&router, 0, 2, |_, w| Some(w)).unwrap().nodes, vec![0, 1, 2]);
+
+        // no_left_turn from 0-1 onto 1-2: detour through 3
+        let (from_way, to_way) = (way(&router, 0, 1), way(&router, 1, 2));
+        router.data.turn_restrictions = vec![TurnRestriction { via: 1, from_way, to_way, only: false }];
+        let path = dijkstra_path(&router, 0, 2, |_, w| Some(w)).unwrap();
+        assert_eq!((path.nodes, path.cost_ms), (vec![0, 1, 3, 2], 3000));
+        assert_eq!(isochrone_nodes(&router.data, 0, 10_000).last(), Some(&(2, 3000)));
+        assert_eq!(one_to_many_ms(&router.data, 0, &[Some(2)]), vec![Some(3000)]);
+        // Arriving from elsewhere the turn is fine, and the CH path is re-routed
+        assert_eq!(dijkstra_path(&router, 3, 2, |_, w| Some(w)).unwrap().nodes, vec![3, 2]);
+        let (nodes, seconds) = calc_route(&router, 0.0, 0.0, 0.01, 0.01).unwrap();
+        assert_eq!((nodes, seconds), (vec![0, 1, 3, 2], 3.0));
+
+        // only_straight_on onto 1-3 has the same effect
+        let to_way = way(&router, 1, 3);
+        router.data.turn_restrictions = vec![TurnRestriction { via: 1, from_way, to_way, only: true }];
+        assert_eq!(dijkstra_path(&router, 0, 2, |_, w| Some(w)).unwrap().nodes, vec![0, 1, 3, 2]);
+        let reached = isochrone_nodes(&router.data, 2, 10_000);
+        assert_eq!(reached.iter().find(|&&(n, _)| n == 0), Some(&(0, 2000)));
+
+        // Batch, matrix, shortest and geometry routes take the same detour
+        let to_way = way(&router, 1, 2);
+        router.data.turn_restrictions = vec![TurnRestriction { via: 1, from_way, to_way, only: false }];
+        assert_eq!(batch_travel_time_s(&router, 0.0, 0.0, 0.01, 0.01), Ok(3.0));
+        assert_eq!(parallel_travel_time_s(&router, 0, 2), Some(3.0));
+        let mut results = [0.0];
+        assert_eq!(fill_matrix(&router.data, &[Some(0)], &[Some(2)], 1.0, &mut results), 1);
+        assert_eq!(results, [3.0]);
+        assert_eq!(calc_shortest_route(&router, 0.0, 0.0, 0.01, 0.01), Some(vec![0, 1, 3, 2]));
+        let mut result = RouteResult { distance_m: 0.0, duration_s: 0.0, num_points: 0 };
+        let mut points = [RoutePoint { lat: 0.0, lon: 0.0 }; 8];
+        let written = write_geometry_route(&router, (0.0, 0.0), (0.01, 0.01), &mut result, points.as_mut_ptr(), 8);
+        assert_eq!((written, result.duration_s), (4, 3.0));
+        assert_eq!((points[2].lon, points[2].lat), (0.02, 0.005));
+    }
+
+    #[test]
+    fn test_restriction_relations() {
+        let mut osm = barrier_junction("residential");
+        osm.barriers.clear();
+        let relation = |tags: Tags| osmpbfreader::Relation {
+            id: osmpbfreader::RelationId(1),
+            tags,
+            refs: vec![
+                osmpbfreader::Ref { member: OsmId::Way(osmpbfreader::WayId(10)), role: "from".into() },
+                osmpbfreader::Ref { member: OsmId::Node(osmpbfreader::NodeId(2)), role: "via".into() },
+                osmpbfreader::Ref { member: OsmId::Way(osmpbfreader::WayId(11)), role: "to".into() },
+            ],
+        };
+        osm.restrictions = vec![relation(tags(&[
+            ("type", "restriction"),
+            ("restriction", "no_left_turn"),
+            ("except", "bicycle"),
+        ]))];
+
+        let auto = build_graph_from_osm(&osm, "auto", &BuildOptions::default());
+        let via = auto.node_positions.iter().position(|&p| p == (0.001, 0.0)).unwrap();
+        assert_eq!(
+            auto.turn_restrictions,
+            vec![TurnRestriction { via, from_way: 10, to_way: 11, only: false }]
+        );
+        assert!(build_graph_from_osm(&osm, "bicycle", &BuildOptions::default()).turn_restrictions.is_empty());
+        assert!(build_graph_from_osm(&osm, "pedestrian", &BuildOptions::default()).turn_restrictions.is_empty());
+
+        assert_eq!(restriction_kind(&tags(&[("type", "restriction"), ("restriction", "only_straight_on")]), "auto"), Some(true));
+        assert_eq!(
+            restriction_kind(&tags(&[("type", "restriction"), ("restriction:bicycle", "no_right_turn")]), "bicycle"),
+            Some(false)
+        );
+        assert_eq!(restriction_kind(&tags(&[("type", "restriction"), ("restriction:bicycle", "no_right_turn")]), "auto"), None);
+        assert_eq!(restriction_kind(&tags(&[("type", "multipolygon"), ("restriction", "no_u_turn")]), "auto"), None);
+    }
+
+    #[test]
+    fn test_access_tags() {
+        let private = tags(&[("highway", "service"), ("access", "private")]);
+        for mode in ["auto", "bicycle", "pedestrian"] {
+            assert!(!is_access_allowed(&private, mode), "{}", mode);
+        }
+        let no_cars = tags(&[("motor_vehicle", "no")]);
+        assert!(!is_access_allowed(&no_cars, "auto"));
+        assert!(is_access_allowed(&no_cars, "bicycle"));
+        assert!(is_access_allowed(&tags(&[("access", "no"), ("foot", "yes")]), "pedestrian"));
+        assert!(!is_access_allowed(&tags(&[("access", "yes"), ("bicycle", "no")]), "bicycle"));
+        assert!(is_access_allowed(&tags(&[("motorcar", "destination")]), "auto"));
+        assert!(!is_access_allowed(&tags(&[("vehicle", "private")]), "bicycle"));
+        assert!(is_access_allowed(&tags(&[("vehicle", "private")]), "pedestrian"));
+
+        // A private crossing way is dropped; a dismount way is slowed to pushing pace
+        let mut osm = barrier_junction("residential");
+        osm.barriers.clear();
+        osm.ways[1].tags = tags(&[("highway", "residential"), ("access", "private")]);
+        let data = build_graph_from_osm(&osm, "auto", &BuildOptions::default());
+        assert!(!connected(&data, (0.001, -0.001), (0.001, 0.001)));
+        assert!(connected(&data, (0.0, 0.0), (0.002, 0.0)));
+
+        osm.ways[1].tags = tags(&[("highway", "residential"), ("bicycle", "dismount")]);
+        let data = build_graph_from_osm(&osm, "bicycle", &BuildOptions::default());
+        let weight = |a: (f64, f64), b: (f64, f64)| {
+            let idx = |p| data.node_positions.iter().position(|&q| q == p).unwrap();
+            edge_weight_ms(&data, idx(a), idx(b)).unwrap()
+        };
+        // Both segments are ~111 m; pushing is much slower than riding
+        assert!(weight((0.001, -0.001), (0.001, 0.0)) > 3 * weight((0.0, 0.0), (0.001, 0.0)));
+    }
+
+    #[test]
+    fn test_bicycle_oneways() {
+        let oneway = tags(&[("highway", "residential"), ("oneway", "yes")]);
+        assert!(is_oneway(&oneway, "auto"));
+        assert!(is_oneway(&oneway, "bicycle"));
+
+        let contraflow = [
+            tags(&[("oneway", "yes"), ("oneway:bicycle", "no")]),
+            tags(&[("oneway", "yes"), ("cycleway", "opposite_lane")]),
+            tags(&[("oneway", "yes"), ("cycleway:left", "opposite")]),
+            tags(&[("oneway", "yes"), ("cycleway:left:oneway", "-1")]),
+        ];
+        for t in &contraflow {
+            assert!(is_oneway(t, "auto"));
+            assert!(!is_oneway(t, "bicycle"));
+        }
+
+        let bike_oneway = tags(&[("oneway:bicycle", "yes")]);
+        assert!(!is_oneway(&bike_oneway, "auto"));
+        assert!(is_oneway(&bike_oneway, "bicycle"));
+        // oneway:bicycle is more specific than a contraflow lane tag
+        assert!(is_oneway(&tags(&[("oneway:bicycle", "yes"), ("cycleway", "opposite")]), "bicycle"));
+    }
+
+    #[test]
+    fn test_barrier_access_and_delays() {
+        let edge_split = BuildOptions::default();
+        let through = |data: &RoutingData| {
+            let idx = |p| data.node_positions.iter().position(|&q| q == p).unwrap();
+            let (a, b, c) = (idx((0.0, 0.0)), idx((0.001, 0.0)), idx((0.002, 0.0)));
+            edge_weight_ms(data, a, b).unwrap() + edge_weight_ms(data, b, c).unwrap()
+        };
+        let mut osm = barrier_junction("residential");
+        osm.ways.truncate(1);
+        osm.barriers.clear();
+        let plain_ms = through(&build_graph_from_osm(&osm, "auto", &edge_split));
+
+        // An open gate delays cars passing it, a locked or private one blocks them
+        osm.barriers.insert(2, tags(&[("barrier", "gate")]));
+        let gated_ms = through(&build_graph_from_osm(&osm, "auto", &edge_split));
+        assert_eq!(gated_ms, plain_ms + 30_000);
+        let walk_gated_ms = through(&build_graph_from_osm(&osm, "pedestrian", &edge_split));
+        osm.barriers.clear();
+        assert_eq!(walk_gated_ms, through(&build_graph_from_osm(&osm, "pedestrian", &edge_split)));
+        for (gate, walkable) in [("locked", "yes"), ("access", "private")].into_iter().zip([true, false]) {
+            osm.barriers.insert(2, tags(&[("barrier", "gate"), gate]));
+            let data = build_graph_from_osm(&osm, "auto", &edge_split);
+            assert!(!connected(&data, (0.0, 0.0), (0.002, 0.0)));
+            let data = build_graph_from_osm(&osm, "pedestrian", &edge_split);
+            assert_eq!(connected(&data, (0.0, 0.0), (0.002, 0.0)), walkable);
+        }
+
+        // Access tags on a barrier override its type's default
+        osm.barriers.insert(2, tags(&[("barrier", "bollard"), ("motor_vehicle", "yes")]));
+        let data = build_graph_from_osm(&osm, "auto", &edge_split);
+        assert!(connected(&data, (0.0, 0.0), (0.002, 0.0)));
+        osm.barriers.insert(2, tags(&[("barrier", "bollard"), ("foot", "no")]));
+        let data = build_graph_from_osm(&osm, "pedestrian", &edge_split);
+        assert!(!connected(&data, (0.0, 0.0), (0.002, 0.0)));
+    }
+
+    #[test]
+    fn test_drop_small_components() {
+        // A three-node road and a separate two-node island
+        let mut osm = barrier_junction("residential");
+        osm.barriers.clear();
+        osm.ways[1].nodes = vec![osmpbfreader::NodeId(4), osmpbfreader::NodeId(5)];
+        let build = |min_component_size| {
+            let options = BuildOptions {
+                min_component_size,
+                ..Default::default()
+            };
+            build_graph_from_osm(&osm, "auto", &options)
+        };
+
+        let data = build(usize::MAX);
+        assert_eq!(data.source_component_count, 2);
+        assert_eq!(data.node_positions.len(), 3);
+        assert!(data.node_positions.iter().all(|&(_, lat)| lat == 0.0));
+        assert!(check_graph(&data).is_ok());
+        assert!(data.spatial_index.iter().all(|p| data.node_positions[p.idx] == (p.lon, p.lat)));
+        let router = Router::new(data);
+        assert!(calc_route(&router, 0.0, 0.0, 0.002, 0.0).is_some());
+
+        assert_eq!(build(2).node_positions.len(), 5);
+        assert_eq!(build(0).node_positions.len(), 5);
+        assert_eq!(build(3).node_positions.len(), 3);
+    }
+
+    #[test]
+    fn test_route_geojson() {
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)], &[(0, 1, 1000), (1, 2, 1000)]);
+        let router = Router::new(data);
+        let (coords, ms) = snapped_route(&router, 0.005, 0.001, 0.02, 0.0).unwrap();
+        assert_eq!(coords, vec![(0.005, 0.0), (0.01, 0.0), (0.02, 0.0)]);
+        assert_eq!(ms, 1500);
+
+        let geojson: serde_json::Value =
+            serde_json::from_str(&route_to_geojson(&coords, coords_length_m(&coords), 1.5)).unwrap();
+        assert_eq!(geojson["type"], "Feature");
+        assert_eq!(geojson["geometry"]["type"], "LineString");
+        assert_eq!(geojson["geometry"]["coordinates"][0], serde_json::json!([0.005, 0.0]));
+        assert_eq!(geojson["geometry"]["coordinates"].as_array().unwrap().len(), 3);
+        let distance_m = geojson["properties"]["distance_m"].as_f64().unwrap();
+        assert!((distance_m - 1668.0).abs() < 5.0, "{}", distance_m);
+        assert_eq!(geojson["properties"]["duration_s"], 1.5);
+    }
+
+    #[test]
+    fn test_encode_polyline() {
+        // Example from the Google polyline algorithm documentation
+        let coords = [(-120.2, 38.5), (-120.95, 40.7), (-126.453, 43.252)];
+        assert_eq!(encode_polyline(&coords, 5), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+        assert_eq!(encode_polyline(&[(0.000_001, 0.0)], 6), "?A");
+        assert_eq!(encode_polyline(&[], 5), "");
+    }
+
+    #[test]
+    fn test_reverse_isochrone() {
+        // 0 -> 1 one-way, 1 <-> 2
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 2, 1500), (2, 1, 1500)],
+        );
+        let router = Router::new(data);
+        assert_eq!(reverse_isochrone_nodes(&router, 2, 10_000), vec![(2, 0), (1, 1500), (0, 2500)]);
+        assert_eq!(reverse_isochrone_nodes(&router, 2, 2000), vec![(2, 0), (1, 1500)]);
+        assert_eq!(reverse_isochrone_nodes(&router, 0, 10_000), vec![(0, 0)]);
+        assert_eq!(isochrone_nodes(&router.data, 2, 10_000), vec![(2, 0), (1, 1500)]);
+    }
+
+    #[test]
+    fn test_reverse_isochrone_turn_restrictions() {
+        // Same square as test_turn_restrictions, with 0-1 onto 1-2 banned
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.01, 0.01), (0.02, 0.005)],
+            &[
+                (0, 1, 1000),
+                (1, 0, 1000),
+                (1, 2, 1000),
+                (2, 1, 1000),
+                (1, 3, 1000),
+                (3, 1, 1000),
+                (3, 2, 1000),
+                (2, 3, 1000),
+            ],
+        );
+        let mut router = Router::new(data);
+        let way = |router: &Router, a, b| router.data.edge_info[edge_between(router, a, b).unwrap()].way_id;
+        let (from_way, to_way) = (way(&router, 0, 1), way(&router, 1, 2));
+        router.data.turn_restrictions = vec![TurnRestriction { via: 1, from_way, to_way, only: false }];
+        let reached = reverse_isochrone_nodes(&router, 2, 10_000);
+        assert_eq!(reached.iter().find(|&&(n, _)| n == 0), Some(&(0, 3000)));
+        assert_eq!(reached.iter().find(|&&(n, _)| n == 1), Some(&(1, 1000)));
+    }
+
+    #[test]
+    fn test_merge_osm() {
+        // Way 10 is in both extracts; way 11 references node 2, which only the first defines
+        let full = barrier_junction("residential");
+        let mut first = barrier_junction("residential");
+        first.ways.truncate(1);
+        first.nodes.retain(|&id, _| id <= 3);
+        let mut second = barrier_junction("residential");
+        second.nodes.retain(|&id, _| id != 2);
+        second.barriers.clear();
+
+        let merged = merge_osm(vec![first, second]);
+        assert_eq!(merged.nodes, full.nodes);
+        assert_eq!(merged.ways.iter().map(|w| w.id.0).collect::<Vec<_>>(), vec![10, 11]);
+        let data = build_graph_from_osm(&merged, "pedestrian", &BuildOptions::default());
+        assert!(connected(&data, (0.0, 0.0), (0.001, 0.001)));
+        assert_eq!(data.node_positions.len(), 5);
+    }
+
+    #[test]
+    fn test_bbox_clip() {
+        // Way 10 runs west-east through (0.001, 0); way 11 runs south-north through it
+        let osm = barrier_junction("residential");
+        let clip = |bbox| {
+            let options = BuildOptions {
+                bbox: Some(bbox),
+                ..Default::default()
+            };
+            build_graph_from_osm(&osm, "pedestrian", &options)
+        };
+
+        // Only the segment from (0, 0) to the junction touches the box; it is kept whole
+        let data = clip((-0.0005, -0.0005, 0.0004, 0.0004));
+        let mut positions = data.node_positions.to_vec();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(positions, vec![(0.0, 0.0), (0.001, 0.0)]);
+
+        let data = clip((0.0005, -0.002, 0.0015, -0.0005));
+        assert!(connected(&data, (0.001, -0.001), (0.001, 0.0)));
+        assert_eq!(data.node_positions.len(), 2);
+
+        assert_eq!(clip((1.0, 1.0, 2.0, 2.0)).node_positions.len(), 0);
+    }
+
+    #[test]
+    fn test_load_error_message() {
+        let missing = std::env::temp_dir().join("routing_test_missing.osm.pbf");
+        let missing = missing.to_str().unwrap();
+        assert_eq!(load_with_options(&[missing], "auto", &BuildOptions::default()), -1);
+        let message = LAST_ERROR.with(|e| e.borrow().clone());
+        assert!(message.starts_with(&format!("Could not open PBF file {}: ", missing)), "{}", message);
+
+        assert_eq!(not_loaded("auto"), -2);
+        assert_eq!(LAST_ERROR.with(|e| e.borrow().clone()), "No routing graph loaded for mode 'auto'");
+    }
+
+    #[test]
+    fn test_thread_calculators() {
+        let line = |n: usize| {
+            let positions = (0..n).map(|i| (i as f64 * 0.01, 0.0)).collect();
+            let edges: Vec<_> = (1..n).map(|i| (i - 1, i, 1000)).collect();
+            Router::new(make_data(positions, &edges))
+        };
+        // Alternating between graphs of different sizes keeps one calculator per size
+        let (short, long) = (line(3), line(5));
+        for _ in 0..2 {
+            assert_eq!(parallel_travel_time_s(&short, 0, 2), Some(2.0));
+            assert_eq!(parallel_travel_time_s(&long, 0, 4), Some(4.0));
+        }
+        assert_eq!(THREAD_CALCS.with(|calcs| calcs.borrow().len()), 2);
+
+        // Reads from several threads share one router
+        let times: Vec<_> = (0..4)
+            .into_par_iter()
+            .map(|i| calc_route(&long, 0.0, 0.0, i as f64 * 0.01, 0.0).map(|(_, s)| s))
+            .collect();
+        assert_eq!(times, vec![Some(0.0), Some(1.0), Some(2.0), Some(3.0)]);
+    }
+
+    #[test]
+    fn test_route_names() {
+        // 0 - 1 - 2 - 3 along the equator: two stretches of "Main St", then "Side St"
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 1000), (2, 1, 1000), (2, 3, 1000), (3, 2, 1000)],
+        );
+        data.names = vec!["Main St".to_string(), "Side St".to_string()];
+        for (i, info) in data.edge_info.iter_mut().enumerate() {
+            info.name = if i < 4 { 0 } else { 1 };
+        }
+        let router = Router::new(data);
+
+        let route = snapped_route_named(&router, 0.005, 0.0, 0.025, 0.0).unwrap();
+        assert_eq!(route.coords, vec![(0.005, 0.0), (0.01, 0.0), (0.02, 0.0), (0.025, 0.0)]);
+        assert_eq!(route.names, vec![0, 0, 1, NO_NAME]);
+        assert_eq!(
+            name_changes_to_json(&route.names, &router.data.names),
+            r#"[{"index":0,"name":"Main St"},{"index":2,"name":"Side St"}]"#
+        );
+
+        // Starting exactly on node 1: the repeated point keeps the name of the way ahead
+        let route = snapped_route_named(&router, 0.01, 0.0, 0.025, 0.0).unwrap();
+        assert_eq!(route.coords.len(), route.names.len());
+        assert_eq!(route.names.first(), Some(&0));
+        assert_eq!(route.names.last(), Some(&NO_NAME));
+
+        // Both points on one segment
+        let route = snapped_route_named(&router, 0.022, 0.0, 0.028, 0.0).unwrap();
+        assert_eq!((route.coords.len(), route.names), (2, vec![1, NO_NAME]));
+        assert_eq!(name_changes_to_json(&[NO_NAME], &router.data.names), "[]");
+        assert_eq!(name_changes_to_json(&[NO_NAME, NO_NAME], &router.data.names), r#"[{"index":0,"name":null}]"#);
+    }
+
+    #[test]
+    fn test_speed_factor() {
+        assert_eq!(duration_factor("auto"), 1.0);
+        let mode = std::ffi::CString::new("agricultural").unwrap();
+        assert_eq!(routing_set_speed_factor(mode.as_ptr(), 0.0), -1);
+        assert_eq!(routing_set_speed_factor(mode.as_ptr(), f64::NAN), -1);
+        assert_eq!(routing_set_speed_factor(mode.as_ptr(), 1.4), 0);
+        assert_eq!(duration_factor("agricultural"), 1.4);
+        assert_eq!(duration_factor("auto"), 1.0);
+        assert_eq!(routing_set_speed_factor(mode.as_ptr(), 1.0), 0);
+
+        // A 1.4x slowdown shrinks a 14 s isochrone to what 10 s reach unscaled
+        assert_eq!(budget_ms(14.0, 1.4), 10_000);
+        assert_eq!(budget_ms(14.0, 1.0), 14_000);
+    }
+
+    #[test]
+    fn test_find_nearest_nodes() {
+        // Two carriageways of a divided highway 20 m north and 30 m south of the query point,
+        // and a node 0.0005 degrees east: farther than the southern carriageway in planar
+        // degrees, but only 28 m away on the ground at 60N
+        let lat = 60.0;
+        let data = make_data(
+            vec![(10.0, lat + 0.00018), (10.0, lat - 0.00027), (10.0005, lat), (10.01, lat)],
+            &[(0, 3, 1000), (1, 3, 1000), (2, 3, 1000)],
+        );
+
+        let nearest = find_nearest_nodes(&data, 10.0, lat, 2);
+        assert_eq!(nearest.iter().map(|&(idx, _)| idx).collect::<Vec<_>>(), vec![0, 2]);
+        assert!((nearest[0].1 - 20.0).abs() < 1.0, "{}", nearest[0].1);
+        assert!((nearest[1].1 - 28.0).abs() < 1.0, "{}", nearest[1].1);
+        assert_eq!(nearest[0].0, find_nearest_node(&data, 10.0, lat).unwrap());
+
+        let all = find_nearest_nodes(&data, 10.0, lat, 10);
+        assert_eq!(all.iter().map(|&(idx, _)| idx).collect::<Vec<_>>(), vec![0, 2, 1, 3]);
+        assert!(find_nearest_nodes(&data, 10.0, lat, 0).is_empty());
+    }
+
+    #[test]
+    fn test_snap_index_by_mode() {
+        assert!(is_snap_road("footway", "pedestrian", false));
+        assert!(is_snap_road("cycleway", "bicycle", false));
+        assert!(!is_snap_road("track", "auto", false));
+        assert!(is_snap_road("track", "auto", true));
+
+        // The cycleway end nodes are indexed for bicycles, so a point next to one snaps there
+        let osm = barrier_junction("cycleway");
+        let data = build_graph_from_osm(&osm, "bicycle", &BuildOptions::default());
+        assert_eq!(data.spatial_index.size(), data.node_positions.len());
+        let idx = find_nearest_node(&data, 0.001, -0.0011).unwrap();
+        assert_eq!(data.node_positions[idx], (0.001, -0.001));
+
+        let osm = barrier_junction("residential");
+        let options = BuildOptions {
+            snap_all_roads: true,
+            ..Default::default()
+        };
+        let data = build_graph_from_osm(&osm, "auto", &options);
+        assert_eq!(data.spatial_index.size(), data.node_positions.len());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration_s("01:30:15"), Some(5415.0));
+        assert_eq!(parse_duration_s("1:30"), Some(5400.0));
+        assert_eq!(parse_duration_s("45"), Some(2700.0));
+        assert_eq!(parse_duration_s("00:00"), None);
+        assert_eq!(parse_duration_s("PT1H"), None);
+        assert_eq!(parse_duration_s("1:2:3:4"), None);
+    }
+
+    #[test]
+    fn test_ferry() {
+        // Mainland road 1-2, ferry 2-3, island road 3-4
+        let ferry = |extra: &[(&str, &str)]| {
+            let mut pairs = vec![("route", "ferry"), ("duration", "00:30")];
+            pairs.extend_from_slice(extra);
+            let ferry_tags = tags(&pairs);
+            let way = |id: i64, tags: Tags, nodes: &[i64]| osmpbfreader::Way {
+                id: osmpbfreader::WayId(id),
+                tags,
+                nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+            };
+            OsmData {
+                nodes: HashMap::from([(1, (0.0, 0.0)), (2, (0.001, 0.0)), (3, (0.1, 0.0)), (4, (0.101, 0.0))]),
+                barriers: HashMap::new(),
+                crossings: HashSet::new(),
+                ways: vec![
+                    way(10, tags(&[("highway", "residential")]), &[1, 2]),
+                    way(11, ferry_tags, &[2, 3]),
+                    way(12, tags(&[("highway", "residential")]), &[3, 4]),
+                ],
+                restrictions: Vec::new(),
+            }
+        };
+        let options = BuildOptions::default();
+        let (mainland, island) = ((0.0, 0.0), (0.101, 0.0));
+
+        // Passenger ferry: walkers and cyclists cross, cars need motor_vehicle=yes
+        let osm = ferry(&[]);
+        assert!(connected(&build_graph_from_osm(&osm, "pedestrian", &options), mainland, island));
+        assert!(connected(&build_graph_from_osm(&osm, "bicycle", &options), mainland, island));
+        assert!(!connected(&build_graph_from_osm(&osm, "auto", &options), mainland, island));
+
+        let osm = ferry(&[("motor_vehicle", "yes"), ("foot", "no")]);
+        let data = build_graph_from_osm(&osm, "auto", &options);
+        assert!(connected(&data, mainland, island));
+        assert!(!connected(&build_graph_from_osm(&osm, "pedestrian", &options), mainland, island));
+
+        // The crossing takes the tagged 30 minutes
+        let crossing_ms = |data: &RoutingData| {
+            let idx = |p| data.node_positions.iter().position(|&q| q == p).unwrap();
+            let (from, to) = (idx((0.001, 0.0)), idx((0.1, 0.0)));
+            data.adj_list[from].iter().find(|&&(next, _)| next == to).unwrap().1
+        };
+        assert!(crossing_ms(&data).abs_diff(1_800_000) <= 1, "{}", crossing_ms(&data));
+
+        // Without a duration the default ferry speed applies
+        let mut osm = ferry(&[("motor_vehicle", "yes")]);
+        osm.ways[1].tags.remove("duration");
+        let crossing_ms = crossing_ms(&build_graph_from_osm(&osm, "auto", &options));
+        let length_m = Haversine::distance(Point::new(0.001, 0.0), Point::new(0.1, 0.0));
+        let expected_ms = length_m / 1000.0 / FERRY_SPEED_KMH * 3_600_000.0;
+        assert!((crossing_ms as f64 - expected_ms).abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_avoid_tolls() {
+        // Tolled motorway 0 -> 1 -> 3 against a slower free road 0 -> 2 -> 3
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.01, 0.01), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 3, 1000), (0, 2, 3000), (2, 3, 3000)],
+        );
+        for (i, info) in data.edge_info.iter_mut().enumerate() {
+            let (from, to) = [(0, 1), (0, 2), (1, 3), (2, 3)][i];
+            info.toll = (from, to) == (0, 1) || (from, to) == (1, 3);
+        }
+        let router = Router::new(data);
+
+        let path = toll_free_path(&router, 0, 3).unwrap();
+        assert_eq!(path.nodes, vec![0, 2, 3]);
+        assert_eq!(path.cost_ms, 6000);
+
+        // With no way around, the toll road is still used and its cost is the plain travel time
+        let path = toll_free_path(&router, 0, 1).unwrap();
+        assert_eq!(path.nodes, vec![0, 1]);
+        assert_eq!(path.cost_ms, 1000);
+    }
+
+    #[test]
+    fn test_route_segments() {
+        // 0 -> 1 is a 111 m service road taking 20 s, 1 -> 2 an unclassified type
+        let mut data = make_data(vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0)], &[(0, 1, 20_000), (1, 2, 10_000)]);
+        data.edge_info[0].highway = highway_class("service");
+        let router = Router::new(data);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&route_segments_to_json(&router, &[0, 1, 2], 1.0, DistanceUnit::Meters)).unwrap();
+        let segments = json.as_array().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0]["highway"], "service");
+        assert_eq!(segments[0]["duration_s"], 20.0);
+        assert_eq!(segments[0]["way_id"], 1);
+        assert_eq!(segments[0]["to_lon"], 0.001);
+        let speed = segments[0]["speed_kmh"].as_f64().unwrap();
+        assert!((speed - 20.0).abs() < 0.1, "{}", speed);
+        assert!(segments[1]["highway"].is_null());
+
+        // Durations follow the speed factor, distances the unit
+        let json: serde_json::Value =
+            serde_json::from_str(&route_segments_to_json(&router, &[0, 1], 2.0, DistanceUnit::Kilometers)).unwrap();
+        assert_eq!(json[0]["duration_s"], 40.0);
+        assert!((json[0]["distance_m"].as_f64().unwrap() - 0.111).abs() < 0.001);
+        assert_eq!(route_segments_to_json(&router, &[0], 1.0, DistanceUnit::Meters), "[]");
+    }
+
+    #[test]
+    fn test_surface_factors() {
+        let none = Profile::default();
+        let surface = |value: &str| tags(&[("surface", value)]);
+        assert_eq!(surface_factor(&tags(&[]), "bicycle", &none), Some(1.0));
+        assert_eq!(surface_factor(&surface("asphalt"), "bicycle", &none), Some(1.0));
+        assert_eq!(surface_factor(&surface("gravel"), "bicycle", &none), Some(0.8));
+        assert_eq!(surface_factor(&surface("sand"), "bicycle", &none), Some(0.5));
+        assert_eq!(surface_factor(&surface("cobblestone"), "bicycle", &none), Some(0.7));
+        assert_eq!(surface_factor(&surface("mud"), "pedestrian", &none), Some(0.7));
+        assert_eq!(surface_factor(&surface("mud"), "auto", &none), Some(1.0));
+        assert_eq!(surface_factor(&surface("moon_dust"), "bicycle", &none), Some(1.0));
+
+        let profile = Profile::from_json(r#"{"track": 12, "surface": {"gravel": 0.6, "sand": 0}}"#).unwrap();
+        assert_eq!(profile.speeds.len(), 1);
+        assert_eq!(surface_factor(&surface("gravel"), "bicycle", &profile), Some(0.6));
+        assert_eq!(surface_factor(&surface("sand"), "bicycle", &profile), None);
+        assert_eq!(surface_factor(&surface("mud"), "bicycle", &profile), Some(0.5));
+        assert!(Profile::from_json(r#"{"surface": [0.5]}"#).is_err());
+        assert!(Profile::from_json(r#"{"surface": {"sand": "slow"}}"#).is_err());
+
+        // Surfaces change the profile hash; speed-only profiles keep theirs
+        let speeds_only = Profile::from_json(r#"{"track": 12}"#).unwrap();
+        assert_ne!(profile.hash(), speeds_only.hash());
+        assert_eq!(speeds_only.hash(), fnv1a(b"track\0".iter().copied().chain(12.0f64.to_bits().to_le_bytes())));
+        assert!(!Profile::from_json(r#"{"surface": {"sand": 0.4}}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_way_penalties() {
+        let profile = Profile::from_json(
+            r#"{"penalties": [{"tag": "highway", "value": "primary", "multiplier": 3},
+                {"tag": "lit", "value": "no", "multiplier": 1.5}, {"tag": "toll", "multiplier": 0}]}"#,
+        )
+        .unwrap();
+        assert!(!profile.is_empty());
+        assert_eq!(way_penalty(&tags(&[("highway", "primary")]), &profile), Some(3.0));
+        assert_eq!(way_penalty(&tags(&[("highway", "primary"), ("lit", "no")]), &profile), Some(4.5));
+        assert_eq!(way_penalty(&tags(&[("highway", "secondary"), ("lit", "yes")]), &profile), Some(1.0));
+        assert_eq!(way_penalty(&tags(&[("highway", "primary"), ("toll", "no")]), &profile), None);
+        assert!(Profile::from_json(r#"{"penalties": {"tag": "highway"}}"#).is_err());
+        assert!(Profile::from_json(r#"{"penalties": [{"tag": "highway", "value": "primary"}]}"#).is_err());
+
+        // Rule order does not change the hash; the rules do
+        let reordered = Profile::from_json(
+            r#"{"penalties": [{"tag": "toll", "multiplier": 0}, {"tag": "lit", "value": "no", "multiplier": 1.5},
+                {"tag": "highway", "value": "primary", "multiplier": 3}]}"#,
+        )
+        .unwrap();
+        assert_eq!(profile.hash(), reordered.hash());
+        let fewer = Profile::from_json(r#"{"penalties": [{"tag": "toll", "multiplier": 0}]}"#).unwrap();
+        assert_ne!(profile.hash(), fewer.hash());
+        assert_ne!(fewer.hash(), Profile::default().hash());
+
+        // A tripled primary road takes three times as long in the built graph
+        let way = osmpbfreader::Way {
+            id: osmpbfreader::WayId(10),
+            tags: tags(&[("highway", "primary")]),
+            nodes: vec![osmpbfreader::NodeId(1), osmpbfreader::NodeId(2)],
+        };
+        let nodes = HashMap::from([(1, (0.0, 0.0)), (2, (0.01, 0.0))]);
+        let segment_ms = |profile: Profile| {
+            let options = BuildOptions {
+                profile,
+                ..Default::default()
+            };
+            way_segments(&way, "auto", &options, &nodes, &HashMap::new()).unwrap()[0].forward_ms
+        };
+        let plain = segment_ms(Profile::default());
+        assert!(segment_ms(profile).abs_diff(plain * 3) <= 3);
+        assert_eq!(segment_ms(fewer), plain);
     }
 
-    let from_wkt = match unsafe { CStr::from_ptr(from_wkt) }.to_str() {
-        Ok(s) if !from_wkt.is_null() => s,
-        _ => return -1,
-    };
+    #[test]
+    fn test_parse_incline() {
+        assert_eq!(parse_incline("10%"), Some(0.1));
+        assert_eq!(parse_incline("-5 %"), Some(-0.05));
+        assert_eq!(parse_incline("8"), Some(0.08));
+        assert!((parse_incline("45°").unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(parse_incline("up"), None);
+        assert_eq!(parse_incline("down"), None);
+    }
 
-    let to_wkt = match unsafe { CStr::from_ptr(to_wkt) }.to_str() {
-        Ok(s) if !to_wkt.is_null() => s,
-        _ => return -1,
-    };
+    #[test]
+    fn test_elevation_grid() {
+        // 3 x 2 cells of 0.01 degrees; the north-east cell has no data
+        let grid = ElevationGrid::from_ascii(
+            "ncols 3\nnrows 2\nxllcorner 10.0\nyllcorner 45.0\ncellsize 0.01\nNODATA_value -9999\n\
+             100 110 -9999\n\
+             100 120 130\n",
+        )
+        .unwrap();
+        let near = |h: Option<f64>, expected: f64| h.is_some_and(|h| (h - expected).abs() < 1e-6);
+        assert!(near(grid.height(10.005, 45.015), 100.0));
+        assert!(near(grid.height(10.015, 45.005), 120.0));
+        // Halfway between two cell centers
+        assert!(near(grid.height(10.01, 45.015), 105.0));
+        assert!(near(grid.height(10.01, 45.01), 107.5));
+        assert_eq!(grid.height(10.025, 45.015), None);
+        assert_eq!(grid.height(9.9, 45.005), None);
+
+        let centered = "ncols 1 nrows 1 xllcenter 10.005 yllcenter 45.005 cellsize 0.01 7";
+        let centered = ElevationGrid::from_ascii(centered).unwrap();
+        assert!(near(centered.height(10.001, 45.009), 7.0));
+        assert!(ElevationGrid::from_ascii("ncols 2 nrows 2 xllcorner 0 yllcorner 0 cellsize 1 1 2 3").is_err());
+        assert!(ElevationGrid::from_ascii("ncols 1 nrows 1 cellsize 1 5").is_err());
+    }
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+    #[test]
+    fn test_grade_speed_factor() {
+        assert_eq!(grade_speed_factor("pedestrian", 0.0, 5.0), 1.0);
+        assert!(grade_speed_factor("pedestrian", -0.05, 5.0) > 1.0);
+        assert!((grade_speed_factor("pedestrian", 0.1, 5.0) - (-0.35f64).exp()).abs() < 1e-9);
+        // Walkers slow down on steep descents too
+        assert!(grade_speed_factor("pedestrian", -0.2, 5.0) < 1.0);
+
+        let up = grade_speed_factor("bicycle", 0.05, 18.0);
+        let down = grade_speed_factor("bicycle", -0.05, 18.0);
+        assert!(up > 0.3 && up < 0.7, "{}", up);
+        assert!(down > 1.0 && down <= MAX_DESCENT_SPEED_FACTOR, "{}", down);
+        assert!(grade_speed_factor("bicycle", 0.1, 18.0) < up);
+        assert_eq!(grade_speed_factor("bicycle", -0.2, 18.0), MAX_DESCENT_SPEED_FACTOR);
+        assert_eq!(grade_speed_factor("auto", 0.1, 50.0), 1.0);
+    }
 
-    // Parse WKT and get centroids
-    let (lon1, lat1) = match wkt_to_centroid(from_wkt) {
-        Some(c) => c,
-        None => return -1,
-    };
+    #[test]
+    fn test_elevation_build() {
+        // A footway climbing 10 m over ~111 m to the east, per the terrain model
+        const RAMP: &str = "ncols 2 nrows 1 xllcorner -0.0005 yllcorner -0.0005 cellsize 0.001 0 10";
+        let way = |tags: Tags| osmpbfreader::Way {
+            id: osmpbfreader::WayId(1),
+            tags,
+            nodes: vec![osmpbfreader::NodeId(1), osmpbfreader::NodeId(2)],
+        };
+        let osm = |tags: Tags| OsmData {
+            nodes: HashMap::from([(1, (0.0, 0.0)), (2, (0.001, 0.0))]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![way(tags)],
+            restrictions: Vec::new(),
+        };
+        let options = |dem: Option<&str>| BuildOptions {
+            elevation: Some(Arc::new(Elevation {
+                dem_hash: dem.map_or(0, |text| fnv1a(text.bytes())),
+                grid: dem.map(|text| ElevationGrid::from_ascii(text).unwrap()),
+            })),
+            ..Default::default()
+        };
+        let weights = |data: &RoutingData| {
+            let idx = |p| data.node_positions.iter().position(|&q| q == p).unwrap();
+            let (west, east) = (idx((0.0, 0.0)), idx((0.001, 0.0)));
+            let weight = |a: usize, b: usize| data.adj_list[a].iter().find(|&&(n, _)| n == b).unwrap().1;
+            (weight(west, east), weight(east, west))
+        };
 
-    let (lon2, lat2) = match wkt_to_centroid(to_wkt) {
-        Some(c) => c,
-        None => return -1,
-    };
+        let footway = tags(&[("highway", "footway")]);
+        let flat = build_graph_from_osm(&osm(footway.clone()), "pedestrian", &Default::default());
+        let (flat_up, flat_down) = weights(&flat);
+        assert_eq!(flat_up, flat_down);
+        let ramp = options(Some(RAMP));
+        let (up, down) = weights(&build_graph_from_osm(&osm(footway), "pedestrian", &ramp));
+        assert!(up > flat_up && down < up, "{} {} {}", flat_up, up, down);
+
+        // Incline tags apply in way direction without a terrain model; bridges ignore the terrain
+        let inclined = tags(&[("highway", "footway"), ("incline", "-9%")]);
+        let (up, down) = weights(&build_graph_from_osm(&osm(inclined), "pedestrian", &options(None)));
+        assert!(up < down);
+        let bridge = tags(&[("highway", "footway"), ("bridge", "yes")]);
+        assert_eq!(weights(&build_graph_from_osm(&osm(bridge), "pedestrian", &ramp)), (flat_up, flat_down));
+
+        // The cache is keyed by the DEM's contents, so a DEM replaced under the same path gets a new file
+        let path = |options: &BuildOptions| cache_path_for("/data/it.osm.pbf", "bicycle", options);
+        assert_eq!(path(&options(None)), "/data/it.osm.pbf.bicycle.elev_0000000000000000.routing");
+        assert!(path(&ramp).starts_with("/data/it.osm.pbf.bicycle.elev_") && path(&ramp).ends_with(".routing"));
+        assert_ne!(path(&ramp), path(&options(None)));
+        assert_ne!(path(&ramp), path(&options(Some(&RAMP.replace(" 10", " 20")))));
+    }
 
-    let mutex = get_router_for_mode(mode);
-    let mut guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
-    };
+    #[test]
+    fn test_match_trace() {
+        // Two parallel two-way streets 220 m apart, joined at both ends:
+        // south 0 - 1 - 2 - 3 - 4 and north 5 - 6 - 7 - 8 - 9, with 0 - 5 and 4 - 9
+        let mut positions = Vec::new();
+        for lat in [0.0, 0.002] {
+            for i in 0..5 {
+                positions.push((i as f64 * 0.001, lat));
+            }
+        }
+        let mut edges = Vec::new();
+        for (a, b) in [(0, 1), (1, 2), (2, 3), (3, 4), (5, 6), (6, 7), (7, 8), (8, 9), (0, 5), (4, 9)] {
+            edges.push((a, b, 10_000));
+            edges.push((b, a, 10_000));
+        }
+        let router = Router::new(make_data(positions, &edges));
 
-    let router = match guard.as_mut() {
-        Some(r) => r,
-        None => return -2,
-    };
+        // A noisy trace along the south street; the second fix strays towards the north one
+        let trace = [(0.0001, 0.0002), (0.0011, 0.0009), (0.0019, -0.0002), (0.0031, 0.0001), (0.004, 0.0003)];
+        let (nodes, duration_ms) = match_trace(&router, &trace, None).unwrap();
+        assert_eq!(nodes, vec![0, 1, 2, 3, 4]);
+        assert_eq!(duration_ms, 40_000);
 
-    // Find nearest nodes
-    let from_idx = match find_nearest_node(&router.data, lon1, lat1) {
-        Some(idx) => idx,
-        None => return -1,
-    };
+        // Sparse fixes are joined by road paths
+        let (nodes, _) = match_trace(&router, &[(0.0, 0.0), (0.004, 0.0)], Some(&[0.0, 60.0])).unwrap();
+        assert_eq!(nodes, vec![0, 1, 2, 3, 4]);
 
-    let to_idx = match find_nearest_node(&router.data, lon2, lat2) {
-        Some(idx) => idx,
-        None => return -1,
-    };
+        // A jump too fast for the elapsed time breaks the model; the path jumps too
+        let (nodes, _) = match_trace(&router, &[(0.0, 0.0), (0.004, 0.0)], Some(&[0.0, 1.0])).unwrap();
+        assert_eq!(nodes, vec![0, 4]);
 
-    // Calculate path
-    let path = match router
-        .calculator
-        .calc_path(&router.data.fast_graph, from_idx, to_idx)
-    {
-        Some(p) => p,
-        None => return -1,
-    };
+        let (nodes, duration_ms) = match_trace(&router, &[(0.0021, 0.0019)], None).unwrap();
+        assert_eq!((nodes, duration_ms), (vec![7], 0));
+        assert!(match_trace(&router, &[], None).is_none());
+    }
+
+    #[test]
+    fn test_avoid_area() {
+        // Direct street 0 - 1 - 2 along the equator, detour 0 - 3 - 4 - 2 to the north
+        let positions = vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0), (0.0, 0.002), (0.002, 0.002)];
+        let mut edges = Vec::new();
+        for (a, b, w) in [(0, 1, 1000), (1, 2, 1000), (0, 3, 3000), (3, 4, 3000), (4, 2, 3000)] {
+            edges.push((a, b, w));
+            edges.push((b, a, w));
+        }
+        let router = Router::new(make_data(positions, &edges));
+        let route = |area: &geo::MultiPolygon<f64>| {
+            let blocked = edges_in_area(&router, area);
+            dijkstra_path(&router, 0, 2, |edge_id, base| (!blocked.contains(&edge_id)).then_some(base)).map(|p| p.nodes)
+        };
 
-    let path_nodes = path.get_nodes();
-    let duration_s = path.get_weight() as f64 / 1000.0;
+        // Closing the middle node of the direct street forces the detour
+        let closure =
+            parse_avoid_area("POLYGON((0.0009 -0.0001, 0.0011 -0.0001, 0.0011 0.0001, 0.0009 0.0001, 0.0009 -0.0001))")
+                .unwrap();
+        assert_eq!(edges_in_area(&router, &closure).len(), 4);
+        assert_eq!(route(&closure), Some(vec![0, 3, 4, 2]));
+
+        // A narrow band across an edge blocks it even with both endpoints outside
+        let band =
+            parse_avoid_area("POLYGON((0.0004 -0.0001, 0.0005 -0.0001, 0.0005 0.0001, 0.0004 0.0001, 0.0004 -0.0001))")
+                .unwrap();
+        assert_eq!(route(&band), Some(vec![0, 3, 4, 2]));
+
+        // Closing both streets disconnects the endpoints
+        let both = parse_avoid_area(
+            "SRID=4326;MULTIPOLYGON(((0.0009 -0.0001, 0.0011 -0.0001, 0.0011 0.0001, 0.0009 0.0001, 0.0009 -0.0001)),\
+             ((0.0009 0.0019, 0.0011 0.0019, 0.0011 0.0021, 0.0009 0.0021, 0.0009 0.0019)))",
+        )
+        .unwrap();
+        assert_eq!(route(&both), None);
+
+        let far = parse_avoid_area("POLYGON((1 1, 2 1, 2 2, 1 2, 1 1))").unwrap();
+        assert!(edges_in_area(&router, &far).is_empty());
+        assert_eq!(route(&far), Some(vec![0, 1, 2]));
+        assert!(parse_avoid_area("POINT(0 0)").is_err());
+        assert!(parse_avoid_area("POLYGON((0 0").is_err());
+    }
 
-    // Calculate actual road distance and collect points
-    let mut total_distance_m = 0.0;
-    let out_points = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
-    let num_points = path_nodes.len().min(max_points as usize);
+    #[test]
+    fn test_reachable_poi_count() {
+        // Chain 0 - 1 - 2 - 3, one minute per edge
+        let positions = vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0), (0.003, 0.0)];
+        let mut edges = Vec::new();
+        for (a, b) in [(0, 1), (1, 2), (2, 3)] {
+            edges.push((a, b, 60_000));
+            edges.push((b, a, 60_000));
+        }
+        let data = make_data(positions, &edges);
+
+        // Two POIs share node 1, one is on node 3
+        let pois_at: HashMap<usize, u32> = [(1, 2), (3, 1)].into_iter().collect();
+        assert_eq!(reachable_poi_count(&data, 0, &pois_at, 30_000), 0);
+        assert_eq!(reachable_poi_count(&data, 0, &pois_at, 60_000), 2);
+        assert_eq!(reachable_poi_count(&data, 0, &pois_at, 180_000), 3);
+        assert_eq!(reachable_poi_count(&data, 2, &pois_at, 60_000), 3);
+        assert_eq!(reachable_poi_count(&data, 1, &pois_at, 0), 2);
+    }
 
-    for i in 0..num_points {
-        let node_idx = path_nodes[i];
-        let (lon, lat) = router.data.node_positions[node_idx];
-        out_points[i] = RoutePoint { lat, lon };
+    #[test]
+    fn test_matrix_by_id() {
+        // 0 -> 1 -> 2, oneway, 10 s per edge
+        let data = make_data(vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0)], &[(0, 1, 10_000), (1, 2, 10_000)]);
+
+        assert_eq!(node_from_id(&data, 0), Some(0));
+        assert_eq!(node_from_id(&data, 2), Some(2));
+        assert_eq!(node_from_id(&data, 3), None);
+        assert_eq!(node_from_id(&data, -1), None);
+
+        let sources = [Some(0), node_from_id(&data, 7), Some(2)];
+        let targets = [Some(2), Some(1)];
+        let mut results = vec![0.0; 6];
+        assert_eq!(fill_matrix(&data, &sources, &targets, 1.0, &mut results), 3);
+        assert_eq!(results, vec![20.0, 10.0, -1.0, -1.0, 0.0, -1.0]);
+
+        assert_eq!(fill_matrix(&data, &sources, &[], 1.0, &mut []), 0);
+    }
 
-        if i > 0 {
-            let prev_idx = path_nodes[i - 1];
-            let (prev_lon, prev_lat) = router.data.node_positions[prev_idx];
-            let p1 = Point::new(prev_lon, prev_lat);
-            let p2 = Point::new(lon, lat);
-            total_distance_m += p1.haversine_distance(&p2);
+    #[test]
+    fn test_contract_parallel_chains() {
+        // Short residential 1-2-3 and a longer but faster primary 1-4-3 between the same junctions
+        let way = |id: i64, highway: &str, nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags: tags(&[("highway", highway)]),
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        let osm = OsmData {
+            nodes: HashMap::from([(1, (0.0, 0.0)), (2, (0.001, 0.0005)), (3, (0.002, 0.0)), (4, (0.001, -0.0015))]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![way(10, "residential", &[1, 2, 3]), way(11, "primary", &[1, 4, 3])],
+            restrictions: Vec::new(),
+        };
+        let options = BuildOptions { contract_chains: true, ..BuildOptions::default() };
+        let router = Router::new(build_graph_from_osm(&osm, "auto", &options));
+        // One chain keeps its interior node
+        assert_eq!(router.data.node_positions.len(), 3);
+
+        let pos = |id: i64| osm.nodes[&id];
+        let (from, to) = (pos(1), pos(3));
+        let shortest = calc_shortest_route(&router, from.0, from.1, to.0, to.1).unwrap();
+        assert_eq!(path_coords(&router.data, &shortest), vec![pos(1), pos(2), pos(3)]);
+        let distance_m = path_distance_m(&router.data, &shortest);
+        assert!((distance_m - coords_length_m(&[pos(1), pos(2), pos(3)])).abs() < 1e-6);
+        let (fastest, _) = calc_route(&router, from.0, from.1, to.0, to.1).unwrap();
+        assert_eq!(path_coords(&router.data, &fastest), vec![pos(1), pos(4), pos(3)]);
+    }
+
+    #[test]
+    fn test_contract_chains() {
+        // Curved street 1-2-3-4 with branches to 5 and 6, a oneway 6-7-8-1 back, a loop
+        // 5-40-41-5 and a separate ring 30-31-32
+        let way = |id: i64, pairs: &[(&str, &str)], nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags: tags(pairs),
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        let residential = [("highway", "residential")];
+        let osm = OsmData {
+            nodes: HashMap::from([
+                (1, (0.0, 0.0)),
+                (2, (0.001, 0.0005)),
+                (3, (0.002, 0.0)),
+                (4, (0.003, 0.0)),
+                (5, (0.003, 0.001)),
+                (6, (0.003, -0.001)),
+                (7, (0.002, -0.002)),
+                (8, (0.0, -0.001)),
+                (40, (0.004, 0.002)),
+                (41, (0.002, 0.002)),
+                (30, (0.01, 0.0)),
+                (31, (0.011, 0.0)),
+                (32, (0.0105, 0.001)),
+            ]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![
+                way(10, &residential, &[1, 2, 3, 4]),
+                way(11, &residential, &[4, 5]),
+                way(12, &residential, &[4, 6]),
+                way(13, &[("highway", "residential"), ("oneway", "yes")], &[6, 7, 8, 1]),
+                way(14, &residential, &[5, 40, 41, 5]),
+                way(20, &residential, &[30, 31, 32, 30]),
+            ],
+            restrictions: Vec::new(),
+        };
+        let options = BuildOptions {
+            min_component_size: 0,
+            ..BuildOptions::default()
+        };
+        let plain = Router::new(build_graph_from_osm(&osm, "auto", &options));
+        let contracted = Router::new(build_graph_from_osm(
+            &osm,
+            "auto",
+            &BuildOptions {
+                contract_chains: true,
+                ..options.clone()
+            },
+        ));
+        assert_eq!(plain.data.node_positions.len(), 13);
+        // 2, 3, 7 and 8 are collapsed; the loop and the ring are too short to collapse without
+        // leaving two edges between the same nodes
+        assert_eq!(contracted.data.node_positions.len(), 9);
+
+        let pos = |id: i64| osm.nodes[&id];
+        let idx = |router: &Router, id: i64| router.data.node_positions.iter().position(|&p| p == pos(id));
+        let path = |router: &Router, a: usize, b: usize| {
+            with_calculator(&router.data.fast_graph, |calc| calc.calc_path(&router.data.fast_graph, a, b))
+                .map(|p| (p.get_nodes().clone(), p.get_weight()))
+        };
+        let route = |router: &Router, a: i64, b: i64| path(router, idx(router, a).unwrap(), idx(router, b).unwrap());
+
+        // Same travel times, geometry and distances
+        for (a, b) in [(1, 5), (5, 1), (6, 4), (40, 5), (5, 41)] {
+            let (plain_nodes, plain_ms) = route(&plain, a, b).unwrap();
+            let (nodes, ms) = route(&contracted, a, b).unwrap();
+            assert_eq!(ms, plain_ms, "{} -> {}", a, b);
+            assert_eq!(path_coords(&contracted.data, &nodes), path_coords(&plain.data, &plain_nodes));
+            let distance_m = path_distance_m(&contracted.data, &nodes);
+            assert!((distance_m - path_distance_m(&plain.data, &plain_nodes)).abs() < 1e-6);
         }
+        let (nodes, _) = route(&contracted, 1, 5).unwrap();
+        assert_eq!(path_coords(&contracted.data, &nodes), vec![pos(1), pos(2), pos(3), pos(4), pos(5)]);
+        assert_eq!(nodes.len(), 3);
+
+        // The ring is still a two-way loop
+        let ring: Vec<usize> = (0..9).filter(|&n| contracted.data.node_positions[n].0 >= 0.01).collect();
+        assert_eq!(ring.len(), 3);
+        assert!(path(&contracted, ring[0], ring[1]).is_some() && path(&contracted, ring[1], ring[0]).is_some());
+
+        // Edge snaps land on the shape and give the same door-to-door time
+        let snap_route = |router: &Router| {
+            let origin = find_nearest_edge(router, 0.0013, 0.0004).unwrap();
+            let dest = find_nearest_edge(router, 0.0031, 0.0008).unwrap();
+            (origin, route_between_snaps(router, &origin, &dest).unwrap().1)
+        };
+        let ((plain_origin, plain_ms), (origin, ms)) = (snap_route(&plain), snap_route(&contracted));
+        assert!((origin.lon - plain_origin.lon).abs() < 1e-9 && (origin.lat - plain_origin.lat).abs() < 1e-9);
+        assert!(ms.abs_diff(plain_ms) <= 2, "{} vs {}", ms, plain_ms);
+        let coords = snapped_route(&contracted, 0.0013, 0.0004, 0.0031, 0.0008).unwrap().0;
+        let plain_coords = snapped_route(&plain, 0.0013, 0.0004, 0.0031, 0.0008).unwrap().0;
+        assert_eq!(coords.len(), plain_coords.len());
+        assert!(coords.iter().zip(&plain_coords).all(|(a, b)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9));
+
+        // Shapes survive the cache
+        let mut bytes = Vec::new();
+        write_graph(&mut bytes, &CacheHeader::new("auto", &options), &contracted.data).unwrap();
+        let loaded = read_graph(bytes.as_slice(), "auto", None).unwrap();
+        assert_eq!(loaded.edge_shapes, contracted.data.edge_shapes);
+
+        // Kept nodes are not collapsed
+        let chain: AdjList = vec![vec![(1, 10)], vec![(0, 10), (2, 10)], vec![(1, 10)]];
+        let info = EdgeInfo {
+            way_id: 1,
+            name: NO_NAME,
+            highway: HIGHWAY_OTHER,
+            roundabout: false,
+            toll: false,
+        };
+        let infos: Vec<Vec<EdgeInfo>> = chain.iter().map(|edges| vec![info; edges.len()]).collect();
+        let positions = [(0.0, 0.0), (0.001, 0.0), (0.002, 0.0)];
+        let (new_index, adj, _, shapes) = contract_chains(&positions, &chain, &infos, &[false; 3]);
+        assert_eq!(new_index, vec![Some(0), None, Some(1)]);
+        assert_eq!(adj, vec![vec![(1, 20)], vec![(0, 20)]]);
+        assert_eq!(shapes.interior(0), &[(0.001, 0.0)]);
+        let (new_index, _, _, _) = contract_chains(&positions, &chain, &infos, &[false, true, false]);
+        assert_eq!(new_index, vec![Some(0), Some(1), Some(2)]);
     }
 
-    unsafe {
-        *out_result = RouteResult {
-            distance_m: total_distance_m,
-            duration_s,
-            num_points: num_points as i32,
+    #[test]
+    fn test_load_buffer() {
+        let hex = |digest: [u8; 32]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        // A temporary base directory instead of the user's cache directory
+        let base = std::env::temp_dir().join(format!("routing-cache-home-{}", std::process::id()));
+        let dir = private_cache_dir(&base).unwrap();
+        assert_eq!(dir, base.join("duckdb-routing"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&dir).unwrap().permissions().mode() & 0o077, 0);
+        }
+        std::fs::remove_dir_all(&base).unwrap();
+        let digest = sha256(b"pbf bytes");
+        let key = buffer_cache_key(&dir, &digest, 9);
+        assert_eq!(key, buffer_cache_key(&dir, &sha256(b"pbf bytes"), 9));
+        assert_ne!(key, buffer_cache_key(&dir, &sha256(b"other bytes"), 11));
+        assert_eq!(Path::new(&key), dir.join(format!("routing-buffer-{}-9", hex(digest))));
+        assert!(read_osm_bytes(b"not a pbf file").is_err());
+
+        // A cache is only used for the bytes it was built from
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]);
+        let path = std::env::temp_dir().join(format!("routing-buffer-test-{}.routing", std::process::id()));
+        let path = path.to_str().unwrap();
+        let built_from = |digest| {
+            CacheHeader::new("auto", &BuildOptions { source_digest: Some(digest), ..BuildOptions::default() })
         };
+        save_graph(&data, &built_from(digest), path, 0).unwrap();
+        assert!(load_graph(path, &built_from(digest)).is_ok());
+        let err = load_graph(path, &built_from(sha256(b"other bytes"))).err().unwrap();
+        assert!(err.to_string().contains("different PBF bytes"));
+        std::fs::remove_file(path).unwrap();
     }
 
-    num_points as i32
-}
+    #[test]
+    fn test_snapped_cost() {
+        let line = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 1000), (2, 1, 1000)],
+        );
+        let router = Router::new(line);
+        let (meters, ms) = snapped_cost(&router, 0.004, 0.0, 0.016, 0.0).unwrap();
+        assert_eq!(ms, 1200);
+        let (coords, _) = snapped_route(&router, 0.004, 0.0, 0.016, 0.0).unwrap();
+        assert!((meters - coords_length_m(&coords)).abs() < 1e-9);
+        assert!((meters - 1334.0).abs() < 1.0);
+    }
 
-/// Calculate route with full geometry using WKB geometries as input
-/// Uses centroid of each geometry as routing point
-/// Returns number of path points written, or -1 on error, -2 if not loaded
-#[no_mangle]
-pub extern "C" fn routing_route_wkb(
-    from_wkb: *const u8,
-    from_wkb_len: i32,
-    to_wkb: *const u8,
-    to_wkb_len: i32,
-    mode: *const c_char,
-    out_result: *mut RouteResult,
-    out_points: *mut RoutePoint,
-    max_points: i32,
-) -> i32 {
-    if from_wkb.is_null() || to_wkb.is_null() || out_result.is_null() || out_points.is_null() || max_points <= 0 {
-        return -1;
+    #[test]
+    fn test_exclude_options() {
+        assert!(is_excluded(&tags(&[("route", "ferry")]), "", EXCLUDE_FERRIES));
+        assert!(!is_excluded(&tags(&[("route", "ferry")]), "", EXCLUDE_TOLLS | EXCLUDE_UNPAVED));
+        assert!(is_excluded(&tags(&[("toll", "yes")]), "motorway", EXCLUDE_TOLLS));
+        assert!(!is_excluded(&tags(&[("toll", "no")]), "motorway", EXCLUDE_ALL));
+        assert!(is_excluded(&tags(&[]), "track", EXCLUDE_UNPAVED));
+        assert!(is_excluded(&tags(&[("surface", "gravel")]), "residential", EXCLUDE_UNPAVED));
+        assert!(!is_excluded(&tags(&[("surface", "asphalt")]), "residential", EXCLUDE_UNPAVED));
+        assert!(!is_excluded(&tags(&[]), "track", 0));
+
+        // Mainland road 1-2, car ferry 2-3, island road 3-4
+        let way = |id: i64, tags: Tags, nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags,
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        let osm = OsmData {
+            nodes: HashMap::from([(1, (0.0, 0.0)), (2, (0.001, 0.0)), (3, (0.1, 0.0)), (4, (0.101, 0.0))]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![
+                way(10, tags(&[("highway", "residential")]), &[1, 2]),
+                way(11, tags(&[("route", "ferry"), ("motor_vehicle", "yes")]), &[2, 3]),
+                way(12, tags(&[("highway", "residential")]), &[3, 4]),
+            ],
+            restrictions: Vec::new(),
+        };
+        let (mainland, island) = ((0.0, 0.0), (0.101, 0.0));
+        let options = BuildOptions::default();
+        let data = build_graph_from_osm(&osm, "auto", &options);
+        assert!(connected(&data, mainland, island));
+        assert_eq!(data.exclude, 0);
+
+        let strict = BuildOptions {
+            exclude: EXCLUDE_FERRIES,
+            ..options.clone()
+        };
+        let data = build_graph_from_osm(&osm, "auto", &strict);
+        assert!(!connected(&data, mainland, island));
+        assert_eq!(data.exclude, EXCLUDE_FERRIES);
+
+        // Each exclusion set has its own cache file
+        assert_eq!(cache_path_for("a.pbf", "auto", &options), cache_path("a.pbf", "auto"));
+        assert_eq!(cache_path_for("a.pbf", "auto", &strict), "a.pbf.auto.x1.routing");
+
+        // So have the component and spur filters
+        let pruned = BuildOptions {
+            min_component_size: 50,
+            min_stub_length_m: 12.5,
+            ..BuildOptions::default()
+        };
+        assert_eq!(cache_path_for("a.pbf", "auto", &pruned), "a.pbf.auto.comp_50.stub_12.5.routing");
+        let all_components = BuildOptions { min_component_size: 0, ..BuildOptions::default() };
+        assert_eq!(cache_path_for("a.pbf", "auto", &all_components), "a.pbf.auto.comp_0.routing");
     }
 
-    let from_bytes = unsafe { std::slice::from_raw_parts(from_wkb, from_wkb_len as usize) };
-    let to_bytes = unsafe { std::slice::from_raw_parts(to_wkb, to_wkb_len as usize) };
+    #[test]
+    fn test_load_cached_rebuild() {
+        let key = std::env::temp_dir().join(format!("routing-rebuild-{}", std::process::id()));
+        let key = key.to_str().unwrap();
+        let options = BuildOptions::default();
+        let builds = AtomicUsize::new(0);
+        // The graph isn't installed, as other tests use the global routers concurrently
+        let load = |rebuild: bool| {
+            cached_graph(key, "hgv", &options, rebuild, &Progress::NONE, |_, _| {
+                builds.fetch_add(1, AtomicOrdering::Relaxed);
+                Ok(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]))
+            })
+        };
+        assert!(load(false).is_ok());
+        assert_eq!(load(false).unwrap().node_positions.len(), 2);
+        assert_eq!(builds.load(AtomicOrdering::Relaxed), 1);
+        assert!(load(true).is_ok());
+        assert_eq!(builds.load(AtomicOrdering::Relaxed), 2);
+        std::fs::remove_file(cache_path(key, "hgv")).unwrap();
+    }
 
-    let mode = match unsafe { CStr::from_ptr(mode) }.to_str() {
-        Ok(s) if !mode.is_null() => s,
-        _ => return -1,
-    };
+    #[test]
+    fn test_batch_failure_sentinels() {
+        let router = Router::new(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]));
+        assert_eq!(batch_travel_time_s(&router, 0.0, 0.0, 0.01, 0.0), Ok(1.0));
+        assert_eq!(batch_travel_time_s(&router, 0.01, 0.0, 0.0, 0.0), Err(BATCH_NO_PATH));
+
+        let mut empty = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]);
+        empty.spatial_index = RTree::new();
+        assert_eq!(batch_travel_time_s(&Router::new(empty), 0.0, 0.0, 0.01, 0.0), Err(BATCH_NO_ORIGIN_SNAP));
+    }
 
-    // Parse WKB and get centroids
-    let (lon1, lat1) = match wkb_to_centroid(from_bytes) {
-        Some(c) => c,
-        None => return -1,
-    };
+    #[test]
+    fn test_thread_pool() {
+        assert_eq!(routing_set_threads(2), 0);
+        assert_eq!(in_thread_pool(rayon::current_num_threads), 2);
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000), (1, 0, 1000)]);
+        let mut results = vec![0.0; 4];
+        assert_eq!(fill_matrix(&data, &[Some(0), Some(1)], &[Some(0), Some(1)], 1.0, &mut results), 4);
+        assert_eq!(results, vec![0.0, 1.0, 1.0, 0.0]);
+
+        assert_eq!(routing_set_threads(0), 0);
+        assert_eq!(in_thread_pool(rayon::current_num_threads), rayon::current_num_threads());
+    }
 
-    let (lon2, lat2) = match wkb_to_centroid(to_bytes) {
-        Some(c) => c,
-        None => return -1,
-    };
+    #[test]
+    fn test_load_progress() {
+        extern "C" fn record(phase: *const c_char, percent: i32, user_data: *mut c_void) {
+            let phases = unsafe { &mut *(user_data as *mut Vec<(String, i32)>) };
+            let phase = unsafe { CStr::from_ptr(phase) }.to_string_lossy().into_owned();
+            phases.push((phase, percent));
+        }
+        let mut phases: Vec<(String, i32)> = Vec::new();
+        let progress = Progress {
+            callback: Some(record),
+            user_data: &mut phases as *mut Vec<(String, i32)> as *mut c_void,
+        };
 
-    let mutex = get_router_for_mode(mode);
-    let mut guard = match mutex.lock() {
-        Ok(g) => g,
-        Err(_) => return -1,
-    };
+        let key = std::env::temp_dir().join(format!("routing-progress-{}", std::process::id()));
+        let key = key.to_str().unwrap();
+        let build = |_: &BuildOptions, progress: &Progress| {
+            progress.report(c"reading", 0);
+            Ok(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]))
+        };
+        // As RouterBuilder::load_with_progress, without installing into the global routers
+        assert!(cached_graph(key, "hgv", &BuildOptions::default(), true, &progress, build).is_ok());
+        assert!(cached_graph(key, "hgv", &BuildOptions::default(), false, &progress, build).is_ok());
+        let labels: Vec<&str> = phases.iter().map(|(phase, _)| phase.as_str()).collect();
+        assert_eq!(labels, vec!["reading", "writing", "loading"]);
+        assert_eq!(phases.iter().map(|&(_, percent)| percent).collect::<Vec<_>>(), vec![0, 90, 0]);
+        std::fs::remove_file(cache_path(key, "hgv")).unwrap();
+    }
 
-    let router = match guard.as_mut() {
-        Some(r) => r,
-        None => return -2,
-    };
+    #[test]
+    fn test_directed_node_snapping() {
+        // One-way 0 -> 1 -> 2 along the equator, two-way 2 - 3
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000), (3, 2, 1000)],
+        );
+        let router = Router::new(data);
+        // Wherever the point lies between 0 and 1, leaving can only reach 1 and arriving comes from 0
+        assert_eq!(find_origin_node(&router, 0.002, 0.0001), Some(1));
+        assert_eq!(find_dest_node(&router, 0.002, 0.0001), Some(0));
+        assert_eq!(find_origin_node(&router, 0.008, 0.0001), Some(1));
+        assert_eq!(find_dest_node(&router, 0.008, 0.0001), Some(0));
+        // Two-way segments keep the nearest node
+        assert_eq!(find_origin_node(&router, 0.026, 0.0001), Some(3));
+        assert_eq!(find_dest_node(&router, 0.024, 0.0001), Some(2));
+
+        // Node snapping to 0 would make the route from a point just past it start backwards
+        let (nodes, _) = calc_route(&router, 0.002, 0.0, 0.03, 0.0).unwrap();
+        assert_eq!(nodes, vec![1, 2, 3]);
+    }
 
-    let from_idx = match find_nearest_node(&router.data, lon1, lat1) {
-        Some(idx) => idx,
-        None => return -1,
-    };
+    #[test]
+    fn test_rust_api() {
+        assert!(matches!(Router::builder("walking").load("missing.osm.pbf"), Err(Error::UnknownMode(_))));
+        let bad_level = Router::builder("auto").compression_level(23).load("missing.osm.pbf");
+        assert!(matches!(bad_level, Err(Error::InvalidArgument(_))));
+        assert!(matches!(Router::load("missing.osm.pbf", "auto"), Err(Error::Load(_))));
+
+        // Two-way 0 - 1 - 2 along the equator, one-way 2 -> 3
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 1000), (2, 1, 1000), (2, 3, 1000)],
+        );
+        let router = Router::new(data);
+        assert_eq!(router.mode(), "auto");
+        assert_eq!(router.travel_time(0.0, 0.004, 0.0, 0.016).unwrap(), 1.2);
+        assert!(matches!(router.travel_time(0.0, 0.03, 0.0, 0.0), Err(Error::NoRoute(_))));
+
+        let route = router.route(0.0, 0.004, 0.0, 0.016).unwrap();
+        assert_eq!(route.duration_s, 1.2);
+        let lons: Vec<f64> = route.points.iter().map(|p| p.lon).collect();
+        assert_eq!(lons, vec![0.004, 0.01, 0.016]);
+        assert!((route.distance_m - snapped_cost(&router, 0.004, 0.0, 0.016, 0.0).unwrap().0).abs() < 1e-9);
+
+        let mut reached: Vec<(f64, f64)> = router
+            .isochrone(0.0, 0.0, 2.0)
+            .unwrap()
+            .iter()
+            .map(|r| (r.lon, r.seconds))
+            .collect();
+        reached.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(reached, vec![(0.0, 0.0), (0.01, 1.0), (0.02, 2.0)]);
+
+        let matrix = router.matrix(&[(0.0, 0.0), (0.0, 0.03)], &[(0.0, 0.03), (0.0, 0.0)]).unwrap();
+        assert_eq!(matrix, vec![vec![Some(3.0), Some(0.0)], vec![Some(0.0), None]]);
+        assert_eq!(router.matrix(&[(0.0, 0.0)], &[]).unwrap(), vec![Vec::<Option<f64>>::new()]);
+    }
 
-    let to_idx = match find_nearest_node(&router.data, lon2, lat2) {
-        Some(idx) => idx,
-        None => return -1,
-    };
+    #[test]
+    fn test_multi_snap() {
+        // Main road 0 - 1 - 2 - 3 along the equator; dead-end stub 4 - 5 just north of it,
+        // reachable only through a long road from 3
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0), (0.004, 0.0003), (0.006, 0.0003)],
+            &[
+                (0, 1, 1000),
+                (1, 0, 1000),
+                (1, 2, 1000),
+                (2, 1, 1000),
+                (2, 3, 1000),
+                (3, 2, 1000),
+                (4, 5, 1000),
+                (5, 4, 1000),
+                (5, 3, 60_000),
+                (3, 5, 60_000),
+            ],
+        );
+        let router = Router::new(data);
+        let snaps = find_nearest_edges(&router, 0.005, 0.0002, SNAP_CANDIDATES);
+        assert_eq!((snaps[0].from, snaps[0].to), (4, 5));
+        assert_eq!((snaps[1].from, snaps[1].to), (0, 1));
+        assert!(snaps.windows(2).all(|pair| pair[0].distance_m <= pair[1].distance_m));
+
+        // The nearest segment is the stub
+        let (origin, _, nodes, ms) = snapped_path(&router, 0.005, 0.0002, 0.02, 0.0, 1).unwrap();
+        assert_eq!((origin.from, origin.to), (4, 5));
+        assert_eq!(nodes[..2], [5, 3]);
+        assert_eq!(ms, 61_500);
+        // The main road is within the tolerance and much faster
+        let (origin, _, nodes, ms) = snapped_path(&router, 0.005, 0.0002, 0.02, 0.0, SNAP_CANDIDATES).unwrap();
+        assert_eq!((origin.from, origin.to), (0, 1));
+        assert_eq!(nodes[0], 1);
+        assert_eq!(ms, 1500);
+        // Beyond the tolerance the stub is kept
+        let (origin, _, _, _) = snapped_path(&router, 0.005, 0.0006, 0.02, 0.0, SNAP_CANDIDATES).unwrap();
+        assert_eq!((origin.from, origin.to), (4, 5));
+    }
 
-    let path = match router
-        .calculator
-        .calc_path(&router.data.fast_graph, from_idx, to_idx)
-    {
-        Some(p) => p,
-        None => return -1,
-    };
+    #[test]
+    fn test_default_speed() {
+        let profile = Profile::from_json(r#"{"default": 25}"#).unwrap();
+        let none = tags(&[]);
+        assert_eq!(way_speed_kmh(&none, "future_road", "auto", &profile), Some(25.0));
+        assert_eq!(way_speed_kmh(&none, "future_road", "auto", &Profile::default()), None);
+        // Types known to some mode and non-road values are unaffected
+        assert_eq!(way_speed_kmh(&none, "footway", "auto", &profile), None);
+        assert_eq!(way_speed_kmh(&none, "track", "auto", &profile), None);
+        assert_eq!(way_speed_kmh(&none, "construction", "auto", &profile), None);
+        assert_eq!(way_speed_kmh(&none, "primary", "auto", &profile), Some(80.0));
+        assert_eq!(way_speed_kmh(&tags(&[("maxspeed", "50")]), "future_road", "auto", &profile), Some(50.0));
+
+        assert!(Profile::from_json(r#"{"default": "fast"}"#).is_err());
+        assert_eq!(Profile::from_json(r#"{"default": 0}"#).unwrap(), Profile::default());
+        assert!(!profile.is_empty());
+        assert_ne!(profile.hash(), Profile::default().hash());
+
+        // A way of an unknown type joins two roads only with the fallback
+        let way = |id: i64, tags: Tags, nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags,
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        let osm = OsmData {
+            nodes: HashMap::from([(1, (0.0, 0.0)), (2, (0.001, 0.0)), (3, (0.002, 0.0)), (4, (0.003, 0.0))]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![
+                way(10, tags(&[("highway", "residential")]), &[1, 2]),
+                way(11, tags(&[("highway", "future_road")]), &[2, 3]),
+                way(12, tags(&[("highway", "residential")]), &[3, 4]),
+            ],
+            restrictions: Vec::new(),
+        };
+        let (west, east) = ((0.0, 0.0), (0.003, 0.0));
+        for mode in ["auto", "bicycle", "pedestrian"] {
+            assert!(!connected(&build_graph_from_osm(&osm, mode, &BuildOptions::default()), west, east));
+            let fallback = BuildOptions {
+                profile: profile.clone(),
+                ..Default::default()
+            };
+            assert!(connected(&build_graph_from_osm(&osm, mode, &fallback), west, east), "{}", mode);
+        }
+    }
 
-    let path_nodes = path.get_nodes();
-    let duration_s = path.get_weight() as f64 / 1000.0;
+    #[test]
+    fn test_interior_routing_point() {
+        use geo::Contains;
+        // C shape open to the east: the centroid falls in the gap
+        let wkt = "POLYGON((0 0, 3 0, 3 1, 1 1, 1 2, 3 2, 3 3, 0 3, 0 0))";
+        let polygon = match parse_wkt(wkt).unwrap() {
+            Geometry::Polygon(p) => p,
+            _ => unreachable!(),
+        };
+        let (lon, lat) = wkt_to_point(wkt, false).unwrap();
+        assert!(!polygon.contains(&Point::new(lon, lat)));
+        let (lon, lat) = wkt_to_point(wkt, true).unwrap();
+        assert!(polygon.contains(&Point::new(lon, lat)));
+
+        assert_eq!(wkt_to_point("POINT(12.45 43.94)", true).ok(), Some((12.45, 43.94)));
+        let (lon, lat) = wkt_to_point("LINESTRING(0 0, 1 0, 1 1)", true).unwrap();
+        assert!([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)].contains(&(lon, lat)));
+        assert!(wkt_to_point("POLYGON EMPTY", true).is_err());
+    }
 
-    let mut total_distance_m = 0.0;
-    let out_points = unsafe { std::slice::from_raw_parts_mut(out_points, max_points as usize) };
-    let num_points = path_nodes.len().min(max_points as usize);
+    #[test]
+    fn test_parse_ewkb() {
+        let point = |big_endian: bool, srid: Option<u32>, x: f64, y: f64| {
+            let type_id = 1 | srid.map_or(0, |_| EWKB_SRID_FLAG);
+            let mut bytes = vec![u8::from(!big_endian)];
+            let mut push = |word: &[u8], reversed: bool| {
+                bytes.extend(if reversed { word.iter().rev().copied().collect() } else { word.to_vec() })
+            };
+            push(&type_id.to_le_bytes(), big_endian);
+            if let Some(srid) = srid {
+                push(&srid.to_le_bytes(), big_endian);
+            }
+            push(&x.to_le_bytes(), big_endian);
+            push(&y.to_le_bytes(), big_endian);
+            bytes
+        };
+        assert_eq!(wkb_to_point(&point(false, None, 12.45, 43.94), false).ok(), Some((12.45, 43.94)));
+        assert_eq!(wkb_to_point(&point(true, None, 12.45, 43.94), false).ok(), Some((12.45, 43.94)));
+        assert_eq!(wkb_to_point(&point(false, Some(4326), 12.45, 43.94), false).ok(), Some((12.45, 43.94)));
+        assert_eq!(wkb_to_point(&point(true, Some(4326), 12.45, 43.94), false).ok(), Some((12.45, 43.94)));
+
+        let err = parse_wkb(&point(false, Some(3857), 1385863.0, 5458560.0)).unwrap_err();
+        assert!(err.to_string().contains("Unsupported SRID 3857"));
+        // Projected coordinates without an SRID
+        let err = parse_wkb(&point(false, None, 1385863.0, 5458560.0)).unwrap_err();
+        assert!(err.to_string().contains("outside the lon/lat range"));
+        assert!(parse_wkt("POINT(1385863 5458560)").is_err());
+
+        assert!(parse_wkb(&[]).is_err());
+        assert!(parse_wkb(&[1, 1, 0]).is_err());
+        assert!(parse_wkb(&point(false, None, 12.45, 43.94)[..12]).is_err());
+        assert!(parse_wkb(&[7, 1, 0, 0, 0]).unwrap_err().to_string().contains("byte order"));
+    }
 
-    for i in 0..num_points {
-        let node_idx = path_nodes[i];
-        let (lon, lat) = router.data.node_positions[node_idx];
-        out_points[i] = RoutePoint { lat, lon };
+    #[test]
+    fn test_round_trip() {
+        // Two-way 6 x 6 grid, 1 s per edge
+        let n = 6;
+        let positions = (0..n * n).map(|i| ((i % n) as f64 * 0.01, (i / n) as f64 * 0.01)).collect();
+        let mut edges = Vec::new();
+        for i in 0..n * n {
+            for j in [i + 1, i + n] {
+                if j < n * n && (j == i + n || j % n != 0) {
+                    edges.extend([(i, j, 1000), (j, i, 1000)]);
+                }
+            }
+        }
+        let router = Router::new(make_data(positions, &edges));
+        let start = 14;
+        for seed in 0..8 {
+            let (nodes, cost_ms) = round_trip_path(&router, start, 12_000, seed).unwrap();
+            assert_eq!((nodes[0], nodes[nodes.len() - 1]), (start, start));
+            assert_eq!(path_cumulative_ms(&router.data, &nodes).last(), Some(&cost_ms));
+            assert!((12_000..=14_000).contains(&cost_ms), "seed {}: {} ms", seed, cost_ms);
+            // A loop rather than out and back
+            let distinct: HashSet<usize> = nodes.iter().copied().collect();
+            assert!(distinct.len() > nodes.len() / 2 + 1, "seed {}: {:?}", seed, nodes);
+            assert_eq!(round_trip_path(&router, start, 12_000, seed), Some((nodes, cost_ms)));
+        }
+        let trips: HashSet<Vec<usize>> =
+            (0..8).map(|seed| round_trip_path(&router, start, 12_000, seed).unwrap().0).collect();
+        assert!(trips.len() > 1);
 
-        if i > 0 {
-            let prev_idx = path_nodes[i - 1];
-            let (prev_lon, prev_lat) = router.data.node_positions[prev_idx];
-            let p1 = Point::new(prev_lon, prev_lat);
-            let p2 = Point::new(lon, lat);
-            total_distance_m += p1.haversine_distance(&p2);
+        // An isolated node has no loop
+        let lone = Router::new(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[]));
+        assert_eq!(round_trip_path(&lone, 0, 10_000, 1), None);
+    }
+
+    #[test]
+    fn test_preload() {
+        let key = std::env::temp_dir().join(format!("routing-preload-{}", std::process::id()));
+        let key = key.to_str().unwrap();
+        let options = BuildOptions::default();
+        let cached = cached_graph(key, "agricultural", &options, false, &Progress::NONE, |_, _| {
+            Ok(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]))
+        });
+        assert!(cached.is_ok());
+
+        // The cached mode loads without the PBF; the others share its failed read
+        let loads = load_modes(key, &["agricultural", "pedestrian", "bicycle"], &options);
+        assert_eq!(loads.len(), 3);
+        assert_eq!(loads[0].as_ref().map(|d| d.node_positions.len()).ok(), Some(2));
+        for load in &loads[1..] {
+            let message = format!("{:#}", load.as_ref().err().unwrap());
+            assert!(message.contains("Could not open PBF file"), "{}", message);
         }
+        std::fs::remove_file(cache_path(key, "agricultural")).unwrap();
+
+        let path = std::ffi::CString::new(key).unwrap();
+        assert_eq!(routing_preload(path.as_ptr(), c"auto,boat".as_ptr()), -4);
+        assert_eq!(routing_preload(path.as_ptr(), c" , ".as_ptr()), -1);
+        assert_eq!(routing_preload(path.as_ptr(), c"pedestrian, pedestrian".as_ptr()), 0);
     }
 
-    unsafe {
-        *out_result = RouteResult {
-            distance_m: total_distance_m,
-            duration_s,
-            num_points: num_points as i32,
-        };
+    #[test]
+    fn test_same_component() {
+        // Two-way 0 - 1 with one-way 1 -> 2 along the equator; separate two-way 3 - 4 to the north
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.0, 0.1), (0.01, 0.1)],
+            &[(0, 1, 1000), (1, 0, 1000), (1, 2, 1000), (3, 4, 1000), (4, 3, 1000)],
+        );
+        let router = Router::new(data);
+        assert!(router.same_component(0.0, 0.002, 0.0, 0.008).unwrap());
+        assert!(!router.same_component(0.0, 0.002, 0.1, 0.008).unwrap());
+        // Same component, but the one-way leaves no way back
+        assert!(router.same_component(0.0, 0.02, 0.0, 0.0).unwrap());
+        assert!(matches!(router.travel_time(0.0, 0.02, 0.0, 0.0), Err(Error::NoRoute(_))));
+
+        let mut empty = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]);
+        empty.spatial_index = RTree::new();
+        assert!(matches!(Router::new(empty).same_component(0.0, 0.0, 0.0, 0.01), Err(Error::NoSnap)));
     }
 
-    num_points as i32
-}
+    #[test]
+    fn test_route_way_ids() {
+        // One-way 0 -> 1 -> 2 -> 3 along the equator: ways 10 (two edges) and 20
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.03, 0.0)],
+            &[(0, 1, 1000), (1, 2, 1000), (2, 3, 1000)],
+        );
+        for (info, way_id) in data.edge_info.iter_mut().zip([10, 10, 20]) {
+            info.way_id = way_id;
+        }
+        let router = Router::new(data);
+        let (path_nodes, _) = calc_route(&router, 0.0, 0.0, 0.03, 0.0).unwrap();
+        assert_eq!(path_nodes, vec![0, 1, 2, 3]);
+        assert_eq!(path_way_ids(&router, &path_nodes), vec![10, 20]);
+        assert_eq!(path_way_ids(&router, &path_nodes[1..3]), vec![10]);
+        assert!(path_way_ids(&router, &[2]).is_empty());
+        assert_eq!(snapped_way_ids(&router, 0.005, 0.0, 0.03, 0.0), Some(vec![10, 20]));
+        assert_eq!(snapped_way_ids(&router, 0.0, 0.0, 0.005, 0.0), Some(vec![10]));
+
+        // Road 10 along the equator through 1, road 20 parallel to it 0.002 north, joined at the
+        // east end by road 30. A point just below road 20 snaps to node 1 of road 10, but to road 20
+        let mut data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0), (0.0, 0.002), (0.02, 0.002)],
+            &[
+                (0, 1, 1000),
+                (1, 0, 1000),
+                (1, 2, 1000),
+                (2, 1, 1000),
+                (3, 4, 2000),
+                (4, 3, 2000),
+                (2, 4, 200),
+                (4, 2, 200),
+            ],
+        );
+        // Edge info is in adjacency order, by from node
+        for (info, way_id) in data.edge_info.iter_mut().zip([10, 10, 10, 10, 30, 20, 20, 30]) {
+            info.way_id = way_id;
+        }
+        let router = Router::new(data);
+        let (path_nodes, _) = calc_route(&router, 0.01, 0.0015, 0.02, 0.002).unwrap();
+        assert_eq!(path_way_ids(&router, &path_nodes), vec![10, 30]);
+        assert_eq!(snapped_way_ids(&router, 0.01, 0.0015, 0.02, 0.002), Some(vec![20]));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_mode_validation() {
+        assert_eq!(mode_arg(c"bicycle".as_ptr()), Ok("bicycle"));
+        assert_eq!(mode_arg(std::ptr::null()), Err(-1));
+        assert_eq!(mode_arg(c"walking".as_ptr()), Err(-4));
+        let message = LAST_ERROR.with(|e| e.borrow().clone());
+        assert_eq!(message, "Unknown mode 'walking' (expected one of auto, bicycle, pedestrian, agricultural, hgv)");
+
+        // A typo fails instead of answering from the auto graph
+        assert_eq!(routing_node_count(c"walking".as_ptr()), -4);
+        assert_eq!(routing_travel_time(0.0, 0.0, 0.0, 0.01, c"Auto".as_ptr()), -4.0);
+        assert_eq!(routing_is_loaded(c"walking".as_ptr()), 0);
+        assert_eq!(routing_load(c"missing.osm.pbf".as_ptr(), c"walking".as_ptr()), -4);
+
+        let mut buf = [0 as c_char; 64];
+        let len = routing_supported_modes(buf.as_mut_ptr(), buf.len() as i32);
+        let modes = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap();
+        assert_eq!(modes, "auto,bicycle,pedestrian,agricultural,hgv");
+        assert_eq!(len as usize, modes.len());
+        assert_eq!(routing_supported_modes(buf.as_mut_ptr(), 4), len);
+    }
 
     #[test]
-    fn test_speed_lookup() {
-        assert_eq!(get_speed_kmh("motorway", "auto"), Some(120.0));
-        assert_eq!(get_speed_kmh("cycleway", "bicycle"), Some(20.0));
-        assert_eq!(get_speed_kmh("footway", "pedestrian"), Some(5.0));
-        assert_eq!(get_speed_kmh("railway", "auto"), None);
+    fn test_isochrone_boundary_points() {
+        // Two-way spokes from 0: 1 s east to 1, 4 s north to 2
+        let data = make_data(
+            vec![(0.0, 0.0), (0.01, 0.0), (0.0, 0.01)],
+            &[(0, 1, 1000), (1, 0, 1000), (0, 2, 4000), (2, 0, 4000)],
+        );
+        let reached = isochrone_nodes(&data, 0, 2000);
+        assert_eq!(reached, vec![(0, 0), (1, 1000)]);
+        // Half of the north spoke; 1 -> 0 ends exactly on the budget
+        let points = isochrone_boundary_points(&data, &reached, 2000);
+        assert_eq!(points.len(), 1);
+        assert!(points[0].0.abs() < 1e-9 && (points[0].1 - 0.005).abs() < 1e-9, "{:?}", points);
+
+        // 1 -> 0 runs out of budget half-way, but 0 itself is reached
+        let points = isochrone_boundary_points(&data, &reached, 1500);
+        assert_eq!(points.len(), 1);
+        assert!(points[0].0.abs() < 1e-9 && (points[0].1 - 0.00375).abs() < 1e-9, "{:?}", points);
+        assert!(isochrone_boundary_points(&data, &reached, 10_000).is_empty());
     }
 
     #[test]
-    fn test_is_main_road() {
-        assert!(is_main_road("motorway"));
-        assert!(is_main_road("residential"));
-        assert!(!is_main_road("footway"));
-        assert!(!is_main_road("cycleway"));
+    fn test_prune_stubs() {
+        // Road 1-2-3-4 east along the equator (111 m per segment) with a 44 m spur 2-5-6 north,
+        // and a separate 11 m island 7-8
+        let way = |id: i64, nodes: &[i64]| osmpbfreader::Way {
+            id: osmpbfreader::WayId(id),
+            tags: tags(&[("highway", "residential")]),
+            nodes: nodes.iter().map(|&n| osmpbfreader::NodeId(n)).collect(),
+        };
+        let osm = OsmData {
+            nodes: HashMap::from([
+                (1, (0.0, 0.0)),
+                (2, (0.001, 0.0)),
+                (3, (0.002, 0.0)),
+                (4, (0.003, 0.0)),
+                (5, (0.001, 0.0002)),
+                (6, (0.001, 0.0004)),
+                (7, (0.01, 0.01)),
+                (8, (0.0101, 0.01)),
+            ]),
+            barriers: HashMap::new(),
+            crossings: HashSet::new(),
+            ways: vec![way(10, &[1, 2, 3, 4]), way(11, &[2, 5, 6]), way(12, &[7, 8])],
+            restrictions: Vec::new(),
+        };
+        let build = |min_stub_length_m| {
+            let options = BuildOptions {
+                min_component_size: 0,
+                min_stub_length_m,
+                ..Default::default()
+            };
+            build_graph_from_osm(&osm, "auto", &options)
+        };
+
+        assert_eq!(build(0.0).node_positions.len(), 8);
+        // The spur goes; the road's ends are longer than the threshold and the island is
+        // left to the component filter
+        let data = build(100.0);
+        assert_eq!(data.node_positions.len(), 6);
+        assert!(!data.node_positions.contains(&(0.001, 0.0002)) && !data.node_positions.contains(&(0.001, 0.0004)));
+        assert!(check_graph(&data).is_ok());
+        assert!(data.spatial_index.iter().all(|p| data.node_positions[p.idx] == (p.lon, p.lat)));
+        assert!(connected(&data, (0.0, 0.0), (0.003, 0.0)));
+        let router = Router::new(data);
+        // A point at the spur's end snaps to the junction it left
+        let (path, _) = calc_route(&router, 0.001, 0.0004, 0.003, 0.0).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(router.data.node_positions[path[0]], (0.001, 0.0));
+
+        // Dead ends are measured up to the junction: the west end (111 m) goes, the east end
+        // (222 m through node 3) stays
+        let data = build(200.0);
+        assert_eq!(data.node_positions.len(), 5);
+        assert!(!data.node_positions.contains(&(0.0, 0.0)) && data.node_positions.contains(&(0.003, 0.0)));
     }
 
     #[test]
-    fn test_rtree_nearest() {
-        let points = vec![
-            IndexedPoint { lon: 0.0, lat: 0.0, idx: 0 },
-            IndexedPoint { lon: 1.0, lat: 1.0, idx: 1 },
-            IndexedPoint { lon: 2.0, lat: 2.0, idx: 2 },
-        ];
-        let tree = RTree::bulk_load(points);
+    fn test_matrix_snap_distances() {
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000), (1, 0, 1000)]);
+        let router = Router::new(data);
+        // Origins on node 0 and 0.001 degrees north of node 1 (111 m); destination on node 1
+        let mut results = vec![0.0; 2];
+        let (mut origin_snap_m, mut dest_snap_m) = (vec![0.0; 2], vec![0.0; 1]);
+        let routed = router.matrix_into(
+            &[(0.0, 0.0), (0.001, 0.01)],
+            &[(0.0, 0.01)],
+            &mut results,
+            &mut origin_snap_m,
+            &mut dest_snap_m,
+        );
+        assert_eq!(routed, 2);
+        assert_eq!(results, vec![1.0, 0.0]);
+        assert_eq!(origin_snap_m[0], 0.0);
+        assert!((origin_snap_m[1] - 111.2).abs() < 0.1, "{}", origin_snap_m[1]);
+        assert_eq!(dest_snap_m, vec![0.0]);
+
+        let mut empty = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]);
+        empty.spatial_index = RTree::new();
+        // Slots past the points are left alone
+        let second = origin_snap_m[1];
+        let routed = Router::new(empty).matrix_into(&[(0.0, 0.0)], &[], &mut [], &mut origin_snap_m, &mut []);
+        assert_eq!(routed, 0);
+        assert_eq!(origin_snap_m, vec![-1.0, second]);
+    }
 
-        let nearest = tree.nearest_neighbor(&[0.1, 0.1]).unwrap();
-        assert_eq!(nearest.idx, 0);
+    #[test]
+    fn test_great_circle_estimate() {
+        // 0.01 degrees of longitude on the equator is 1112 m
+        let auto_s = great_circle_estimate_s("auto", 0.0, 0.0, 0.01, 0.0);
+        assert!((auto_s - 1111.95 / (40.0 / 3.6)).abs() < 0.01, "{}", auto_s);
+        let walk_s = great_circle_estimate_s("pedestrian", 0.0, 0.0, 0.01, 0.0);
+        assert!((walk_s / auto_s - 40.0 / 4.5).abs() < 1e-9);
+        assert_eq!(great_circle_estimate_s("bicycle", 0.01, 0.01, 0.01, 0.01), 0.0);
+
+        // Failures before any routing keep their status and leave the flag clear
+        let mut estimated = 1;
+        let seconds = routing_travel_time_or_estimate(0.0, 0.0, 0.0, 0.01, c"walking".as_ptr(), &mut estimated);
+        assert_eq!((seconds, estimated), (-4.0, 0));
+    }
 
-        let nearest = tree.nearest_neighbor(&[1.9, 1.9]).unwrap();
-        assert_eq!(nearest.idx, 2);
+    #[test]
+    fn test_ch_order_reuse() {
+        let mut osm = barrier_junction("residential");
+        osm.barriers.clear();
+        let options = BuildOptions::default();
+        let data = build_graph_from_osm(&osm, "auto", &options);
+        let route = |data: RoutingData| calc_route(&Router::new(data), 0.0, 0.0, 0.002, 0.0).map(|(path, _)| path);
+        let order = ChOrder::of(&data);
+
+        // The same nodes are contracted in the given order
+        let reversed = ChOrder {
+            time: order.time.iter().rev().copied().collect(),
+            distance: order.distance.iter().rev().copied().collect(),
+            node_positions: order.node_positions.clone(),
+        };
+        let with_order = BuildOptions {
+            prior_order: Some(Arc::new(reversed)),
+            ..Default::default()
+        };
+        let rebuilt = build_graph_from_osm(&osm, "auto", &with_order);
+        let prior = with_order.prior_order.as_deref().unwrap();
+        assert_eq!(fast_paths::get_node_ordering(&rebuilt.fast_graph), prior.time);
+        assert_eq!(fast_paths::get_node_ordering(&rebuilt.distance_graph), prior.distance);
+        assert_eq!(route(rebuilt), route(data));
+
+        // A rebuild over the cache passes its orders to the build
+        let key = std::env::temp_dir().join(format!("routing-ch-order-{}", std::process::id()));
+        let key = key.to_str().unwrap();
+        let graph = || make_data(vec![(0.0, 0.0), (0.01, 0.0), (0.02, 0.0)], &[(0, 1, 1000), (1, 2, 1000)]);
+        let first = cached_graph(key, "auto", &options, false, &Progress::NONE, |options, _| {
+            assert!(options.prior_order.is_none());
+            Ok(graph())
+        });
+        let expected = ChOrder::of(&first.unwrap());
+        let rebuilt = cached_graph(key, "auto", &options, true, &Progress::NONE, |options, _| {
+            let prior = options.prior_order.as_deref().unwrap();
+            assert_eq!((&prior.time, &prior.node_positions), (&expected.time, &expected.node_positions));
+            Ok(graph())
+        });
+        assert!(rebuilt.is_ok());
+        std::fs::remove_file(cache_path(key, "auto")).unwrap();
+
+        assert_eq!(routing_set_ch_params(-0.1, 500, 100, 500, 100), -1);
+        assert_eq!(routing_set_ch_params(0.1, 500, -1, 500, 100), -1);
+        assert_eq!(*CH_PARAMS.read().unwrap(), ChParams::default());
     }
 
     #[test]
-    fn test_cache_path() {
+    fn test_pedestrian_node_delays() {
+        let mut osm = barrier_junction("footway");
+        osm.barriers.clear();
+        osm.crossings.insert(2);
+        let build = |osm: &OsmData, mode: &str, profile: &str| {
+            let options = BuildOptions {
+                profile: Profile::from_json(profile).unwrap(),
+                ..Default::default()
+            };
+            build_graph_from_osm(osm, mode, &options)
+        };
+        let weight = |data: &RoutingData, a: (f64, f64), b: (f64, f64)| {
+            let idx = |p| data.node_positions.iter().position(|&q| q == p).unwrap();
+            edge_weight_ms(data, idx(a), idx(b)).unwrap()
+        };
+        let (road, footway) = (((0.0, 0.0), (0.001, 0.0)), ((0.001, -0.001), (0.001, 0.0)));
+
+        // Crossing the road pays half the delay into the crossing node, walking along it nothing
+        let data = build(&osm, "pedestrian", "{}");
+        assert_eq!(weight(&data, footway.0, footway.1), weight(&data, road.0, road.1) + 2_500);
+        let data = build(&osm, "pedestrian", r#"{"crossing_delay": 20}"#);
+        assert_eq!(weight(&data, footway.0, footway.1), weight(&data, road.0, road.1) + 10_000);
+        let data = build(&osm, "pedestrian", r#"{"crossing_delay": 0}"#);
+        assert_eq!(weight(&data, footway.0, footway.1), weight(&data, road.0, road.1));
+        // Only walkers wait at crossings by default
+        let bicycle_ms = weight(&build(&osm, "bicycle", "{}"), footway.0, footway.1);
+
+        assert!(is_unsignalled_crossing(&tags(&[("highway", "crossing"), ("crossing", "uncontrolled")])));
+        assert!(!is_unsignalled_crossing(&tags(&[("highway", "crossing"), ("crossing", "traffic_signals")])));
+        assert!(!is_unsignalled_crossing(&tags(&[("highway", "crossing"), ("crossing:signals", "yes")])));
+        assert!(!is_unsignalled_crossing(&tags(&[("highway", "traffic_signals")])));
+
+        // Steps with a step count are slowed by the climb, spread over their segments
+        osm.crossings.clear();
+        assert_eq!(weight(&build(&osm, "bicycle", "{}"), footway.0, footway.1), bicycle_ms);
+        let steps_ms = |osm: &OsmData, profile: &str| {
+            let data = build(osm, "pedestrian", profile);
+            weight(&data, (0.001, -0.001), (0.001, 0.0)) + weight(&data, (0.001, 0.0), (0.001, 0.001))
+        };
+        osm.ways[1].tags = tags(&[("highway", "steps")]);
+        let untagged = steps_ms(&osm, "{}");
+        osm.ways[1].tags = tags(&[("highway", "steps"), ("step_count", "20")]);
+        assert!(steps_ms(&osm, "{}").abs_diff(untagged + 10_000) <= 1);
+        assert!(steps_ms(&osm, r#"{"step_delay": 1.5}"#).abs_diff(untagged + 30_000) <= 1);
+        assert_eq!(steps_ms(&osm, r#"{"step_delay": 0}"#), untagged);
+
+        let profile = Profile::from_json(r#"{"step_delay": 1}"#).unwrap();
+        assert!(!profile.is_empty());
+        assert_ne!(profile.hash(), Profile::from_json(r#"{"crossing_delay": 1}"#).unwrap().hash());
+        assert!(Profile::from_json(r#"{"step_delay": "slow"}"#).is_err());
+    }
+
+    #[test]
+    fn test_isochrone_edges() {
+        // 0 -- 1 -- 2 -- 3 in a line, 10 s per edge both ways
+        let positions = vec![(0.0, 0.0), (0.001, 0.0), (0.002, 0.0), (0.003, 0.0)];
+        let edges = [(0, 1, 10_000), (1, 0, 10_000), (1, 2, 10_000), (2, 1, 10_000), (2, 3, 10_000)];
+        let data = make_data(positions, &edges);
+        let reached = isochrone_nodes(&data, 1, 15_000);
+        let mut segments = isochrone_edges(&data, &reached, 15_000);
+        segments.sort_by_key(|&(_, _, ms)| ms);
+        // Both roads leaving the origin are kept once, outwards; 2 -> 3 ends past the budget
         assert_eq!(
-            cache_path("/data/italy.osm.pbf", "auto"),
-            "/data/italy.osm.pbf.auto.routing"
+            segments,
+            vec![((0.001, 0.0), (0.0, 0.0), 10_000), ((0.001, 0.0), (0.002, 0.0), 10_000)]
         );
+
+        let reached = isochrone_nodes(&data, 0, 30_000);
+        let segments = isochrone_edges(&data, &reached, 30_000);
+        let arrivals: Vec<u32> = segments.iter().map(|&(_, _, ms)| ms).collect();
+        assert_eq!(arrivals, vec![10_000, 20_000, 30_000]);
+        assert!(isochrone_edges(&data, &reached, 5_000).is_empty());
+    }
+
+    #[test]
+    fn test_isochrone_tie_order() {
+        // A star of equal-cost spokes: every leaf ties with the others
+        let mut positions = vec![(0.0, 0.0)];
+        let mut edges = Vec::new();
+        for i in 1..=8 {
+            positions.push((0.001 * i as f64, 0.001));
+            edges.push((0, i, 1000));
+        }
+        let data = make_data(positions, &edges);
+        let reached = isochrone_nodes(&data, 0, 5000);
+        let order: Vec<usize> = reached.iter().map(|&(node, _)| node).collect();
+        assert_eq!(order, (0..=8).collect::<Vec<_>>());
+        for _ in 0..3 {
+            assert_eq!(isochrone_nodes(&data, 0, 5000), reached);
+        }
+
+        let mut heap = BinaryHeap::from([DijkstraState { cost: 5, node: 3 }, DijkstraState { cost: 5, node: 1 }]);
+        heap.push(DijkstraState { cost: 2, node: 9 });
+        let popped: Vec<usize> = std::iter::from_fn(|| heap.pop()).map(|s| s.node).collect();
+        assert_eq!(popped, vec![9, 1, 3]);
+    }
+
+    #[test]
+    fn test_named_routers() {
+        let handle = std::ffi::CString::new(format!("test-named-{}", std::process::id())).unwrap();
+        let missing = std::ffi::CString::new("test-named-missing").unwrap();
+        assert_eq!(routing_travel_time_named(handle.as_ptr(), 0.0, 0.0, 0.0, 0.01), -2.0);
+
+        let data = make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000), (1, 0, 1000)]);
+        let mut router = Router::new(data);
+        router.mode = "pedestrian";
+        let expected = router.travel_time(0.0, 0.0, 0.0, 0.01).unwrap();
+        assert_eq!(install_named(handle.to_str().unwrap(), router), 0);
+        assert_eq!(routing_travel_time_named(handle.as_ptr(), 0.0, 0.0, 0.0, 0.01), expected);
+        assert_eq!(routing_travel_time_named(missing.as_ptr(), 0.0, 0.0, 0.0, 0.01), -2.0);
+        assert_eq!(routing_travel_time_named(std::ptr::null(), 0.0, 0.0, 0.0, 0.01), -1.0);
+        // The graph keeps the mode it was loaded for
+        assert!(named_router(handle.as_ptr()).is_ok_and(|r| r.mode == "pedestrian"));
+
+        assert_eq!(routing_free_named(handle.as_ptr()), 0);
+        assert_eq!(routing_free_named(handle.as_ptr()), -2);
+        assert_eq!(routing_travel_time_named(handle.as_ptr(), 0.0, 0.0, 0.0, 0.01), -2.0);
+    }
+
+    #[test]
+    fn test_coordinate_validation() {
+        let mode = c"auto".as_ptr();
+        // Tokyo with lat and lon swapped, a latitude past the pole and NaN
+        assert_eq!(routing_travel_time(139.69, 35.69, 35.68, 139.76, mode), -5.0);
+        let message = LAST_ERROR.with(|e| e.borrow().clone());
+        assert!(message.contains("lat 139.69, lon 35.69") && message.contains("swapped"), "{}", message);
+        assert_eq!(routing_travel_time(35.69, 139.69, 200.0, 0.0, mode), -5.0);
+        assert_eq!(routing_same_component(0.0, f64::NAN, 0.0, 0.0, mode), -5);
+        assert_eq!(routing_isochrone_edges(-90.5, 0.0, 60.0, mode, std::ptr::null_mut(), 0), -5);
+        assert_eq!(routing_add_edge(0.0, 0.0, 0.0, 180.5, 50.0, mode), -5);
+        // Checked before the mode
+        assert_eq!(routing_snap_degree(91.0, 0.0, c"walking".as_ptr(), &mut 0), -5);
+
+        assert!(is_valid_coord(90.0, -180.0) && is_valid_coord(-90.0, 180.0));
+        assert_eq!(coords_arg([(0.0, 0.0), (1.0, 1.0)]), Ok(()));
+
+        // A geometry's out-of-range point fails while parsing, with the same code
+        let mut result = RouteResult { distance_m: 0.0, duration_s: 0.0, num_points: 0 };
+        let mut points = [RoutePoint { lat: 0.0, lon: 0.0 }; 2];
+        let (result_ptr, points_ptr) = (&mut result as *mut RouteResult, points.as_mut_ptr());
+        let (from, to) = (c"POINT(139.69 95.0)".as_ptr(), c"POINT(0 0)".as_ptr());
+        assert_eq!(routing_route_geom(from, to, mode, result_ptr, points_ptr, 2), -5);
+        let message = LAST_ERROR.with(|e| e.borrow().clone());
+        assert!(message.starts_with("Start geometry: Coordinates outside") && message.contains("lat 95"), "{}", message);
+        assert_eq!(routing_route_geom(to, c"POINT(1385863 5458560)".as_ptr(), mode, result_ptr, points_ptr, 2), -5);
+        assert_eq!(routing_route_geom(c"POINT(".as_ptr(), to, mode, result_ptr, points_ptr, 2), -1);
+
+        // A batch marks the pair instead of failing
+        let router = Router::new(make_data(vec![(0.0, 0.0), (0.01, 0.0)], &[(0, 1, 1000)]));
+        assert_eq!(batch_travel_time_s(&router, 0.0, 0.0, 0.01, 0.0), Ok(1.0));
+        assert_eq!(batch_travel_time_s(&router, 0.0, 95.0, 0.01, 0.0), Err(BATCH_INVALID_COORD));
+        assert_eq!(batch_travel_time_s(&router, 0.0, 0.0, 181.0, 0.0), Err(BATCH_INVALID_COORD));
     }
 }